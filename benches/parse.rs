@@ -0,0 +1,39 @@
+use std::{hint::black_box, str::FromStr};
+use criterion::{criterion_group, criterion_main, Criterion};
+use pngme::{chunk::Chunk, chunk_type::ChunkType, png::{ParseOptions, Png}};
+
+/// PNG with a realistic layout: header, 64 IDAT chunks of 8 KiB and IEND
+fn typical_png() -> Vec<u8> {
+    let mut chunks = vec![Chunk::new(ChunkType::from_str("IHDR").unwrap(), vec![0; 13])];
+    chunks.extend((0..64).map(|_| Chunk::new(ChunkType::from_str("IDAT").unwrap(), vec![0xab; 8192])));
+    chunks.push(Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()));
+    Png::from_chunks(chunks).as_bytes()
+}
+
+/// Crafted PNG made of a million zero-length chunks
+fn adversarial_png() -> Vec<u8> {
+    let empty = Chunk::new(ChunkType::from_str("emPt").unwrap(), Vec::new()).as_bytes();
+    let mut bytes = Png::from_chunks(Vec::new()).as_bytes();
+    for _ in 0..1_000_000 {
+        bytes.extend(&empty);
+    }
+    bytes
+}
+
+fn parse(c: &mut Criterion) {
+    let typical = typical_png();
+    c.bench_function("parse typical", |b| b.iter(|| Png::try_from(black_box(&typical[..])).unwrap()));
+
+    let adversarial = adversarial_png();
+    let unlimited = ParseOptions { max_chunks: None, ..ParseOptions::default() };
+    let limited = ParseOptions::default();
+
+    let mut group = c.benchmark_group("parse adversarial");
+    group.sample_size(10);
+    group.bench_function("unlimited", |b| b.iter(|| Png::parse(black_box(&adversarial), &unlimited).unwrap()));
+    group.bench_function("limited", |b| b.iter(|| Png::parse(black_box(&adversarial), &limited).is_err()));
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
@@ -1,45 +1,419 @@
-use clap::{Parser, Subcommand};
-use std::env;
+use clap::{Args as ClapArgs, Parser, Subcommand, ValueEnum};
+use crate::{cancel::CancellationToken, capacity::Growth, compress::Compression, crypto::{CipherKind, KdfKind}, format::{DataType, MessageFormat}, oplog::LogFormat, placement::{Mode, Placement}, png, survive::Transform, text::OnDuplicate};
 
 #[derive(Parser, Debug)]
 #[command(name = "PNGMe")]
 #[command(author = "Brian Lee <brianjslmit@gmail.com>")]
 #[command(version = "1.0")]
 #[command(about = "Encode/Decode secret messages in your PNGs", long_about = None)]
-
 /// Specifies the type of Command Line Argument
 pub struct Args {
     #[command(subcommand)]
     pub command: Commands,
+
+    #[command(flatten)]
+    pub global: GlobalArgs,
+}
+
+/// Options shared by every command
+#[derive(ClapArgs, Debug, Clone)]
+pub struct GlobalArgs {
+    /// Wait for other processes to release their lock on the PNG file
+    /// instead of failing immediately
+    #[arg(long, global = true)]
+    pub wait: bool,
+
+    /// Embed a manifest of chunk hashes into every PNG that is written,
+    /// so later modifications can be found with verify-manifest
+    #[arg(long, global = true)]
+    pub manifest: bool,
+
+    /// Record every encode and remove in an audit chunk of the PNG, listed
+    /// by the history command
+    #[arg(long, global = true)]
+    pub audit: bool,
+
+    /// Refuse PNG files with more chunks than this
+    #[arg(long, global = true, default_value_t = png::DEFAULT_MAX_CHUNKS)]
+    pub max_chunks: usize,
+
+    /// Keep chunks with CRC mismatches and data after IEND byte for byte
+    /// instead of refusing the file
+    #[arg(long, global = true)]
+    pub preserve: bool,
+
+    /// Append a structured record of the operation to this log file
+    #[arg(long, global = true)]
+    pub log_file: Option<String>,
+
+    /// Layout of the log file lines
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::default())]
+    pub log_format: LogFormat,
+
+    /// Refuse operations that could reach the network: fetching PNG files
+    /// from URLs, running --exec commands and gpg, which may fetch keys
+    #[arg(long, global = true)]
+    pub no_network: bool,
+
+    /// Refuse operations that could reach the clipboard: running --exec
+    /// commands (pngme itself never uses the clipboard)
+    #[arg(long, global = true)]
+    pub no_clipboard: bool,
+
+    /// Abort the operation when it runs longer than this, unless it has
+    /// started writing files, which it then finishes
+    #[arg(long, global = true, value_name = "SECONDS")]
+    pub max_runtime: Option<u64>,
+
+    /// Refuse to write PNG files, messages or extracted data larger than
+    /// this. Compressed messages are otherwise refused past 64 MiB, and
+    /// only this raises that limit
+    #[arg(long, global = true, value_name = "BYTES")]
+    pub max_output_size: Option<u64>,
+
+    /// Warn when a PNG that fails to read looks damaged by a text-mode
+    /// (CR LF converting) transfer
+    #[arg(long, global = true)]
+    pub crlf_safe: bool,
+
+    /// Only warn about tEXt, zTXt and iTXt keywords that break the PNG
+    /// specification's rules instead of refusing to write them
+    #[arg(long, global = true)]
+    pub allow_nonstandard_keyword: bool,
+
+    /// Cancelled when --max-runtime runs out, so the command stops at the
+    /// next check instead of being cut off mid-write
+    #[arg(skip)]
+    pub cancel: CancellationToken,
 }
 
 /// Seperates type of Command Line Argument
+// parsed once per run, so boxing the large encode variant would buy nothing
+#[allow(clippy::large_enum_variant, non_camel_case_types)]
 #[derive(Debug, Subcommand)]
 pub enum Commands {
     /// Encodes a message string of a given PNG chunk type to a 
     /// specified a PNG file
     encode {
-        /// Path to the PNG File
+        /// Path to the PNG File, or its http(s) URL (with the net feature)
         file_path: String, 
 
         /// PNG chunk type as a UTF-8 string
         chunk_type: String,
 
-        /// Message to be encoded
-        message: String,
+        /// Message to be encoded, or - to read it from standard input (after
+        /// the line holding the password, when that is piped in too)
+        #[arg(required_unless_present_any = ["exec", "file"])]
+        message: Option<String>,
 
         /// Optional Output file for the modified PNG
-        output_file: Option<String>
+        output_file: Option<String>,
+
+        /// Encode the standard output of this shell command instead of a
+        /// message, streaming it into the chunk. The argument after the
+        /// chunk type is then the optional output file.
+        #[arg(long, value_name = "COMMAND", conflicts_with_all = ["encrypt", "recipients", "hmac_key", "obfuscate"])]
+        exec: Option<String>,
+
+        /// Encode the raw bytes of this file (a PDF, a zip, ...) instead of
+        /// a message, along with its name, size, mime type and
+        /// modification time. The argument after the chunk type is then
+        /// the optional output file.
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["exec", "keyword"])]
+        file: Option<String>,
+
+        /// Refuse to encode into a carrier that fails the health checks
+        /// instead of only warning about it
+        #[arg(long)]
+        strict_carrier: bool,
+
+        /// Take the smallest unused carrier that fits the payload from this
+        /// pool directory (see `pngme pool`). FILE_PATH is then where the
+        /// encoded PNG is written; the pooled carrier is left as it is.
+        #[arg(long, value_name = "DIR", conflicts_with_all = ["exec", "output_file", "strict_carrier"])]
+        from_pool: Option<String>,
+
+        /// Kind of data the message holds. JSON is checked and minified
+        /// before it is stored.
+        #[arg(long, value_enum, default_value_t = DataType::default(), conflicts_with_all = ["exec", "file"])]
+        data_type: DataType,
+
+        /// Store the message as text under this keyword, laid out as the
+        /// tEXt, zTXt or iTXt chunk type requires
+        #[arg(long, conflicts_with_all = ["exec", "encrypt", "recipients", "hmac_key"])]
+        keyword: Option<String>,
+
+        /// What to do with text chunks already using the keyword
+        /// [default: append]
+        #[arg(long, value_enum, requires = "keyword")]
+        on_duplicate: Option<OnDuplicate>,
+
+        /// Compress the message before embedding (and encrypting) it. It
+        /// is stored uncompressed when that is smaller, and decode
+        /// decompresses it either way.
+        #[arg(long, value_enum, default_value_t = Compression::default(), conflicts_with_all = ["exec", "keyword"])]
+        compress: Compression,
+
+        /// Store the SHA-256 of the message with it, so decode fails
+        /// loudly when what it recovers is not what was encoded
+        #[arg(long, conflicts_with_all = ["exec", "keyword"])]
+        checksum: bool,
+
+        /// Record who encoded the message, shown by decode --info along
+        /// with the time it was encoded
+        #[arg(long, conflicts_with_all = ["exec", "keyword"])]
+        author: Option<String>,
+
+        /// Record a note about the message, shown by decode --info along
+        /// with the time it was encoded
+        #[arg(long, conflicts_with_all = ["exec", "keyword"])]
+        note: Option<String>,
+
+        /// Where to store the payload. Resilient placement adds copies in
+        /// a private safe-to-copy chunk and an iTXt chunk, so the message
+        /// survives pipelines that strip one kind of chunk.
+        #[arg(long, value_enum, default_value_t = Placement::default(), conflicts_with = "exec")]
+        placement: Placement,
+
+        /// What to do when the file already has chunks of the chunk type:
+        /// add another message, replace the existing ones (with their
+        /// shards and resilient copies) or refuse to encode
+        #[arg(long, value_enum, default_value_t = Mode::default(), conflicts_with_all = ["exec", "keyword"])]
+        mode: Mode,
+
+        /// Split payloads longer than this across several chunks of the
+        /// type, each holding at most this many bytes, which decode joins
+        /// again [default: the largest chunk PNG allows]
+        #[arg(long, value_name = "BYTES", conflicts_with_all = ["exec", "keyword", "placement"])]
+        shard_size: Option<u32>,
+
+        /// Add Reed-Solomon parity data, this many bytes for each byte of
+        /// the sealed payload (at most 1), so decode can repair a payload
+        /// with damaged bytes. Read damaged files with --preserve.
+        #[arg(long, value_name = "RATIO", conflicts_with_all = ["exec", "keyword"])]
+        ecc: Option<f64>,
+
+        /// Encrypt the message with a password, by default with
+        /// ChaCha20-Poly1305 under an Argon2id key (see --cipher, --kdf and
+        /// the --kdf-* costs)
+        #[arg(long)]
+        encrypt: bool,
+
+        /// Password for --encrypt, prompted for (without echo) when not
+        /// given. Passing it here leaves it in shell history and ps output.
+        #[arg(long, requires = "encrypt")]
+        password: Option<String>,
+
+        /// Read the key for --encrypt from this file (raw or hex-encoded)
+        /// instead of a password
+        #[arg(long, requires = "encrypt", conflicts_with = "password")]
+        key_file: Option<String>,
+
+        /// Read the password for --encrypt from this environment variable
+        #[arg(long, value_name = "VAR", requires = "encrypt")]
+        password_env: Option<String>,
+
+        /// Read the password for --encrypt from the OS keyring, storing the
+        /// prompted password there when it has none yet
+        #[cfg(feature = "keyring")]
+        #[arg(long, requires = "encrypt")]
+        use_keyring: bool,
+
+        /// Cipher for --encrypt, recorded in the payload so decode picks
+        /// it automatically
+        #[arg(long, value_enum, default_value_t = CipherKind::default(), requires = "encrypt")]
+        cipher: CipherKind,
+
+        /// Function deriving the key from the password for --encrypt,
+        /// recorded in the payload along with its parameters
+        #[arg(long, value_enum, default_value_t = KdfKind::default(), requires = "encrypt")]
+        kdf: KdfKind,
+
+        /// Memory the KDF uses in KiB [default: 65536 for argon2, 131072
+        /// for scrypt]. Lower it for low-memory devices.
+        #[arg(long, value_name = "KIB", requires = "encrypt")]
+        kdf_memory: Option<u32>,
+
+        /// Passes the KDF makes over its memory [default: 3] (argon2 only)
+        #[arg(long, requires = "encrypt")]
+        kdf_iterations: Option<u32>,
+
+        /// Lanes the KDF runs in [default: 4 for argon2, 1 for scrypt]
+        #[arg(long, requires = "encrypt")]
+        kdf_parallelism: Option<u32>,
+
+        /// Store the message under a chunk type derived from the password
+        /// and the chunk type given, which then only names the message.
+        /// Decode it with --decrypt --stealth and the same chunk type.
+        #[arg(long, requires = "encrypt", conflicts_with = "decoy")]
+        stealth: bool,
+
+        /// Seal this decoy message in the same chunk under a second
+        /// password, which decrypts only the decoy. Nothing in the chunk
+        /// shows that it holds two messages.
+        #[arg(long, requires = "encrypt")]
+        decoy: Option<String>,
+
+        /// Password for --decoy, prompted for (without echo) when not given
+        #[arg(long, requires = "decoy")]
+        decoy_password: Option<String>,
+
+        /// Encrypt the message with age to this public key (age1...), so
+        /// only its keyholder can decode it. May be repeated.
+        #[arg(long = "recipient", conflicts_with = "encrypt")]
+        recipients: Vec<String>,
+
+        /// Append an HMAC-SHA256 tag made with the key in this file (raw or
+        /// hex-encoded), so decode can detect modified messages
+        #[arg(long, conflicts_with_all = ["encrypt", "recipients"])]
+        hmac_key: Option<String>,
+
+        /// XOR the message with a pseudo-random stream so it does not read
+        /// as text in a hex editor. This hides, it does not encrypt:
+        /// decode undoes it without any key.
+        #[arg(long, conflicts_with_all = ["encrypt", "recipients", "hmac_key", "keyword"])]
+        obfuscate: bool,
+
+        /// Encrypt the message with gpg to this OpenPGP key (key id,
+        /// fingerprint or user id from your keyring), which gpg must
+        /// consider valid. May be repeated.
+        #[cfg(feature = "pgp")]
+        #[arg(long = "pgp-recipient", conflicts_with_all = ["exec", "encrypt", "recipients", "hmac_key", "keyword", "obfuscate"])]
+        pgp_recipients: Vec<String>,
+
+        /// Encrypt the message under a key wrapped with the hmac-secret of
+        /// a new credential on your FIDO2 security key, so decoding needs
+        /// the key
+        #[cfg(feature = "fido")]
+        #[arg(long, conflicts_with_all = ["exec", "encrypt", "recipients", "hmac_key", "keyword", "obfuscate"])]
+        fido: bool,
     },
 
     /// Decodes encoded message strings of a specified PNG chunk 
     /// type from a specified PNG file
     decode {
-        /// Path to the PNG File
+        /// Path to the PNG File, or its http(s) URL (with the net feature)
         file_path: String,
 
         /// Message to be encoded
-        chunk_type: String
+        #[arg(required_unless_present = "all")]
+        chunk_type: Option<String>,
+
+        /// Print every message hidden in the file's non-standard ancillary
+        /// chunks, with its chunk type and index, instead of one
+        #[arg(long, conflicts_with_all = ["chunk_type", "index", "exec", "output", "restore_name", "describe", "info", "stealth"])]
+        all: bool,
+
+        /// Decode the Nth message of the chunk type (counting from 0) when
+        /// several were encoded under it, instead of the first that opens
+        #[arg(long, value_name = "N", conflicts_with = "exec")]
+        index: Option<usize>,
+
+        /// Run this shell command with the raw chunk data streamed into its
+        /// standard input instead of printing the message
+        #[arg(long, value_name = "COMMAND", conflicts_with_all = ["decrypt", "identity", "hmac_key"])]
+        exec: Option<String>,
+
+        /// Write the raw message bytes to this file instead of printing
+        /// the message. Binary messages are never printed to a terminal.
+        #[arg(long, value_name = "PATH", conflicts_with = "exec")]
+        output: Option<String>,
+
+        /// Write a file encoded with --file under its original name in the
+        /// current directory, with its original modification time
+        #[arg(long, conflicts_with_all = ["exec", "output"])]
+        restore_name: bool,
+
+        /// Print the name, size, mime type and modification time of a file
+        /// encoded with --file instead of extracting it
+        #[arg(long, conflicts_with_all = ["exec", "output", "restore_name"])]
+        describe: bool,
+
+        /// Print who encoded the message, when, and the note recorded with
+        /// encode --author / --note instead of the message
+        #[arg(long, conflicts_with_all = ["exec", "output", "restore_name", "describe"])]
+        info: bool,
+
+        /// Format to print (or write to --output) the message in. Hex and
+        /// base64 print binary messages safely on a terminal.
+        #[arg(long, value_enum, default_value_t = MessageFormat::default(), conflicts_with_all = ["exec", "restore_name", "describe", "info"])]
+        format: MessageFormat,
+
+        /// Kind of data the message holds. JSON is checked and
+        /// pretty-printed.
+        #[arg(long, value_enum, default_value_t = DataType::default(), 
+            conflicts_with_all = ["all", "exec", "restore_name", "describe", "info", "format"])]
+        data_type: DataType,
+
+        /// Decrypt a message encoded with --encrypt
+        #[arg(long)]
+        decrypt: bool,
+
+        /// Password for --decrypt, prompted for (without echo) when not
+        /// given. Passing it here leaves it in shell history and ps output.
+        #[arg(long, requires = "decrypt")]
+        password: Option<String>,
+
+        /// Read the key for --decrypt from this file (raw or hex-encoded)
+        /// instead of a password
+        #[arg(long, requires = "decrypt", conflicts_with = "password")]
+        key_file: Option<String>,
+
+        /// Read the password for --decrypt from this environment variable
+        #[arg(long, value_name = "VAR", requires = "decrypt")]
+        password_env: Option<String>,
+
+        /// Read the password for --decrypt from the OS keyring
+        #[cfg(feature = "keyring")]
+        #[arg(long, requires = "decrypt")]
+        use_keyring: bool,
+
+        /// Read the message from the chunk type encode --stealth derived
+        /// from the password and the chunk type given
+        #[arg(long, requires = "decrypt")]
+        stealth: bool,
+
+        /// KDF encode --stealth derived the chunk type with, as given to
+        /// encode --kdf
+        #[arg(long, value_enum, default_value_t = KdfKind::default(), requires = "stealth")]
+        kdf: KdfKind,
+
+        /// Memory of the KDF for --stealth in KiB, as given to encode
+        /// --kdf-memory
+        #[arg(long, value_name = "KIB", requires = "stealth")]
+        kdf_memory: Option<u32>,
+
+        /// Passes of the KDF for --stealth, as given to encode
+        /// --kdf-iterations
+        #[arg(long, requires = "stealth")]
+        kdf_iterations: Option<u32>,
+
+        /// Lanes of the KDF for --stealth, as given to encode
+        /// --kdf-parallelism
+        #[arg(long, requires = "stealth")]
+        kdf_parallelism: Option<u32>,
+
+        /// Decrypt a message encoded with --recipient using the keys in
+        /// this age identity file
+        #[arg(long, conflicts_with = "decrypt")]
+        identity: Option<String>,
+
+        /// Verify the HMAC tag of a message encoded with --hmac-key using
+        /// the key in this file, refusing modified messages
+        #[arg(long, conflicts_with_all = ["decrypt", "identity"])]
+        hmac_key: Option<String>,
+
+        /// Decrypt a message encoded with --pgp-recipient using gpg and
+        /// your keyring
+        #[cfg(feature = "pgp")]
+        #[arg(long, conflicts_with_all = ["decrypt", "identity", "exec"])]
+        pgp: bool,
+
+        /// Decrypt a message encoded with --fido using the security key
+        /// it was encoded with
+        #[cfg(feature = "fido")]
+        #[arg(long, conflicts_with_all = ["decrypt", "identity", "hmac_key", "exec"])]
+        fido: bool,
     },
 
     /// Removes encoded messages of a specified PNG chunk type 
@@ -50,11 +424,628 @@ pub enum Commands {
 
         /// Message to be encoded
         chunk_type: String,
+
+        /// Remove the Nth message of the chunk type (counting from 0) instead
+        /// of the first
+        #[arg(long, value_name = "N")]
+        index: Option<usize>,
+
+        /// Rename a rewritten copy over the file, then overwrite the chunk's
+        /// bytes in the old file with zeros and flush them to disk, so the
+        /// message cannot be recovered from the old file blocks
+        #[arg(long)]
+        secure: bool,
+    },
+
+    /// Re-encrypts the password-encrypted messages of a chunk type under a
+    /// new password, replacing the file only once every one succeeded
+    Rekey {
+        /// Path to the PNG File
+        file_path: String,
+
+        /// PNG chunk type as a UTF-8 string
+        chunk_type: String,
+
+        /// Current password, prompted for (without echo) when not given
+        #[arg(long)]
+        password: Option<String>,
+
+        /// Read the current key from this file (raw or hex-encoded)
+        #[arg(long, conflicts_with = "password")]
+        key_file: Option<String>,
+
+        /// Read the current password from this environment variable
+        #[arg(long, value_name = "VAR")]
+        password_env: Option<String>,
+
+        /// New password, prompted for (without echo) when not given
+        #[arg(long)]
+        new_password: Option<String>,
+
+        /// Read the new key from this file (raw or hex-encoded)
+        #[arg(long, conflicts_with = "new_password")]
+        new_key_file: Option<String>,
+
+        /// Read the new password from this environment variable
+        #[arg(long, value_name = "VAR")]
+        new_password_env: Option<String>,
+    },
+
+    /// Concatenates the IDAT chunks of a PNG file into a single chunk,
+    /// preserving the compressed image data byte for byte
+    MergeIdat {
+        /// Path to the PNG File
+        file_path: String,
+
+        /// Optional Output file for the modified PNG
+        output_file: Option<String>,
+
+        /// Split the merged image data into IDAT chunks of at most this
+        /// many bytes
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        target_size: Option<u32>,
+    },
+
+    /// Verifies the signature and every chunk CRC of a file while
+    /// streaming it, using constant memory for files of any size
+    Check {
+        /// Path to the PNG File
+        file_path: String,
+    },
+
+    /// Undoes the damage a text-mode transfer (such as FTP in ASCII mode)
+    /// did to a PNG file, checking every rebuilt chunk against its CRC
+    Repair {
+        /// Path to the PNG File
+        file_path: String,
+
+        /// Optional Output file for the repaired PNG
+        output_file: Option<String>,
+    },
+
+    /// Reports deviations from the PNG specification that decoders usually
+    /// tolerate, such as misplaced ancillary chunks, with rule IDs
+    Lint {
+        /// Path to the PNG File
+        file_path: String,
+
+        /// Optional Output file for the fixed PNG
+        output_file: Option<String>,
+
+        /// Correct the fixable findings, writing the PNG in place unless an
+        /// output file is given
+        #[arg(long)]
+        fix: bool,
+    },
+
+    /// Prints the SHA-256 and BLAKE3 digests of a file, of the data of the
+    /// chunks of a type, or of the data of every chunk
+    Digest {
+        /// Path to the PNG File
+        file_path: String,
+
+        /// Digest the data of the chunks of this type instead of the file
+        chunk_type: Option<String>,
+
+        /// Digest the data of every chunk instead of the file
+        #[arg(long, conflicts_with = "chunk_type")]
+        chunks: bool,
+
+        /// Print the digests as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Splits a message into shares embedded in several PNG files, any
+    /// threshold of which recover the message while fewer reveal nothing
+    Split {
+        /// Message to split
+        message: String,
+
+        /// Number of files needed to recover the message
+        #[arg(short = 'k', long, value_parser = clap::value_parser!(u8).range(1..))]
+        threshold: u8,
+
+        /// Paths to the PNG files, one share each
+        #[arg(required = true, num_args = 2..=255)]
+        file_paths: Vec<String>,
+    },
+
+    /// Recovers a split message from the shares in several PNG files
+    Combine {
+        /// Paths to the PNG files
+        #[arg(required = true)]
+        file_paths: Vec<String>,
+    },
+
+    /// Checks a PNG file against its embedded manifest of chunk hashes
+    VerifyManifest {
+        /// Path to the PNG File
+        file_path: String,
+    },
+
+    /// Lists the encode and remove operations recorded in the audit chunk
+    /// of a PNG file by --audit
+    History {
+        /// Path to the PNG File
+        file_path: String,
+    },
+
+    /// Signs the type and hash of every chunk of a PNG file into a seal
+    /// chunk, so verify-seal can report which chunks changed since
+    Seal {
+        /// Path to the PNG File
+        file_path: String,
+
+        /// Optional Output file for the sealed PNG
+        output_file: Option<String>,
+
+        /// Ed25519 signing key (32 bytes, raw or hex), as made by
+        /// seal-keygen
+        #[arg(long, value_name = "KEY_FILE")]
+        key: String,
+
+        /// Write the signature to this file instead of embedding it,
+        /// leaving the PNG byte for byte as it is
+        #[arg(long, value_name = "SIG_FILE", conflicts_with = "output_file")]
+        detached: Option<String>,
+    },
+
+    /// Checks the seal of a PNG file and lists the chunks changed since it
+    /// was sealed
+    VerifySeal {
+        /// Path to the PNG File
+        file_path: String,
+
+        /// Require the seal to be signed by this Ed25519 public key (32
+        /// bytes, raw or hex). Without it, anyone could have made the seal.
+        #[arg(long, value_name = "KEY_FILE")]
+        public_key: Option<String>,
+
+        /// Check the detached signature in this file (made by seal
+        /// --detached) instead of a seal chunk
+        #[arg(long, value_name = "SIG_FILE")]
+        signature: Option<String>,
+    },
+
+    /// Generates an Ed25519 key pair for seal, writing the public key next
+    /// to the signing key with a .pub extension
+    SealKeygen {
+        /// Path to write the signing key to
+        key_file: String,
+    },
+
+    /// Writes license, copyright and asset ID tags into iTXt chunks of a
+    /// PNG file, or checks that the required tags exist
+    Tag {
+        /// Path to the PNG File
+        file_path: String,
+
+        /// Optional Output file for the modified PNG
+        output_file: Option<String>,
+
+        /// License of the asset (e.g. an SPDX identifier such as CC-BY-4.0)
+        #[arg(long)]
+        license: Option<String>,
+
+        /// Copyright notice of the asset
+        #[arg(long)]
+        copyright: Option<String>,
+
+        /// Identifier of the asset in its pipeline or catalogue
+        #[arg(long)]
+        asset_id: Option<String>,
+
+        /// What to do with text chunks already using a tag's keyword
+        #[arg(long, value_enum, default_value_t = OnDuplicate::default())]
+        on_duplicate: OnDuplicate,
+
+        /// Check that the required tags exist instead of writing any
+        #[arg(long, conflicts_with_all = ["output_file", "license", "copyright", "asset_id", "on_duplicate"])]
+        check: bool,
+
+        /// Tags that --check requires
+        #[arg(long, value_enum, value_delimiter = ',', default_value = "license,copyright,asset-id")]
+        require: Vec<TagField>,
+    },
+
+    /// Reads and writes the XMP metadata packet of a PNG file
+    Xmp {
+        #[command(subcommand)]
+        command: XmpCommands,
+    },
+
+    /// Embeds or extracts a small preview thumbnail of a PNG file
+    Thumbnail {
+        #[command(subcommand)]
+        command: ThumbnailCommands,
+    },
+
+    /// Creates and applies chunk-level patches between PNG files
+    Patch {
+        #[command(subcommand)]
+        command: PatchCommands,
+    },
+
+    /// Adds PNG files to a container that stores the chunks they share
+    /// only once, such as per-recipient copies of one carrier
+    Pack {
+        /// Path to the container, created if it does not exist
+        container: String,
+
+        /// Paths to the PNG Files, stored under their file names
+        #[arg(required = true)]
+        file_paths: Vec<String>,
+    },
+
+    /// Writes every PNG file stored in a container into a directory
+    Unpack {
+        /// Path to the container
+        container: String,
+
+        /// Directory to write the PNG Files to (the current directory by
+        /// default)
+        output_dir: Option<String>,
+    },
+
+    /// Writes a uniquely watermarked copy of a PNG file for each recipient
+    /// in a CSV file, recording the copies in a ledger
+    Fanout {
+        /// Path to the PNG File
+        file_path: String,
+
+        /// Path to a CSV file with a header row and an id column, whose
+        /// values name the copies
+        recipients: String,
+
+        /// Directory to write the copies and the ledger to
+        output_dir: String,
+
+        /// Sign the watermarks with the HMAC-SHA256 key in this file (hex
+        /// or raw bytes)
+        #[arg(long, value_name = "KEY_FILE")]
+        hmac_key: Option<String>,
+    },
+
+    /// Identifies which recipient's copy a leaked PNG file is
+    DetectLeak {
+        /// Path to the ledger written by fanout
+        ledger: String,
+
+        /// Path to the leaked PNG File
+        file_path: String,
+
+        /// Verify signed watermarks with the HMAC-SHA256 key in this file
+        #[arg(long, value_name = "KEY_FILE")]
+        hmac_key: Option<String>,
+    },
+
+    /// Keeps local notes on chunk types alongside the data, shown by print
+    Note {
+        #[command(subcommand)]
+        command: NoteCommands,
+    },
+
+    /// Indexes many PNG files into a local workspace and queries their
+    /// chunks without rescanning the files
+    Workspace {
+        /// Name of the workspace
+        #[arg(long, default_value = "default")]
+        name: String,
+
+        #[command(subcommand)]
+        command: WorkspaceCommands,
+    },
+
+    /// Maintains a directory of vetted carrier PNGs for encode --from-pool
+    Pool {
+        #[command(subcommand)]
+        command: PoolCommands,
+    },
+
+    /// Runs a PNG file through simulated processing pipelines and reports
+    /// whether the message of a chunk type survives each of them
+    SurviveTest {
+        /// Path to the PNG File
+        file_path: String,
+
+        /// PNG chunk type of the message
+        chunk_type: String,
+
+        /// Transform to simulate, repeatable [default: all of them]
+        #[arg(long = "transform", value_enum)]
+        transforms: Vec<Transform>,
+    },
+
+    /// Estimates the largest message encode can hide in a PNG file with
+    /// the given options, assuming it does not compress unless --sample
+    /// says otherwise
+    Capacity {
+        /// Path to the PNG File
+        file_path: String,
+
+        /// Error correction ratio, as encode --ecc takes
+        #[arg(long, value_name = "RATIO")]
+        ecc: Option<f64>,
+
+        /// Largest chunk in bytes, as encode --shard-size takes
+        #[arg(long, value_name = "BYTES")]
+        shard_size: Option<u32>,
+
+        /// How much the file may grow, in bytes or as a percentage of its
+        /// size (such as 10%)
+        #[arg(long, value_name = "SIZE")]
+        max_growth: Option<Growth>,
+
+        /// Compression to estimate the capacity for, measured on --sample
+        #[arg(long, value_enum, requires = "sample")]
+        compress: Option<Compression>,
+
+        /// File like the messages to encode, compressed with --compress to
+        /// estimate how much more of such data fits
+        #[arg(long, value_name = "PATH", requires = "compress")]
+        sample: Option<String>,
+    },
+
+    /// Looks for messages in every place pngme hides them (non-standard
+    /// chunks and data after IEND), and for files other tools append, and
+    /// prints what it finds
+    Peek {
+        /// Path to the PNG File
+        file_path: String,
+
+        /// Also open password-encrypted messages with this password.
+        /// Passing it here leaves it in shell history and ps output.
+        #[arg(long)]
+        password: Option<String>,
+
+        /// Also open password-encrypted messages with the key in this
+        /// file (raw or hex-encoded)
+        #[arg(long, conflicts_with = "password")]
+        key_file: Option<String>,
+
+        /// Also open password-encrypted messages with the password in this
+        /// environment variable
+        #[arg(long, value_name = "VAR", conflicts_with_all = ["password", "key_file"])]
+        password_env: Option<String>,
+
+        /// Write every payload that could be opened to a file in this
+        /// directory, named after where it was found
+        #[arg(long, value_name = "DIR")]
+        extract: Option<String>,
     },
 
     /// Prints a list of PNG chunks that can be searched for messages
     print {
+        /// Path to the PNG File, or its http(s) URL (with the net feature)
+        file_path: String,
+    },
+
+    /// Prints the JSON Schema of the JSON a command outputs, for
+    /// validating it or generating code against it
+    Schema {
+        /// Output to describe
+        #[arg(value_enum)]
+        output: JsonOutput,
+    },
+
+    /// Explains what a chunk type code means: its property bits, whether
+    /// the PNG specification registers it and what it is for
+    Explain {
+        /// Four-letter chunk type, such as tEXt
+        chunk_type: String,
+    },
+}
+
+/// Operations on the XMP packet of a PNG file
+#[derive(Debug, Subcommand)]
+pub enum XmpCommands {
+    /// Prints the XMP packet of a PNG file
+    Get {
         /// Path to the PNG File
         file_path: String,
     },
+
+    /// Replaces the XMP packet of a PNG file with the XMP data in a file
+    Set {
+        /// Path to the PNG File
+        file_path: String,
+
+        /// Path to the XMP data (an x:xmpmeta or rdf:RDF element)
+        xmp_file: String,
+
+        /// Optional Output file for the modified PNG
+        output_file: Option<String>,
+    },
+
+    /// Merges the XMP data in a file into the XMP packet of a PNG file
+    Merge {
+        /// Path to the PNG File
+        file_path: String,
+
+        /// Path to the XMP data (an x:xmpmeta or rdf:RDF element)
+        xmp_file: String,
+
+        /// Optional Output file for the modified PNG
+        output_file: Option<String>,
+    },
+}
+
+/// Operations on patches between PNG files
+#[derive(Debug, Subcommand)]
+pub enum PatchCommands {
+    /// Writes the changes from one PNG file to another as a patch
+    Create {
+        /// Path to the original PNG File
+        original_file: String,
+
+        /// Path to the modified PNG File
+        modified_file: String,
+
+        /// Path to write the patch to
+        patch_file: String,
+    },
+
+    /// Applies a patch to the PNG file it was created against
+    Apply {
+        /// Path to the PNG File
+        file_path: String,
+
+        /// Path to the patch
+        patch_file: String,
+
+        /// Optional Output file for the patched PNG
+        output_file: Option<String>,
+    },
+}
+
+/// Operations on the thumbnail of a PNG file
+#[derive(Debug, Subcommand)]
+pub enum ThumbnailCommands {
+    /// Generates a thumbnail of a PNG file and embeds it into the file
+    Add {
+        /// Path to the PNG File
+        file_path: String,
+
+        /// Optional Output file for the modified PNG
+        output_file: Option<String>,
+
+        /// Largest width and height of the generated thumbnail in pixels
+        #[arg(long, default_value_t = crate::thumbnail::DEFAULT_SIZE, value_parser = clap::value_parser!(u32).range(1..))]
+        size: u32,
+
+        /// Embed this PNG file as the thumbnail instead of generating one
+        #[arg(long, conflicts_with = "size")]
+        from: Option<String>,
+    },
+
+    /// Writes the embedded thumbnail of a PNG file to its own PNG file
+    Extract {
+        /// Path to the PNG File
+        file_path: String,
+
+        /// Path the thumbnail PNG is written to
+        thumbnail_file: String,
+    },
+}
+
+/// Operations on the local notes
+#[derive(Debug, Subcommand)]
+pub enum NoteCommands {
+    /// Attaches a note to a chunk type in a specific PNG file
+    Add {
+        /// Path to the PNG File
+        file_path: String,
+
+        /// PNG chunk type as a UTF-8 string
+        chunk_type: String,
+
+        /// Text of the note
+        text: String,
+    },
+
+    /// Attaches a note to a chunk type in every PNG file
+    AddType {
+        /// PNG chunk type as a UTF-8 string
+        chunk_type: String,
+
+        /// Text of the note
+        text: String,
+    },
+
+    /// Lists the notes that apply to a PNG file, or every note
+    List {
+        /// Path to the PNG File
+        file_path: Option<String>,
+    },
+
+    /// Removes a note by its id
+    Remove {
+        /// Id of the note as shown by list
+        id: u64,
+    },
+}
+
+/// Operations on a carrier pool
+#[derive(Debug, Subcommand)]
+pub enum PoolCommands {
+    /// Vets PNG files (or every .png file under directories) and copies
+    /// the healthy ones into the pool
+    Add {
+        /// Pool directory, created if it does not exist
+        dir: String,
+
+        /// Paths to PNG files or directories
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+
+    /// Lists the carriers in the pool with their capacities
+    List {
+        /// Pool directory
+        dir: String,
+    },
+
+    /// Removes a carrier from the pool and deletes its file
+    Remove {
+        /// Pool directory
+        dir: String,
+
+        /// File name of the carrier in the pool
+        name: String,
+    },
+}
+
+/// Operations on an investigation workspace
+#[derive(Debug, Subcommand)]
+pub enum WorkspaceCommands {
+    /// Adds PNG files (or every .png file under directories) to the
+    /// workspace, re-indexing files that are already in it
+    Index {
+        /// Paths to PNG files or directories
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+
+    /// Lists the indexed chunks matching every given condition
+    Query {
+        /// Only chunks of this type
+        #[arg(long)]
+        chunk_type: Option<String>,
+
+        /// Only chunks with at least this many bytes of data
+        #[arg(long)]
+        min_size: Option<u32>,
+
+        /// Only chunks with at most this many bytes of data
+        #[arg(long)]
+        max_size: Option<u32>,
+
+        /// Only chunk types not registered by the PNG specification
+        #[arg(long)]
+        non_standard: bool,
+    },
+
+    /// Lists the indexed files with their hashes and findings
+    List,
+
+    /// Deletes the workspace
+    Clear,
+}
+
+/// JSON outputs the schema command describes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum JsonOutput {
+    /// digest --json
+    Digest,
+    /// Lines of --log-format json
+    Log,
+}
+
+/// Structured fields written by the tag command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TagField {
+    License,
+    Copyright,
+    AssetId,
 }
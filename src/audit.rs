@@ -0,0 +1,141 @@
+//!
+//! Audit chunk recording the operations pngme performed on a PNG, each
+//! with the time, the pngme version, the chunk type it touched and a hash
+//! of the PNG afterwards
+//!
+use std::{fmt, error, str::{self, FromStr}, time::{SystemTime, UNIX_EPOCH}};
+use sha2::{Digest, Sha256};
+use crate::{chunk::Chunk, chunk_type::ChunkType, manifest::MANIFEST_TYPE, png::Png, seal::SEAL_TYPE, Result};
+
+/// Chunk type of the audit log (ancillary, private, safe to copy)
+pub const AUDIT_TYPE: &str = "pnGa";
+
+/// A single recorded operation
+#[derive(Debug, PartialEq, Eq)]
+pub struct AuditEntry {
+    /// Seconds since the Unix epoch at which the operation ran
+    pub timestamp: u64,
+    pub version: String,
+    pub operation: String,
+    pub chunk_type: String,
+    /// Hash of the PNG after the operation (see `content_hash`)
+    pub hash: [u8; 32],
+}
+
+impl fmt::Display for AuditEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {} {} pngme/{}", self.timestamp, self.operation, self.chunk_type, hex::encode(self.hash), self.version)
+    }
+}
+
+/// Hashes the type and data of every chunk except the audit log and the
+/// manifest and seal, which are derived from the other chunks
+pub fn content_hash(png: &Png) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for chunk in png.chunks() {
+        if ![AUDIT_TYPE, MANIFEST_TYPE, SEAL_TYPE].contains(&chunk.chunk_type().as_str().as_str()) {
+            hasher.update(chunk.chunk_type().bytes());
+            hasher.update(chunk.length().to_be_bytes());
+            hasher.update(chunk.data());
+        }
+    }
+    hasher.finalize().into()
+}
+
+/// Returns the entries of the audit log stored in the PNG, oldest first
+pub fn history(png: &Png) -> Result<Vec<AuditEntry>> {
+    png.chunk_by_type(AUDIT_TYPE).map_or(Ok(Vec::new()), parse)
+}
+
+/// Parses an audit chunk, one entry per line in the format of `Display`
+fn parse(chunk: &Chunk) -> Result<Vec<AuditEntry>> {
+    let mut entries = Vec::new();
+    for line in str::from_utf8(chunk.data())?.lines() {
+        let fields: Vec<&str> = line.split(' ').collect();
+        let [timestamp, operation, chunk_type, hash, version] = fields[..] else {
+            return Err(Box::new(InvalidAuditLogError));
+        };
+        entries.push(AuditEntry {
+            timestamp: timestamp.parse().map_err(|_| InvalidAuditLogError)?,
+            version: String::from(version.strip_prefix("pngme/").ok_or(InvalidAuditLogError)?),
+            operation: String::from(operation),
+            chunk_type: String::from(chunk_type),
+            hash: hex::decode(hash)?.try_into().map_err(|_| InvalidAuditLogError)?,
+        });
+    }
+    Ok(entries)
+}
+
+/// Appends an entry for the operation to the audit log of the PNG, placed
+/// right before the IEND chunk and created if the PNG has none
+pub fn record(png: &mut Png, operation: &str, chunk_type: &str) -> Result<()> {
+    let mut entries = history(png)?;
+    png.retain_chunks(|chunk| chunk.chunk_type().as_str() != AUDIT_TYPE);
+    entries.push(AuditEntry {
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+        version: String::from(env!("CARGO_PKG_VERSION")),
+        operation: String::from(operation),
+        chunk_type: String::from(chunk_type),
+        hash: content_hash(png),
+    });
+
+    let data: String = entries.iter().map(|entry| format!("{}\n", entry)).collect();
+    let index = png.chunks().iter()
+        .position(|chunk| chunk.chunk_type().as_str() == "IEND")
+        .unwrap_or(png.chunks().len());
+    png.insert_chunk(index, Chunk::new(ChunkType::from_str(AUDIT_TYPE).unwrap(), data.into_bytes()));
+    Ok(())
+}
+
+/// Error specifying that an audit chunk could not be parsed
+#[derive(Debug)]
+pub struct InvalidAuditLogError;
+
+impl fmt::Display for InvalidAuditLogError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Audit Log Chunk!")
+    }
+}
+
+impl error::Error for InvalidAuditLogError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::testing::{chunk, testing_png};
+
+    #[test]
+    fn test_record() {
+        let mut png = testing_png();
+        png.insert_chunk(2, chunk("ruSt", b"secret"));
+        record(&mut png, "encode", "ruSt").unwrap();
+        png.remove_chunk("ruSt").unwrap();
+        record(&mut png, "remove", "ruSt").unwrap();
+
+        let png = Png::try_from(&png.as_bytes()[..]).unwrap();
+        assert_eq!(png.chunks()[2].chunk_type().as_str(), AUDIT_TYPE);
+        let entries = history(&png).unwrap();
+        let operations: Vec<&str> = entries.iter().map(|entry| entry.operation.as_str()).collect();
+        assert_eq!(operations, vec!["encode", "remove"]);
+        assert_ne!(entries[0].hash, entries[1].hash);
+        assert_eq!(entries[1].hash, content_hash(&png));
+        assert_eq!(entries[1].version, env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let mut png = testing_png();
+        let hash = content_hash(&png);
+        crate::manifest::embed(&mut png);
+        assert_eq!(content_hash(&png), hash);
+        png.insert_chunk(1, chunk("tEXt", b"Title\0x"));
+        assert_ne!(content_hash(&png), hash);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(history(&Png::from_chunks(vec![chunk(AUDIT_TYPE, b"1 encode ruSt")])).is_err());
+        assert!(history(&Png::from_chunks(vec![chunk(AUDIT_TYPE, b"x encode ruSt 00 pngme/0.1.0")])).is_err());
+        assert!(history(&testing_png()).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,241 @@
+//!
+//! Payload keys wrapped with a FIDO2 security key. The message is encrypted
+//! under a random key, and that key under the hmac-secret the token derives
+//! from a salt with a credential only it holds, so decrypting needs the
+//! physical token. pngme talks to the token through libfido2's fido2-token,
+//! fido2-cred and fido2-assert tools, which ask for the PIN and a touch.
+//!
+//! | Field                                | Size |
+//! |--------------------------------------|------|
+//! | magic `PMFD`                         | 4    |
+//! | version                              | 1    |
+//! | cipher id                            | 1    |
+//! | credential id length                 | 2    |
+//! | credential id                        | n    |
+//! | hmac-secret salt                     | 32   |
+//! | nonce and wrapped key with its tag   | 12 + 48 |
+//! | nonce and message with its tag       | 12 + rest |
+//!
+use std::{env, error, fmt, io::Write, process::{Command, Stdio}};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use crate::{cipher::{self, Cipher}, crypto::{self, KEY_LEN, NONCE_LEN}, envelope, Result};
+
+/// Marks the start of a payload whose key is wrapped by a security key
+pub const MAGIC: &[u8; 4] = b"PMFD";
+
+/// Environment variable naming the token device, the first one fido2-token
+/// lists by default
+pub const DEVICE_VAR: &str = "PNGME_FIDO_DEVICE";
+
+/// Relying party the credentials are made for
+const RP_ID: &str = "pngme";
+
+pub const VERSION: u8 = 1;
+const SALT_LEN: usize = 32;
+const TAG_LEN: usize = 16;
+
+/// Checks if the payload starts with the magic bytes of a wrapped key
+pub fn is_fido_wrapped(payload: &[u8]) -> bool {
+    payload.starts_with(MAGIC)
+}
+
+/// Makes a new hmac-secret credential on the token and encrypts the
+/// plaintext under a key wrapped with its secret
+pub fn encrypt(cipher: &dyn Cipher, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let device = device()?;
+    let credential = make_credential(&device)?;
+    let salt = crypto::random_bytes();
+    let secret = hmac_secret(&device, &credential, &salt)?;
+    seal(cipher, &credential, &salt, &secret, plaintext)
+}
+
+/// Asks the token for the secret of the credential recorded in the payload
+/// and decrypts it
+pub fn decrypt(payload: &[u8]) -> Result<Vec<u8>> {
+    open(payload, |credential, salt| hmac_secret(&device()?, credential, salt))
+}
+
+/// Encrypts the plaintext under a random key wrapped with the secret
+fn seal(cipher: &dyn Cipher, credential: &[u8], salt: &[u8; SALT_LEN], secret: &[u8; KEY_LEN], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let mut payload = MAGIC.to_vec();
+    payload.extend([VERSION, cipher.id()]);
+    payload.extend(u16::try_from(credential.len()).map_err(|_| InvalidFidoPayloadError)?.to_be_bytes());
+    payload.extend(credential);
+    payload.extend(salt);
+
+    let key: [u8; KEY_LEN] = crypto::random_bytes();
+    let nonce: [u8; NONCE_LEN] = crypto::random_bytes();
+    let wrapped = cipher.seal(secret, &nonce, &key, &payload)?;
+    payload.extend(nonce);
+    payload.extend(wrapped);
+
+    let nonce: [u8; NONCE_LEN] = crypto::random_bytes();
+    let sealed = cipher.seal(&key, &nonce, plaintext, &payload)?;
+    payload.extend(nonce);
+    payload.extend(sealed);
+    Ok(payload)
+}
+
+/// Decrypts the payload with the secret the function returns for its
+/// credential id and salt
+fn open<F>(payload: &[u8], secret_for: F) -> Result<Vec<u8>>
+where
+    F: FnOnce(&[u8], &[u8; SALT_LEN]) -> Result<[u8; KEY_LEN]>,
+{
+    if !is_fido_wrapped(payload) || payload.len() < MAGIC.len() + 4 {
+        return Err(Box::new(InvalidFidoPayloadError));
+    }
+    envelope::negotiate(payload)?;
+    let cipher = cipher::by_id(payload[5])?;
+    let credential_len = u16::from_be_bytes(payload[6..8].try_into()?) as usize;
+
+    let salt_start = 8 + credential_len;
+    let wrap_start = salt_start + SALT_LEN;
+    let message_start = wrap_start + NONCE_LEN + KEY_LEN + TAG_LEN;
+    if payload.len() < message_start + NONCE_LEN + TAG_LEN {
+        return Err(Box::new(InvalidFidoPayloadError));
+    }
+
+    let secret = secret_for(&payload[8..salt_start], payload[salt_start..wrap_start].try_into()?)?;
+    let key: [u8; KEY_LEN] = cipher.open(&secret, payload[wrap_start..wrap_start + NONCE_LEN].try_into()?,
+        &payload[wrap_start + NONCE_LEN..message_start], &payload[..wrap_start])?
+        .try_into().map_err(|_| InvalidFidoPayloadError)?;
+    cipher.open(&key, payload[message_start..message_start + NONCE_LEN].try_into()?,
+        &payload[message_start + NONCE_LEN..], &payload[..message_start])
+}
+
+/// Returns the device named by the environment, or the first token found
+fn device() -> Result<String> {
+    if let Ok(device) = env::var(DEVICE_VAR) {
+        return Ok(device);
+    }
+    // each line reads "<device>: vendor=..., product=... (<name>)"
+    fido_tool("fido2-token", &["-L"], &[])?.first()
+        .and_then(|line| line.split_once(": "))
+        .map(|(device, _)| String::from(device))
+        .ok_or(Box::new(NoFidoDeviceError))
+}
+
+/// Makes a credential with the hmac-secret extension, returning its id
+fn make_credential(device: &str) -> Result<Vec<u8>> {
+    eprintln!("Touch your security key to create a credential...");
+    let input = [
+        STANDARD.encode(crypto::random_bytes::<32>()),
+        String::from(RP_ID),
+        String::from("pngme"),
+        STANDARD.encode(crypto::random_bytes::<16>()),
+    ];
+    // prints client data hash, rp id, format, authenticator data, credential id, ...
+    let output = fido_tool("fido2-cred", &["-M", "-h", device], &input)?;
+    Ok(STANDARD.decode(output.get(4).ok_or(FidoError(String::from("no credential id in the fido2-cred output")))?)?)
+}
+
+/// Asks the token for the hmac-secret of the credential and salt
+fn hmac_secret(device: &str, credential: &[u8], salt: &[u8; SALT_LEN]) -> Result<[u8; KEY_LEN]> {
+    eprintln!("Touch your security key...");
+    let input = [
+        STANDARD.encode(crypto::random_bytes::<32>()),
+        String::from(RP_ID),
+        STANDARD.encode(credential),
+        STANDARD.encode(salt),
+    ];
+    // prints client data hash, rp id, authenticator data, signature, hmac-secret
+    let output = fido_tool("fido2-assert", &["-G", "-h", device], &input)?;
+    let secret = STANDARD.decode(output.get(4).ok_or(FidoError(String::from("no hmac-secret in the fido2-assert output")))?)?;
+    Ok(secret.try_into().map_err(|_| FidoError(String::from("hmac-secret is not 32 bytes")))?)
+}
+
+/// Runs a libfido2 tool with the lines as input, returning its output
+/// lines. Its prompts go straight to the terminal.
+fn fido_tool(program: &str, args: &[&str], input: &[String]) -> Result<Vec<String>> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| FidoError(format!("could not run {}: {}", program, e)))?;
+
+    let mut stdin = child.stdin.take().unwrap();
+    for line in input {
+        writeln!(stdin, "{}", line)?;
+    }
+    drop(stdin);
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(Box::new(FidoError(format!("{} exited with {}", program, output.status))));
+    }
+    Ok(String::from_utf8(output.stdout)?.lines().map(String::from).collect())
+}
+
+/// Error specifying that a libfido2 tool failed
+#[derive(Debug)]
+pub struct FidoError(String);
+
+impl fmt::Display for FidoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Security Key Failed! ({})", self.0)
+    }
+}
+
+impl error::Error for FidoError {}
+
+/// Error specifying that no security key is connected
+#[derive(Debug)]
+pub struct NoFidoDeviceError;
+
+impl fmt::Display for NoFidoDeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "No Security Key Found! (connect one or set {})", DEVICE_VAR)
+    }
+}
+
+impl error::Error for NoFidoDeviceError {}
+
+/// Error specifying a payload that is not a well formed wrapped key payload
+#[derive(Debug)]
+pub struct InvalidFidoPayloadError;
+
+impl fmt::Display for InvalidFidoPayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Security Key Payload!")
+    }
+}
+
+impl error::Error for InvalidFidoPayloadError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_cipher() -> &'static dyn Cipher {
+        crypto::CipherKind::default().cipher().unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let secret = crypto::random_bytes();
+        let salt = crypto::random_bytes();
+        let payload = seal(testing_cipher(), b"credential", &salt, &secret, b"secret message").unwrap();
+        assert!(is_fido_wrapped(&payload));
+
+        let message = open(&payload, |credential, got_salt| {
+            assert_eq!(credential, b"credential");
+            assert_eq!(got_salt, &salt);
+            Ok(secret)
+        }).unwrap();
+        assert_eq!(message, b"secret message");
+    }
+
+    #[test]
+    fn test_wrong_secret() {
+        let payload = seal(testing_cipher(), b"credential", &[1; SALT_LEN], &[2; KEY_LEN], b"secret message").unwrap();
+        assert!(open(&payload, |_, _| Ok([3; KEY_LEN])).is_err());
+
+        // the credential id and salt are authenticated with the wrapped key
+        let mut modified = payload.clone();
+        modified[10] ^= 1;
+        assert!(open(&modified, |_, _| Ok([2; KEY_LEN])).is_err());
+        assert!(open(&payload[..payload.len() - TAG_LEN], |_, _| Ok([2; KEY_LEN])).is_err());
+    }
+}
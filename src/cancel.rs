@@ -0,0 +1,96 @@
+//!
+//! Cooperative cancellation of long-running operations. A GUI or server
+//! hands a token to the operation and cancels it from another thread; the
+//! operation checks the token between units of work and stops with a
+//! `CancelledError`, leaving its inputs as they were. Before it starts
+//! writing files the operation commits, after which it can no longer be
+//! cancelled, so it never stops halfway through a write.
+//!
+use std::{error, fmt, sync::{atomic::{AtomicU8, Ordering}, Arc}};
+use crate::Result;
+
+/// The operation has not committed and may be cancelled
+const RUNNING: u8 = 0;
+/// The operation was told to stop
+const CANCELLED: u8 = 1;
+/// The operation is writing and runs to completion
+const COMMITTED: u8 = 2;
+
+/// Shared state telling operations to stop. Clones share the state, so one
+/// can be kept to cancel the operation the other was given to.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicU8>);
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Asks every operation holding the token to stop, returning false
+    /// when the operation has already committed and will run to completion
+    pub fn cancel(&self) -> bool {
+        self.0.compare_exchange(RUNNING, CANCELLED, Ordering::SeqCst, Ordering::SeqCst)
+            .map_or_else(|state| state == CANCELLED, |_| true)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst) == CANCELLED
+    }
+
+    /// Starts the write phase of the operation, after which cancelling has
+    /// no effect. Returns a `CancelledError` if the token was cancelled first.
+    pub fn commit(&self) -> Result<()> {
+        match self.0.compare_exchange(RUNNING, COMMITTED, Ordering::SeqCst, Ordering::SeqCst) {
+            Err(CANCELLED) => Err(Box::new(CancelledError)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns a `CancelledError` once the token is cancelled
+    pub fn check(&self) -> Result<()> {
+        match self.is_cancelled() {
+            true => Err(Box::new(CancelledError)),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Error specifying an operation stopped by its cancellation token
+#[derive(Debug)]
+pub struct CancelledError;
+
+impl fmt::Display for CancelledError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Operation Cancelled!")
+    }
+}
+
+impl error::Error for CancelledError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_cancel() {
+        let token = CancellationToken::new();
+        assert!(token.check().is_ok());
+
+        let clone = token.clone();
+        thread::spawn(move || clone.cancel()).join().unwrap();
+        assert!(token.is_cancelled());
+        assert!(token.check().unwrap_err().is::<CancelledError>());
+        assert!(token.commit().unwrap_err().is::<CancelledError>());
+    }
+
+    #[test]
+    fn test_commit() {
+        let token = CancellationToken::new();
+        token.commit().unwrap();
+        token.commit().unwrap();
+        assert!(!token.cancel());
+        assert!(!token.is_cancelled());
+        assert!(token.check().is_ok());
+    }
+}
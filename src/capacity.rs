@@ -0,0 +1,243 @@
+//!
+//! Estimates of the largest message a carrier takes, once the room encode
+//! --ecc and --shard-size take and a budget for how much the file may grow
+//! are accounted for
+//!
+use std::{error, fmt, str::FromStr};
+use crate::{chunk::Chunk, compress::{self, Compression}, ecc, png::{Png, SHARD_HEADER_LEN}, Result};
+
+/// Bytes a chunk adds to the file besides its data: length, type and CRC
+const CHUNK_OVERHEAD: u64 = 12;
+
+/// Longest payload the u32 length fields of the error correction and shard
+/// headers can describe
+const MAX_PAYLOAD_LEN: u64 = u32::MAX as u64;
+
+/// How much a carrier may grow, in bytes or in percent of its size
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Growth {
+    Bytes(u64),
+    Percent(f64),
+}
+
+impl Growth {
+    /// Growth in bytes allowed for a file of the length
+    pub fn bytes(&self, file_len: u64) -> u64 {
+        match self {
+            Growth::Bytes(bytes) => *bytes,
+            Growth::Percent(percent) => (file_len as f64 * percent / 100.0) as u64,
+        }
+    }
+}
+
+impl FromStr for Growth {
+    type Err = InvalidGrowthError;
+
+    fn from_str(s: &str) -> std::result::Result<Growth, InvalidGrowthError> {
+        let invalid = || InvalidGrowthError(String::from(s));
+        match s.strip_suffix('%') {
+            Some(percent) => percent.trim().parse().ok()
+                .filter(|percent: &f64| percent.is_finite() && *percent >= 0.0)
+                .map(Growth::Percent)
+                .ok_or_else(invalid),
+            None => s.trim().parse().map(Growth::Bytes).map_err(|_| invalid()),
+        }
+    }
+}
+
+/// Encode options that change how much room a message takes
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options {
+    /// Error correction ratio, as encode --ecc takes
+    pub ecc: Option<f64>,
+    /// Longest chunk data, as encode --shard-size takes
+    pub shard_size: Option<usize>,
+    /// Largest growth of the file in bytes
+    pub max_growth: Option<u64>,
+    /// Bytes the file can grow by before it is larger than --max-output-size
+    pub output_room: Option<u64>,
+}
+
+/// What keeps a longer message from fitting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// The longest data a single chunk holds
+    ChunkLength,
+    /// The growth budget
+    Growth,
+    /// The largest file pngme is allowed to write
+    OutputSize,
+    /// The longest payload the length fields of the headers describe
+    PayloadLength,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::ChunkLength => write!(f, "the {} byte maximum the PNG specification allows in a chunk (see --shard-size); \
+                decoders and image pipelines usually give up far sooner, so set --max-growth or --max-output-size", Chunk::MAX_LENGTH),
+            Limit::Growth => write!(f, "the growth budget"),
+            Limit::OutputSize => write!(f, "--max-output-size"),
+            Limit::PayloadLength => write!(f, "the {} byte limit of a payload", MAX_PAYLOAD_LEN),
+        }
+    }
+}
+
+/// Room a message takes in the carrier
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Footprint {
+    /// Length of the payload, error correction included
+    pub stored_len: u64,
+    /// Number of chunks the payload is stored in
+    pub chunks: u64,
+    /// Bytes the file grows by
+    pub growth: u64,
+}
+
+/// Largest message that fits, with the room it takes and what keeps a
+/// longer one from fitting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capacity {
+    pub message_len: u64,
+    pub footprint: Footprint,
+    pub limit: Limit,
+}
+
+/// Returns the room a message of the length takes with the options
+pub fn footprint(message_len: u64, options: &Options) -> Result<Footprint> {
+    let stored_len = match options.ecc {
+        Some(ratio) => ecc::protected_len(message_len as usize, ratio)? as u64,
+        None => message_len,
+    };
+    let (chunks, growth) = match options.shard_size {
+        Some(shard_size) => match Png::shard_count(stored_len as usize, shard_size) as u64 {
+            1 => (1, stored_len + CHUNK_OVERHEAD),
+            chunks => (chunks, stored_len + chunks * (SHARD_HEADER_LEN as u64 + CHUNK_OVERHEAD)),
+        },
+        None => (1, stored_len + CHUNK_OVERHEAD),
+    };
+    Ok(Footprint { stored_len, chunks, growth })
+}
+
+/// Finds the largest message that fits with the options, assuming it does
+/// not compress
+pub fn estimate(options: &Options) -> Result<Capacity> {
+    // the limit a message of the length breaks, if any
+    let broken = |message_len: u64| -> Result<Option<Limit>> {
+        let footprint = footprint(message_len, options)?;
+        Ok(if footprint.stored_len > MAX_PAYLOAD_LEN {
+            Some(Limit::PayloadLength)
+        } else if options.shard_size.is_none() && footprint.stored_len > Chunk::MAX_LENGTH as u64 {
+            Some(Limit::ChunkLength)
+        } else if options.max_growth.is_some_and(|max| footprint.growth > max) {
+            Some(Limit::Growth)
+        } else if options.output_room.is_some_and(|room| footprint.growth > room) {
+            Some(Limit::OutputSize)
+        } else {
+            None
+        })
+    };
+
+    if let Some(limit) = broken(0)? {
+        return Err(Box::new(NoCapacityError(limit)));
+    }
+    // the room a message takes only grows with its length
+    let (mut fits, mut too_long) = (0, MAX_PAYLOAD_LEN + 1);
+    while too_long - fits > 1 {
+        let middle = fits + (too_long - fits) / 2;
+        match broken(middle)? {
+            Some(_) => too_long = middle,
+            None => fits = middle,
+        }
+    }
+    Ok(Capacity {
+        message_len: fits,
+        footprint: footprint(fits, options)?,
+        limit: broken(too_long)?.unwrap_or(Limit::PayloadLength),
+    })
+}
+
+/// Scales the capacity by how well the sample compresses, estimating how
+/// much data like it fits once compressed. Returns the estimate with the
+/// compressed length of the sample.
+pub fn compressed_capacity(capacity: u64, compression: Compression, sample: &[u8]) -> Result<(u64, usize)> {
+    let compressed = compress::compress(compression, sample)?.len();
+    if compressed == 0 {
+        return Ok((capacity, 0));
+    }
+    Ok(((capacity as f64 * sample.len() as f64 / compressed as f64) as u64, compressed))
+}
+
+/// Error specifying a growth budget that is neither bytes nor a percentage
+#[derive(Debug)]
+pub struct InvalidGrowthError(String);
+
+impl fmt::Display for InvalidGrowthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Growth Budget! ({:?}, expected bytes such as 4096 or a percentage such as 10%)", self.0)
+    }
+}
+
+impl error::Error for InvalidGrowthError {}
+
+/// Error specifying options under which not even an empty message fits
+#[derive(Debug)]
+pub struct NoCapacityError(Limit);
+
+impl fmt::Display for NoCapacityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "No Room for a Message! (not even an empty one fits within {})", self.0)
+    }
+}
+
+impl error::Error for NoCapacityError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_growth_budget() {
+        let options = Options { max_growth: Some(1000), ..Options::default() };
+        let capacity = estimate(&options).unwrap();
+        assert_eq!(capacity.message_len, 1000 - CHUNK_OVERHEAD);
+        assert_eq!(capacity.footprint, Footprint { stored_len: 988, chunks: 1, growth: 1000 });
+        assert_eq!(capacity.limit, Limit::Growth);
+
+        let sharded = Options { shard_size: Some(100), ..options };
+        let capacity = estimate(&sharded).unwrap();
+        assert_eq!((capacity.message_len, capacity.footprint.chunks), (748, 9));
+        assert!(footprint(capacity.message_len + 1, &sharded).unwrap().growth > 1000);
+
+        let protected = Options { ecc: Some(0.5), ..options };
+        let capacity = estimate(&protected).unwrap();
+        assert!(capacity.message_len < 700 && capacity.footprint.growth <= 1000);
+        assert!(estimate(&Options { max_growth: Some(5), ..options }).is_err());
+    }
+
+    #[test]
+    fn test_chunk_limit() {
+        let capacity = estimate(&Options::default()).unwrap();
+        assert_eq!(capacity.message_len, Chunk::MAX_LENGTH as u64);
+        assert_eq!(capacity.limit, Limit::ChunkLength);
+        assert_eq!(estimate(&Options { shard_size: Some(1 << 20), ..Options::default() }).unwrap().limit, Limit::PayloadLength);
+    }
+
+    #[test]
+    fn test_output_limit() {
+        let capacity = estimate(&Options { output_room: Some(500), ..Options::default() }).unwrap();
+        assert_eq!((capacity.message_len, capacity.limit), (500 - CHUNK_OVERHEAD, Limit::OutputSize));
+
+        let capacity = estimate(&Options { max_growth: Some(100), output_room: Some(500), ..Options::default() }).unwrap();
+        assert_eq!(capacity.limit, Limit::Growth);
+        assert!(estimate(&Options { output_room: Some(0), ..Options::default() }).is_err());
+    }
+
+    #[test]
+    fn test_parse_growth() {
+        assert_eq!("4096".parse::<Growth>().unwrap(), Growth::Bytes(4096));
+        assert_eq!("10%".parse::<Growth>().unwrap().bytes(5000), 500);
+        assert!("ten".parse::<Growth>().is_err());
+        assert!("-5%".parse::<Growth>().is_err());
+    }
+}
@@ -0,0 +1,126 @@
+//!
+//! Health checks for PNGs used as message carriers
+//!
+use std::{fmt, error};
+use crate::png::{ImageHeader, Png};
+
+/// Palettes with fewer entries than this leave no room for LSB embedding
+const MIN_PALETTE_ENTRIES: usize = 16;
+
+/// A property of a carrier PNG that makes it a poor choice for embedding
+#[derive(Debug, PartialEq, Eq)]
+pub enum CarrierWarning {
+    /// Indexed-color image whose palette only has the given number of entries
+    TinyPalette(usize),
+    /// Chunk types of the non-standard ancillary chunks already in the PNG
+    ExistingPayloads(Vec<String>),
+    /// Image is stored with Adam7 interlacing
+    Interlaced,
+    /// Apple CgBI file, which is not a standard PNG
+    CgBI,
+}
+
+impl fmt::Display for CarrierWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CarrierWarning::TinyPalette(n) => write!(f,
+                "indexed-color image with a {}-entry palette has no LSB capacity", n),
+            CarrierWarning::ExistingPayloads(types) => write!(f,
+                "already contains non-standard chunks: {}", types.join(", ")),
+            CarrierWarning::Interlaced => write!(f,
+                "image is interlaced (Adam7), which pixel-domain embedding does not support"),
+            CarrierWarning::CgBI => write!(f,
+                "Apple CgBI file, other PNG tools may fail to read it"),
+        }
+    }
+}
+
+/// Runs every carrier health check against the PNG
+pub fn check(png: &Png) -> Vec<CarrierWarning> {
+    let mut warnings = Vec::new();
+
+    if png.chunk_by_type("CgBI").is_some() {
+        warnings.push(CarrierWarning::CgBI);
+    }
+
+    if let Some(header) = png.image_header() {
+        if header.color_type == ImageHeader::INDEXED_COLOR {
+            // every palette entry is 3 bytes long
+            let entries = png.chunk_by_type("PLTE").map_or(0, |plte| plte.data().len() / 3);
+            if entries < MIN_PALETTE_ENTRIES {
+                warnings.push(CarrierWarning::TinyPalette(entries));
+            }
+        }
+
+        if header.is_interlaced() {
+            warnings.push(CarrierWarning::Interlaced);
+        }
+    }
+
+    let existing: Vec<String> = png.hidden_chunks().iter()
+        .map(|(_, chunk)| chunk.chunk_type().as_str())
+        .collect();
+    if !existing.is_empty() {
+        warnings.push(CarrierWarning::ExistingPayloads(existing));
+    }
+
+    warnings
+}
+
+/// Error specifying that a carrier failed its health checks in strict mode
+#[derive(Debug)]
+pub struct UnhealthyCarrierError(pub Vec<CarrierWarning>);
+
+impl fmt::Display for UnhealthyCarrierError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reasons: Vec<String> = self.0.iter().map(|w| w.to_string()).collect();
+        write!(f, "Unsuitable carrier PNG: {}", reasons.join("; "))
+    }
+}
+
+impl error::Error for UnhealthyCarrierError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::png::testing::chunk;
+
+    fn ihdr(color_type: u8, interlace_method: u8) -> Chunk {
+        let mut data: Vec<u8> = Vec::new();
+        data.extend(16u32.to_be_bytes());
+        data.extend(16u32.to_be_bytes());
+        data.extend([8, color_type, 0, 0, interlace_method]);
+        chunk("IHDR", &data)
+    }
+
+    #[test]
+    fn test_healthy_carrier() {
+        let png = Png::from_chunks(vec![ihdr(6, 0), chunk("IEND", b"")]);
+        assert!(check(&png).is_empty());
+    }
+
+    #[test]
+    fn test_tiny_palette() {
+        let png = Png::from_chunks(vec![ihdr(3, 0), chunk("PLTE", &[0; 6])]);
+        assert_eq!(check(&png), vec![CarrierWarning::TinyPalette(2)]);
+    }
+
+    #[test]
+    fn test_interlaced() {
+        let png = Png::from_chunks(vec![ihdr(2, 1)]);
+        assert_eq!(check(&png), vec![CarrierWarning::Interlaced]);
+    }
+
+    #[test]
+    fn test_cgbi() {
+        let png = Png::from_chunks(vec![chunk("CgBI", &[0; 4]), ihdr(6, 0)]);
+        assert_eq!(check(&png), vec![CarrierWarning::CgBI]);
+    }
+
+    #[test]
+    fn test_existing_payloads() {
+        let png = Png::from_chunks(vec![ihdr(6, 0), chunk("tEXt", b""), chunk("ruSt", b"")]);
+        assert_eq!(check(&png), vec![CarrierWarning::ExistingPayloads(vec![String::from("ruSt")])]);
+    }
+}
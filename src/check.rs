@@ -0,0 +1,207 @@
+//!
+//! Streaming integrity check: verifies the signature and every chunk CRC
+//! while reading the file in fixed-size pieces, so files of any size are
+//! checked in constant memory
+//!
+use std::{error, fmt, io::{self, Read}};
+use crate::{chunk::ISO_3309, chunk_type::ChunkType, png::Png, Result};
+
+/// Size of the buffer chunk data is streamed through
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Problem found by the check
+#[derive(Debug, PartialEq, Eq)]
+pub enum Issue {
+    InvalidSignature,
+    InvalidChunkType { offset: u64 },
+    CrcMismatch { index: u64, offset: u64, chunk_type: String },
+    /// The file ends in the middle of a chunk
+    Truncated { offset: u64 },
+    MissingIend,
+    /// The file has more chunks than the check was allowed to read
+    TooManyChunks { offset: u64 },
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Issue::InvalidSignature => write!(f, "invalid PNG signature"),
+            Issue::InvalidChunkType { offset } => write!(f, "invalid chunk type at offset {}", offset),
+            Issue::CrcMismatch { index, offset, chunk_type }
+            => write!(f, "CRC mismatch in chunk {} ({}) at offset {}", index, chunk_type, offset),
+            Issue::Truncated { offset } => write!(f, "file truncated in the chunk at offset {}", offset),
+            Issue::MissingIend => write!(f, "no IEND chunk"),
+            Issue::TooManyChunks { offset } => write!(f, "too many chunks, stopped at offset {}", offset),
+        }
+    }
+}
+
+/// Result of checking a file
+#[derive(Debug, Default)]
+pub struct Report {
+    pub chunks: u64,
+    pub bytes: u64,
+    pub issues: Vec<Issue>,
+}
+
+/// Checks the PNG read from the reader. Checking stops at the first issue
+/// that makes the rest of the file unreadable (a bad signature, an invalid
+/// chunk type or truncation); CRC mismatches are collected. Files with more
+/// than `max_chunks` chunks are checked no further, which also bounds the
+/// number of issues reported.
+pub fn check<R: Read>(mut reader: R, max_chunks: Option<usize>) -> Result<Report> {
+    let mut report = Report::default();
+
+    let mut signature = [0u8; 8];
+    if read_full(&mut reader, &mut signature)? < signature.len() || signature != Png::STANDARD_HEADER {
+        report.issues.push(Issue::InvalidSignature);
+        return Ok(report);
+    }
+    report.bytes = signature.len() as u64;
+
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    let mut seen_iend = false;
+    loop {
+        let offset = report.bytes;
+        if max_chunks.is_some_and(|max| report.chunks >= max as u64) {
+            report.issues.push(Issue::TooManyChunks { offset });
+            return Ok(report);
+        }
+
+        let mut header = [0u8; 8];
+        match read_full(&mut reader, &mut header)? {
+            0 => break,
+            8 => {},
+            _ => {
+                report.issues.push(Issue::Truncated { offset });
+                return Ok(report);
+            },
+        }
+
+        let length = u32::from_be_bytes(header[..4].try_into()?) as u64;
+        let type_bytes: [u8; 4] = header[4..].try_into()?;
+        let chunk_type = match ChunkType::try_from(type_bytes) {
+            Ok(chunk_type) => chunk_type,
+            Err(_) => {
+                report.issues.push(Issue::InvalidChunkType { offset });
+                return Ok(report);
+            },
+        };
+
+        let mut digest = ISO_3309.digest();
+        digest.update(&type_bytes);
+
+        let mut remaining = length;
+        while remaining > 0 {
+            let piece = &mut buffer[..remaining.min(BUFFER_SIZE as u64) as usize];
+            if read_full(&mut reader, piece)? < piece.len() {
+                report.issues.push(Issue::Truncated { offset });
+                return Ok(report);
+            }
+            digest.update(piece);
+            remaining -= piece.len() as u64;
+        }
+
+        let mut crc = [0u8; 4];
+        if read_full(&mut reader, &mut crc)? < crc.len() {
+            report.issues.push(Issue::Truncated { offset });
+            return Ok(report);
+        }
+
+        if digest.finalize() != u32::from_be_bytes(crc) {
+            report.issues.push(Issue::CrcMismatch { index: report.chunks, offset, chunk_type: chunk_type.to_string() });
+        }
+
+        report.chunks += 1;
+        report.bytes += 12 + length;
+        seen_iend |= &type_bytes == b"IEND";
+    }
+
+    if !seen_iend {
+        report.issues.push(Issue::MissingIend);
+    }
+    Ok(report)
+}
+
+/// Reads until the buffer is full or the reader ends, returning the number
+/// of bytes read
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+    while read < buf.len() {
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read)
+}
+
+/// Error specifying the number of issues a check found
+#[derive(Debug)]
+pub struct IntegrityError(pub usize);
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Integrity Check Failed! ({} issues)", self.0)
+    }
+}
+
+impl error::Error for IntegrityError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::{testing::chunk, Png};
+
+    fn testing_bytes() -> Vec<u8> {
+        Png::from_chunks(vec![chunk("IHDR", &[7; 13]), chunk("IDAT", &[7; 200_000]), chunk("IEND", b"")]).as_bytes()
+    }
+
+    #[test]
+    fn test_valid_file() {
+        let bytes = testing_bytes();
+        let report = check(&bytes[..], None).unwrap();
+        assert_eq!(report.chunks, 3);
+        assert_eq!(report.bytes, bytes.len() as u64);
+        assert!(report.issues.is_empty());
+    }
+
+    #[test]
+    fn test_crc_mismatch() {
+        let mut bytes = testing_bytes();
+        // a byte in the middle of the IDAT data
+        bytes[8 + 25 + 100_000] ^= 1;
+        let report = check(&bytes[..], None).unwrap();
+        assert_eq!(report.issues, vec![Issue::CrcMismatch { index: 1, offset: 33, chunk_type: String::from("IDAT") }]);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let bytes = testing_bytes();
+        let report = check(&bytes[..bytes.len() - 20], None).unwrap();
+        assert_eq!(report.issues, vec![Issue::Truncated { offset: 33 }]);
+    }
+
+    #[test]
+    fn test_invalid_signature_and_missing_iend() {
+        assert_eq!(check(&b"GIF89a"[..], None).unwrap().issues, vec![Issue::InvalidSignature]);
+        assert_eq!(check(&Png::STANDARD_HEADER[..], None).unwrap().issues, vec![Issue::MissingIend]);
+    }
+
+    #[test]
+    fn test_max_chunks() {
+        let mut bytes = Png::from_chunks((0..10).map(|_| chunk("emPt", b"x")).collect()).as_bytes();
+        // every chunk has a bad CRC
+        for i in 0..10 {
+            bytes[8 + 13 * i + 9] ^= 1;
+        }
+        assert_eq!(check(&bytes[..], None).unwrap().issues.len(), 11);
+
+        let report = check(&bytes[..], Some(4)).unwrap();
+        assert_eq!(report.chunks, 4);
+        assert_eq!(report.issues.len(), 5);
+        assert_eq!(report.issues[4], Issue::TooManyChunks { offset: 8 + 13 * 4 });
+    }
+}
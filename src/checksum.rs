@@ -0,0 +1,68 @@
+//!
+//! SHA-256 of the original message stored in front of it, so decode can
+//! tell whether the layers undone on the way out (decompression,
+//! decryption, error correction) gave back exactly what was encoded. The
+//! chunk CRC only covers the bytes as they are stored.
+//!
+use std::{error, fmt};
+use sha2::{Digest, Sha256};
+use crate::Result;
+
+pub const MAGIC: &[u8; 4] = b"PMCK";
+
+const HASH_LEN: usize = 32;
+
+/// Checks if the payload starts with the magic bytes of a checksummed one
+pub fn is_checksummed(payload: &[u8]) -> bool {
+    payload.starts_with(MAGIC)
+}
+
+/// Puts the SHA-256 of the message in front of it
+pub fn add(message: &[u8]) -> Vec<u8> {
+    let mut payload = MAGIC.to_vec();
+    payload.extend(Sha256::digest(message));
+    payload.extend(message);
+    payload
+}
+
+/// Returns the message of a payload made by `add`, refusing it when it
+/// does not match its SHA-256
+pub fn verify(payload: &[u8]) -> Result<&[u8]> {
+    if !is_checksummed(payload) || payload.len() < MAGIC.len() + HASH_LEN {
+        return Err(Box::new(ChecksumMismatchError));
+    }
+    let (hash, message) = payload[MAGIC.len()..].split_at(HASH_LEN);
+    if Sha256::digest(message)[..] != *hash {
+        return Err(Box::new(ChecksumMismatchError));
+    }
+    Ok(message)
+}
+
+/// Error specifying a message that does not match its stored checksum
+#[derive(Debug)]
+pub struct ChecksumMismatchError;
+
+impl fmt::Display for ChecksumMismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Checksum Mismatch! (the decoded message is not the one that was encoded)")
+    }
+}
+
+impl error::Error for ChecksumMismatchError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let payload = add(b"message");
+        assert!(is_checksummed(&payload));
+        assert_eq!(verify(&payload).unwrap(), b"message");
+
+        let mut tampered = payload.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(verify(&tampered).is_err());
+        assert!(verify(&payload[..20]).is_err());
+    }
+}
@@ -1,8 +1,8 @@
 use std::{fmt, str, error};
-use crc::{Crc, Algorithm, CRC_32_ISO_HDLC};
+use crc::{Crc, CRC_32_ISO_HDLC};
 use crate::Result;
 
-use crate::chunk_type;
+use crate::{chunk_type, cipher, crypto, format};
 
 pub const ISO_3309: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
 
@@ -14,30 +14,61 @@ pub struct Chunk {
 }
 
 impl Chunk {
-    pub fn is_valid_crc(chunk_type: &chunk_type::ChunkType, data: &Vec<u8>, crc: u32) -> bool{
-        let mut x: Vec<u8> = chunk_type.bytes().to_vec();
-        x.extend(data);
+    /// Largest data length the PNG specification allows in a single chunk
+    pub const MAX_LENGTH: usize = (1 << 31) - 1;
 
-        let crc_output = ISO_3309.checksum(&x);
+    pub fn is_valid_crc(chunk_type: &chunk_type::ChunkType, data: &[u8], crc: u32) -> bool{
+        Self::checksum(chunk_type, data) == crc 
+    }
 
-        crc_output == crc 
+    /// CRC over the chunk type and data, computed without copying them
+    fn checksum(chunk_type: &chunk_type::ChunkType, data: &[u8]) -> u32 {
+        let mut digest = ISO_3309.digest();
+        digest.update(&chunk_type.bytes());
+        digest.update(data);
+        digest.finalize()
     }
 
     pub fn new(chunk_type: chunk_type::ChunkType, data: Vec<u8>) -> Chunk {
         let length: u32 = data.len() as u32;
-        let mut x: Vec<u8> = chunk_type.bytes().to_vec();
-        x.extend(&data);
-        
-        let crc = ISO_3309.checksum(&x);
+        let crc = Self::checksum(&chunk_type, &data);
 
         Self {
-            length: length,
-            chunk_type: chunk_type,
-            data: data,
-            crc: crc
+            length,
+            chunk_type,
+            data,
+            crc
         }
     }
 
+    /// Creates a chunk whose data is the message encrypted under the
+    /// password with the default cipher (see the crypto module)
+    pub fn new_encrypted(chunk_type: chunk_type::ChunkType, message: &[u8], password: &[u8]) -> Result<Chunk> {
+        Chunk::new_encrypted_with(crypto::CipherKind::default().cipher()?, crypto::Kdf::default(), chunk_type, message, password)
+    }
+
+    /// Creates a chunk whose data is the message encrypted under the
+    /// password with the given cipher and KDF
+    pub fn new_encrypted_with(cipher: &dyn cipher::Cipher, kdf: crypto::Kdf, chunk_type: chunk_type::ChunkType, message: &[u8], password: &[u8]) -> Result<Chunk> {
+        Ok(Chunk::new(chunk_type, crypto::encrypt_with(cipher, kdf, password, message)?))
+    }
+
+    /// Decrypts the data of a chunk created by `new_encrypted`
+    pub fn decrypted_data(&self, password: &[u8]) -> Result<Vec<u8>> {
+        crypto::decrypt(password, &self.data)
+    }
+
+    /// Creates a chunk whose data is the message with an HMAC-SHA256 tag
+    pub fn new_authenticated(chunk_type: chunk_type::ChunkType, message: &[u8], key: &[u8]) -> Chunk {
+        Chunk::new(chunk_type, crypto::authenticate(key, message))
+    }
+
+    /// Returns the message of a chunk created by `new_authenticated` after
+    /// checking its tag
+    pub fn authenticated_data(&self, key: &[u8]) -> Result<&[u8]> {
+        crypto::verify_authenticated(key, &self.data)
+    }
+
     pub fn length(&self) -> u32 {
         self.length
     }
@@ -62,6 +93,17 @@ impl Chunk {
         }
     }
 
+    /// Returns the data as text, replacing invalid UTF-8 with U+FFFD
+    pub fn data_as_lossy_string(&self) -> String {
+        String::from_utf8_lossy(&self.data).into_owned()
+    }
+
+    /// Returns the data as text, writing invalid UTF-8 and control
+    /// characters as `\xNN` escapes so no byte is lost or hidden
+    pub fn data_as_escaped_string(&self) -> String {
+        format::escape(&self.data)
+    }
+
     pub fn as_bytes(&self) -> Vec<u8> {
         let length_bytes = self.length.to_be_bytes();
         let chunk_type_bytes = self.chunk_type.bytes();
@@ -75,13 +117,13 @@ impl Chunk {
         bytes
     }
 
-}
-
-impl TryFrom<&[u8]> for Chunk {
-    
-    type Error = crate::Error;
+    /// Parses a chunk like `try_from`, but keeps a CRC that does not match
+    /// the chunk so the chunk is written back exactly as it was read
+    pub fn try_from_unchecked(value: &[u8]) -> Result<Chunk> {
+        Chunk::parse(value, false)
+    }
 
-    fn try_from(value: &[u8]) -> Result<Self> {
+    fn parse(value: &[u8], check_crc: bool) -> Result<Chunk> {
 
         //get length
         let length_bytes: [u8;4] = value[..4].try_into()?;
@@ -92,31 +134,39 @@ impl TryFrom<&[u8]> for Chunk {
         let chunk_type = chunk_type::ChunkType::try_from(chunk_type_bytes)?;
 
         //get data
-        let data: Vec<u8>= value[8..(8+length as usize)].try_into()?;
+        let data: Vec<u8>= value[8..(8+length as usize)].to_vec();
 
         //get crc
         let crc_bytes: [u8;4] = value[(8 + length as usize)..].try_into()?;
         let crc = u32::from_be_bytes(crc_bytes);
 
-        match Self::is_valid_crc(&chunk_type, &data, crc) {
+        match !check_crc || Self::is_valid_crc(&chunk_type, &data, crc) {
             true => {}, 
             false => {return Err(Box::new(InvalidCrcError))}
         }
 
         Ok(Self {
-            length: length,
-            chunk_type: chunk_type,
-            data: data,
-            crc: crc
+            length,
+            chunk_type,
+            data,
+            crc
         })
     }
+}
+
+impl TryFrom<&[u8]> for Chunk {
+    
+    type Error = crate::Error;
 
+    fn try_from(value: &[u8]) -> Result<Self> {
+        Chunk::parse(value, true)
+    }
 }
 
 impl fmt::Display for Chunk {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let data_str = self.data_as_string().unwrap(); 
-        write!(f, "{}", data_str)
+        // binary data must not make formatting panic
+        write!(f, "{}", self.data_as_lossy_string())
     }
 }
 
@@ -258,4 +308,28 @@ mod tests {
         
         let _chunk_string = format!("{}", chunk);
     }
-}
\ No newline at end of file
+
+    #[cfg(feature = "chacha20poly1305")]
+    #[test]
+    fn test_new_encrypted() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        let chunk = Chunk::new_encrypted(chunk_type, b"secret", b"hunter2").unwrap();
+        assert_ne!(chunk.data(), b"secret");
+        assert_eq!(chunk.decrypted_data(b"hunter2").unwrap(), b"secret");
+        assert!(chunk.decrypted_data(b"hunter3").is_err());
+    }
+
+    #[test]
+    fn test_binary_data() {
+        let data: Vec<u8> = (0..=255).collect();
+        let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), data.clone());
+        let chunk = Chunk::try_from(&chunk.as_bytes()[..]).unwrap();
+        assert_eq!(chunk.data(), &data[..]);
+        assert!(chunk.data_as_string().is_err());
+        let _chunk_string = format!("{}", chunk);
+
+        let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), b"caf\xc3\xa9 \xff".to_vec());
+        assert_eq!(chunk.data_as_lossy_string(), "caf\u{e9} \u{fffd}");
+        assert_eq!(chunk.data_as_escaped_string(), "caf\u{e9} \\xff");
+    }
+}
@@ -3,6 +3,13 @@
 //!
 use std::{fmt, str, convert::TryFrom, error};
 
+/// Chunk types defined by the PNG specification and its registered extensions
+pub const REGISTERED_TYPES: [&str; 33] = [
+    "IHDR", "PLTE", "IDAT", "IEND", "tRNS", "cHRM", "gAMA", "iCCP", "sBIT",
+    "sRGB", "cICP", "mDCV", "cLLI", "tEXt", "zTXt", "iTXt", "bKGD", "hIST",
+    "pHYs", "sPLT", "eXIf", "tIME", "acTL", "fcTL", "fdAT", "oFFs", "pCAL",
+    "sCAL", "sTER", "gIFg", "gIFx", "gIFt", "fRAc",
+];
 
 /// 4 byte ChunkType Field of Chunk Object 
 #[derive(PartialEq, Eq, Debug)]
@@ -11,7 +18,7 @@ pub struct ChunkType(u8, u8, u8, u8);
 impl ChunkType {
 
     fn is_valid_b(val: u8) -> bool {
-        (65<= val && val <= 90) || (97 <= val && val <= 122) 
+        (65..=90).contains(&val) || (97..=122).contains(&val)
     }
 
     /// Gets the fifth bit of the val as a bool
@@ -55,6 +62,53 @@ impl ChunkType {
         ChunkType::get_fifth_bit(self.3)
     }
 
+    /// Checks if the ChunkType is one registered by the PNG specification
+    /// (including the APNG extension)
+    pub fn is_registered(&self) -> bool {
+        REGISTERED_TYPES.contains(&self.as_str().as_str())
+    }
+
+    /// Describes the purpose of a registered chunk type
+    pub fn description(&self) -> Option<&'static str> {
+        let description = match &self.bytes() {
+            b"IHDR" => "Image header: width, height, bit depth, color type and interlacing",
+            b"PLTE" => "Palette of up to 256 RGB entries for indexed-color images",
+            b"IDAT" => "Image data: a piece of the zlib-compressed pixel stream",
+            b"IEND" => "Marks the end of the PNG datastream",
+            b"tRNS" => "Transparency: alpha for palette entries or a single transparent color",
+            b"cHRM" => "Chromaticities of the primaries and white point",
+            b"gAMA" => "Gamma of the image",
+            b"iCCP" => "Embedded ICC color profile",
+            b"sBIT" => "Number of significant bits in each channel of the original image",
+            b"sRGB" => "Image uses the sRGB color space, with a rendering intent",
+            b"cICP" => "Coding-independent code points identifying the color space (such as HDR)",
+            b"mDCV" => "Color volume of the display the image was mastered on",
+            b"cLLI" => "Content light level information for HDR images",
+            b"tEXt" => "Uncompressed Latin-1 text under a keyword",
+            b"zTXt" => "Compressed Latin-1 text under a keyword",
+            b"iTXt" => "International (UTF-8) text under a keyword, optionally compressed",
+            b"bKGD" => "Default background color",
+            b"hIST" => "Approximate usage frequency of each palette entry",
+            b"pHYs" => "Intended pixel size or aspect ratio",
+            b"sPLT" => "Suggested palette for displays with fewer colors",
+            b"eXIf" => "Exif metadata",
+            b"tIME" => "Time of the last image modification",
+            b"acTL" => "APNG animation control: number of frames and plays",
+            b"fcTL" => "APNG frame control: size, position, delay and disposal of a frame",
+            b"fdAT" => "APNG frame data: image data of a frame after the first",
+            b"oFFs" => "Image position on a printed page",
+            b"pCAL" => "Calibration mapping pixel values to physical values",
+            b"sCAL" => "Physical scale of the image subject",
+            b"sTER" => "Image is a stereo pair of side by side views",
+            b"gIFg" => "GIF graphic control extension: disposal and delay",
+            b"gIFx" => "GIF application extension",
+            b"gIFt" => "GIF plain text extension (deprecated)",
+            b"fRAc" => "Fractal image parameters (never specified in detail)",
+            _ => return None,
+        };
+        Some(description)
+    }
+
     pub fn as_str(&self) -> String {
         let bytes = &self.bytes();
         let ct_as_str = str::from_utf8(bytes).unwrap();
@@ -81,9 +135,10 @@ impl str::FromStr for ChunkType {
     type Err = crate::Error;
 
     fn from_str(s: &str) -> crate::Result<Self> {
-        let bytes: &[u8] = s.as_bytes();
-        assert_eq!(bytes.len(), 4);
-        let bytes_owned: [u8;4] = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        let bytes_owned: [u8;4] = match s.as_bytes().try_into() {
+            Ok(bytes) => bytes,
+            Err(_) => return Err(Box::new(InvalidChunkTypeError)),
+        };
 
         ChunkType::try_from(bytes_owned)
     }
@@ -191,6 +246,8 @@ mod tests {
         let chunk = ChunkType::from_str("Ru1t");
         println!("{}", chunk.is_err());
         assert!(chunk.is_err());
+
+        assert!(ChunkType::from_str("RuStY").is_err());
     }
 
     #[test]
@@ -199,6 +256,19 @@ mod tests {
         assert_eq!(&chunk.to_string(), "RuSt");
     }
 
+    #[test]
+    pub fn test_chunk_type_is_registered() {
+        assert!(ChunkType::from_str("IHDR").unwrap().is_registered());
+        assert!(ChunkType::from_str("tEXt").unwrap().is_registered());
+        assert!(!ChunkType::from_str("ruSt").unwrap().is_registered());
+    }
+
+    #[test]
+    pub fn test_chunk_type_description() {
+        assert!(REGISTERED_TYPES.iter().all(|t| ChunkType::from_str(t).unwrap().description().is_some()));
+        assert!(ChunkType::from_str("ruSt").unwrap().description().is_none());
+    }
+
     #[test]
     pub fn test_chunk_type_trait_impls() {
         let chunk_type_1: ChunkType = TryFrom::try_from([82, 117, 83, 116]).unwrap();
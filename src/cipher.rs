@@ -0,0 +1,215 @@
+//!
+//! AEAD cipher backends sealing password-encrypted payloads. The built-in
+//! ciphers are each behind a feature of the same name, and applications
+//! can register their own (backed by an HSM or a certified module, say) so
+//! that payloads naming them can be sealed and opened without changing the
+//! rest of the crate.
+//!
+use std::{fmt, error, sync::RwLock};
+use crate::{crypto::{UnknownCipherError, KEY_LEN, NONCE_LEN}, Result};
+
+/// Highest cipher id reserved for ciphers built into this crate
+pub const MAX_BUILT_IN_ID: u8 = 127;
+
+/// AEAD cipher sealing payloads under a 256-bit key and a 96-bit nonce,
+/// appending a 128-bit tag as the payload formats expect
+pub trait Cipher: Send + Sync {
+    /// Identifier recorded in payload headers. Ids up to `MAX_BUILT_IN_ID`
+    /// are reserved for the built-in ciphers.
+    fn id(&self) -> u8;
+
+    /// Name shown to users, such as `chacha20-poly1305`
+    fn name(&self) -> &str;
+
+    /// Encrypts the plaintext and authenticates it together with the
+    /// associated data, returning the ciphertext followed by the tag
+    fn seal(&self, key: &[u8; KEY_LEN], nonce: &[u8; NONCE_LEN], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>>;
+
+    /// Checks the tag at the end of the sealed bytes and decrypts the
+    /// ciphertext before it
+    fn open(&self, key: &[u8; KEY_LEN], nonce: &[u8; NONCE_LEN], sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>>;
+}
+
+impl fmt::Debug for dyn Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Cipher({}, {})", self.id(), self.name())
+    }
+}
+
+/// ChaCha20-Poly1305 (RFC 8439), the default cipher
+#[cfg(feature = "chacha20poly1305")]
+#[derive(Debug, Clone, Copy)]
+pub struct ChaCha20Poly1305;
+
+#[cfg(feature = "chacha20poly1305")]
+impl Cipher for ChaCha20Poly1305 {
+    fn id(&self) -> u8 {
+        1
+    }
+
+    fn name(&self) -> &str {
+        "chacha20-poly1305"
+    }
+
+    fn seal(&self, key: &[u8; KEY_LEN], nonce: &[u8; NONCE_LEN], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        use chacha20poly1305::{aead::{Aead, KeyInit, Payload}, ChaCha20Poly1305};
+        ChaCha20Poly1305::new(key.into()).encrypt(nonce.into(), Payload { msg: plaintext, aad })
+            .map_err(|_| "encryption failed".into())
+    }
+
+    fn open(&self, key: &[u8; KEY_LEN], nonce: &[u8; NONCE_LEN], sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        use chacha20poly1305::{aead::{Aead, KeyInit, Payload}, ChaCha20Poly1305};
+        ChaCha20Poly1305::new(key.into()).decrypt(nonce.into(), Payload { msg: sealed, aad })
+            .map_err(|_| Box::new(crate::crypto::DecryptionError).into())
+    }
+}
+
+/// AES-256-GCM, for environments that require AES
+#[cfg(feature = "aes-gcm")]
+#[derive(Debug, Clone, Copy)]
+pub struct Aes256Gcm;
+
+#[cfg(feature = "aes-gcm")]
+impl Cipher for Aes256Gcm {
+    fn id(&self) -> u8 {
+        2
+    }
+
+    fn name(&self) -> &str {
+        "aes-256-gcm"
+    }
+
+    fn seal(&self, key: &[u8; KEY_LEN], nonce: &[u8; NONCE_LEN], plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        use aes_gcm::{aead::{Aead, KeyInit, Payload}, Aes256Gcm};
+        Aes256Gcm::new(key.into()).encrypt(nonce.into(), Payload { msg: plaintext, aad })
+            .map_err(|_| "encryption failed".into())
+    }
+
+    fn open(&self, key: &[u8; KEY_LEN], nonce: &[u8; NONCE_LEN], sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        use aes_gcm::{aead::{Aead, KeyInit, Payload}, Aes256Gcm};
+        Aes256Gcm::new(key.into()).decrypt(nonce.into(), Payload { msg: sealed, aad })
+            .map_err(|_| Box::new(crate::crypto::DecryptionError).into())
+    }
+}
+
+/// Ciphers registered by the application
+static REGISTERED: RwLock<Vec<&'static dyn Cipher>> = RwLock::new(Vec::new());
+
+/// Makes the cipher available to payloads naming its id. The id must be
+/// above `MAX_BUILT_IN_ID` and not registered yet.
+pub fn register(cipher: &'static dyn Cipher) -> Result<()> {
+    let mut registered = REGISTERED.write().map_err(|_| "cipher registry poisoned")?;
+    if cipher.id() <= MAX_BUILT_IN_ID || registered.iter().any(|other| other.id() == cipher.id()) {
+        return Err(Box::new(CipherIdTakenError(cipher.id())));
+    }
+    registered.push(cipher);
+    Ok(())
+}
+
+/// Returns the built-in or registered cipher with the id
+pub fn by_id(id: u8) -> Result<&'static dyn Cipher> {
+    match id {
+        #[cfg(feature = "chacha20poly1305")]
+        1 => Ok(&ChaCha20Poly1305),
+        #[cfg(feature = "aes-gcm")]
+        2 => Ok(&Aes256Gcm),
+        #[cfg(not(feature = "chacha20poly1305"))]
+        1 => Err(Box::new(CipherNotBuiltError("chacha20-poly1305", "chacha20poly1305"))),
+        #[cfg(not(feature = "aes-gcm"))]
+        2 => Err(Box::new(CipherNotBuiltError("aes-256-gcm", "aes-gcm"))),
+        _ => REGISTERED.read().map_err(|_| "cipher registry poisoned")?
+            .iter()
+            .find(|cipher| cipher.id() == id)
+            .copied()
+            .ok_or_else(|| Box::new(UnknownCipherError(id)).into()),
+    }
+}
+
+/// Error specifying a cipher id that is reserved or already registered
+#[derive(Debug)]
+pub struct CipherIdTakenError(u8);
+
+impl fmt::Display for CipherIdTakenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Cipher Id {} Taken! (ids above {} are free for registered ciphers)", self.0, MAX_BUILT_IN_ID)
+    }
+}
+
+impl error::Error for CipherIdTakenError {}
+
+/// Error specifying a built-in cipher left out of this build
+#[derive(Debug)]
+pub struct CipherNotBuiltError(&'static str, &'static str);
+
+impl fmt::Display for CipherNotBuiltError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Cipher {} Not Built In! (needs the {} feature)", self.0, self.1)
+    }
+}
+
+impl error::Error for CipherNotBuiltError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{self, Kdf, CIPHER_TAG_LEN};
+
+    /// Stand-in for an external backend: XOR with the key, tag of zeros
+    struct XorCipher(u8);
+
+    impl Cipher for XorCipher {
+        fn id(&self) -> u8 {
+            self.0
+        }
+
+        fn name(&self) -> &str {
+            "xor"
+        }
+
+        fn seal(&self, key: &[u8; KEY_LEN], _: &[u8; NONCE_LEN], plaintext: &[u8], _: &[u8]) -> Result<Vec<u8>> {
+            let mut sealed: Vec<u8> = plaintext.iter().zip(key.iter().cycle()).map(|(p, k)| p ^ k).collect();
+            sealed.extend([0; CIPHER_TAG_LEN]);
+            Ok(sealed)
+        }
+
+        fn open(&self, key: &[u8; KEY_LEN], nonce: &[u8; NONCE_LEN], sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+            let ciphertext = &sealed[..sealed.len() - CIPHER_TAG_LEN];
+            let opened = self.seal(key, nonce, ciphertext, aad)?;
+            Ok(opened[..ciphertext.len()].to_vec())
+        }
+    }
+
+    #[test]
+    fn test_built_in() {
+        for (id, built) in [(1, cfg!(feature = "chacha20poly1305")), (2, cfg!(feature = "aes-gcm"))] {
+            if !built {
+                assert!(by_id(id).unwrap_err().is::<CipherNotBuiltError>());
+                continue;
+            }
+            let cipher = by_id(id).unwrap();
+            let sealed = cipher.seal(&[7; KEY_LEN], &[1; NONCE_LEN], b"message", b"header").unwrap();
+            assert_eq!(sealed.len(), 7 + CIPHER_TAG_LEN);
+            assert_eq!(cipher.open(&[7; KEY_LEN], &[1; NONCE_LEN], &sealed, b"header").unwrap(), b"message");
+            assert!(cipher.open(&[7; KEY_LEN], &[1; NONCE_LEN], &sealed, b"other").is_err());
+        }
+        assert!(by_id(3).is_err());
+    }
+
+    #[test]
+    fn test_register() {
+        static XOR: XorCipher = XorCipher(200);
+        assert!(by_id(200).is_err());
+        register(&XOR).unwrap();
+        assert_eq!(by_id(200).unwrap().name(), "xor");
+        assert!(register(&XOR).is_err());
+
+        // payloads sealed with it open like any other
+        let kdf = Kdf::Argon2 { memory_kib: 64, iterations: 1, parallelism: 1 };
+        let payload = crypto::encrypt_with(&XOR, kdf, b"hunter2", b"message").unwrap();
+        assert_eq!(crypto::payload_cipher(&payload).unwrap().id(), 200);
+        assert_eq!(crypto::decrypt(b"hunter2", &payload).unwrap(), b"message");
+
+        static RESERVED: XorCipher = XorCipher(2);
+        assert!(register(&RESERVED).is_err());
+    }
+}
@@ -1,91 +1,2169 @@
-use crate::{args::{Args, Commands}, chunk, chunk_type, png};
-use std::{fs, str::FromStr, io, io::Write};
+use crate::{args::{Args, Commands, GlobalArgs, JsonOutput, NoteCommands, PatchCommands, PoolCommands, TagField, ThumbnailCommands, WorkspaceCommands, XmpCommands}, 
+    audit, capacity, carrier, check, checksum, chunk, chunk_type, compress, crypto, digest, ecc, envelope, fanout, fileinfo, format, lint, manifest, notes, oplog, pack, patch, peek, placement, platform, png, pool, provenance, recover, reflink, remote, seal, shares, storage, stream, survive, text, thumbnail, whiten, workspace, xmp};
+use std::{error, fmt, fs, str::{self, FromStr}, io, io::{BufRead, IsTerminal, Read, Seek, SeekFrom, Write}, path::{Path, PathBuf}, process::{Command, Stdio}, sync::mpsc, thread, time::{Duration, Instant, UNIX_EPOCH}};
+use clap::ValueEnum;
 use crate::Result;
+#[cfg(feature = "pgp")]
+use crate::pgp;
+#[cfg(feature = "fido")]
+use crate::auth;
 
 /// Runs the specified command corresponding to the argument configuration 
+/// and records it in the operation log when one is configured
 pub fn run(args: Args) -> Result<()> {
-    match args.command {
-        Commands::encode{file_path , chunk_type, message, output_file} 
+    let Args { command, global } = args;
+    let (operation, file, chunks) = describe(&command);
+
+    let start = Instant::now();
+    let result = check_sandbox(&command, &global).and_then(|_| match global.max_runtime {
+        Some(seconds) => dispatch_with_deadline(command, global.clone(), Duration::from_secs(seconds)),
+        None => dispatch(command, &global),
+    });
+
+    if let Some(log_file) = &global.log_file {
+        let error = result.as_ref().err().map(|e| e.to_string());
+        let entry = oplog::LogEntry::new(operation, file, chunks, start.elapsed(), error);
+        oplog::append(Path::new(log_file), &entry, global.log_format)?;
+    }
+    result
+}
+
+/// Refuses commands that --no-network or --no-clipboard rule out. The
+/// programs pngme runs cannot be confined, so running them is refused.
+fn check_sandbox(command: &Commands, g: &GlobalArgs) -> Result<()> {
+    let runs_command = matches!(command, Commands::encode { exec: Some(_), .. } | Commands::decode { exec: Some(_), .. });
+    if runs_command && (g.no_network || g.no_clipboard) {
+        return Err(Box::new(SandboxError("--exec runs an external command")));
+    }
+    let fetches = match command {
+        Commands::encode { file_path, from_pool: None, .. } | Commands::decode { file_path, .. } 
+        | Commands::print { file_path } => remote::is_url(file_path),
+        _ => false,
+    };
+    if fetches && g.no_network {
+        return Err(Box::new(SandboxError("the PNG file is fetched from a URL")));
+    }
+
+    #[cfg(feature = "pgp")]
+    if g.no_network {
+        let runs_gpg = match command {
+            Commands::encode { pgp_recipients, .. } => !pgp_recipients.is_empty(),
+            Commands::decode { pgp, .. } => *pgp,
+            _ => false,
+        };
+        if runs_gpg {
+            return Err(Box::new(SandboxError("gpg may fetch keys from the network")));
+        }
+    }
+    Ok(())
+}
+
+/// Runs the command on a worker thread and gives up on it after the time
+/// limit. The worker is cancelled and given a moment to stop between
+/// steps; returning the error ends the process and with it the worker. A
+/// worker that has committed to writing is waited for instead, as ending
+/// it would leave the file half written.
+fn dispatch_with_deadline(command: Commands, g: GlobalArgs, limit: Duration) -> Result<()> {
+    let cancel = g.cancel.clone();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        // errors cannot be sent between threads, so their messages are
+        let _ = sender.send(dispatch(command, &g).map_err(|e| e.to_string()));
+    });
+
+    match receiver.recv_timeout(limit) {
+        Ok(result) => result.map_err(|e| e.into()),
+        Err(mpsc::RecvTimeoutError::Timeout) if !cancel.cancel() => match receiver.recv() {
+            Ok(result) => result.map_err(|e| e.into()),
+            Err(_) => Err("the operation panicked".into()),
+        },
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            let _ = receiver.recv_timeout(CANCEL_GRACE);
+            Err(Box::new(RuntimeExceededError(limit.as_secs())))
+        },
+        Err(mpsc::RecvTimeoutError::Disconnected) => Err("the operation panicked".into()),
+    }
+}
+
+/// How long a command cancelled by --max-runtime gets to stop
+const CANCEL_GRACE: Duration = Duration::from_secs(1);
+
+/// Refuses output larger than --max-output-size
+fn check_output_size(len: u64, g: &GlobalArgs) -> Result<()> {
+    match g.max_output_size {
+        Some(max) if len > max => Err(Box::new(OutputTooLargeError(len, max))),
+        _ => Ok(()),
+    }
+}
+
+/// Returns the operation name, PNG file and chunk types of a command for
+/// the operation log
+fn describe(command: &Commands) -> (&'static str, Option<String>, Vec<String>) {
+    let types = |types: &[&str]| types.iter().map(|t| String::from(*t)).collect();
+    match command {
+        Commands::encode { file_path, chunk_type, .. } => ("encode", Some(file_path.clone()), vec![chunk_type.clone()]),
+        Commands::decode { file_path, chunk_type, .. } => ("decode", Some(file_path.clone()), chunk_type.iter().cloned().collect()),
+        Commands::Rekey { file_path, chunk_type, .. } => ("rekey", Some(file_path.clone()), vec![chunk_type.clone()]),
+        Commands::remove { file_path, chunk_type, .. } => ("remove", Some(file_path.clone()), vec![chunk_type.clone()]),
+        Commands::MergeIdat { file_path, .. } => ("merge-idat", Some(file_path.clone()), types(&["IDAT"])),
+        Commands::Check { file_path } => ("check", Some(file_path.clone()), Vec::new()),
+        Commands::Repair { file_path, .. } => ("repair", Some(file_path.clone()), Vec::new()),
+        Commands::Lint { file_path, .. } => ("lint", Some(file_path.clone()), Vec::new()),
+        Commands::Digest { file_path, chunk_type, .. } 
+        => ("digest", Some(file_path.clone()), chunk_type.iter().cloned().collect()),
+        Commands::Split { file_paths, .. } => ("split", Some(file_paths.join(",")), types(&[shares::SHARE_TYPE])),
+        Commands::Combine { file_paths } => ("combine", Some(file_paths.join(",")), types(&[shares::SHARE_TYPE])),
+        Commands::VerifyManifest { file_path } 
+        => ("verify-manifest", Some(file_path.clone()), types(&[manifest::MANIFEST_TYPE])),
+        Commands::History { file_path } => ("history", Some(file_path.clone()), types(&[audit::AUDIT_TYPE])),
+        Commands::Seal { file_path, .. } => ("seal", Some(file_path.clone()), types(&[seal::SEAL_TYPE])),
+        Commands::VerifySeal { file_path, .. } => ("verify-seal", Some(file_path.clone()), types(&[seal::SEAL_TYPE])),
+        Commands::SealKeygen { .. } => ("seal-keygen", None, Vec::new()),
+        Commands::Tag { file_path, .. } => ("tag", Some(file_path.clone()), types(&["iTXt"])),
+        Commands::Xmp { command } => match command {
+            XmpCommands::Get { file_path } => ("xmp get", Some(file_path.clone()), types(&["iTXt"])),
+            XmpCommands::Set { file_path, .. } => ("xmp set", Some(file_path.clone()), types(&["iTXt"])),
+            XmpCommands::Merge { file_path, .. } => ("xmp merge", Some(file_path.clone()), types(&["iTXt"])),
+        },
+        Commands::Thumbnail { command } => match command {
+            ThumbnailCommands::Add { file_path, .. } 
+            => ("thumbnail add", Some(file_path.clone()), types(&[thumbnail::THUMBNAIL_TYPE])),
+            ThumbnailCommands::Extract { file_path, .. } 
+            => ("thumbnail extract", Some(file_path.clone()), types(&[thumbnail::THUMBNAIL_TYPE])),
+        },
+        Commands::Patch { command } => match command {
+            PatchCommands::Create { modified_file, .. } => ("patch create", Some(modified_file.clone()), Vec::new()),
+            PatchCommands::Apply { file_path, .. } => ("patch apply", Some(file_path.clone()), Vec::new()),
+        },
+        Commands::Pack { container, .. } => ("pack", Some(container.clone()), Vec::new()),
+        Commands::Unpack { container, .. } => ("unpack", Some(container.clone()), Vec::new()),
+        Commands::Fanout { file_path, .. } 
+        => ("fanout", Some(file_path.clone()), types(&[fanout::WATERMARK_TYPE])),
+        Commands::DetectLeak { file_path, .. } 
+        => ("detect-leak", Some(file_path.clone()), types(&[fanout::WATERMARK_TYPE])),
+        Commands::Note { .. } => ("note", None, Vec::new()),
+        Commands::Workspace { .. } => ("workspace", None, Vec::new()),
+        Commands::Pool { command } => match command {
+            PoolCommands::Add { dir, .. } => ("pool add", Some(dir.clone()), Vec::new()),
+            PoolCommands::List { dir } => ("pool list", Some(dir.clone()), Vec::new()),
+            PoolCommands::Remove { dir, .. } => ("pool remove", Some(dir.clone()), Vec::new()),
+        },
+        Commands::SurviveTest { file_path, chunk_type, .. } 
+        => ("survive-test", Some(file_path.clone()), vec![chunk_type.clone()]),
+        Commands::Capacity { file_path, .. } => ("capacity", Some(file_path.clone()), Vec::new()),
+        Commands::Peek { file_path, .. } => ("peek", Some(file_path.clone()), Vec::new()),
+        Commands::print { file_path } => ("print", Some(file_path.clone()), Vec::new()),
+        Commands::Explain { chunk_type } => ("explain", None, vec![chunk_type.clone()]),
+        Commands::Schema { .. } => ("schema", None, Vec::new()),
+    }
+}
+
+fn dispatch(command: Commands, g: &GlobalArgs) -> Result<()> {
+    match command {
+        Commands::encode{file_path , chunk_type, message, output_file, exec: Some(command), strict_carrier, encrypt, recipients, hmac_key, obfuscate, 
+            #[cfg(feature = "pgp")] pgp_recipients, #[cfg(feature = "fido")] fido, .. } 
+        => {
+            // the message position holds the output file
+            if output_file.is_some() {
+                return Err(Box::new(ExecOutputError));
+            }
+            // the command output is stored as it is, so a seal clap let
+            // through would leave it readable
+            #[cfg(feature = "pgp")]
+            let encrypt = encrypt || !pgp_recipients.is_empty();
+            #[cfg(feature = "fido")]
+            let encrypt = encrypt || fido;
+            if encrypt || !recipients.is_empty() || hmac_key.is_some() || obfuscate {
+                return Err(Box::new(ExecSealError));
+            }
+            encode_exec(file_path, chunk_type, command, message, strict_carrier, g)
+        },
+        Commands::encode{file_path , chunk_type, message, output_file, exec: None, file, strict_carrier, from_pool, data_type, keyword, on_duplicate, compress, checksum, author, note, placement, mode, shard_size, ecc, encrypt, password, key_file, password_env, 
+            #[cfg(feature = "keyring")] use_keyring, cipher, kdf, kdf_memory, kdf_iterations, kdf_parallelism, stealth, decoy, decoy_password, 
+            recipients, hmac_key, obfuscate, 
+            #[cfg(feature = "pgp")] pgp_recipients, #[cfg(feature = "fido")] fido} 
+        => {
+            let sources = SecretSources { password, key_file, password_env, 
+                #[cfg(feature = "keyring")] use_keyring };
+            let seal = match hmac_key {
+                Some(hmac_key) => Some(Seal::Hmac(crypto::parse_key_material(&fs::read(hmac_key)?))),
+                _ if encrypt => {
+                    let kdf = crypto::Kdf::new(kdf, kdf_memory, kdf_iterations, kdf_parallelism)?;
+                    match decoy {
+                        Some(decoy) => {
+                            let password = read_secret(sources, "Password", true)?;
+                            let decoy_password = match decoy_password {
+                                Some(decoy_password) => decoy_password.into_bytes(),
+                                None => prompt_password("Decoy password", true)?,
+                            };
+                            Some(Seal::Envelope(cipher, kdf, password, decoy, decoy_password))
+                        },
+                        None => Some(Seal::Password(cipher, kdf, read_secret(sources, "Password", true)?)),
+                    }
+                },
+                _ if !recipients.is_empty() => Some(Seal::Recipients(recipients)),
+                _ if obfuscate => Some(Seal::Whiten),
+                #[cfg(feature = "pgp")]
+                _ if !pgp_recipients.is_empty() => Some(Seal::Pgp(pgp_recipients)),
+                #[cfg(feature = "fido")]
+                _ if fido => Some(Seal::Fido),
+                _ => None,
+            };
+            let (message, output_file) = match file {
+                Some(path) => {
+                    // the message position holds the output file
+                    if output_file.is_some() {
+                        return Err(Box::new(ExecOutputError));
+                    }
+                    let contents = fs::read(&path)?;
+                    let name = Path::new(&path).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+                    let modified = fs::metadata(&path)?.modified().ok()
+                        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                        .map(|since| since.as_secs());
+                    let info = fileinfo::FileInfo::new(&name, &contents, modified)?;
+                    (fileinfo::wrap(&info, &contents), message)
+                },
+                // clap requires the message without --exec or --file
+                None => match keyword {
+                    Some(keyword) 
+                    => (text::text_data(&chunk_type, &keyword, &String::from_utf8(data_type.prepare(&read_message(message.unwrap())?)?)?)?, output_file),
+                    None => (envelope::escape(&data_type.prepare(&read_message(message.unwrap())?)?), output_file),
+                },
+            };
+            let message = match (author, note) {
+                (None, None) => message,
+                (author, note) => provenance::wrap(&provenance::Provenance::new(author, note)?, &message),
+            };
+            let message = match checksum {
+                true => checksum::add(&message),
+                false => message,
+            };
+            let message = compress::compress(compress, &message)?;
+            // clap requires --encrypt without a decoy for --stealth
+            let chunk_type = match &seal {
+                Some(Seal::Password(_, kdf, password)) if stealth => crypto::stealth_chunk_type(kdf, password, &chunk_type)?.to_string(),
+                _ => chunk_type,
+            };
+            let on_duplicate = on_duplicate.unwrap_or(text::OnDuplicate::Append);
+            let storage = Storage { 
+                placement, 
+                mode,
+                shard_size: shard_size.map_or(chunk::Chunk::MAX_LENGTH, |size| size as usize), 
+                ecc,
+            };
+            match from_pool {
+                Some(dir) => encode_from_pool(dir, file_path, chunk_type, message, on_duplicate, storage, seal, g),
+                None => encode(file_path, chunk_type, message, output_file, strict_carrier, on_duplicate, storage, seal, g),
+            }
+        },
+        Commands::decode{file_path, chunk_type: Some(chunk_type), exec: Some(command), .. } => decode_exec(file_path, chunk_type, command, g),
+        Commands::decode{file_path, chunk_type, all: _, index, exec: _, output, restore_name, describe, info, format, data_type, decrypt, password, key_file, password_env, 
+            #[cfg(feature = "keyring")] use_keyring, stealth, kdf, kdf_memory, kdf_iterations, kdf_parallelism, identity, hmac_key, #[cfg(feature = "pgp")] pgp, 
+            #[cfg(feature = "fido")] fido} 
+        => {
+            let sources = SecretSources { password, key_file, password_env, 
+                #[cfg(feature = "keyring")] use_keyring };
+            let unseal = match identity {
+                Some(identity) => Some(Unseal::Identity(fs::read(identity)?)),
+                None if hmac_key.is_some() 
+                => Some(Unseal::Hmac(crypto::parse_key_material(&fs::read(hmac_key.unwrap())?))),
+                None if decrypt => Some(Unseal::Password(read_secret(sources, "Password", false)?)),
+                #[cfg(feature = "pgp")]
+                None if pgp => Some(Unseal::Pgp),
+                #[cfg(feature = "fido")]
+                None if fido => Some(Unseal::Fido),
+                None => None,
+            };
+            let chunk_type = match (chunk_type, &unseal) {
+                (Some(chunk_type), Some(Unseal::Password(password))) if stealth => {
+                    let kdf = crypto::Kdf::new(kdf, kdf_memory, kdf_iterations, kdf_parallelism)?;
+                    crypto::stealth_chunk_type(&kdf, password, &chunk_type)?.to_string()
+                },
+                (Some(chunk_type), _) => chunk_type,
+                (None, _) => return decode_all(file_path, unseal, format, g),
+            };
+            decode(file_path, chunk_type, index, unseal, output, restore_name, describe, info, format, data_type, g)
+        },
+        Commands::remove { file_path, chunk_type, index, secure } => remove(file_path, chunk_type, index, secure, g),
+        Commands::Rekey { file_path, chunk_type, password, key_file, password_env, new_password, new_key_file, new_password_env } 
+        => {
+            let old = SecretSources { password, key_file, password_env, 
+                #[cfg(feature = "keyring")] use_keyring: false };
+            let new = SecretSources { password: new_password, key_file: new_key_file, password_env: new_password_env, 
+                #[cfg(feature = "keyring")] use_keyring: false };
+            rekey(file_path, chunk_type, read_secret(old, "Current password", false)?, read_secret(new, "New password", true)?, g)
+        },
+        Commands::MergeIdat { file_path, output_file, target_size } 
+        => merge_idat(file_path, output_file, target_size, g),
+        Commands::Check { file_path } => check(file_path, g),
+        Commands::Repair { file_path, output_file } => repair(file_path, output_file, g),
+        Commands::Lint { file_path, output_file, fix } => lint(file_path, output_file, fix, g),
+        Commands::Digest { file_path, chunk_type, chunks, json } => digest(file_path, chunk_type, chunks, json, g),
+        Commands::Split { message, threshold, file_paths } => split(message, threshold, file_paths, g),
+        Commands::Combine { file_paths } => combine(file_paths, g),
+        Commands::VerifyManifest { file_path } => verify_manifest(file_path, g),
+        Commands::History { file_path } => history(file_path, g),
+        Commands::Seal { file_path, key, detached: Some(sfp), .. } => seal_detached(file_path, key, sfp, g),
+        Commands::Seal { file_path, output_file, key, detached: None } => seal(file_path, output_file, key, g),
+        Commands::VerifySeal { file_path, public_key, signature } => verify_seal(file_path, public_key, signature, g),
+        Commands::SealKeygen { key_file } => seal_keygen(key_file),
+        Commands::Tag { file_path, output_file, license, copyright, asset_id, on_duplicate, check, require } 
+        => {
+            if check {
+                check_tags(file_path, require, g)
+            } else {
+                let tags = [(TagField::License, license), (TagField::Copyright, copyright), (TagField::AssetId, asset_id)];
+                tag(file_path, output_file, tags, on_duplicate, g)
+            }
+        },
+        Commands::Xmp { command } => match command {
+            XmpCommands::Get { file_path } => xmp_get(file_path, g),
+            XmpCommands::Set { file_path, xmp_file, output_file } 
+            => xmp_set(file_path, xmp_file, output_file, false, g),
+            XmpCommands::Merge { file_path, xmp_file, output_file } 
+            => xmp_set(file_path, xmp_file, output_file, true, g),
+        },
+        Commands::Thumbnail { command } => match command {
+            ThumbnailCommands::Add { file_path, output_file, size, from } 
+            => thumbnail_add(file_path, output_file, size, from, g),
+            ThumbnailCommands::Extract { file_path, thumbnail_file } 
+            => thumbnail_extract(file_path, thumbnail_file, g),
+        },
+        Commands::Patch { command } => match command {
+            PatchCommands::Create { original_file, modified_file, patch_file } 
+            => patch_create(original_file, modified_file, patch_file, g),
+            PatchCommands::Apply { file_path, patch_file, output_file } 
+            => patch_apply(file_path, patch_file, output_file, g),
+        },
+        Commands::Pack { container, file_paths } => pack(container, file_paths, g),
+        Commands::Unpack { container, output_dir } => unpack(container, output_dir, g),
+        Commands::Fanout { file_path, recipients, output_dir, hmac_key } 
+        => fanout(file_path, recipients, output_dir, hmac_key, g),
+        Commands::DetectLeak { ledger, file_path, hmac_key } => detect_leak(ledger, file_path, hmac_key, g),
+        Commands::Note { command } => note(command),
+        Commands::Workspace { name, command } => workspace(name, command, g),
+        Commands::Pool { command } => pool(command, g),
+        Commands::SurviveTest { file_path, chunk_type, transforms } => survive_test(file_path, chunk_type, transforms, g),
+        Commands::Capacity { file_path, ecc, shard_size, max_growth, compress, sample } 
+        => capacity(file_path, ecc, shard_size, max_growth, compress.zip(sample), g),
+        Commands::Peek { file_path, password, key_file, password_env, extract } 
         => {
-            return encode(file_path, chunk_type, message, output_file);
-        }, 
-        Commands::decode{file_path, chunk_type} => {
-            return decode(file_path, chunk_type);
+            let password = if password.is_some() || key_file.is_some() || password_env.is_some() {
+                let sources = SecretSources { password, key_file, password_env, 
+                    #[cfg(feature = "keyring")] use_keyring: false };
+                Some(read_secret(sources, "Password", false)?)
+            } else {
+                None
+            };
+            peek(file_path, password, extract, g)
+        },
+        Commands::print{file_path} => print(file_path, g),
+        Commands::Explain { chunk_type } => explain(chunk_type),
+        Commands::Schema { output } => schema(output),
+    }
+}
+
+/// Opens a file and takes an advisory lock on it which is held until the
+/// returned file is dropped. Writers take an exclusive lock and readers a 
+/// shared one, so a reader never sees a half-written PNG. Without `wait`, 
+/// a lock held by another process is reported as a FileLockedError. The
+/// file must exist, so a mistyped input is an error rather than an empty PNG.
+fn open_locked(fp: &str, exclusive: bool, wait: bool) -> Result<fs::File> {
+    lock_file(fp, exclusive, false, wait)
+}
+
+/// Opens and exclusively locks an output file like `open_locked`, creating
+/// it when it does not exist
+fn create_locked(fp: &str, wait: bool) -> Result<fs::File> {
+    lock_file(fp, true, true, wait)
+}
+
+/// Opens and locks the file. A file that rekey renamed a new copy over
+/// while the lock was awaited is no longer the one at the path, so the
+/// lock is dropped and the new file opened instead.
+fn lock_file(fp: &str, exclusive: bool, create: bool, wait: bool) -> Result<fs::File> {
+    if cfg!(windows) && exclusive {
+        platform::check_writable_name(Path::new(fp))?;
+    }
+    let path = if cfg!(windows) { platform::long_path(fp) } else { String::from(fp) };
+
+    loop {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(exclusive)
+            .create(create)
+            .truncate(false)
+            .open(&path)?;
+
+        let locked = match (exclusive, wait) {
+            (true, true) => file.lock().map_err(fs::TryLockError::Error),
+            (true, false) => file.try_lock(),
+            (false, true) => file.lock_shared().map_err(fs::TryLockError::Error),
+            (false, false) => file.try_lock_shared(),
+        };
+
+        match locked {
+            Ok(()) if is_current(&file, &path)? => return Ok(file),
+            Ok(()) => continue,
+            Err(fs::TryLockError::WouldBlock) => return Err(Box::new(FileLockedError(String::from(fp)))),
+            Err(fs::TryLockError::Error(e)) => return Err(Box::new(e)),
+        }
+    }
+}
+
+/// Checks if the open file is still the file at the path
+#[cfg(unix)]
+fn is_current(file: &fs::File, path: &str) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let held = file.metadata()?;
+    match fs::metadata(path) {
+        Ok(current) => Ok(held.dev() == current.dev() && held.ino() == current.ino()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Checks if the open file is still the file at the path. Windows refuses
+/// to rename over an open file, so it always is.
+#[cfg(not(unix))]
+fn is_current(_file: &fs::File, _path: &str) -> io::Result<bool> {
+    Ok(true)
+}
+
+/// How encode encrypts the message
+enum Seal {
+    /// Password or key file contents
+    Password(crypto::CipherKind, crypto::Kdf, Vec<u8>),
+    /// Password, then a decoy message and the password only it opens with
+    Envelope(crypto::CipherKind, crypto::Kdf, Vec<u8>, String, Vec<u8>),
+    /// age public keys
+    Recipients(Vec<String>),
+    /// OpenPGP keys in the user's keyring
+    #[cfg(feature = "pgp")]
+    Pgp(Vec<String>),
+    /// Key wrapped with a FIDO2 security key
+    #[cfg(feature = "fido")]
+    Fido,
+    /// HMAC key authenticating the unencrypted message
+    Hmac(Vec<u8>),
+    /// XOR with a pseudo-random stream, hiding the message from casual view
+    Whiten,
+}
+
+/// How encode stores the sealed payload
+#[derive(Clone, Copy)]
+struct Storage {
+    placement: placement::Placement,
+    /// What happens to payloads already stored under the chunk type
+    mode: placement::Mode,
+    /// Longest chunk data before the payload is split into shards
+    shard_size: usize,
+    /// Reed-Solomon parity bytes added for each payload byte
+    ecc: Option<f64>,
+}
+
+/// How decode decrypts the message
+enum Unseal {
+    /// Password or key file contents
+    Password(Vec<u8>),
+    /// Contents of an age identity file
+    Identity(Vec<u8>),
+    /// Secret keys in the user's keyring
+    #[cfg(feature = "pgp")]
+    Pgp,
+    /// The FIDO2 security key the message was encoded with
+    #[cfg(feature = "fido")]
+    Fido,
+    /// HMAC key the message tag is checked with
+    Hmac(Vec<u8>),
+}
+
+/// Places the password or key of --encrypt and --decrypt can come from
+struct SecretSources {
+    password: Option<String>,
+    key_file: Option<String>,
+    password_env: Option<String>,
+    #[cfg(feature = "keyring")]
+    use_keyring: bool,
+}
+
+/// Service and user name of pngme's OS keyring entry
+#[cfg(feature = "keyring")]
+const KEYRING_ENTRY: (&str, &str) = ("pngme", "default");
+
+/// Returns the password or key from the first source that has one, in
+/// this order: the key file, --password, the --password-env variable (if
+/// set), the OS keyring (if it has an entry) and finally a prompt. New
+/// passwords (`confirm`) are asked for twice, and stored in the keyring
+/// when it was asked for but empty.
+fn read_secret(sources: SecretSources, name: &str, confirm: bool) -> Result<Vec<u8>> {
+    if let Some(key_file) = sources.key_file {
+        return Ok(crypto::parse_key_material(&fs::read(key_file)?));
+    }
+    if let Some(password) = sources.password {
+        return Ok(password.into_bytes());
+    }
+    if let Some(password) = sources.password_env.and_then(|var| std::env::var(var).ok()) {
+        return Ok(password.into_bytes());
+    }
+
+    #[cfg(feature = "keyring")]
+    if sources.use_keyring {
+        let entry = keyring::Entry::new(KEYRING_ENTRY.0, KEYRING_ENTRY.1)?;
+        match entry.get_password() {
+            Ok(password) => return Ok(password.into_bytes()),
+            Err(keyring::Error::NoEntry) if confirm => {
+                let password = prompt_password(name, true)?;
+                entry.set_password(str::from_utf8(&password)?)?;
+                return Ok(password);
+            },
+            Err(keyring::Error::NoEntry) => {},
+            Err(e) => return Err(Box::new(e)),
+        }
+    }
+
+    prompt_password(name, confirm)
+}
+
+/// Returns the bytes of the message argument, or everything left on
+/// stdin when it is `-`
+fn read_message(message: String) -> Result<Vec<u8>> {
+    if message != "-" {
+        return Ok(message.into_bytes());
+    }
+    let mut bytes = Vec::new();
+    io::stdin().lock().read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Reads a password from the terminal, or from stdin when it is not one
+fn prompt_password(name: &str, confirm: bool) -> Result<Vec<u8>> {
+    // without a terminal the password is piped in, one per line
+    if !io::stdin().is_terminal() {
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+        return Ok(line.trim_end_matches(['\r', '\n']).as_bytes().to_vec());
+    }
+
+    // prompt on the terminal with echo disabled
+    let password = rpassword::prompt_password(format!("{}: ", name))?;
+    if confirm && rpassword::prompt_password(format!("Confirm {}: ", name.to_lowercase()))? != password {
+        return Err(Box::new(PasswordMismatchError));
+    }
+    Ok(password.into_bytes())
+}
+
+fn read_png(file: &mut fs::File, g: &GlobalArgs) -> Result<png::Png> {
+    let mut bytes: Vec<u8> = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    parse_png(&bytes, g)
+}
+
+fn parse_png(bytes: &[u8], g: &GlobalArgs) -> Result<png::Png> {
+    let png = png::Png::parse(bytes, &png::ParseOptions { max_chunks: Some(g.max_chunks), preserve: g.preserve });
+
+    if png.is_err() && g.crlf_safe {
+        if let Some(damage) = recover::text_mode_damage(bytes) {
+            eprintln!("Warning: {}", damage);
+        }
+    }
+    png
+}
+
+/// Reads the PNG at the path, or downloads it when the path is an
+/// http(s) URL
+fn load_png(fp: &str, g: &GlobalArgs) -> Result<png::Png> {
+    match remote::is_url(fp) {
+        true => parse_png(&remote::fetch(fp)?, g),
+        false => read_png(&mut open_locked(fp, false, g.wait)?, g),
+    }
+}
+
+/// Returns the output file, or None when it is the input file itself. The
+/// input is then modified in place under its one exclusive lock, instead of
+/// waiting on a second lock of the output it already holds.
+fn distinct_output(fp: &str, of: Option<String>) -> Option<String> {
+    of.filter(|ofp| match (fs::canonicalize(fp), fs::canonicalize(ofp)) {
+        (Ok(input), Ok(output)) => input != output,
+        _ => true,
+    })
+}
+
+/// Opens the carrier encode modifies, returning it with the output file
+/// still to be written: the input file, locked for writing when it is
+/// modified in place, or for an http(s) URL the output file, which is
+/// given the downloaded PNG and then modified in place
+fn open_carrier(fp: &str, of: Option<String>, g: &GlobalArgs) -> Result<(fs::File, Option<String>)> {
+    if !remote::is_url(fp) {
+        let of = distinct_output(fp, of);
+        return Ok((open_locked(fp, of.is_none(), g.wait)?, of));
+    }
+    let ofp = of.ok_or(remote::OutputRequiredError)?;
+    let bytes = remote::fetch(fp)?;
+    // nothing is written for a download that is not a PNG
+    parse_png(&bytes, g)?;
+    check_output_size(bytes.len() as u64, g)?;
+
+    let mut file = create_locked(&ofp, g.wait)?;
+    g.cancel.commit()?;
+    file.set_len(0)?;
+    file.write_all(&bytes)?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok((file, None))
+}
+
+/// Replaces the contents of a locked file with the PNG. Only the bytes
+/// from the first difference onwards are written, so appending a chunk
+/// before IEND rewrites just the tail of the file. The whole file is
+/// rewritten if its current contents cannot be read back, as the unchanged
+/// prefix is then unknown. The file is cut to length only after the tail
+/// is written, so an interrupted shrinking rewrite leaves the complete new
+/// PNG followed by stale bytes after IEND rather than a truncated PNG.
+fn write_png(file: &mut fs::File, png: &png::Png) -> Result<()> {
+    let bytes = png.as_bytes();
+    let start = unchanged_prefix(file, &bytes).unwrap_or(0);
+
+    if start < bytes.len() {
+        file.seek(SeekFrom::Start(start as u64))?;
+        file.write_all(&bytes[start..])?;
+    }
+    file.set_len(bytes.len() as u64)?;
+    Ok(())
+}
+
+/// Returns how many leading bytes of the file already match the bytes
+fn unchanged_prefix(file: &mut fs::File, bytes: &[u8]) -> io::Result<usize> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut reader = io::BufReader::new(file);
+    let mut matched = 0;
+
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() || matched == bytes.len() {
+            return Ok(matched);
+        }
+
+        let n = buf.len().min(bytes.len() - matched);
+        match buf[..n].iter().zip(&bytes[matched..]).position(|(a, b)| a != b) {
+            Some(i) => return Ok(matched + i),
+            None => matched += n,
+        }
+        reader.consume(n);
+    }
+}
+
+/// Writes the PNG to the output file if one is given, otherwise back into
+/// the (exclusively locked) input file. A fresh manifest is embedded first
+/// when requested.
+fn save_png(file: &mut fs::File, of: Option<String>, png: &mut png::Png, g: &GlobalArgs) -> Result<()> {
+    if g.manifest {
+        manifest::embed(png);
+    }
+    check_output_size(8 + png.chunks().iter().map(|chunk| 12 + chunk.length() as u64).sum::<u64>(), g)?;
+
+    g.cancel.commit()?;
+    match of {
+        Some(ofp) => write_png(&mut open_output(file, &ofp, g)?, png),
+        None => write_png(file, png),
+    }
+}
+
+/// Opens and exclusively locks the output file. A new output starts as a
+/// clone of the input where the filesystem supports it, so only the
+/// changed tail has to be written.
+fn open_output(file: &fs::File, ofp: &str, g: &GlobalArgs) -> Result<fs::File> {
+    if !Path::new(ofp).exists() {
+        let _ = reflink::clone_into(file, Path::new(ofp));
+    }
+    create_locked(ofp, g.wait)
+}
+
+/// Warns about (or with `strict` refuses) carriers that are poorly suited
+/// for embedding
+fn check_carrier(png: &png::Png, strict: bool) -> Result<()> {
+    let warnings = carrier::check(png);
+    if strict && !warnings.is_empty() {
+        return Err(Box::new(carrier::UnhealthyCarrierError(warnings)));
+    }
+    for warning in &warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn encode(fp: String, ct: String, msg: Vec<u8>, of: Option<String>, strict: bool, on_duplicate: text::OnDuplicate, 
+    storage: Storage, seal: Option<Seal>, g: &GlobalArgs) -> Result<()> {
+
+    // lock the input for writing only when it is modified in place
+    let (mut file, of) = open_carrier(&fp, of, g)?;
+
+    // get PNG struct from file path
+    let mut png = read_png(&mut file, g)?;
+
+    // warn about (or refuse) carriers that are poorly suited for embedding
+    check_carrier(&png, strict)?;
+
+    // get chunk_type from specified chunk type string
+    let chunk_type = chunk_type::ChunkType::from_str(&ct)?;
+
+    //convert chunk type and message into new chunk to be appended
+    let secret_chunk = seal_chunk(chunk_type, msg, seal, storage.ecc)?;
+    append_secret(&mut png, secret_chunk, &ct, on_duplicate, storage, g)?;
+
+    save_png(&mut file, of, &mut png, g)
+}
+
+/// Encodes into the smallest unused carrier of the pool that fits the
+/// sealed message, writing the result to the output file
+#[allow(clippy::too_many_arguments)]
+fn encode_from_pool(dir: String, ofp: String, ct: String, msg: Vec<u8>, on_duplicate: text::OnDuplicate, 
+    storage: Storage, seal: Option<Seal>, g: &GlobalArgs) -> Result<()> {
+
+    let dir = Path::new(&dir);
+    let mut pool = pool::Pool::load(dir)?;
+    let secret_chunk = seal_chunk(chunk_type::ChunkType::from_str(&ct)?, msg, seal, storage.ecc)?;
+    let carrier = pool.take(secret_chunk.length() as u64)?.clone();
+
+    let mut file = open_locked(&dir.join(&carrier.name).to_string_lossy(), false, g.wait)?;
+    let mut png = read_png(&mut file, g)?;
+    // the carrier was vetted when it was added, unless it changed since
+    check_carrier(&png, true)?;
+    append_secret(&mut png, secret_chunk, &ct, on_duplicate, storage, g)?;
+    save_png(&mut file, Some(ofp), &mut png, g)?;
+
+    // only a carrier that was actually used is marked as such
+    pool.save(dir)?;
+    println!("Used carrier {}", carrier.name);
+    Ok(())
+}
+
+/// Turns the message into the chunk to be appended, encrypted or
+/// authenticated as requested and protected with `ecc` parity bytes per
+/// byte when that is given
+fn seal_chunk(chunk_type: chunk_type::ChunkType, msg: Vec<u8>, seal: Option<Seal>, ecc: Option<f64>) -> Result<chunk::Chunk> {
+    let chunk = match seal {
+        // without a decoy the second slot is random bytes, so a payload
+        // does not show whether it holds one
+        Some(Seal::Password(cipher, kdf, password)) 
+        => chunk::Chunk::new(chunk_type, envelope::seal_deniable(cipher.cipher()?, kdf, &password, &msg, None)?),
+        Some(Seal::Envelope(cipher, kdf, password, decoy, decoy_password)) 
+        => chunk::Chunk::new(chunk_type, envelope::seal_deniable(cipher.cipher()?, kdf, &password, &msg, 
+            Some((&decoy_password, decoy.as_bytes())))?),
+        Some(Seal::Recipients(recipients)) 
+        => chunk::Chunk::new(chunk_type, crypto::encrypt_to_recipients(&recipients, &msg)?),
+        #[cfg(feature = "pgp")]
+        Some(Seal::Pgp(recipients)) => chunk::Chunk::new(chunk_type, pgp::encrypt(&recipients, &msg)?),
+        #[cfg(feature = "fido")]
+        Some(Seal::Fido) => chunk::Chunk::new(chunk_type, auth::encrypt(crypto::CipherKind::default().cipher()?, &msg)?),
+        Some(Seal::Hmac(key)) => chunk::Chunk::new_authenticated(chunk_type, &msg, &key),
+        Some(Seal::Whiten) => chunk::Chunk::new(chunk_type, whiten::whiten(&msg)),
+        None => chunk::Chunk::new(chunk_type, msg),
+    };
+    Ok(match ecc {
+        Some(ratio) => chunk::Chunk::new(chunk_type::ChunkType::try_from(chunk.chunk_type().bytes())?, ecc::protect(chunk.data(), ratio)?),
+        None => chunk,
+    })
+}
+
+/// Appends the sealed chunk to the PNG, making room for text chunks and
+/// for payloads under the chunk type as `--mode` says, adding copies of it for resilient placement, splitting it into shards
+/// and recording the operation when auditing
+fn append_secret(png: &mut png::Png, secret_chunk: chunk::Chunk, ct: &str, on_duplicate: text::OnDuplicate, 
+    storage: Storage, g: &GlobalArgs) -> Result<()> {
+    if text::is_text_chunk(&secret_chunk) {
+        check_keyword(secret_chunk.data(), g)?;
+        if let Ok(keyword) = text::keyword(secret_chunk.data()) {
+            text::make_room(png, &keyword, on_duplicate)?;
+        }
+    }
+    placement::make_room(png, ct, storage.mode)?;
+
+    // a broken random source must not go unnoticed as a repeated nonce
+    crypto::check_fresh_nonces(secret_chunk.data(), png.chunks().iter()
+        .filter(|chunk| chunk.chunk_type() == secret_chunk.chunk_type())
+        .map(|chunk| chunk.data()))?;
+
+    let resilient = storage.placement == placement::Placement::Resilient;
+    if resilient {
+        placement::replicate(png, ct, secret_chunk.data());
+    }
+    let end = png.chunks().len();
+    png.append_sharded(secret_chunk, storage.shard_size);
+    if resilient {
+        placement::move_before_iend(png, end);
+    }
+    if g.audit {
+        audit::record(png, "encode", ct)?;
+    }
+    Ok(())
+}
+
+/// Copies at most `budget` bytes of the output into the writer, putting
+/// `envelope::PLAIN_MAGIC` in front when `escape` is set and the output
+/// starts with the magic bytes of a layer, as `envelope::escape` does
+fn escaped_copy(output: &mut impl Read, writer: &mut impl Write, budget: u64, escape: bool) -> io::Result<u64> {
+    let mut head = Vec::new();
+    output.take(envelope::PLAIN_MAGIC.len() as u64).read_to_end(&mut head)?;
+    if escape && envelope::Layer::detect(&head).is_some() {
+        writer.write_all(envelope::PLAIN_MAGIC)?;
+    }
+    io::copy(&mut io::Cursor::new(head).chain(output).take(budget), writer)
+}
+
+/// Appends a chunk holding the standard output of the shell command,
+/// streaming it into the file without buffering the payload
+fn encode_exec(fp: String, ct: String, command: String, of: Option<String>, strict: bool, g: &GlobalArgs) -> Result<()> {
+
+    let (mut file, of) = open_carrier(&fp, of, g)?;
+    let png = read_png(&mut file, g)?;
+    check_carrier(&png, strict)?;
+    let chunk_type = chunk_type::ChunkType::from_str(&ct)?;
+
+    // the command's output is streamed straight into the file, so it runs
+    // to completion however long it takes
+    g.cancel.commit()?;
+    let mut out = match &of {
+        Some(ofp) => {
+            let mut out = open_output(&file, ofp, g)?;
+            write_png(&mut out, &png)?;
+            out
+        },
+        None => file,
+    };
+    let png_len = out.seek(SeekFrom::End(0))?;
+
+    // with --max-output-size, read one byte past what fits to notice overflow
+    let budget = g.max_output_size.map_or(u64::MAX, |max| max.saturating_sub(png_len + 12).saturating_add(1));
+
+    let mut child = shell(&command).stdout(Stdio::piped()).spawn()?;
+    let mut writer = stream::ChunkWriter::new(&mut out, &chunk_type)?;
+    let copied = escaped_copy(child.stdout.as_mut().unwrap(), &mut writer, budget, !text::is_text_chunk_type(&ct));
+    if copied.as_ref().is_ok_and(|&copied| copied == budget) {
+        child.kill()?;
+    }
+    let status = child.wait()?;
+
+    // leave the PNG as it was if the command or the copy failed
+    match copied {
+        Err(e) => {
+            out.set_len(png_len)?;
+            return Err(Box::new(e));
+        },
+        Ok(copied) if copied == budget => {
+            out.set_len(png_len)?;
+            return Err(Box::new(OutputTooLargeError(png_len + 12 + copied, g.max_output_size.unwrap())));
+        },
+        Ok(_) => {},
+    }
+    if !status.success() {
+        out.set_len(png_len)?;
+        return Err(Box::new(CommandFailedError(command)));
+    }
+    writer.finish()?;
+
+    // the keyword is at the start of the streamed data
+    if text::is_text_chunk_type(&ct) {
+        let mut head = Vec::new();
+        out.seek(SeekFrom::Start(png_len + 8))?;
+        (&mut out).take(text::MAX_KEYWORD_LEN as u64 + 1).read_to_end(&mut head)?;
+        if let Err(e) = check_keyword(&head, g) {
+            out.set_len(png_len)?;
+            return Err(e);
+        }
+    }
+
+    if g.manifest || g.audit {
+        out.seek(SeekFrom::Start(0))?;
+        let mut png = read_png(&mut out, g)?;
+        if g.audit {
+            audit::record(&mut png, "encode", &ct)?;
+        }
+        if g.manifest {
+            manifest::embed(&mut png);
+        }
+        write_png(&mut out, &png)?;
+    }
+    Ok(())
+}
+
+/// Refuses (or with --allow-nonstandard-keyword only warns about) text
+/// chunk data whose keyword breaks the PNG specification's rules, and warns
+/// about keywords it does not register
+fn check_keyword(data: &[u8], g: &GlobalArgs) -> Result<()> {
+    match text::keyword(data).and_then(|keyword| text::validate_keyword(&keyword).map(|()| keyword)) {
+        Ok(keyword) if !text::is_registered_keyword(&keyword) 
+        => eprintln!("Warning: {:?} is not a keyword registered by the PNG specification", keyword),
+        Ok(_) => {},
+        Err(e) if g.allow_nonstandard_keyword => eprintln!("Warning: {}", e),
+        Err(e) => return Err(e),
+    }
+    Ok(())
+}
+
+/// Builds a command running the command line in the platform's shell
+fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.args(["/C", command]);
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.args(["-c", command]);
+        shell
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn decode(fp: String, ct: String, index: Option<usize>, unseal: Option<Unseal>, of: Option<String>, restore_name: bool, describe: bool, info: bool,
+    format: format::MessageFormat, data_type: format::DataType, g: &GlobalArgs) -> Result<()> {
+    
+    let png = load_png(&fp, g)?;
+
+    let opened = message_copies(&png, &ct, index)
+        .and_then(|copies| open_message(&copies, unseal.as_ref(), g))
+        .and_then(|message| open_records(&message));
+    let (provenance, file_info, message) = match opened {
+        // text from before plain messages were escaped may start with the
+        // magic bytes of a layer by chance
+        Err(e) if unseal.is_none() => match legacy_message(&png, &ct, index) {
+            Some(message) => {
+                eprintln!("Warning: the message starts like a payload layer but does not open as one ({}), decoding it as plain text", e);
+                (None, None, message)
+            },
+            None => return Err(e),
+        },
+        opened => opened?,
+    };
+    if info {
+        match provenance {
+            Some(provenance) => println!("{}", provenance),
+            None => println!("nothing recorded about the message (encode it with --author or --note)"),
+        }
+        return Ok(());
+    }
+    if describe {
+        match file_info {
+            Some(info) => println!("{}", info),
+            None => println!("message of {} bytes, not a file encoded with --file", message.len()),
+        }
+        return Ok(());
+    }
+    if restore_name {
+        let info = file_info.ok_or(fileinfo::NoFileInfoError)?;
+        let mut file = fs::OpenOptions::new().write(true).create_new(true).open(&info.name)?;
+        file.write_all(&message)?;
+        if let Some(modified) = info.modified {
+            file.set_modified(UNIX_EPOCH + Duration::from_secs(modified))?;
+        }
+        println!("Restored {}", info);
+        return Ok(());
+    }
+
+    // unless another format is asked for, the raw bytes go to the output
+    // file and text to the terminal
+    let message = data_type.present(&message)?;
+    let output = match (format, &of) {
+        (format::MessageFormat::Auto, Some(_)) => message,
+        (format, _) => match format.format(&message)? {
+            Some(output) => output,
+            None if io::stdout().is_terminal() => return Err(Box::new(BinaryMessageError(message.len()))),
+            None => message,
+        },
+    };
+    match of {
+        Some(ofp) => fs::write(ofp, output)?,
+        None => io::stdout().write_all(&output)?,
+    }
+    Ok(())
+}
+
+/// Recovers the message from the first of the stored payloads that opens,
+/// undoing the error correction, sealing, compression and checksum layers
+/// encode added
+fn open_message(copies: &[Vec<u8>], unseal: Option<&Unseal>, g: &GlobalArgs) -> Result<Vec<u8>> {
+    let mut message = Err(Box::new(png::ChunkNotFoundError).into());
+    for payload in copies {
+        check_output_size(payload.len() as u64, g)?;
+        let payload = match ecc::is_protected(payload) {
+            true => match ecc::recover(payload) {
+                Ok((payload, 0)) => payload,
+                Ok((payload, corrected)) => {
+                    eprintln!("Corrected {} damaged byte(s) with the error correction data", corrected);
+                    payload
+                },
+                Err(e) => {
+                    message = Err(e);
+                    continue;
+                },
+            },
+            false => payload.to_vec(),
+        };
+        message = open_payload(&payload, unseal);
+        if message.is_ok() {
+            break;
+        }
+    }
+    let message = match message? {
+        message if compress::is_compressed(&message) => compress::decompress(&message, g.max_output_size.unwrap_or(compress::DEFAULT_MAX_LEN))?,
+        message => message,
+    };
+    Ok(match checksum::is_checksummed(&message) {
+        true => checksum::verify(&message)?.to_vec(),
+        false => message,
+    })
+}
+
+/// Searches the PNG for the chunk type and copies of its payload left by
+/// --placement resilient, joining the chunks of a payload split by
+/// --shard-size and returning a ChunkNotFoundError if there are none.
+/// `index` picks one of several messages encoded under the type.
+fn message_copies(png: &png::Png, ct: &str, index: Option<usize>) -> Result<Vec<Vec<u8>>> {
+    let copies = match index {
+        Some(index) => {
+            let position = *png.message_positions(ct).get(index).ok_or(png::ChunkNotFoundError)?;
+            match png.reassemble_at(position)? {
+                Some(payload) => vec![payload],
+                None => vec![png.chunks()[position].data().to_vec()],
+            }
         },
-        Commands::remove { file_path, chunk_type } => {
-            return remove(file_path, chunk_type);
+        None => match png.reassemble(ct)? {
+            Some(payload) => vec![payload],
+            None => placement::copies(png, ct),
+        },
+    };
+    if copies.is_empty() {
+        return Err(Box::new(png::ChunkNotFoundError));
+    }
+    let messages = png.message_positions(ct).len();
+    if index.is_none() && messages > 1 {
+        eprintln!("Warning: {} messages found under {}, decoding the first that opens (pick one with --index)", messages, ct);
+    } else if copies.len() > 1 {
+        eprintln!("Warning: {} different copies of the message found, decoding the first that opens", copies.len());
+    }
+    Ok(copies)
+}
+
+/// Returns the data of the chunk holding the message when it is a plain
+/// message written before `envelope::escape` existed
+fn legacy_message(png: &png::Png, ct: &str, index: Option<usize>) -> Option<Vec<u8>> {
+    let position = *png.message_positions(ct).get(index.unwrap_or(0))?;
+    let data = png.chunks()[position].data();
+    envelope::is_legacy_plain(data).then(|| data.to_vec())
+}
+
+/// Splits the opened message into the provenance record of --author and
+/// --note, the file header of --file and the plain message behind them
+fn open_records(message: &[u8]) -> Result<(Option<provenance::Provenance>, Option<fileinfo::FileInfo>, Vec<u8>)> {
+    let (provenance, message) = match provenance::is_recorded(message) {
+        true => provenance::unwrap(message).map(|(provenance, message)| (Some(provenance), message))?,
+        false => (None, message),
+    };
+    match fileinfo::is_file_info(message) {
+        true => fileinfo::unwrap(message).map(|(info, contents)| (provenance, Some(info), contents.to_vec())),
+        false => Ok((provenance, None, envelope::unescape(message).to_vec())),
+    }
+}
+
+/// Prints every message hidden in the PNG with its chunk type and index,
+/// skipping the chunks pngme keeps its own metadata in and the copies
+/// --placement resilient made. Messages that do not open are reported
+/// without stopping the others.
+fn decode_all(fp: String, unseal: Option<Unseal>, format: format::MessageFormat, g: &GlobalArgs) -> Result<()> {
+
+    let png = load_png(&fp, g)?;
+
+    let mut found = false;
+    for (position, chunk) in png.hidden_chunks() {
+        let ct = chunk.chunk_type().as_str();
+        if ct == placement::REPLICA_TYPE || peek::METADATA_TYPES.contains(&ct.as_str()) {
+            continue;
+        }
+        // a payload split by --shard-size is printed once, joined
+        let index = match png.message_positions(&ct).iter().position(|&i| i == position) {
+            Some(index) => index,
+            None => continue,
+        };
+        found = true;
+
+        println!("{} #{}:", ct, index);
+        // the key only has to open the messages that need one
+        let message = png.reassemble_at(position)
+            .map(|joined| joined.unwrap_or_else(|| chunk.data().to_vec()))
+            .and_then(|payload| match open_message(std::slice::from_ref(&payload), unseal.as_ref(), g) {
+                Err(e) if unseal.is_some() => open_message(&[payload], None, g).map_err(|_| e),
+                message => message,
+            });
+        let message = message.and_then(|message| open_records(&message)).map(|(provenance, info, message)| {
+            if let Some(provenance) = provenance {
+                println!("  {}", provenance);
+            }
+            if let Some(info) = info {
+                println!("  file {}", info);
+            }
+            message
+        });
+        let message = match message {
+            Err(_) if envelope::is_legacy_plain(chunk.data()) => Ok(chunk.data().to_vec()),
+            message => message,
+        };
+        let output = match message.and_then(|message| Ok((format.format(&message)?, message.len()))) {
+            Ok((Some(output), _)) => output,
+            Ok((None, len)) => format!("({} bytes of binary data)\n", len).into_bytes(),
+            Err(e) => format!("({})\n", e).into_bytes(),
+        };
+        match String::from_utf8(output) {
+            Ok(text) => println!("  {}", text.trim_end_matches('\n').replace('\n', "\n  ")),
+            Err(e) => io::stdout().write_all(e.as_bytes())?,
+        }
+    }
+    if !found {
+        println!("No hidden messages found.");
+    }
+    Ok(())
+}
+
+/// Decrypts or verifies the payload as the unseal option says, refusing
+/// encrypted payloads without one
+fn open_payload(payload: &[u8], unseal: Option<&Unseal>) -> Result<Vec<u8>> {
+    Ok(match unseal {
+        Some(Unseal::Password(password)) => crypto::decrypt(password, payload)?,
+        Some(Unseal::Identity(identity)) => crypto::decrypt_with_identities(identity, payload)?,
+        #[cfg(feature = "pgp")]
+        Some(Unseal::Pgp) => pgp::decrypt(payload)?,
+        #[cfg(feature = "fido")]
+        Some(Unseal::Fido) => auth::decrypt(payload)?,
+        Some(Unseal::Hmac(key)) => crypto::verify_authenticated(key, payload)?.to_vec(),
+        None if crypto::is_encrypted(payload) || crypto::is_age_encrypted(payload) 
+        => return Err(Box::new(EncryptedMessageError)),
+        #[cfg(feature = "pgp")]
+        None if pgp::is_pgp_encrypted(payload) => return Err(Box::new(EncryptedMessageError)),
+        #[cfg(feature = "fido")]
+        None if auth::is_fido_wrapped(payload) => return Err(Box::new(EncryptedMessageError)),
+        None if crypto::is_authenticated(payload) => {
+            eprintln!("Warning: message has an HMAC tag that was not verified (decode it with --hmac-key)");
+            crypto::unverified_message(payload)?.to_vec()
+        },
+        None if whiten::is_whitened(payload) => whiten::unwhiten(payload)?,
+        None => payload.to_vec(),
+    })
+}
+
+fn decode_exec(fp: String, ct: String, command: String, g: &GlobalArgs) -> Result<()> {
+
+    let input: Box<dyn BufRead> = match remote::is_url(&fp) {
+        true => Box::new(io::Cursor::new(remote::fetch(&fp)?)),
+        false => Box::new(io::BufReader::new(open_locked(&fp, false, g.wait)?)),
+    };
+    let mut reader = match stream::find_chunk(input, &ct)? {
+        Some(reader) => reader,
+        None => return Err(Box::new(png::ChunkNotFoundError)),
+    };
+
+    let mut child = shell(&command).stdin(Stdio::piped()).spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    let copied = check_output_size(reader.remaining() as u64, g).and_then(|_| {
+        // the output of encode --exec is escaped like plain messages
+        let mut head = Vec::new();
+        (&mut reader).take(envelope::PLAIN_MAGIC.len() as u64).read_to_end(&mut head)?;
+        if head != envelope::PLAIN_MAGIC || text::is_text_chunk_type(&ct) {
+            stdin.write_all(&head)?;
+        }
+        Ok(io::copy(&mut reader, &mut stdin)?)
+    });
+    // close the pipe so the command sees the end of its input
+    drop(stdin);
+    let status = child.wait()?;
+
+    if !status.success() {
+        return Err(Box::new(CommandFailedError(command)));
+    }
+    copied?;
+    reader.finish()?;
+    Ok(())
+}
+
+fn remove(fp: String, ct: String, index: Option<usize>, secure: bool, g: &GlobalArgs) -> Result<()> {
+    
+    let mut file = open_locked(&fp, true, g.wait)?;
+
+    let mut png = read_png(&mut file, g)?;
+
+    //remove the first (or --index-th) chunk corresponding to some chunk
+    //type, or every shard of its payload, along with the copies
+    //--placement resilient made of it
+    let positions = png.message_positions(&ct);
+    let primary = match index {
+        Some(index) => Some(*positions.get(index).ok_or(png::ChunkNotFoundError)?),
+        None => positions.first().copied(),
+    };
+    let shards = primary.map_or_else(Vec::new, |position| png.shard_positions_at(position));
+    let is_copy = |chunk: &chunk::Chunk| match primary {
+        Some(position) => placement::is_copy(chunk, &ct)
+            && placement::stored_payload(chunk, &ct).as_deref() == Some(png.chunks()[position].data()),
+        None => placement::is_copy(chunk, &ct),
+    };
+    let targets: Vec<usize> = (0..png.chunks().len())
+        .filter(|&i| Some(i) == primary || shards.contains(&i) || is_copy(&png.chunks()[i]))
+        .collect();
+    if targets.is_empty() {
+        return Err(Box::new(png::ChunkNotFoundError));
+    }
+
+    // where the chunks are in the file, for overwriting them
+    let regions: Vec<(u64, u64)> = targets.iter()
+        .map(|&i| {
+            let offset = 8 + png.chunks()[..i].iter().map(|chunk| 12 + chunk.length() as u64).sum::<u64>();
+            (offset, 12 + png.chunks()[i].length() as u64)
+        })
+        .collect();
+
+    let mut index = 0;
+    png.retain_chunks(|_| {
+        index += 1;
+        !targets.contains(&(index - 1))
+    });
+    if g.audit {
+        audit::record(&mut png, "remove", &ct)?;
+    }
+
+    if !secure {
+        return save_png(&mut file, None, &mut png, g);
+    }
+
+    // the new file replaces the old one before its chunks are wiped, so an
+    // interruption never leaves a file with neither the old nor new PNG
+    if g.manifest {
+        manifest::embed(&mut png);
+    }
+    let bytes = png.as_bytes();
+    check_output_size(bytes.len() as u64, g)?;
+    g.cancel.commit()?;
+    replace_file(&file, &fp, &format!("{}.remove.tmp", fp), &bytes, g)?;
+    // the old file is still open, so its blocks are not yet reused
+    for (offset, len) in regions {
+        wipe(&mut file, offset, len)?;
+    }
+    Ok(())
+}
+
+/// Replaces the locked file at the path by renaming a complete copy of the
+/// bytes, written to `tmp`, over it. The copy stays locked until the rename
+/// is done, and other pngme processes waiting for the old file's lock then
+/// open the new file instead.
+fn replace_file(file: &fs::File, fp: &str, tmp: &str, bytes: &[u8], g: &GlobalArgs) -> Result<()> {
+    let mut out = create_locked(tmp, g.wait)?;
+    out.set_len(0)?;
+    out.write_all(bytes)?;
+    out.sync_all()?;
+    fs::set_permissions(tmp, file.metadata()?.permissions())?;
+    fs::rename(tmp, fp)?;
+    Ok(())
+}
+
+/// Overwrites a region of the file with zeros and flushes it to disk.
+/// Filesystems that copy on write, snapshots and SSD wear levelling can
+/// still keep the old blocks elsewhere.
+fn wipe(file: &mut fs::File, offset: u64, len: u64) -> Result<()> {
+    const BLOCK: usize = 64 * 1024;
+
+    file.seek(SeekFrom::Start(offset))?;
+    let zeros = [0u8; BLOCK];
+    let mut left = len;
+    while left > 0 {
+        let n = left.min(BLOCK as u64) as usize;
+        file.write_all(&zeros[..n])?;
+        left -= n as u64;
+    }
+    file.sync_data()?;
+    Ok(())
+}
+
+/// Re-encrypts every chunk of the type under the new password. The file
+/// is replaced by renaming a complete copy over it, so it holds either
+/// every old or every new chunk even if pngme is interrupted.
+fn rekey(fp: String, ct: String, old: Vec<u8>, new: Vec<u8>, g: &GlobalArgs) -> Result<()> {
+
+    let mut file = open_locked(&fp, true, g.wait)?;
+    let mut png = read_png(&mut file, g)?;
+
+    // the copies left by --placement resilient are rekeyed along with the
+    // chunks of the type, each payload once, so copies stay identical and
+    // none still opens with the old password
+    let mut payloads: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+    let mut rekeyed = Vec::new();
+    let mut sharded: Vec<(usize, Vec<u8>)> = Vec::new();
+    let mut count = 0;
+    for (position, chunk) in png.chunks().iter().enumerate() {
+        rekeyed.push(None);
+        let shards = match chunk.chunk_type().as_str() == ct {
+            true => png.shard_positions_at(position),
+            false => Vec::new(),
+        };
+        let payload = match (shards.first(), placement::stored_payload(chunk, &ct)) {
+            // the shards are joined and rekeyed at the first of them
+            (Some(&first), _) if first != position => continue,
+            (Some(_), _) => png.reassemble_at(position)?.unwrap(),
+            (None, Some(payload)) => payload,
+            (None, None) => continue,
+        };
+        let new_payload = match payloads.iter().find(|(old_payload, _)| *old_payload == payload) {
+            Some((_, new_payload)) => new_payload.clone(),
+            None => {
+                let new_payload = rekey_payload(&old, &new, &payload)?;
+                payloads.push((payload, new_payload.clone()));
+                new_payload
+            },
+        };
+        match shards.is_empty() {
+            true => {
+                rekeyed[position] = placement::with_payload(chunk, &ct, &new_payload);
+                count += 1;
+            },
+            false => {
+                count += shards.len();
+                sharded.push((position, new_payload));
+            },
+        }
+    }
+    if count == 0 {
+        return Err(Box::new(png::ChunkNotFoundError));
+    }
+
+    let mut rekeyed = rekeyed.into_iter();
+    png.map_chunks(|chunk| rekeyed.next().unwrap().unwrap_or(chunk));
+    for (position, new_payload) in sharded {
+        png.reshard_at(position, &new_payload)?;
+    }
+    if g.manifest {
+        manifest::embed(&mut png);
+    }
+    let bytes = png.as_bytes();
+    check_output_size(bytes.len() as u64, g)?;
+
+    g.cancel.commit()?;
+    replace_file(&file, &fp, &format!("{}.rekey.tmp", fp), &bytes, g)?;
+    drop(file);
+    println!("Rekeyed {} chunk(s)", count);
+    Ok(())
+}
+
+/// Re-encrypts a stored payload under the new password, taking off the
+/// error correction --ecc added around it and adding it back at the same
+/// ratio
+fn rekey_payload(old: &[u8], new: &[u8], payload: &[u8]) -> Result<Vec<u8>> {
+    if !ecc::is_protected(payload) {
+        return crypto::rekey(old, new, payload);
+    }
+    let (sealed, _) = ecc::recover(payload)?;
+    ecc::reprotect(payload, &crypto::rekey(old, new, &sealed)?)
+}
+
+fn merge_idat(fp: String, of: Option<String>, target_size: Option<u32>, g: &GlobalArgs) -> Result<()> {
+
+    let of = distinct_output(&fp, of);
+    let mut file = open_locked(&fp, of.is_none(), g.wait)?;
+
+    let mut png = read_png(&mut file, g)?;
+
+    png.merge_idat_with_cancel(target_size.map(|size| size as usize), &g.cancel)?;
+
+    save_png(&mut file, of, &mut png, g)
+}
+
+fn check(fp: String, g: &GlobalArgs) -> Result<()> {
+
+    let mut file = open_locked(&fp, false, g.wait)?;
+    let report = check::check(io::BufReader::new(&file), Some(g.max_chunks))?;
+
+    if report.issues.is_empty() {
+        println!("OK: {} chunks, {} bytes", report.chunks, report.bytes);
+        return Ok(());
+    }
+
+    for issue in &report.issues {
+        println!("{}", issue);
+    }
+    if g.crlf_safe && report.issues.contains(&check::Issue::InvalidSignature) {
+        let mut start = Vec::new();
+        file.seek(SeekFrom::Start(0))?;
+        (&mut file).take(16).read_to_end(&mut start)?;
+        if let Some(damage) = recover::text_mode_damage(&start) {
+            eprintln!("Warning: {}", damage);
+        }
+    }
+    Err(Box::new(check::IntegrityError(report.issues.len())))
+}
+
+fn repair(fp: String, of: Option<String>, g: &GlobalArgs) -> Result<()> {
+    let of = distinct_output(&fp, of);
+    let mut file = open_locked(&fp, of.is_none(), g.wait)?;
+    let mut bytes: Vec<u8> = Vec::new();
+    file.read_to_end(&mut bytes)?;
+
+    if recover::text_mode_damage(&bytes).is_none() {
+        println!("No text-mode transfer damage found.");
+        return Ok(());
+    }
+
+    let recovery = recover::repair(&bytes)?;
+    let mut png = png::Png::parse(&recovery.bytes, &png::ParseOptions { max_chunks: Some(g.max_chunks), preserve: g.preserve })?;
+    save_png(&mut file, of, &mut png, g)?;
+    println!("Repaired {} chunks, {} of them by trying alternatives against their CRCs", recovery.chunks, recovery.searched);
+    Ok(())
+}
+
+fn lint(fp: String, of: Option<String>, fix: bool, g: &GlobalArgs) -> Result<()> {
+
+    let of = distinct_output(&fp, of);
+    let mut file = open_locked(&fp, fix && of.is_none(), g.wait)?;
+    let mut png = read_png(&mut file, g)?;
+    let findings = lint::lint(&png);
+
+    if findings.is_empty() {
+        println!("OK: no spec deviations");
+        return Ok(());
+    }
+    for finding in &findings {
+        println!("{}", finding);
+    }
+
+    if fix {
+        let fixed = lint::fix(&mut png);
+        if fixed > 0 {
+            save_png(&mut file, of, &mut png, g)?;
+        }
+        println!("Fixed {} of {} findings", fixed, findings.len());
+    }
+    Ok(())
+}
+
+fn digest(fp: String, ct: Option<String>, chunks: bool, json: bool, g: &GlobalArgs) -> Result<()> {
+    let mut file = open_locked(&fp, false, g.wait)?;
+
+    if ct.is_none() && !chunks {
+        let (digests, length) = digest::Digests::of_reader(&mut file)?;
+        if json {
+            let output = digest::DigestOutput::File(digest::FileDigests { file: fp, length, digests });
+            println!("{}", serde_json::to_string_pretty(&output)?);
+        } else {
+            println!("sha256  {}  {}", digests.sha256, fp);
+            println!("blake3  {}  {}", digests.blake3, fp);
+        }
+        return Ok(());
+    }
+
+    let png = read_png(&mut file, g)?;
+    let digests = digest::chunk_digests(&png, ct.as_deref());
+    if ct.is_some() && digests.is_empty() {
+        return Err(Box::new(png::ChunkNotFoundError));
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&digest::DigestOutput::Chunks(digests))?);
+    } else {
+        for chunk in &digests {
+            println!("{} {} ({} bytes)", chunk.index, chunk.chunk_type, chunk.length);
+            println!("  sha256  {}", chunk.digests.sha256);
+            println!("  blake3  {}", chunk.digests.blake3);
+        }
+    }
+    Ok(())
+}
+
+fn split(msg: String, threshold: u8, fps: Vec<String>, g: &GlobalArgs) -> Result<()> {
+
+    // clap limits the number of files to what fits a u8
+    let shares = shares::split(msg.as_bytes(), threshold, fps.len() as u8)?;
+
+    // read every carrier before writing any, so an unreadable file leaves
+    // no partial set of shares behind
+    let mut carriers = Vec::new();
+    for fp in &fps {
+        let mut file = open_locked(fp, true, g.wait)?;
+        let png = read_png(&mut file, g)?;
+        carriers.push((file, png));
+    }
+
+    for ((mut file, mut png), share) in carriers.into_iter().zip(shares) {
+        shares::set_share(&mut png, &share);
+        save_png(&mut file, None, &mut png, g)?;
+    }
+    Ok(())
+}
+
+fn combine(fps: Vec<String>, g: &GlobalArgs) -> Result<()> {
+
+    let mut found = Vec::new();
+    for fp in fps {
+        let png = read_png(&mut open_locked(&fp, false, g.wait)?, g)?;
+        match shares::get_share(&png)? {
+            Some(share) => found.push(share),
+            None => eprintln!("warning: no share in {}", fp),
+        }
+    }
+
+    let message = shares::combine(&found)?;
+    check_output_size(message.len() as u64, g)?;
+    println!("{}", String::from_utf8_lossy(&message));
+    Ok(())
+}
+
+fn verify_manifest(fp: String, g: &GlobalArgs) -> Result<()> {
+
+    let png = read_png(&mut open_locked(&fp, false, g.wait)?, g)?;
+
+    let manifest = match manifest::Manifest::from_png(&png)? {
+        Some(manifest) => manifest,
+        None => return Err(Box::new(png::ChunkNotFoundError)),
+    };
+
+    let mismatches = manifest.verify(&png);
+    if mismatches.is_empty() {
+        println!("All chunks match the manifest.");
+        return Ok(());
+    }
+
+    for mismatch in &mismatches {
+        println!("{}", mismatch);
+    }
+    Err(Box::new(manifest::ManifestMismatchError(mismatches.len())))
+}
+
+fn history(fp: String, g: &GlobalArgs) -> Result<()> {
+
+    let png = read_png(&mut open_locked(&fp, false, g.wait)?, g)?;
+    let entries = audit::history(&png)?;
+
+    let last = match entries.last() {
+        Some(last) => last,
+        None => return Err(Box::new(png::ChunkNotFoundError)),
+    };
+    for entry in &entries {
+        println!("{}", entry);
+    }
+    if last.hash != audit::content_hash(&png) {
+        eprintln!("Warning: the file was modified since the last recorded operation");
+    }
+    Ok(())
+}
+
+fn seal(fp: String, of: Option<String>, kfp: String, g: &GlobalArgs) -> Result<()> {
+
+    let key = seal::signing_key(&fs::read(kfp)?)?;
+    let of = distinct_output(&fp, of);
+    let mut file = open_locked(&fp, of.is_none(), g.wait)?;
+    let mut png = read_png(&mut file, g)?;
+
+    // the manifest goes in first so the seal covers it
+    if g.manifest {
+        manifest::embed(&mut png);
+    }
+    seal::embed(&mut png, &key);
+
+    save_png(&mut file, of, &mut png, &GlobalArgs { manifest: false, ..g.clone() })
+}
+
+/// Signs the chunks of the PNG into a separate signature file, leaving the
+/// PNG untouched
+fn seal_detached(fp: String, kfp: String, sfp: String, g: &GlobalArgs) -> Result<()> {
+
+    let key = seal::signing_key(&fs::read(kfp)?)?;
+    let png = read_png(&mut open_locked(&fp, false, g.wait)?, g)?;
+    fs::write(sfp, seal::Seal::sign(&png, &key).to_detached())?;
+    Ok(())
+}
+
+fn verify_seal(fp: String, pkfp: Option<String>, sfp: Option<String>, g: &GlobalArgs) -> Result<()> {
+
+    let png = read_png(&mut open_locked(&fp, false, g.wait)?, g)?;
+    let trusted = pkfp.map(fs::read).transpose()?.map(|key| seal::verifying_key(&key)).transpose()?;
+
+    let seal = match sfp {
+        Some(sfp) => seal::Seal::from_detached(&fs::read(sfp)?)?,
+        None => match seal::Seal::from_png(&png)? {
+            Some(seal) => seal,
+            None => return Err(Box::new(png::ChunkNotFoundError)),
+        },
+    };
+    seal.verify_signature(trusted.as_ref())?;
+    if trusted.is_none() {
+        eprintln!("Warning: sealed by {}, which was not checked against a trusted key (see --public-key)", 
+            hex::encode(seal.public_key().as_bytes()));
+    }
+
+    let mismatches = seal.verify(&png);
+    if mismatches.is_empty() {
+        println!("All chunks match the seal.");
+        return Ok(());
+    }
+
+    for mismatch in &mismatches {
+        println!("{}", mismatch);
+    }
+    Err(Box::new(seal::SealMismatchError(mismatches.len())))
+}
+
+fn seal_keygen(kfp: String) -> Result<()> {
+
+    let key = seal::generate_key();
+    let public_key = hex::encode(key.verifying_key().as_bytes());
+
+    // never overwrite a key, and keep the signing key private
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(&kfp)?.write_all(hex::encode(key.to_bytes()).as_bytes())?;
+    fs::write(format!("{}.pub", kfp), &public_key)?;
+
+    println!("{}", public_key);
+    Ok(())
+}
+
+/// Returns the iTXt keyword a tag field is stored under. Copyright uses the
+/// registered PNG keyword and all of them map onto XMP properties.
+fn tag_keyword(field: TagField) -> &'static str {
+    match field {
+        TagField::License => "License",
+        TagField::Copyright => "Copyright",
+        TagField::AssetId => "Asset ID",
+    }
+}
+
+fn tag(fp: String, of: Option<String>, tags: [(TagField, Option<String>); 3], on_duplicate: text::OnDuplicate, g: &GlobalArgs) -> Result<()> {
+
+    let of = distinct_output(&fp, of);
+    let mut file = open_locked(&fp, of.is_none(), g.wait)?;
+
+    let mut png = read_png(&mut file, g)?;
+
+    for (field, value) in tags {
+        if let Some(value) = value {
+            text::set_text(&mut png, tag_keyword(field), &value, on_duplicate)?;
+        }
+    }
+
+    save_png(&mut file, of, &mut png, g)
+}
+
+fn check_tags(fp: String, required: Vec<TagField>, g: &GlobalArgs) -> Result<()> {
+
+    let png = read_png(&mut open_locked(&fp, false, g.wait)?, g)?;
+
+    let mut missing: Vec<String> = Vec::new();
+    for field in required {
+        let keyword = tag_keyword(field);
+        match text::get_text(&png, keyword)? {
+            Some(value) => println!("{}: {}", keyword, value),
+            None => {
+                println!("{}: missing", keyword);
+                missing.push(String::from(keyword));
+            }
+        }
+    }
+
+    match missing.is_empty() {
+        true => Ok(()),
+        false => Err(Box::new(MissingTagsError(missing))),
+    }
+}
+
+fn xmp_get(fp: String, g: &GlobalArgs) -> Result<()> {
+
+    let png = read_png(&mut open_locked(&fp, false, g.wait)?, g)?;
+
+    match xmp::get_packet(&png)? {
+        Some(packet) => {
+            check_output_size(packet.len() as u64, g)?;
+            println!("{}", packet);
+            Ok(())
+        },
+        None => Err(Box::new(png::ChunkNotFoundError)),
+    }
+}
+
+/// Replaces the XMP packet, or merges into it when `merge` is set
+fn xmp_set(fp: String, xf: String, of: Option<String>, merge: bool, g: &GlobalArgs) -> Result<()> {
+
+    let xml = fs::read_to_string(xf)?;
+
+    let of = distinct_output(&fp, of);
+    let mut file = open_locked(&fp, of.is_none(), g.wait)?;
+
+    let mut png = read_png(&mut file, g)?;
+
+    let xml = match (merge, xmp::get_packet(&png)?) {
+        (true, Some(packet)) => xmp::merge_packets(&packet, &xml)?,
+        _ => xml,
+    };
+    xmp::set_packet(&mut png, &xml)?;
+
+    save_png(&mut file, of, &mut png, g)
+}
+
+fn thumbnail_add(fp: String, of: Option<String>, size: u32, from: Option<String>, g: &GlobalArgs) -> Result<()> {
+
+    let of = distinct_output(&fp, of);
+    let mut file = open_locked(&fp, of.is_none(), g.wait)?;
+
+    let mut png = read_png(&mut file, g)?;
+
+    let thumbnail = match from {
+        Some(tfp) => fs::read(tfp)?,
+        None => thumbnail::generate(&png.as_bytes(), size)?,
+    };
+    thumbnail::set_thumbnail(&mut png, thumbnail)?;
+
+    save_png(&mut file, of, &mut png, g)
+}
+
+fn thumbnail_extract(fp: String, tfp: String, g: &GlobalArgs) -> Result<()> {
+
+    let png = read_png(&mut open_locked(&fp, false, g.wait)?, g)?;
+
+    match thumbnail::get_thumbnail(&png) {
+        Some(thumbnail) => {
+            check_output_size(thumbnail.len() as u64, g)?;
+            if cfg!(windows) {
+                platform::check_writable_name(Path::new(&tfp))?;
+            }
+            fs::write(tfp, thumbnail)?;
+            Ok(())
         },
-        Commands::print{file_path} => {
-            return print(file_path);
+        None => Err(Box::new(png::ChunkNotFoundError)),
+    }
+}
+
+fn patch_create(ofp: String, mfp: String, pfp: String, g: &GlobalArgs) -> Result<()> {
+
+    let original = read_png(&mut open_locked(&ofp, false, g.wait)?, g)?;
+    let modified = read_png(&mut open_locked(&mfp, false, g.wait)?, g)?;
+
+    let bytes = patch::Patch::create(&original, &modified).to_bytes();
+    check_output_size(bytes.len() as u64, g)?;
+    if cfg!(windows) {
+        platform::check_writable_name(Path::new(&pfp))?;
+    }
+    fs::write(pfp, &bytes)?;
+    println!("Patch written: {} bytes", bytes.len());
+    Ok(())
+}
+
+fn patch_apply(fp: String, pfp: String, of: Option<String>, g: &GlobalArgs) -> Result<()> {
+
+    let patch = patch::Patch::try_from(&fs::read(pfp)?[..])?;
+
+    let of = distinct_output(&fp, of);
+    let mut file = open_locked(&fp, of.is_none(), g.wait)?;
+
+    let mut png = patch.apply(&read_png(&mut file, g)?)?;
+
+    save_png(&mut file, of, &mut png, g)
+}
+
+fn pack(cfp: String, fps: Vec<String>, g: &GlobalArgs) -> Result<()> {
+
+    let mut file = create_locked(&cfp, g.wait)?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    let mut container = if bytes.is_empty() { pack::Pack::default() } else { pack::Pack::try_from(&bytes[..])? };
+
+    for fp in &fps {
+        let png = read_png(&mut open_locked(fp, false, g.wait)?, g)?;
+        let name = Path::new(fp).file_name().ok_or_else(|| pack::InvalidVariantNameError(fp.clone()))?;
+        container.add(&name.to_string_lossy(), &png)?;
+    }
+
+    let bytes = container.to_bytes();
+    check_output_size(bytes.len() as u64, g)?;
+    g.cancel.commit()?;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&bytes)?;
+    file.set_len(bytes.len() as u64)?;
+    println!("Packed {} variants: {} unique chunks, {} bytes",
+        container.names().count(), container.unique_chunks(), bytes.len());
+    Ok(())
+}
+
+fn unpack(cfp: String, od: Option<String>, g: &GlobalArgs) -> Result<()> {
+
+    let mut bytes = Vec::new();
+    open_locked(&cfp, false, g.wait)?.read_to_end(&mut bytes)?;
+    let container = pack::Pack::try_from(&bytes[..])?;
+
+    let dir = PathBuf::from(od.unwrap_or_else(|| String::from(".")));
+    for name in container.names() {
+        let png = container.unpack(name)?;
+        let path = dir.join(name);
+        check_output_size(png.as_bytes().len() as u64, g)?;
+        if cfg!(windows) {
+            platform::check_writable_name(&path)?;
         }
+        fs::write(&path, png.as_bytes())?;
+        println!("{}", path.display());
     }
+    Ok(())
 }
 
-fn get_png(fp: String) -> Result<png::Png> {
-    let bytes: Vec<u8> = fs::read(fp)?;
-    png::Png::try_from(&bytes[..])
+fn fanout(fp: String, rfp: String, od: String, hk: Option<String>, g: &GlobalArgs) -> Result<()> {
+
+    let carrier = read_png(&mut open_locked(&fp, false, g.wait)?, g)?;
+    let recipients = fanout::parse_recipients(&fs::read_to_string(rfp)?)?;
+    let key = hk.map(fs::read).transpose()?.map(|key| crypto::parse_key_material(&key));
+
+    let dir = PathBuf::from(od);
+    fs::create_dir_all(&dir)?;
+    let ledger_path = dir.join(fanout::LEDGER_FILE);
+    let mut ledger: fanout::Ledger = storage::load_json(&ledger_path)?;
+
+    ledger.fanout(&carrier, &recipients, key.as_deref(), |recipient, bytes| {
+        let path = dir.join(format!("{}.png", recipient));
+        check_output_size(bytes.len() as u64, g)?;
+        if cfg!(windows) {
+            platform::check_writable_name(&path)?;
+        }
+        fs::write(path, bytes)?;
+        Ok(())
+    })?;
+    storage::save_json(&ledger_path, &ledger)?;
+    println!("Wrote {} copies to {}", recipients.len(), dir.display());
+    Ok(())
 }
 
-fn encode(fp: String, ct: String, msg: String, of: Option<String>) -> Result<()> {
+fn detect_leak(lfp: String, fp: String, hk: Option<String>, g: &GlobalArgs) -> Result<()> {
 
-    let fp_clone = fp.clone();
+    let ledger: fanout::Ledger = serde_json::from_slice(&fs::read(lfp)?)?;
+    let key = hk.map(fs::read).transpose()?.map(|key| crypto::parse_key_material(&key));
+    let png = read_png(&mut open_locked(&fp, false, g.wait)?, g)?;
 
-    // get PNG struct from file path
-    let mut png = get_png(fp)?;
+    match ledger.identify(&png, key.as_deref())? {
+        Some((issued, fanout::Evidence::Identical)) => println!("{} (identical to the issued copy)", issued.recipient),
+        Some((issued, fanout::Evidence::Watermark)) => {
+            let signed = if issued.signed { "signed " } else { "" };
+            println!("{} ({}watermark matches; the file was modified since it was issued)", issued.recipient, signed);
+        },
+        None => println!("No issued copy matches"),
+    }
+    Ok(())
+}
 
-    // get chunk_type from specified chunk type string
-    let chunk_type = chunk_type::ChunkType::from_str(&ct)?;
+fn notes_path() -> Result<PathBuf> {
+    Ok(storage::data_dir()?.join(notes::NOTES_FILE))
+}
 
-    //convert chunk type and message into new chunk to be appended
-    let secret_chunk = chunk::Chunk::new(chunk_type, msg.into_bytes());
+/// Notes on a file are keyed by its absolute path, and on a downloaded
+/// one by its URL
+fn note_key(fp: &str) -> Result<String> {
+    if remote::is_url(fp) {
+        return Ok(String::from(fp));
+    }
+    Ok(platform::simplify(&fs::canonicalize(fp)?.to_string_lossy()))
+}
+
+fn note(command: NoteCommands) -> Result<()> {
+
+    let path = notes_path()?;
+    let mut store = notes::NoteStore::load(&path)?;
+
+    match command {
+        NoteCommands::Add { file_path, chunk_type, text } => {
+            chunk_type::ChunkType::from_str(&chunk_type)?;
+            let id = store.add(Some(note_key(&file_path)?), &chunk_type, &text);
+            println!("Added note {}", id);
+        },
+        NoteCommands::AddType { chunk_type, text } => {
+            chunk_type::ChunkType::from_str(&chunk_type)?;
+            let id = store.add(None, &chunk_type, &text);
+            println!("Added note {}", id);
+        },
+        NoteCommands::List { file_path } => {
+            let listed: Vec<&notes::Note> = match file_path {
+                Some(fp) => store.notes_for(&note_key(&fp)?),
+                None => store.notes().iter().collect(),
+            };
+            for n in listed {
+                let scope = n.file.as_deref().unwrap_or("(every file)");
+                println!("{}\t{}\t{}\t{}", n.id, n.chunk_type, scope, n.text);
+            }
+            return Ok(());
+        },
+        NoteCommands::Remove { id } => {
+            if !store.remove(id) {
+                return Err(Box::new(NoteNotFoundError(id)));
+            }
+        },
+    }
 
-    png.append_chunk(secret_chunk);
+    store.save(&path)
+}
+
+/// Collects the PNG files at the path: the file itself, or every file with
+/// a .png extension under a directory
+fn collect_pngs(path: &Path, found: &mut Vec<PathBuf>) -> Result<()> {
+    if path.is_dir() {
+        let mut entries: Vec<PathBuf> = fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<io::Result<_>>()?;
+        entries.sort();
 
-    if let Some(ofp) = of {
-        fs::write(ofp, png.as_bytes())?;
+        for entry in entries {
+            let is_png = entry.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+            if entry.is_dir() || is_png {
+                collect_pngs(&entry, found)?;
+            }
+        }
     } else {
-        fs::write(fp_clone, png.as_bytes())?;
+        found.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+fn workspace(name: String, command: WorkspaceCommands, g: &GlobalArgs) -> Result<()> {
+
+    let path = storage::data_dir()?.join(workspace::WORKSPACES_DIR).join(format!("{}.json", name));
+    let mut ws = workspace::Workspace::load(&path)?;
+
+    match command {
+        WorkspaceCommands::Index { paths } => {
+            let mut files = Vec::new();
+            for p in paths {
+                collect_pngs(Path::new(&p), &mut files)?;
+            }
+
+            for file in files {
+                g.cancel.check()?;
+                let fp = platform::simplify(&fs::canonicalize(&file)?.to_string_lossy());
+                let mut bytes: Vec<u8> = Vec::new();
+                open_locked(&fp, false, g.wait)?.read_to_end(&mut bytes)?;
+
+                let indexed = ws.index_file(&fp, &bytes);
+                println!("Indexed {} ({} chunks, {} findings)", fp, indexed.chunks.len(), indexed.findings.len());
+            }
+            ws.save(&path)
+        },
+        WorkspaceCommands::Query { chunk_type, min_size, max_size, non_standard } => {
+            let query = workspace::Query { chunk_type, min_size, max_size, non_standard };
+            for (file, chunk) in ws.query(&query) {
+                println!("{}\t{}\t{}", file.path, chunk.chunk_type, chunk.length);
+            }
+            Ok(())
+        },
+        WorkspaceCommands::List => {
+            for file in ws.files() {
+                println!("{}\t{}\t{} bytes", file.path, file.sha256, file.size);
+                for finding in &file.findings {
+                    println!("\t{}", finding);
+                }
+            }
+            Ok(())
+        },
+        WorkspaceCommands::Clear => match fs::remove_file(&path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(Box::new(e)),
+            _ => Ok(()),
+        },
+    }
+}
+
+fn pool(command: PoolCommands, g: &GlobalArgs) -> Result<()> {
+    match command {
+        PoolCommands::Add { dir, paths } => {
+            let dir = Path::new(&dir);
+            fs::create_dir_all(dir)?;
+            let mut pool = pool::Pool::load(dir)?;
+
+            let mut files = Vec::new();
+            for p in paths {
+                collect_pngs(Path::new(&p), &mut files)?;
+            }
+
+            for file in files {
+                let name = file.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+                let mut bytes: Vec<u8> = Vec::new();
+                open_locked(&file.to_string_lossy(), false, g.wait)?.read_to_end(&mut bytes)?;
+
+                // a bad carrier is skipped rather than failing the whole batch
+                match pool.add(&name, &bytes) {
+                    Ok(carrier) => {
+                        println!("Added {} ({} bytes, capacity {} bytes)", carrier.name, carrier.size, carrier.capacity);
+                        fs::write(dir.join(&name), &bytes)?;
+                    },
+                    Err(e) => eprintln!("Skipped {}: {}", file.display(), e),
+                }
+            }
+            pool.save(dir)
+        },
+        PoolCommands::List { dir } => {
+            for carrier in pool::Pool::load(Path::new(&dir))?.carriers() {
+                let status = if carrier.used { "used" } else { "unused" };
+                println!("{}\t{} bytes\tcapacity {} bytes\t{}", carrier.name, carrier.size, carrier.capacity, status);
+            }
+            Ok(())
+        },
+        PoolCommands::Remove { dir, name } => {
+            let dir = Path::new(&dir);
+            let mut pool = pool::Pool::load(dir)?;
+            let carrier = pool.remove(&name)?;
+            match fs::remove_file(dir.join(&carrier.name)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(Box::new(e)),
+                _ => {},
+            }
+            pool.save(dir)
+        },
+    }
+}
+
+fn schema(output: JsonOutput) -> Result<()> {
+
+    let schema = match output {
+        JsonOutput::Digest => schemars::schema_for!(digest::DigestOutput),
+        JsonOutput::Log => schemars::schema_for!(oplog::LogEntry),
     };
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+    Ok(())
+}
 
+/// Prints what each property bit of the chunk type means, and what the
+/// chunk is for when the specification or pngme defines it
+fn explain(ct: String) -> Result<()> {
+
+    let chunk_type = chunk_type::ChunkType::from_str(&ct)?;
+    let letters: Vec<char> = ct.chars().collect();
+
+    println!("{}", ct);
+    println!("  {}  {}", letters[0], if chunk_type.is_critical() {
+        "critical: decoders that do not know the type must refuse the image"
+    } else {
+        "ancillary: decoders may ignore the chunk"
+    });
+    println!("  {}  {}", letters[1], if chunk_type.is_public() {
+        "public: defined by the PNG specification or registered"
+    } else {
+        "private: defined by an application"
+    });
+    println!("  {}  {}", letters[2], if chunk_type.is_reserved_bit_valid() {
+        "reserved bit clear, as the specification requires"
+    } else {
+        "reserved bit set: not a valid chunk type in PNG 1.2 and later"
+    });
+    println!("  {}  {}", letters[3], if chunk_type.is_safe_to_copy() {
+        "safe to copy: editors may keep the chunk after changing critical chunks"
+    } else {
+        "unsafe to copy: editors must drop the chunk after changing critical chunks"
+    });
+
+    let pngme = match ct.as_str() {
+        manifest::MANIFEST_TYPE => Some("pngme manifest of chunk hashes (--manifest)"),
+        seal::SEAL_TYPE => Some("pngme signed seal (seal)"),
+        audit::AUDIT_TYPE => Some("pngme audit log (--audit)"),
+        shares::SHARE_TYPE => Some("pngme secret share (split)"),
+        thumbnail::THUMBNAIL_TYPE => Some("pngme thumbnail (thumbnail add)"),
+        fanout::WATERMARK_TYPE => Some("pngme recipient watermark (fanout)"),
+        _ => None,
+    };
+    println!("Registered: {}", if chunk_type.is_registered() { "yes" } else { "no" });
+    if let Some(description) = chunk_type.description().or(pngme) {
+        println!("Purpose: {}", description);
+    }
     Ok(())
 }
 
-fn decode(fp: String, ct: String) -> Result<()> {
-    
-    let mut png = get_png(fp)?;
+fn survive_test(fp: String, ct: String, transforms: Vec<survive::Transform>, g: &GlobalArgs) -> Result<()> {
 
-    //Search the PNG for the specified chunk type
-    match png.chunk_by_type(&ct) {
+    let png = read_png(&mut open_locked(&fp, false, g.wait)?, &GlobalArgs { preserve: true, ..g.clone() })?;
+    let transforms = if transforms.is_empty() { survive::Transform::value_variants().to_vec() } else { transforms };
 
-        // Print the chunk message as a string if found
-        // Otherwise, return a ChunkNotFoundError
-        Some(chunk) => {
-            println!("{}", chunk.data_as_string()?);
-            Ok(())
-        }, 
-        None => {
-            Err(Box::new(png::ChunkNotFoundError))
+    let outcomes = survive::survive(&png, &ct, &transforms)?;
+    if outcomes.first().is_some_and(|outcome| outcome.total == 0) {
+        return Err(Box::new(png::ChunkNotFoundError));
+    }
+    for outcome in outcomes {
+        match outcome.surviving {
+            0 => println!("{:<16} lost", outcome.transform.to_string()),
+            n => println!("{:<16} survives ({} of {} copies)", outcome.transform.to_string(), n, outcome.total),
         }
     }
+    if !png.trailer().is_empty() {
+        println!("Data after IEND survives none of these transforms.");
+    }
+    Ok(())
 }
 
-fn remove(fp: String, ct: String) -> Result<()> {
-    
-    let ofp = fp.clone();
+fn capacity(fp: String, ecc: Option<f64>, shard_size: Option<u32>, max_growth: Option<capacity::Growth>, 
+    sample: Option<(compress::Compression, String)>, g: &GlobalArgs) -> Result<()> {
 
-    let mut png = get_png(fp)?;
+    let mut file = open_locked(&fp, false, g.wait)?;
+    let file_len = file.metadata()?.len();
+    read_png(&mut file, g)?;
 
-    //remove chunk corresponding to some chunk type
-    png.remove_chunk(&ct)?;
+    let options = capacity::Options {
+        ecc,
+        shard_size: shard_size.map(|size| size as usize),
+        max_growth: max_growth.map(|growth| growth.bytes(file_len)),
+        output_room: g.max_output_size.map(|max| max.saturating_sub(file_len)),
+    };
+    let estimate = capacity::estimate(&options)?;
+    let footprint = estimate.footprint;
+    match estimate.limit {
+        capacity::Limit::ChunkLength => println!("Largest message: {} bytes (the PNG specification's maximum)", estimate.message_len),
+        _ => println!("Largest message: {} bytes", estimate.message_len),
+    }
+    match footprint.chunks {
+        1 => println!("Stored as {} bytes in 1 chunk, growing the file by {} bytes", footprint.stored_len, footprint.growth),
+        n => println!("Stored as {} bytes in {} chunks, growing the file by {} bytes", footprint.stored_len, n, footprint.growth),
+    }
+    match options.max_growth {
+        Some(max) if estimate.limit == capacity::Limit::Growth => println!("Limited by the growth budget of {} bytes", max),
+        _ if estimate.limit == capacity::Limit::OutputSize => println!("Limited by --max-output-size of {} bytes", g.max_output_size.unwrap()),
+        _ => println!("Limited by {}", estimate.limit),
+    }
+
+    if let Some((compression, path)) = sample {
+        let sample = fs::read(&path)?;
+        let (estimated, compressed) = capacity::compressed_capacity(estimate.message_len, compression, &sample)?;
+        println!("Data like {} ({} bytes, {} compressed) fits about {} bytes with --compress {}", 
+            path, sample.len(), compressed, estimated, compression.to_possible_value().unwrap().get_name());
+    }
+    Ok(())
+}
 
-    fs::write(ofp, png.as_bytes())?;
+fn peek(fp: String, password: Option<Vec<u8>>, od: Option<String>, g: &GlobalArgs) -> Result<()> {
 
+    // data after IEND is one of the places to look
+    let png = read_png(&mut open_locked(&fp, false, g.wait)?, &GlobalArgs { preserve: true, ..g.clone() })?;
+
+    let findings = peek::peek(&png, password.as_deref());
+    if findings.is_empty() {
+        println!("Nothing found.");
+    }
+    for finding in findings {
+        match finding.details {
+            Some(details) => println!("{}: {}, {}, {} bytes", finding.location, finding.format, details, finding.len),
+            None => println!("{}: {}, {} bytes", finding.location, finding.format, finding.len),
+        }
+        match finding.message {
+            Some(message) if od.is_some() => {
+                let path = Path::new(od.as_ref().unwrap()).join(format!("{}.{}", finding.location.file_stem(), finding.format.extension()));
+                fs::create_dir_all(od.as_ref().unwrap())?;
+                fs::write(&path, message)?;
+                println!("  extracted to {}", path.display());
+            },
+            Some(message) => match platform::decode_text(&message) {
+                Ok(text) => println!("  {}", text.replace('\n', "\n  ")),
+                Err(_) => println!("  ({} bytes of binary data)", message.len()),
+            },
+            None if password.is_some() && finding.format == peek::Format::Encrypted
+            => println!("  (the password does not open it)"),
+            None => println!("  (needs a key to open)"),
+        }
+    }
     Ok(())
 }
 
-fn print(fp: String) -> Result<()> {
+fn print(fp: String, g: &GlobalArgs) -> Result<()> {
 
-    let mut png = get_png(fp)?;
+    let png = load_png(&fp, g)?;
 
     let ancilliary_chunks = png.ancillary_chunks();
 
@@ -103,11 +2181,506 @@ fn print(fp: String) -> Result<()> {
                 if i != ancilliary_chunks.len() -1 {
                     print!(", ");
                 } else {
-                    print!("\n");
+                    println!();
                 }
             }
             io::stdout().flush().unwrap();
         }
     }
+
+    // show the analyst's notes on chunk types found in the file
+    let store = notes::NoteStore::load(&notes_path()?)?;
+    let key = note_key(&fp)?;
+    for note in store.notes_for(&key) {
+        if png.chunk_by_type(&note.chunk_type).is_some() {
+            println!("Note on {}: {}", note.chunk_type, note.text);
+        }
+    }
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Error specifying that another process holds a lock on the file
+#[derive(Debug)]
+pub struct FileLockedError(String);
+
+impl fmt::Display for FileLockedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} is locked by another process! (use --wait to block until it is released)", self.0)
+    }
+}
+
+impl error::Error for FileLockedError {}
+
+/// Error specifying that required tags are absent from the PNG
+#[derive(Debug)]
+pub struct MissingTagsError(Vec<String>);
+
+impl fmt::Display for MissingTagsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Missing required tags: {}", self.0.join(", "))
+    }
+}
+
+impl error::Error for MissingTagsError {}
+
+/// Error specifying that no note has the given id
+#[derive(Debug)]
+pub struct NoteNotFoundError(u64);
+
+impl fmt::Display for NoteNotFoundError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Note {} not Found!", self.0)
+    }
+}
+
+impl error::Error for NoteNotFoundError {}
+
+/// Error specifying an encrypted message decoded without a key
+#[derive(Debug)]
+pub struct EncryptedMessageError;
+
+impl fmt::Display for EncryptedMessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Message Is Encrypted! (decode it with --decrypt or --identity)")
+    }
+}
+
+impl error::Error for EncryptedMessageError {}
+
+/// Error specifying that the confirmation of a new password did not match
+#[derive(Debug)]
+pub struct PasswordMismatchError;
+
+impl fmt::Display for PasswordMismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Passwords Do Not Match!")
+    }
+}
+
+impl error::Error for PasswordMismatchError {}
+
+/// Error specifying an output file given after the message position with
+/// --exec or --file, which take no message
+#[derive(Debug)]
+pub struct ExecOutputError;
+
+impl fmt::Display for ExecOutputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Too Many Arguments! (with --exec or --file the output file follows the chunk type)")
+    }
+}
+
+impl error::Error for ExecOutputError {}
+
+/// Error specifying a seal asked for along with --exec, which stores the
+/// command output unsealed
+#[derive(Debug)]
+pub struct ExecSealError;
+
+impl fmt::Display for ExecSealError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Cannot Seal Command Output! (write it to a file and encode that with --file)")
+    }
+}
+
+impl error::Error for ExecSealError {}
+
+/// Error specifying a binary message decode would print to a terminal
+#[derive(Debug)]
+pub struct BinaryMessageError(usize);
+
+impl fmt::Display for BinaryMessageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Binary Message! ({} bytes; print it with --format hex or base64, write it to a file with --output or pipe it)", self.0)
+    }
+}
+
+impl error::Error for BinaryMessageError {}
+
+/// Error specifying a command that exited unsuccessfully
+#[derive(Debug)]
+pub struct CommandFailedError(String);
+
+impl fmt::Display for CommandFailedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Command Failed! ({})", self.0)
+    }
+}
+
+impl error::Error for CommandFailedError {}
+
+/// Error specifying an operation ruled out by --no-network or --no-clipboard
+#[derive(Debug)]
+pub struct SandboxError(&'static str);
+
+impl fmt::Display for SandboxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Refused by Sandbox Flags! ({})", self.0)
+    }
+}
+
+impl error::Error for SandboxError {}
+
+/// Error specifying an operation that ran past --max-runtime
+#[derive(Debug)]
+pub struct RuntimeExceededError(u64);
+
+impl fmt::Display for RuntimeExceededError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Runtime Limit Exceeded! (after {} seconds)", self.0)
+    }
+}
+
+impl error::Error for RuntimeExceededError {}
+
+/// Error specifying output larger than --max-output-size
+#[derive(Debug)]
+pub struct OutputTooLargeError(u64, u64);
+
+impl fmt::Display for OutputTooLargeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Output Too Large! ({} bytes, at most {})", self.0, self.1)
+    }
+}
+
+impl error::Error for OutputTooLargeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+    use clap::Parser;
+    use crate::png::testing::{chunk, testing_png};
+
+    /// Runs pngme with the arguments
+    fn pngme(args: &[&str]) -> Result<()> {
+        run(Args::try_parse_from([&["pngme"], args].concat())?)
+    }
+
+    /// Directory of its own for the files of a test
+    fn testing_dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("pngme-commands-{}-{}", process::id(), test));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes the PNG to the file in the directory, returning its path
+    fn carrier(dir: &Path, name: &str, png: &png::Png) -> String {
+        let path = dir.join(name);
+        fs::write(&path, png.as_bytes()).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    /// Decodes the message into a file and returns it
+    fn decoded(path: &str, args: &[&str]) -> Result<Vec<u8>> {
+        let output = format!("{}.out", path);
+        pngme(&[&["decode", path], args, &["--output", &output]].concat())?;
+        Ok(fs::read(&output)?)
+    }
+
+    #[test]
+    fn test_plain_message_with_layer_magic() {
+        let dir = testing_dir("magic");
+        for (i, layer) in envelope::Layer::ALL.iter().enumerate() {
+            let message = format!("{} is a plain message", str::from_utf8(layer.magic()).unwrap());
+
+            let path = carrier(&dir, &format!("{}.png", i), &testing_png());
+            pngme(&["encode", &path, "ruSt", &message]).unwrap();
+            assert_eq!(decoded(&path, &["ruSt"]).unwrap(), message.as_bytes(), "{}", layer.name());
+            pngme(&["encode", &path, "prIv", &message, "--checksum", "--compress", "deflate"]).unwrap();
+            assert_eq!(decoded(&path, &["prIv"]).unwrap(), message.as_bytes(), "{}", layer.name());
+
+            // written as it is, before plain messages were escaped. Any
+            // bytes after the magic open as a whitened payload, which is
+            // taken over old text that starts like one.
+            if *layer == envelope::Layer::Whitened {
+                continue;
+            }
+            let mut legacy = testing_png();
+            legacy.insert_chunk(2, chunk("ruSt", message.as_bytes()));
+            let path = carrier(&dir, &format!("legacy-{}.png", i), &legacy);
+            let expected = envelope::unescape(message.as_bytes());
+            assert_eq!(decoded(&path, &["ruSt"]).unwrap(), expected, "{}", layer.name());
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_whitened_text() {
+        // this seed whitens the message into bytes that are valid UTF-8,
+        // as old plain text starting with the magic bytes would be
+        let payload = whiten::whiten_with_seed(*b"seed0035", b"secret");
+        assert!(str::from_utf8(&payload).is_ok());
+        let mut png = testing_png();
+        png.insert_chunk(2, chunk("ruSt", &payload));
+        let dir = testing_dir("whitened");
+        let path = carrier(&dir, "carrier.png", &png);
+        assert_eq!(decoded(&path, &["ruSt"]).unwrap(), b"secret");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exec_output_with_layer_magic() {
+        let dir = testing_dir("exec");
+        let path = carrier(&dir, "carrier.png", &testing_png());
+        pngme(&["encode", &path, "ruSt", "--exec", "printf PMCZ-streamed"]).unwrap();
+        assert_eq!(decoded(&path, &["ruSt"]).unwrap(), b"PMCZ-streamed");
+
+        let output = dir.join("piped").to_string_lossy().into_owned();
+        pngme(&["decode", &path, "ruSt", "--exec", &format!("cat > {}", output)]).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), b"PMCZ-streamed");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Writes the PNG over a file holding the original and returns the
+    /// unchanged prefix write_png found, checking the file holds the PNG
+    fn rewritten(dir: &Path, original: &png::Png, png: &png::Png) -> usize {
+        let path = carrier(dir, "rewritten.png", original);
+        let mut file = open_locked(&path, true, false).unwrap();
+        let start = unchanged_prefix(&mut file, &png.as_bytes()).unwrap();
+        write_png(&mut file, png).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), png.as_bytes());
+        start
+    }
+
+    #[test]
+    fn test_write_png() {
+        let dir = testing_dir("write");
+        let original = testing_png();
+        let iend = original.as_bytes().len() - 12;
+
+        // appending before IEND leaves everything up to IEND alone
+        let mut appended = testing_png();
+        appended.insert_chunk(2, chunk("ruSt", b"message"));
+        assert!(rewritten(&dir, &original, &appended) >= iend);
+
+        // shrinking rewrites the tail and cuts the file to length
+        let mut shrunk = testing_png();
+        shrunk.remove_chunk("IDAT").unwrap();
+        assert!(rewritten(&dir, &original, &shrunk) >= 8 + 18);
+
+        // a difference in the header rewrites nearly everything
+        let mut header = testing_png();
+        header.map_chunks(|c| if c.chunk_type().as_str() == "IHDR" { chunk("IHDR", b"HEADER") } else { c });
+        assert_eq!(rewritten(&dir, &original, &header), 8 + 8);
+
+        assert_eq!(rewritten(&dir, &original, &original), original.as_bytes().len());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_secure_remove() {
+        let dir = testing_dir("secure");
+        let path = carrier(&dir, "wiped.png", &testing_png());
+        let mut file = open_locked(&path, true, false).unwrap();
+        wipe(&mut file, 8, 18).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(&bytes[8..26], &[0; 18]);
+        assert_eq!(&bytes[26..], &testing_png().as_bytes()[26..]);
+
+        let mut png = testing_png();
+        png.insert_chunk(1, chunk("ruSt", b"first secret"));
+        png.insert_chunk(2, chunk("ruSt", b"second secret"));
+        let path = carrier(&dir, "carrier.png", &png);
+        let mut old = fs::File::open(&path).unwrap();
+        pngme(&["remove", &path, "ruSt", "--secure"]).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        assert!(!bytes.windows(12).any(|window| window == b"first secret"));
+        assert_eq!(decoded(&path, &["ruSt"]).unwrap(), b"second secret");
+        assert!(!Path::new(&format!("{}.remove.tmp", path)).exists());
+
+        // the new file is renamed into place, and the chunk is then wiped
+        // in the old one
+        let mut bytes = Vec::new();
+        old.read_to_end(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), png.as_bytes().len());
+        assert!(!bytes.windows(12).any(|window| window == b"first secret"));
+        assert!(bytes.windows(13).any(|window| window == b"second secret"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "chacha20poly1305")]
+    #[test]
+    fn test_rekey() {
+        let dir = testing_dir("rekey");
+        let path = carrier(&dir, "carrier.png", &testing_png());
+        let kdf = ["--kdf-memory", "64", "--kdf-iterations", "1"];
+        pngme(&[&["encode", &path, "ruSt", "secret", "--encrypt", "--password", "old"], &kdf[..]].concat()).unwrap();
+        let original = fs::read(&path).unwrap();
+
+        // a wrong password fails before anything is written
+        assert!(pngme(&["rekey", &path, "ruSt", "--password", "wrong", "--new-password", "new"]).is_err());
+        assert_eq!(fs::read(&path).unwrap(), original);
+        assert!(!Path::new(&format!("{}.rekey.tmp", path)).exists());
+
+        pngme(&["rekey", &path, "ruSt", "--password", "old", "--new-password", "new"]).unwrap();
+        assert_eq!(decoded(&path, &["ruSt", "--decrypt", "--password", "new"]).unwrap(), b"secret");
+        assert!(decoded(&path, &["ruSt", "--decrypt", "--password", "old"]).is_err());
+
+        // decode falls back on the resilient copies, so they are rekeyed too
+        let path = carrier(&dir, "resilient.png", &testing_png());
+        pngme(&[&["encode", &path, "ruSt", "secret", "--encrypt", "--password", "old", "--placement", "resilient"], &kdf[..]].concat()).unwrap();
+        pngme(&["rekey", &path, "ruSt", "--password", "old", "--new-password", "new"]).unwrap();
+        assert!(decoded(&path, &["ruSt", "--decrypt", "--password", "old"]).is_err());
+        let png = png::Png::try_from(&fs::read(&path).unwrap()[..]).unwrap();
+        let copies = placement::copies(&png, "ruSt");
+        assert_eq!(copies.len(), 1);
+        assert_eq!(crypto::decrypt(b"new", &copies[0]).unwrap(), b"secret");
+
+        // so is a payload with error correction split into shards
+        let path = carrier(&dir, "ecc.png", &testing_png());
+        pngme(&[&["encode", &path, "ruSt", "secret", "--encrypt", "--password", "old", "--ecc", "0.5", "--shard-size", "64"], &kdf[..]].concat()).unwrap();
+        pngme(&["rekey", &path, "ruSt", "--password", "old", "--new-password", "new"]).unwrap();
+        let png = png::Png::try_from(&fs::read(&path).unwrap()[..]).unwrap();
+        assert!(png.shard_positions("ruSt").len() > 1);
+        let payload = png.reassemble("ruSt").unwrap().unwrap();
+        assert!(ecc::is_protected(&payload));
+        assert_eq!(decoded(&path, &["ruSt", "--decrypt", "--password", "new"]).unwrap(), b"secret");
+        assert!(decoded(&path, &["ruSt", "--decrypt", "--password", "old"]).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "chacha20poly1305")]
+    #[test]
+    fn test_encrypt_without_decoy() {
+        let dir = testing_dir("decoy");
+        let kdf = ["--kdf-memory", "64", "--kdf-iterations", "1"];
+        let payload = |args: &[&str]| {
+            let path = carrier(&dir, "carrier.png", &testing_png());
+            pngme(&[&["encode", &path, "ruSt", "real plan", "--encrypt", "--password", "real"], args, &kdf[..]].concat()).unwrap();
+            png::Png::try_from(&fs::read(&path).unwrap()[..]).unwrap().chunk_by_type("ruSt").unwrap().data().to_vec()
+        };
+
+        // a payload with a decoy looks like one without
+        let single = payload(&[]);
+        let double = payload(&["--decoy", "groceries", "--decoy-password", "decoy"]);
+        assert!(envelope::is_deniable(&single) && envelope::is_deniable(&double));
+        assert_eq!(single.len(), double.len());
+        assert_eq!(crypto::decrypt(b"real", &single).unwrap(), envelope::escape(b"real plan"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_lock_follows_rename() {
+        let dir = testing_dir("rename");
+        let path = carrier(&dir, "carrier.png", &testing_png());
+        let replacement = carrier(&dir, "replacement.png", &png::Png::from_chunks(vec![chunk("IHDR", b"new")]));
+
+        let held = open_locked(&path, true, false).unwrap();
+        let waiter = {
+            let path = path.clone();
+            thread::spawn(move || {
+                let mut bytes = Vec::new();
+                open_locked(&path, true, true).unwrap().read_to_end(&mut bytes).unwrap();
+                bytes
+            })
+        };
+        thread::sleep(Duration::from_millis(100));
+        fs::rename(&replacement, &path).unwrap();
+        drop(held);
+        assert_eq!(waiter.join().unwrap(), fs::read(&path).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_output_is_input() {
+        let dir = testing_dir("same");
+        let path = carrier(&dir, "carrier.png", &testing_png());
+        let spelled = dir.join(".").join("carrier.png").to_string_lossy().into_owned();
+
+        pngme(&["encode", &path, "ruSt", "message", &spelled]).unwrap();
+        assert_eq!(decoded(&path, &["ruSt"]).unwrap(), b"message");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_encode_mode() {
+        let dir = testing_dir("mode");
+        let path = carrier(&dir, "carrier.png", &testing_png());
+        let read = |path: &str| png::Png::try_from(&fs::read(path).unwrap()[..]).unwrap();
+        let count = |path: &str| read(path).chunks().iter().filter(|chunk| chunk.chunk_type().as_str() == "ruSt").count();
+
+        pngme(&["encode", &path, "ruSt", "first", "--mode", "fail"]).unwrap();
+        let before = fs::read(&path).unwrap();
+        let error = pngme(&["encode", &path, "ruSt", "second", "--mode", "fail"]).unwrap_err();
+        assert!(error.is::<placement::ChunkTypeInUseError>());
+        assert_eq!(fs::read(&path).unwrap(), before);
+
+        pngme(&["encode", &path, "ruSt", "second", "--mode", "append"]).unwrap();
+        assert_eq!(count(&path), 2);
+        assert_eq!(decoded(&path, &["ruSt", "--index", "1"]).unwrap(), b"second");
+
+        // the resilient copies go along with the chunks they copy
+        pngme(&["encode", &path, "ruSt", "third", "--mode", "replace", "--placement", "resilient"]).unwrap();
+        assert!(read(&path).chunks().iter().any(|chunk| placement::is_copy(chunk, "ruSt")));
+        pngme(&["encode", &path, "ruSt", "fourth", "--mode", "replace"]).unwrap();
+        assert_eq!(count(&path), 1);
+        assert_eq!(decoded(&path, &["ruSt"]).unwrap(), b"fourth");
+        assert!(!read(&path).chunks().iter().any(|chunk| placement::is_copy(chunk, "ruSt")));
+
+        let error = pngme(&["encode", &path, "IDAT", "data", "--mode", "replace"]).unwrap_err();
+        assert!(error.is::<placement::RegisteredChunkTypeError>());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_index_out_of_range() {
+        let dir = testing_dir("index");
+        let mut png = testing_png();
+        png.append_chunk(chunk("ruSt", b"first"));
+        png.append_chunk(chunk("ruSt", b"second"));
+        let path = carrier(&dir, "carrier.png", &png);
+
+        assert_eq!(decoded(&path, &["ruSt", "--index", "1"]).unwrap(), b"second");
+        for args in [&["decode", &path, "ruSt", "--index", "2"], &["remove", &path, "ruSt", "--index", "2"]] {
+            assert!(pngme(args).unwrap_err().is::<png::ChunkNotFoundError>());
+        }
+        assert_eq!(fs::read(&path).unwrap(), png.as_bytes());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Checks that clap refuses the encode arguments as conflicting
+    fn assert_conflict(args: &[&str]) {
+        let error = pngme(&[&["encode", "carrier.png", "ruSt"], args].concat()).unwrap_err();
+        assert_eq!(error.downcast_ref::<clap::Error>().unwrap().kind(), clap::error::ErrorKind::ArgumentConflict, "{:?}", args);
+    }
+
+    #[test]
+    fn test_seal_conflicts() {
+        // only one seal is applied, so asking for two must fail
+        #[cfg(feature = "pgp")]
+        assert_conflict(&["message", "--hmac-key", "key", "--pgp-recipient", "alice"]);
+        #[cfg(feature = "fido")]
+        assert_conflict(&["message", "--hmac-key", "key", "--fido"]);
+        assert_conflict(&["message", "--hmac-key", "key", "--encrypt"]);
+
+        // the output of --exec is never sealed
+        #[cfg(feature = "pgp")]
+        assert_conflict(&["--exec", "true", "--pgp-recipient", "alice"]);
+        #[cfg(feature = "fido")]
+        assert_conflict(&["--exec", "true", "--fido"]);
+        for seal in [&["--encrypt"][..], &["--recipient", "age1"], &["--hmac-key", "key"], &["--obfuscate"]] {
+            assert_conflict(&[&["--exec", "true"], seal].concat());
+        }
+    }
+
+    #[test]
+    fn test_missing_input() {
+        let dir = testing_dir("missing");
+        let path = dir.join("missing.png").to_string_lossy().into_owned();
+        let error = pngme(&["encode", &path, "ruSt", "message"]).unwrap_err();
+        assert_eq!(error.downcast_ref::<io::Error>().unwrap().kind(), io::ErrorKind::NotFound);
+        assert!(pngme(&["remove", &path, "ruSt"]).is_err());
+        assert!(!Path::new(&path).exists());
+
+        // output files are still created
+        let input = carrier(&dir, "carrier.png", &testing_png());
+        let output = dir.join("output.png").to_string_lossy().into_owned();
+        pngme(&["encode", &input, "ruSt", "message", &output]).unwrap();
+        assert_eq!(decoded(&output, &["ruSt"]).unwrap(), b"message");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+//!
+//! Payload compression applied before sealing. A compressed payload starts
+//! with the magic bytes `PMCZ` and the id of the algorithm, so decode can
+//! decompress it without being told how.
+//!
+use std::{error, fmt, io::{Read, Write}};
+use clap::ValueEnum;
+use flate2::{read::DeflateDecoder, write::DeflateEncoder};
+use crate::Result;
+
+/// Marks the start of a compressed payload
+pub const MAGIC: &[u8; 4] = b"PMCZ";
+
+/// Buffer size of the brotli encoder and decoder
+const BROTLI_BUFFER_LEN: usize = 4096;
+/// Most a payload is decompressed to unless a larger limit is given
+/// explicitly, as a few bytes can expand to gigabytes
+pub const DEFAULT_MAX_LEN: u64 = 64 << 20;
+
+/// Best brotli quality, as payloads are small enough for its slowness
+const BROTLI_QUALITY: u32 = 11;
+/// Base-2 logarithm of the brotli window size, brotli's default
+const BROTLI_WINDOW_BITS: u32 = 22;
+
+/// Compression algorithm of a payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Compression {
+    /// Store the payload as it is
+    #[default]
+    None,
+    /// DEFLATE, as zlib and PNG itself use
+    Deflate,
+    /// Zstandard, usually smaller and faster than DEFLATE
+    Zstd,
+    /// Brotli, slower but noticeably smaller on prose and other text
+    Brotli,
+}
+
+impl Compression {
+    pub fn id(&self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Deflate => 1,
+            Compression::Zstd => 2,
+            Compression::Brotli => 3,
+        }
+    }
+}
+
+/// Checks if the payload starts with the magic bytes of a compressed one
+pub fn is_compressed(payload: &[u8]) -> bool {
+    payload.len() > MAGIC.len() && payload.starts_with(MAGIC)
+}
+
+/// Compresses the data, leaving it as it is when compression does not
+/// make it smaller
+pub fn compress(compression: Compression, data: &[u8]) -> Result<Vec<u8>> {
+    let compressed = match compression {
+        Compression::None => return Ok(data.to_vec()),
+        Compression::Deflate => {
+            let mut encoder = DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+            encoder.write_all(data)?;
+            encoder.finish()?
+        },
+        Compression::Zstd => zstd::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL)?,
+        Compression::Brotli => {
+            let mut encoder = brotli::CompressorWriter::new(Vec::new(), BROTLI_BUFFER_LEN, BROTLI_QUALITY, BROTLI_WINDOW_BITS);
+            encoder.write_all(data)?;
+            encoder.into_inner()
+        },
+    };
+
+    let mut payload = MAGIC.to_vec();
+    payload.push(compression.id());
+    payload.extend(compressed);
+    Ok(if payload.len() < data.len() { payload } else { data.to_vec() })
+}
+
+/// Decompresses a payload made by `compress`, refusing to produce more
+/// than `limit` bytes
+pub fn decompress(payload: &[u8], limit: u64) -> Result<Vec<u8>> {
+    if !is_compressed(payload) {
+        return Err(Box::new(InvalidCompressedPayloadError));
+    }
+    let compressed = &payload[MAGIC.len() + 1..];
+    let mut reader: Box<dyn Read> = match payload[MAGIC.len()] {
+        1 => Box::new(DeflateDecoder::new(compressed)),
+        2 => Box::new(zstd::Decoder::new(compressed)?),
+        3 => Box::new(brotli::Decompressor::new(compressed, BROTLI_BUFFER_LEN)),
+        id => return Err(Box::new(UnknownCompressionError(id))),
+    };
+
+    // a small payload can expand enormously, so stop one byte past the limit
+    let mut data = Vec::new();
+    reader.as_mut().take(limit.saturating_add(1)).read_to_end(&mut data)?;
+    if data.len() as u64 > limit {
+        return Err(Box::new(DecompressedTooLargeError(limit)));
+    }
+    Ok(data)
+}
+
+/// Error specifying a compressed payload that could not be parsed
+#[derive(Debug)]
+pub struct InvalidCompressedPayloadError;
+
+impl fmt::Display for InvalidCompressedPayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Compressed Payload!")
+    }
+}
+
+impl error::Error for InvalidCompressedPayloadError {}
+
+/// Error specifying a compression algorithm id this build does not know
+#[derive(Debug)]
+pub struct UnknownCompressionError(u8);
+
+impl fmt::Display for UnknownCompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown Compression Algorithm! (id {})", self.0)
+    }
+}
+
+impl error::Error for UnknownCompressionError {}
+
+/// Error specifying a payload that decompresses to more than allowed
+#[derive(Debug)]
+pub struct DecompressedTooLargeError(u64);
+
+impl fmt::Display for DecompressedTooLargeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Decompressed Payload Too Large! (over {} bytes)", self.0)
+    }
+}
+
+impl error::Error for DecompressedTooLargeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_text() -> Vec<u8> {
+        b"All work and no play makes Jack a dull boy. ".repeat(100)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for compression in [Compression::Deflate, Compression::Zstd, Compression::Brotli] {
+            let payload = compress(compression, &testing_text()).unwrap();
+            assert!(is_compressed(&payload));
+            assert!(payload.len() < testing_text().len() / 10);
+            assert_eq!(decompress(&payload, DEFAULT_MAX_LEN).unwrap(), testing_text());
+        }
+    }
+
+    #[test]
+    fn test_incompressible() {
+        assert_eq!(compress(Compression::Zstd, b"short").unwrap(), b"short");
+        assert_eq!(compress(Compression::None, &testing_text()).unwrap(), testing_text());
+    }
+
+    #[test]
+    fn test_limit() {
+        let payload = compress(Compression::Deflate, &testing_text()).unwrap();
+        assert!(decompress(&payload, 100).is_err());
+        assert!(decompress(&payload, testing_text().len() as u64).is_ok());
+
+        let mut unknown = payload.clone();
+        unknown[MAGIC.len()] = 9;
+        assert!(decompress(&unknown, DEFAULT_MAX_LEN).is_err());
+    }
+
+    #[test]
+    fn test_bomb() {
+        let zeros = vec![0; DEFAULT_MAX_LEN as usize + 1];
+        let bomb = compress(Compression::Zstd, &zeros).unwrap();
+        assert!(bomb.len() < 10_000);
+        assert!(decompress(&bomb, DEFAULT_MAX_LEN).unwrap_err().is::<DecompressedTooLargeError>());
+        assert_eq!(decompress(&bomb, DEFAULT_MAX_LEN + 1).unwrap().len(), zeros.len());
+    }
+}
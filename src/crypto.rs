@@ -0,0 +1,727 @@
+//!
+//! Payload encryption: password-based, with a key derived from the password
+//! by Argon2id or scrypt and one of the AEAD ciphers of the cipher module, or age
+//! encryption to the public keys of recipients. Unencrypted payloads can be authenticated
+//! with an HMAC-SHA256 tag instead.
+//!
+use std::{fmt, error, io::{Read, Write}, str::FromStr};
+use argon2::{Algorithm, Argon2, Params, Version};
+use clap::ValueEnum;
+use hmac::{Hmac, Mac};
+use rand_core::{OsRng, RngCore};
+use sha2::Sha256;
+use crate::{chunk_type::ChunkType, cipher::{self, Cipher}, envelope::{self, Envelope}, Result};
+
+pub(crate) const KEY_LEN: usize = 32;
+pub(crate) const SALT_LEN: usize = 16;
+/// Both ciphers take 96-bit nonces
+pub(crate) const NONCE_LEN: usize = 12;
+/// Both ciphers append 128-bit tags
+pub(crate) const CIPHER_TAG_LEN: usize = 16;
+/// KDF id followed by three big-endian cost parameters
+pub(crate) const KDF_LEN: usize = 1 + 3 * 4;
+
+/// Most memory a KDF recorded in a payload may ask for (4 GiB), so crafted
+/// payloads cannot exhaust the memory of whoever decodes them
+const MAX_MEMORY_KIB: u64 = 4 << 20;
+const MAX_ITERATIONS: u32 = 64;
+const MAX_PARALLELISM: u32 = 64;
+
+/// Built-in cipher, as chosen on the command line
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum CipherKind {
+    #[default]
+    #[value(name = "chacha20-poly1305")]
+    ChaCha20Poly1305,
+    #[value(name = "aes-256-gcm")]
+    Aes256Gcm,
+}
+
+impl CipherKind {
+    /// Identifier of the cipher in the payload header
+    pub fn id(&self) -> u8 {
+        match self {
+            CipherKind::ChaCha20Poly1305 => 1,
+            CipherKind::Aes256Gcm => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<CipherKind> {
+        match id {
+            1 => Some(CipherKind::ChaCha20Poly1305),
+            2 => Some(CipherKind::Aes256Gcm),
+            _ => None,
+        }
+    }
+
+    /// Returns the backend of the cipher, if it is built in
+    pub fn cipher(&self) -> Result<&'static dyn Cipher> {
+        cipher::by_id(self.id())
+    }
+}
+
+impl fmt::Display for CipherKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_possible_value().unwrap().get_name())
+    }
+}
+
+/// Kind of key derivation function, as chosen on the command line
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum KdfKind {
+    #[default]
+    Argon2,
+    Scrypt,
+}
+
+/// Key derivation function turning a password into the cipher key, with
+/// its cost parameters, recorded in the payload header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kdf {
+    /// Argon2id with its memory in KiB, iterations and lanes
+    Argon2 { memory_kib: u32, iterations: u32, parallelism: u32 },
+    /// scrypt with log2 of its cost N, its block size r and parallelism p
+    Scrypt { log_n: u8, block_size: u32, parallelism: u32 },
+}
+
+impl Default for Kdf {
+    /// Argon2id with 64 MiB, three passes and four lanes (the second
+    /// recommended option of RFC 9106)
+    fn default() -> Kdf {
+        Kdf::Argon2 { memory_kib: 64 * 1024, iterations: 3, parallelism: 4 }
+    }
+}
+
+impl Kdf {
+    const SCRYPT_BLOCK_SIZE: u32 = 8;
+
+    /// Builds a KDF of the kind, overriding its default costs. scrypt uses
+    /// 128 MiB and one lane by default, and has no separate iteration
+    /// count: its cost N is the largest power of two fitting the memory.
+    pub fn new(kind: KdfKind, memory_kib: Option<u32>, iterations: Option<u32>, parallelism: Option<u32>) -> Result<Kdf> {
+        let kdf = match kind {
+            KdfKind::Argon2 => {
+                let Kdf::Argon2 { memory_kib: m, iterations: t, parallelism: p } = Kdf::default() else { unreachable!() };
+                Kdf::Argon2 {
+                    memory_kib: memory_kib.unwrap_or(m),
+                    iterations: iterations.unwrap_or(t),
+                    parallelism: parallelism.unwrap_or(p),
+                }
+            },
+            KdfKind::Scrypt => {
+                if iterations.is_some() {
+                    return Err(Box::new(InvalidKdfError(String::from("scrypt takes no iteration count"))));
+                }
+                let blocks = memory_kib.unwrap_or(128 * 1024) as u64 * 1024 / (128 * Kdf::SCRYPT_BLOCK_SIZE as u64);
+                Kdf::Scrypt {
+                    log_n: blocks.max(2).ilog2() as u8,
+                    block_size: Kdf::SCRYPT_BLOCK_SIZE,
+                    parallelism: parallelism.unwrap_or(1),
+                }
+            },
+        };
+        kdf.validate()?;
+        Ok(kdf)
+    }
+
+    /// Memory the KDF uses in KiB
+    pub fn memory_kib(&self) -> u64 {
+        match *self {
+            Kdf::Argon2 { memory_kib, .. } => memory_kib as u64,
+            Kdf::Scrypt { log_n, block_size, .. } => (128 * block_size as u64) << log_n >> 10,
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<()> {
+        let (iterations, parallelism) = match *self {
+            Kdf::Argon2 { iterations, parallelism, .. } => (iterations, parallelism),
+            Kdf::Scrypt { log_n, parallelism, .. } if log_n < 64 => (1, parallelism),
+            Kdf::Scrypt { log_n, .. } => return Err(Box::new(InvalidKdfError(format!("scrypt log N of {}", log_n)))),
+        };
+        if self.memory_kib() > MAX_MEMORY_KIB {
+            return Err(Box::new(InvalidKdfError(format!("{} KiB of memory (at most {})", self.memory_kib(), MAX_MEMORY_KIB))));
+        }
+        if !(1..=MAX_ITERATIONS).contains(&iterations) {
+            return Err(Box::new(InvalidKdfError(format!("{} iterations (1 to {})", iterations, MAX_ITERATIONS))));
+        }
+        if !(1..=MAX_PARALLELISM).contains(&parallelism) {
+            return Err(Box::new(InvalidKdfError(format!("parallelism of {} (1 to {})", parallelism, MAX_PARALLELISM))));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn to_bytes(self) -> [u8; KDF_LEN] {
+        let (id, params) = match self {
+            Kdf::Argon2 { memory_kib, iterations, parallelism } => (1, [memory_kib, iterations, parallelism]),
+            Kdf::Scrypt { log_n, block_size, parallelism } => (2, [log_n as u32, block_size, parallelism]),
+        };
+        let mut bytes = [id; KDF_LEN];
+        for (i, param) in params.iter().enumerate() {
+            bytes[1 + 4 * i..5 + 4 * i].copy_from_slice(&param.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Reads the KDF from a payload header, refusing unknown KDFs and
+    /// costs beyond the limits
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Kdf> {
+        let param = |i: usize| u32::from_be_bytes(bytes[1 + 4 * i..5 + 4 * i].try_into().unwrap());
+        let kdf = match bytes[0] {
+            1 => Kdf::Argon2 { memory_kib: param(0), iterations: param(1), parallelism: param(2) },
+            2 if param(0) < 64 => Kdf::Scrypt { log_n: param(0) as u8, block_size: param(1), parallelism: param(2) },
+            id => return Err(Box::new(InvalidKdfError(format!("unknown KDF {}", id)))),
+        };
+        kdf.validate()?;
+        Ok(kdf)
+    }
+
+    pub(crate) fn derive_key(&self, password: &[u8], salt: &[u8]) -> Result<[u8; KEY_LEN]> {
+        let mut key = [0u8; KEY_LEN];
+        match *self {
+            Kdf::Argon2 { memory_kib, iterations, parallelism } => {
+                let params = Params::new(memory_kib, iterations, parallelism, Some(KEY_LEN))
+                    .map_err(|e| InvalidKdfError(e.to_string()))?;
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(password, salt, &mut key)
+                    .map_err(|e| e.to_string())?;
+            },
+            Kdf::Scrypt { log_n, block_size, parallelism } => {
+                let params = scrypt::Params::new(log_n, block_size, parallelism, KEY_LEN)
+                    .map_err(|e| InvalidKdfError(e.to_string()))?;
+                scrypt::scrypt(password, salt, &params, &mut key).map_err(|e| e.to_string())?;
+            },
+        }
+        Ok(key)
+    }
+}
+
+impl fmt::Display for Kdf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kdf::Argon2 { memory_kib, iterations, parallelism } 
+            => write!(f, "argon2id (m={} KiB, t={}, p={})", memory_kib, iterations, parallelism),
+            Kdf::Scrypt { log_n, block_size, parallelism } 
+            => write!(f, "scrypt (N=2^{}, r={}, p={})", log_n, block_size, parallelism),
+        }
+    }
+}
+
+/// Returns the key material of a key file: the decoded bytes if the file
+/// holds only hex digits (surrounding whitespace is ignored), otherwise its
+/// raw contents
+pub fn parse_key_material(contents: &[u8]) -> Vec<u8> {
+    let trimmed = contents.trim_ascii();
+    match hex::decode(trimmed) {
+        Ok(key) if !key.is_empty() => key,
+        _ => contents.to_vec(),
+    }
+}
+
+/// Returns bytes from the operating system's secure random source
+pub fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+/// Checks if the payload starts with an envelope or a deniable envelope
+pub fn is_encrypted(payload: &[u8]) -> bool {
+    envelope::is_envelope(payload) || envelope::is_deniable(payload)
+}
+
+/// Returns the KDF that derives the key of an encrypted payload
+pub fn payload_kdf(payload: &[u8]) -> Result<Kdf> {
+    if !is_encrypted(payload) {
+        return Err(Box::new(NotEncryptedError));
+    }
+    Ok(envelope::outer_envelope(payload)?.kdf)
+}
+
+/// Returns the cipher named in the header of an encrypted payload
+pub fn payload_cipher(payload: &[u8]) -> Result<&'static dyn Cipher> {
+    if !is_encrypted(payload) {
+        return Err(Box::new(NotEncryptedError));
+    }
+    cipher::by_id(envelope::outer_envelope(payload)?.cipher)
+}
+
+/// Returns the salt and nonce of an encrypted payload, or of both slots of
+/// a deniable envelope. Other payloads have none.
+pub fn salts_and_nonces(payload: &[u8]) -> Vec<(&[u8], &[u8])> {
+    fn at(bytes: &[u8], start: usize) -> Option<(&[u8], &[u8])> {
+        bytes.get(start..start + SALT_LEN + NONCE_LEN).map(|pair| pair.split_at(SALT_LEN))
+    }
+
+    if envelope::is_envelope(payload) {
+        at(payload, envelope::MAGIC.len() + 2 + KDF_LEN).into_iter().collect()
+    } else if envelope::is_deniable(payload) {
+        envelope::deniable_slots(payload).into_iter().flatten().filter_map(|slot| at(slot, 0)).collect()
+    } else {
+        Vec::new()
+    }
+}
+
+/// Refuses a new payload that repeats the salt and nonce of an existing
+/// payload. Under the same password that would encrypt two messages with
+/// the same key and nonce, which breaks both ciphers.
+pub fn check_fresh_nonces<'a>(payload: &[u8], existing: impl IntoIterator<Item = &'a [u8]>) -> Result<()> {
+    let new = salts_and_nonces(payload);
+    for old in existing {
+        if salts_and_nonces(old).iter().any(|pair| new.contains(pair)) {
+            return Err(Box::new(NonceReuseError));
+        }
+    }
+    Ok(())
+}
+
+/// Encrypts the plaintext under the password with the default KDF
+pub fn encrypt(cipher: &dyn Cipher, password: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    encrypt_with(cipher, Kdf::default(), password, plaintext)
+}
+
+/// Encrypts the plaintext under a key derived from the password by the
+/// KDF, as an `Envelope`
+pub fn encrypt_with(cipher: &dyn Cipher, kdf: Kdf, password: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    Ok(Envelope::seal(cipher, kdf, password, plaintext)?.to_bytes())
+}
+
+/// Decrypts an envelope, or the slot of a deniable envelope the password
+/// opens, with the cipher and KDF named in its header. A wrong password
+/// and a modified payload both fail the authentication check.
+pub fn decrypt(password: &[u8], payload: &[u8]) -> Result<Vec<u8>> {
+    if envelope::is_deniable(payload) {
+        return envelope::open_deniable(password, payload);
+    }
+    if !is_encrypted(payload) {
+        return Err(Box::new(NotEncryptedError));
+    }
+    Envelope::try_from(payload)?.open(password)
+}
+
+/// Re-encrypts a payload under a new password with the cipher and KDF it
+/// was encrypted with. Only the slot of a deniable envelope the old
+/// password opens is re-encrypted.
+pub fn rekey(old_password: &[u8], new_password: &[u8], payload: &[u8]) -> Result<Vec<u8>> {
+    if envelope::is_deniable(payload) {
+        return envelope::rekey_deniable(old_password, new_password, payload);
+    }
+    let plaintext = decrypt(old_password, payload)?;
+    encrypt_with(payload_cipher(payload)?, payload_kdf(payload)?, new_password, &plaintext)
+}
+
+/// Header line every binary age file starts with
+const AGE_MAGIC: &[u8] = b"age-encryption.org/v1\n";
+
+/// Checks if the payload is an age file
+pub fn is_age_encrypted(payload: &[u8]) -> bool {
+    payload.starts_with(AGE_MAGIC)
+}
+
+/// Encrypts the plaintext to the age X25519 public keys (`age1...`), so any
+/// one of the matching identities can decrypt it
+pub fn encrypt_to_recipients(recipients: &[String], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let recipients = recipients.iter()
+        .map(|r| age::x25519::Recipient::from_str(r).map_err(|e| InvalidRecipientError(format!("{}: {}", r, e))))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    let encryptor = age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))?;
+    let mut payload = Vec::new();
+    let mut writer = encryptor.wrap_output(&mut payload)?;
+    writer.write_all(plaintext)?;
+    writer.finish()?;
+    Ok(payload)
+}
+
+/// Decrypts an age payload with the identities of an age identity file
+/// (as written by `age-keygen`)
+pub fn decrypt_with_identities(identity_file: &[u8], payload: &[u8]) -> Result<Vec<u8>> {
+    if !is_age_encrypted(payload) {
+        return Err(Box::new(NotEncryptedError));
+    }
+
+    let identities = age::IdentityFile::from_buffer(identity_file)?.into_identities()?;
+    let decryptor = age::Decryptor::new(payload)?;
+    let mut reader = decryptor.decrypt(identities.iter().map(|i| i.as_ref()))
+        .map_err(|_| DecryptionError)?;
+
+    let mut plaintext = Vec::new();
+    reader.read_to_end(&mut plaintext)?;
+    Ok(plaintext)
+}
+
+/// Marks the start of an authenticated payload
+pub const AUTH_MAGIC: &[u8; 4] = b"PMAC";
+
+const TAG_LEN: usize = 32;
+
+/// Checks if the payload is an authenticated envelope
+pub fn is_authenticated(payload: &[u8]) -> bool {
+    payload.len() >= AUTH_MAGIC.len() + TAG_LEN && payload.starts_with(AUTH_MAGIC)
+}
+
+/// Wraps the message in an envelope of the magic bytes, the message and
+/// an HMAC-SHA256 tag over the message
+pub fn authenticate(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut payload = AUTH_MAGIC.to_vec();
+    payload.extend(message);
+    payload.extend(hmac(key).chain_update(message).finalize().into_bytes());
+    payload
+}
+
+/// Returns the message of an authenticated envelope after checking its tag
+pub fn verify_authenticated<'a>(key: &[u8], payload: &'a [u8]) -> Result<&'a [u8]> {
+    let (message, tag) = split_authenticated(payload)?;
+    hmac(key).chain_update(message).verify_slice(tag).map_err(|_| AuthenticationError)?;
+    Ok(message)
+}
+
+/// Returns the message of an authenticated envelope without checking its
+/// tag
+pub fn unverified_message(payload: &[u8]) -> Result<&[u8]> {
+    Ok(split_authenticated(payload)?.0)
+}
+
+fn split_authenticated(payload: &[u8]) -> Result<(&[u8], &[u8])> {
+    if !is_authenticated(payload) {
+        return Err(Box::new(NotAuthenticatedError));
+    }
+    Ok(payload[AUTH_MAGIC.len()..].split_at(payload.len() - AUTH_MAGIC.len() - TAG_LEN))
+}
+
+/// Salt of the stealth key, fixed so decode derives the same key, and
+/// separating it from the keys derived for payloads
+const STEALTH_SALT: &[u8; SALT_LEN] = b"pngme stealth\0\0\0";
+
+/// Derives the chunk type a stealth payload is stored under from the
+/// password and the label the user names the payload by, so the file shows
+/// neither and only the password holder can tell which chunk to read. The
+/// type is ancillary, private and safe to copy like other payload chunks,
+/// and never takes pngme's own `pn` prefix.
+///
+/// The type still narrows the password search: about 19 bits of it can be
+/// checked against each guess without touching the payload. The HMAC is
+/// keyed with the password run through the KDF, so each of those checks
+/// costs as much as a guess at the payload itself.
+pub fn stealth_chunk_type(kdf: &Kdf, password: &[u8], label: &str) -> Result<ChunkType> {
+    let key = kdf.derive_key(password, STEALTH_SALT)?;
+    let mut counter: u32 = 0;
+    loop {
+        let digest = hmac(&key).chain_update(counter.to_be_bytes()).chain_update(label).finalize().into_bytes();
+        let letter = |i: usize, first: u8| first + digest[i] % 26;
+        let bytes = [letter(0, b'a'), letter(1, b'a'), letter(2, b'A'), letter(3, b'a')];
+        if &bytes[..2] != b"pn" {
+            return Ok(ChunkType::try_from(bytes).unwrap());
+        }
+        counter += 1;
+    }
+}
+
+fn hmac(key: &[u8]) -> Hmac<Sha256> {
+    // HMAC accepts keys of any length
+    <Hmac<Sha256> as Mac>::new_from_slice(key).unwrap()
+}
+
+/// Error specifying a payload that could not be decrypted with the password
+#[derive(Debug)]
+pub struct DecryptionError;
+
+impl fmt::Display for DecryptionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Decryption Failed! (wrong password or modified payload)")
+    }
+}
+
+impl error::Error for DecryptionError {}
+
+/// Error specifying a payload that repeats the salt and nonce of another
+#[derive(Debug)]
+pub struct NonceReuseError;
+
+impl fmt::Display for NonceReuseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Nonce Reused! (the payload repeats the salt and nonce of an existing payload)")
+    }
+}
+
+impl error::Error for NonceReuseError {}
+
+/// Error specifying a payload without the encrypted payload header
+#[derive(Debug)]
+pub struct NotEncryptedError;
+
+impl fmt::Display for NotEncryptedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Payload Is Not Encrypted!")
+    }
+}
+
+impl error::Error for NotEncryptedError {}
+
+/// Error specifying a payload header naming a cipher this version does not
+/// know
+#[derive(Debug)]
+pub struct UnknownCipherError(pub u8);
+
+impl fmt::Display for UnknownCipherError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown Cipher {} in Payload Header!", self.0)
+    }
+}
+
+impl error::Error for UnknownCipherError {}
+
+/// Error specifying a string that is not an age public key
+#[derive(Debug)]
+pub struct InvalidRecipientError(String);
+
+impl fmt::Display for InvalidRecipientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Recipient {}!", self.0)
+    }
+}
+
+impl error::Error for InvalidRecipientError {}
+
+/// Error specifying an authenticated payload whose tag does not match
+#[derive(Debug)]
+pub struct AuthenticationError;
+
+impl fmt::Display for AuthenticationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Authentication Failed! (wrong key or modified payload)")
+    }
+}
+
+impl error::Error for AuthenticationError {}
+
+/// Error specifying a payload without an authentication tag
+#[derive(Debug)]
+pub struct NotAuthenticatedError;
+
+impl fmt::Display for NotAuthenticatedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Payload Is Not Authenticated!")
+    }
+}
+
+impl error::Error for NotAuthenticatedError {}
+
+/// Error specifying KDF parameters that are unknown or out of bounds
+#[derive(Debug)]
+pub struct InvalidKdfError(String);
+
+impl fmt::Display for InvalidKdfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid KDF Parameters! ({})", self.0)
+    }
+}
+
+impl error::Error for InvalidKdfError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use age::secrecy::ExposeSecret;
+    #[cfg(feature = "aes-gcm")]
+    use crate::cipher::Aes256Gcm;
+    #[cfg(feature = "chacha20poly1305")]
+    use crate::cipher::ChaCha20Poly1305;
+
+    #[cfg(feature = "chacha20poly1305")]
+    #[test]
+    fn test_round_trip() {
+        let payload = encrypt(&ChaCha20Poly1305, b"hunter2", b"This is where your secret message will be!").unwrap();
+        assert!(is_encrypted(&payload));
+        assert_eq!(decrypt(b"hunter2", &payload).unwrap(), b"This is where your secret message will be!");
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_aes_256_gcm() {
+        let payload = encrypt(&Aes256Gcm, b"hunter2", b"message").unwrap();
+        assert_eq!(payload_cipher(&payload).unwrap().name(), "aes-256-gcm");
+        assert_eq!(decrypt(b"hunter2", &payload).unwrap(), b"message");
+    }
+
+    #[cfg(feature = "chacha20poly1305")]
+    #[test]
+    fn test_unknown_cipher() {
+        let mut payload = encrypt(&ChaCha20Poly1305, b"hunter2", b"message").unwrap();
+        // the cipher id follows the magic bytes and the version
+        payload[envelope::MAGIC.len() + 1] = 0xff;
+        assert!(payload_cipher(&payload).is_err());
+        assert!(decrypt(b"hunter2", &payload).is_err());
+    }
+
+    #[cfg(feature = "chacha20poly1305")]
+    #[test]
+    fn test_random_salt_and_nonce() {
+        assert_ne!(encrypt(&ChaCha20Poly1305, b"hunter2", b"message").unwrap(), encrypt(&ChaCha20Poly1305, b"hunter2", b"message").unwrap());
+    }
+
+    #[cfg(feature = "chacha20poly1305")]
+    #[test]
+    fn test_nonce_reuse() {
+        let payload = encrypt(&ChaCha20Poly1305, b"hunter2", b"message").unwrap();
+        let other = encrypt(&ChaCha20Poly1305, b"hunter2", b"message").unwrap();
+        assert_eq!(salts_and_nonces(&payload), vec![(&payload[6 + KDF_LEN..22 + KDF_LEN], &payload[22 + KDF_LEN..34 + KDF_LEN])]);
+        assert!(check_fresh_nonces(&payload, [&other[..], b"plain message"]).is_ok());
+        assert!(check_fresh_nonces(&payload, [&other[..], &payload[..]]).is_err());
+
+        // the slots of a deniable envelope count too
+        let kdf = Kdf::Argon2 { memory_kib: 64, iterations: 1, parallelism: 1 };
+        let deniable = envelope::seal_deniable(&ChaCha20Poly1305, kdf, b"real", b"message", Some((b"decoy", b"other"))).unwrap();
+        assert_eq!(salts_and_nonces(&deniable).len(), 2);
+        assert!(check_fresh_nonces(&deniable, [&deniable[..]]).is_err());
+        assert!(salts_and_nonces(b"plain message").is_empty());
+    }
+
+    #[cfg(feature = "chacha20poly1305")]
+    #[test]
+    fn test_wrong_password() {
+        let payload = encrypt(&ChaCha20Poly1305, b"hunter2", b"message").unwrap();
+        assert!(decrypt(b"hunter3", &payload).is_err());
+    }
+
+    #[cfg(feature = "chacha20poly1305")]
+    #[test]
+    fn test_modified_payload() {
+        let mut payload = encrypt(&ChaCha20Poly1305, b"hunter2", b"message").unwrap();
+        *payload.last_mut().unwrap() ^= 1;
+        assert!(decrypt(b"hunter2", &payload).is_err());
+    }
+
+    #[cfg(all(feature = "aes-gcm", feature = "chacha20poly1305"))]
+    #[test]
+    fn test_rekey() {
+        let kdf = Kdf::Argon2 { memory_kib: 64, iterations: 1, parallelism: 1 };
+        let payload = encrypt_with(&Aes256Gcm, kdf, b"hunter2", b"message").unwrap();
+        let rekeyed = rekey(b"hunter2", b"correct horse", &payload).unwrap();
+        assert_eq!(decrypt(b"correct horse", &rekeyed).unwrap(), b"message");
+        assert!(decrypt(b"hunter2", &rekeyed).is_err());
+        assert_eq!(payload_cipher(&rekeyed).unwrap().name(), "aes-256-gcm");
+        assert_eq!(payload_kdf(&rekeyed).unwrap(), kdf);
+
+        assert!(rekey(b"hunter3", b"correct horse", &payload).is_err());
+        let deniable = envelope::seal_deniable(&ChaCha20Poly1305, kdf, b"real", b"message", Some((b"decoy", b"other"))).unwrap();
+        let rekeyed = rekey(b"real", b"correct horse", &deniable).unwrap();
+        assert_eq!(decrypt(b"correct horse", &rekeyed).unwrap(), b"message");
+        assert_eq!(decrypt(b"decoy", &rekeyed).unwrap(), b"other");
+        assert_eq!(payload_kdf(&rekeyed).unwrap(), kdf);
+    }
+
+    #[test]
+    fn test_not_encrypted() {
+        assert!(!is_encrypted(b"plain message"));
+        assert!(decrypt(b"hunter2", b"plain message").is_err());
+    }
+
+    #[cfg(feature = "chacha20poly1305")]
+    #[test]
+    fn test_scrypt() {
+        let kdf = Kdf::new(KdfKind::Scrypt, Some(1024), None, None).unwrap();
+        assert_eq!(kdf, Kdf::Scrypt { log_n: 10, block_size: 8, parallelism: 1 });
+
+        let payload = encrypt_with(&ChaCha20Poly1305, kdf, b"hunter2", b"message").unwrap();
+        assert_eq!(payload_kdf(&payload).unwrap(), kdf);
+        assert_eq!(decrypt(b"hunter2", &payload).unwrap(), b"message");
+        assert!(Kdf::new(KdfKind::Scrypt, None, Some(3), None).is_err());
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_argon2_parameters() {
+        let kdf = Kdf::new(KdfKind::Argon2, Some(256), Some(1), None).unwrap();
+        assert_eq!(kdf, Kdf::Argon2 { memory_kib: 256, iterations: 1, parallelism: 4 });
+
+        let payload = encrypt_with(&Aes256Gcm, kdf, b"hunter2", b"message").unwrap();
+        assert_eq!(payload_kdf(&payload).unwrap(), kdf);
+        assert_eq!(decrypt(b"hunter2", &payload).unwrap(), b"message");
+    }
+
+    #[cfg(feature = "chacha20poly1305")]
+    #[test]
+    fn test_kdf_limits() {
+        assert!(Kdf::new(KdfKind::Argon2, Some(u32::MAX), None, None).is_err());
+        assert!(Kdf::new(KdfKind::Argon2, None, Some(0), None).is_err());
+        assert!(Kdf::new(KdfKind::Scrypt, None, None, Some(1000)).is_err());
+
+        // a payload asking for more memory than allowed is refused up front
+        let kdf = Kdf::Argon2 { memory_kib: 64, iterations: 1, parallelism: 1 };
+        let mut payload = encrypt_with(&ChaCha20Poly1305, kdf, b"hunter2", b"message").unwrap();
+        // the memory follows the magic bytes, version, cipher id and KDF id
+        payload[envelope::MAGIC.len() + 3..envelope::MAGIC.len() + 7].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(payload_kdf(&payload).is_err());
+        assert!(decrypt(b"hunter2", &payload).is_err());
+    }
+
+    #[test]
+    fn test_age_round_trip() {
+        let identity = age::x25519::Identity::generate();
+        let other = age::x25519::Identity::generate();
+        let recipients = vec![identity.to_public().to_string(), other.to_public().to_string()];
+
+        let payload = encrypt_to_recipients(&recipients, b"message").unwrap();
+        assert!(is_age_encrypted(&payload));
+        assert!(!is_encrypted(&payload));
+
+        let identity_file = format!("# test key\n{}\n", identity.to_string().expose_secret());
+        assert_eq!(decrypt_with_identities(identity_file.as_bytes(), &payload).unwrap(), b"message");
+    }
+
+    #[test]
+    fn test_age_wrong_identity() {
+        let recipient = age::x25519::Identity::generate().to_public().to_string();
+        let payload = encrypt_to_recipients(&[recipient], b"message").unwrap();
+
+        let identity_file = age::x25519::Identity::generate().to_string();
+        assert!(decrypt_with_identities(identity_file.expose_secret().as_bytes(), &payload).is_err());
+    }
+
+    #[test]
+    fn test_invalid_recipient() {
+        assert!(encrypt_to_recipients(&[String::from("age1notakey")], b"message").is_err());
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let payload = authenticate(b"key", b"message");
+        assert!(is_authenticated(&payload));
+        assert_eq!(verify_authenticated(b"key", &payload).unwrap(), b"message");
+        assert_eq!(unverified_message(&payload).unwrap(), b"message");
+        assert!(verify_authenticated(b"other key", &payload).is_err());
+    }
+
+    #[test]
+    fn test_authenticate_modified() {
+        let mut payload = authenticate(b"key", b"message");
+        payload[AUTH_MAGIC.len()] ^= 1;
+        assert!(verify_authenticated(b"key", &payload).is_err());
+        assert!(verify_authenticated(b"key", b"message").is_err());
+    }
+
+    #[test]
+    fn test_stealth_chunk_type() {
+        let kdf = Kdf::Argon2 { memory_kib: 64, iterations: 1, parallelism: 1 };
+        let stealth = |kdf: &Kdf, password: &[u8], label: &str| stealth_chunk_type(kdf, password, label).unwrap();
+        let chunk_type = stealth(&kdf, b"hunter2", "ruSt");
+        assert_eq!(chunk_type, stealth(&kdf, b"hunter2", "ruSt"));
+        assert!(chunk_type.is_valid() && !chunk_type.is_critical() && !chunk_type.is_public() && chunk_type.is_safe_to_copy());
+        assert!(!chunk_type.as_str().starts_with("pn"));
+
+        assert_ne!(chunk_type, stealth(&kdf, b"hunter3", "ruSt"));
+        assert_ne!(chunk_type, stealth(&kdf, b"hunter2", "ruSu"));
+        // the key goes through the KDF, not just the password
+        let other = Kdf::Argon2 { memory_kib: 64, iterations: 2, parallelism: 1 };
+        let labels = ["ruSt", "ruSu", "ruSv", "ruSw"];
+        assert!(labels.iter().any(|label| stealth(&kdf, b"hunter2", label) != stealth(&other, b"hunter2", label)));
+    }
+
+    #[test]
+    fn test_parse_key_material() {
+        assert_eq!(parse_key_material(b"00ff10\n"), vec![0x00, 0xff, 0x10]);
+        assert_eq!(parse_key_material(b"not hex"), b"not hex".to_vec());
+        assert_eq!(parse_key_material(&[0x00, 0xff]), vec![0x00, 0xff]);
+        assert_eq!(parse_key_material(b""), Vec::<u8>::new());
+    }
+}
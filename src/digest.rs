@@ -0,0 +1,134 @@
+//!
+//! SHA-256 and BLAKE3 digests of whole files and of chunk data, for
+//! integrity checks in pipelines
+//!
+use std::io::{self, Read};
+use schemars::JsonSchema;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use crate::png::Png;
+
+/// Size of the buffer files are streamed through
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// SHA-256 and BLAKE3 digests of some bytes, in hex
+#[derive(Debug, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct Digests {
+    pub sha256: String,
+    pub blake3: String,
+}
+
+impl Digests {
+    pub fn of(bytes: &[u8]) -> Digests {
+        Digests { sha256: hex::encode(Sha256::digest(bytes)), blake3: blake3::hash(bytes).to_hex().to_string() }
+    }
+
+    /// Digests everything the reader yields in constant memory, returning
+    /// the digests and the number of bytes read
+    pub fn of_reader<R: Read>(mut reader: R) -> io::Result<(Digests, u64)> {
+        let mut sha256 = Sha256::new();
+        let mut blake3 = blake3::Hasher::new();
+        let mut buffer = vec![0u8; BUFFER_SIZE];
+        let mut length = 0;
+        loop {
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            sha256.update(&buffer[..read]);
+            blake3.update(&buffer[..read]);
+            length += read as u64;
+        }
+        Ok((Digests { sha256: hex::encode(sha256.finalize()), blake3: blake3.finalize().to_hex().to_string() }, length))
+    }
+}
+
+/// Digests of a whole file
+#[derive(Debug, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct FileDigests {
+    pub file: String,
+    pub length: u64,
+    #[serde(flatten)]
+    pub digests: Digests,
+}
+
+/// Digests of the data of a chunk
+#[derive(Debug, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct ChunkDigests {
+    /// Position of the chunk in the file
+    pub index: usize,
+    pub chunk_type: String,
+    pub length: usize,
+    #[serde(flatten)]
+    pub digests: Digests,
+}
+
+/// Output of `digest --json`: the digests of the whole file, or of the
+/// data of chunks
+#[derive(Debug, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(untagged)]
+pub enum DigestOutput {
+    File(FileDigests),
+    Chunks(Vec<ChunkDigests>),
+}
+
+/// Digests the data of every chunk in the PNG, or only of the chunks of the
+/// type, in file order
+pub fn chunk_digests(png: &Png, chunk_type: Option<&str>) -> Vec<ChunkDigests> {
+    png.chunks().iter().enumerate()
+        .filter(|(_, chunk)| chunk_type.is_none_or(|chunk_type| chunk.chunk_type().as_str() == chunk_type))
+        .map(|(index, chunk)| ChunkDigests {
+            index,
+            chunk_type: chunk.chunk_type().as_str(),
+            length: chunk.data().len(),
+            digests: Digests::of(chunk.data()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::testing::chunk;
+
+    #[test]
+    fn test_digests() {
+        let digests = Digests::of(b"abc");
+        assert_eq!(digests.sha256, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(digests.blake3, "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85");
+
+        let long = vec![7u8; 3 * BUFFER_SIZE + 5];
+        assert_eq!(Digests::of_reader(&long[..]).unwrap(), (Digests::of(&long), long.len() as u64));
+    }
+
+    #[test]
+    fn test_chunk_digests() {
+        let png = Png::from_chunks(vec![chunk("IHDR", b"header"), chunk("ruSt", b"abc"), chunk("IEND", b"")]);
+
+        assert_eq!(chunk_digests(&png, None).len(), 3);
+        let digests = chunk_digests(&png, Some("ruSt"));
+        assert_eq!(digests, vec![ChunkDigests {
+            index: 1,
+            chunk_type: String::from("ruSt"),
+            length: 3,
+            digests: Digests::of(b"abc"),
+        }]);
+        assert!(chunk_digests(&png, Some("teSt")).is_empty());
+    }
+
+    #[test]
+    fn test_output_schema() {
+        let schema = serde_json::to_value(schemars::schema_for!(DigestOutput)).unwrap();
+        let output = DigestOutput::File(FileDigests { file: String::from("dice.png"), length: 3, digests: Digests::of(b"abc") });
+        let output = serde_json::to_value(output).unwrap();
+
+        let mut required: Vec<&str> = schema["$defs"]["FileDigests"]["required"].as_array().unwrap()
+            .iter().map(|field| field.as_str().unwrap()).collect();
+        let mut fields: Vec<&str> = output.as_object().unwrap().keys().map(String::as_str).collect();
+        required.sort();
+        fields.sort();
+        assert_eq!(required, fields);
+    }
+}
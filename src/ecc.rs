@@ -0,0 +1,411 @@
+//!
+//! Reed-Solomon error correction of payloads over GF(2^8). The payload is
+//! cut into blocks of equal length, each followed by parity bytes, and
+//! the blocks are interleaved so a run of damaged bytes is spread over
+//! all of them. Every block corrects up to half as many damaged bytes as
+//! it has parity bytes.
+//!
+//! | Field                                   | Size       |
+//! |-----------------------------------------|------------|
+//! | magic `PMRS`                            | 4          |
+//! | parity bytes per block                  | 1          |
+//! | payload length                          | 4          |
+//! | the two fields above, twice more        | 10         |
+//! | interleaved blocks                      | variable   |
+//!
+use std::{error, fmt, sync::OnceLock};
+use crate::Result;
+
+pub const MAGIC: &[u8; 4] = b"PMRS";
+
+/// Length of one copy of the parity and length fields
+const FIELDS_LEN: usize = 5;
+
+/// Largest number of bytes in a block, parity included
+const MAX_BLOCK_LEN: usize = 255;
+
+/// Exponent and logarithm tables of GF(2^8) with the polynomial 0x11d
+struct Field {
+    exp: [u8; 512],
+    log: [u8; 256],
+}
+
+fn field() -> &'static Field {
+    static FIELD: OnceLock<Field> = OnceLock::new();
+    FIELD.get_or_init(|| {
+        let mut field = Field { exp: [0; 512], log: [0; 256] };
+        let mut x: u16 = 1;
+        for i in 0..255 {
+            field.exp[i] = x as u8;
+            field.log[x as usize] = i as u8;
+            x <<= 1;
+            if x & 0x100 != 0 {
+                x ^= 0x11d;
+            }
+        }
+        for i in 255..512 {
+            field.exp[i] = field.exp[i - 255];
+        }
+        field
+    })
+}
+
+fn mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    let f = field();
+    f.exp[f.log[a as usize] as usize + f.log[b as usize] as usize]
+}
+
+fn div(a: u8, b: u8) -> u8 {
+    if a == 0 {
+        return 0;
+    }
+    let f = field();
+    f.exp[(f.log[a as usize] as usize + 255 - f.log[b as usize] as usize) % 255]
+}
+
+fn pow(x: u8, power: usize) -> u8 {
+    let f = field();
+    f.exp[(f.log[x as usize] as usize * power) % 255]
+}
+
+fn inverse(x: u8) -> u8 {
+    let f = field();
+    f.exp[255 - f.log[x as usize] as usize]
+}
+
+// polynomials are stored with the highest degree coefficient first
+
+fn poly_scale(p: &[u8], x: u8) -> Vec<u8> {
+    p.iter().map(|&c| mul(c, x)).collect()
+}
+
+fn poly_add(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let len = p.len().max(q.len());
+    let mut r = vec![0; len];
+    for (i, &c) in p.iter().enumerate() {
+        r[i + len - p.len()] = c;
+    }
+    for (i, &c) in q.iter().enumerate() {
+        r[i + len - q.len()] ^= c;
+    }
+    r
+}
+
+fn poly_mul(p: &[u8], q: &[u8]) -> Vec<u8> {
+    let mut r = vec![0; p.len() + q.len() - 1];
+    for (j, &b) in q.iter().enumerate() {
+        for (i, &a) in p.iter().enumerate() {
+            r[i + j] ^= mul(a, b);
+        }
+    }
+    r
+}
+
+fn poly_eval(p: &[u8], x: u8) -> u8 {
+    p[1..].iter().fold(p[0], |y, &c| mul(y, x) ^ c)
+}
+
+/// Returns the generator polynomial of a code with `nsym` parity bytes
+fn generator(nsym: usize) -> Vec<u8> {
+    (0..nsym).fold(vec![1], |g, i| poly_mul(&g, &[1, pow(2, i)]))
+}
+
+/// Returns the block followed by its parity bytes
+fn encode_block(data: &[u8], generator: &[u8]) -> Vec<u8> {
+    let mut out = data.to_vec();
+    out.resize(data.len() + generator.len() - 1, 0);
+    for i in 0..data.len() {
+        let coef = out[i];
+        if coef != 0 {
+            for (j, &g) in generator.iter().enumerate().skip(1) {
+                out[i + j] ^= mul(g, coef);
+            }
+        }
+    }
+    out[..data.len()].copy_from_slice(data);
+    out
+}
+
+/// Syndromes of the block, preceded by a zero
+fn syndromes(block: &[u8], nsym: usize) -> Vec<u8> {
+    std::iter::once(0).chain((0..nsym).map(|i| poly_eval(block, pow(2, i)))).collect()
+}
+
+/// Finds the error locator polynomial with the Berlekamp-Massey algorithm
+fn error_locator(synd: &[u8], nsym: usize) -> Result<Vec<u8>> {
+    let mut err_loc = vec![1];
+    let mut old_loc = vec![1];
+    for i in 0..nsym {
+        let k = i + 1;
+        let mut delta = synd[k];
+        for j in (1..err_loc.len()).filter(|&j| j <= k) {
+            delta ^= mul(err_loc[err_loc.len() - 1 - j], synd[k - j]);
+        }
+        old_loc.push(0);
+        if delta != 0 {
+            if old_loc.len() > err_loc.len() {
+                let new_loc = poly_scale(&old_loc, delta);
+                old_loc = poly_scale(&err_loc, inverse(delta));
+                err_loc = new_loc;
+            }
+            err_loc = poly_add(&err_loc, &poly_scale(&old_loc, delta));
+        }
+    }
+
+    let start = err_loc.iter().position(|&c| c != 0).unwrap_or(err_loc.len() - 1);
+    let err_loc = err_loc[start..].to_vec();
+    if (err_loc.len() - 1) * 2 > nsym {
+        return Err(Box::new(UncorrectableError));
+    }
+    Ok(err_loc)
+}
+
+/// Finds the positions of the errors as the roots of the reversed
+/// locator (Chien search)
+fn error_positions(reversed_loc: &[u8], len: usize) -> Result<Vec<usize>> {
+    let positions: Vec<usize> = (0..len)
+        .filter(|&i| poly_eval(reversed_loc, pow(2, i)) == 0)
+        .map(|i| len - 1 - i)
+        .collect();
+    if positions.len() != reversed_loc.len() - 1 {
+        return Err(Box::new(UncorrectableError));
+    }
+    Ok(positions)
+}
+
+/// Repairs the errors at the positions with the Forney algorithm
+fn correct_errata(block: &mut [u8], synd: &[u8], positions: &[usize]) -> Result<()> {
+    let coef_pos: Vec<usize> = positions.iter().map(|&p| block.len() - 1 - p).collect();
+    let err_loc = coef_pos.iter().fold(vec![1], |loc, &i| poly_mul(&loc, &poly_add(&[1], &[pow(2, i), 0])));
+
+    // the evaluator is the product's remainder modulo x^(errors + 1)
+    let reversed_synd: Vec<u8> = synd.iter().rev().copied().collect();
+    let product = poly_mul(&reversed_synd, &err_loc);
+    let evaluator: Vec<u8> = product[product.len().saturating_sub(err_loc.len())..].to_vec();
+
+    let x: Vec<u8> = coef_pos.iter().map(|&i| pow(2, i)).collect();
+    for (i, &xi) in x.iter().enumerate() {
+        let xi_inv = inverse(xi);
+        let err_loc_prime = x.iter().enumerate()
+            .filter(|&(j, _)| j != i)
+            .fold(1, |prime, (_, &xj)| mul(prime, 1 ^ mul(xi_inv, xj)));
+        if err_loc_prime == 0 {
+            return Err(Box::new(UncorrectableError));
+        }
+        let y = mul(xi, poly_eval(&evaluator, xi_inv));
+        block[positions[i]] ^= div(y, err_loc_prime);
+    }
+    Ok(())
+}
+
+/// Repairs the block in place, returning the number of bytes corrected
+fn correct_block(block: &mut [u8], nsym: usize) -> Result<usize> {
+    let synd = syndromes(block, nsym);
+    if synd.iter().all(|&s| s == 0) {
+        return Ok(0);
+    }
+
+    let err_loc = error_locator(&synd, nsym)?;
+    let reversed: Vec<u8> = err_loc.iter().rev().copied().collect();
+    let positions = error_positions(&reversed, block.len())?;
+    correct_errata(block, &synd, &positions)?;
+
+    if syndromes(block, nsym).iter().any(|&s| s != 0) {
+        return Err(Box::new(UncorrectableError));
+    }
+    Ok(positions.len())
+}
+
+/// Number of parity bytes per block for a ratio of parity to data bytes
+pub fn parity_len(ratio: f64) -> Result<usize> {
+    if !(ratio > 0.0 && ratio <= 1.0) {
+        return Err(Box::new(InvalidRatioError(ratio)));
+    }
+    Ok(((MAX_BLOCK_LEN as f64 * ratio / (1.0 + ratio)).round() as usize).clamp(2, MAX_BLOCK_LEN / 2 + 1))
+}
+
+/// Number of blocks and data bytes per block of a payload
+fn layout(len: usize, nsym: usize) -> (usize, usize) {
+    let blocks = len.div_ceil(MAX_BLOCK_LEN - nsym).max(1);
+    (blocks, len.div_ceil(blocks).max(1))
+}
+
+/// Length of the payload `protect` makes of one `len` bytes long
+pub fn protected_len(len: usize, ratio: f64) -> Result<usize> {
+    let nsym = parity_len(ratio)?;
+    let (blocks, data_len) = layout(len, nsym);
+    Ok(MAGIC.len() + 3 * FIELDS_LEN + blocks * (data_len + nsym))
+}
+
+/// Checks if the payload starts with the magic bytes of a protected one
+pub fn is_protected(payload: &[u8]) -> bool {
+    payload.starts_with(MAGIC)
+}
+
+/// Adds parity data to the payload, `ratio` parity bytes for each byte
+/// of it (at most 1)
+pub fn protect(payload: &[u8], ratio: f64) -> Result<Vec<u8>> {
+    Ok(protect_with(payload, parity_len(ratio)?))
+}
+
+/// Adds parity data to the payload with as many parity bytes per block as
+/// the protected payload has, so it can replace it at the same ratio
+pub fn reprotect(protected: &[u8], payload: &[u8]) -> Result<Vec<u8>> {
+    Ok(protect_with(payload, fields(protected)?.0))
+}
+
+/// Adds parity data to the payload, `nsym` parity bytes per block
+fn protect_with(payload: &[u8], nsym: usize) -> Vec<u8> {
+    let (blocks, data_len) = layout(payload.len(), nsym);
+    let generator = generator(nsym);
+
+    let mut padded = payload.to_vec();
+    padded.resize(blocks * data_len, 0);
+    let codewords: Vec<Vec<u8>> = padded.chunks(data_len)
+        .map(|data| encode_block(data, &generator))
+        .collect();
+
+    let mut bytes = MAGIC.to_vec();
+    for _ in 0..3 {
+        bytes.push(nsym as u8);
+        bytes.extend((payload.len() as u32).to_be_bytes());
+    }
+    for i in 0..data_len + nsym {
+        bytes.extend(codewords.iter().map(|codeword| codeword[i]));
+    }
+    bytes
+}
+
+/// Returns the parity bytes per block and the payload length from the
+/// header of a protected payload
+fn fields(payload: &[u8]) -> Result<(usize, usize)> {
+    let header = payload.get(MAGIC.len()..MAGIC.len() + 3 * FIELDS_LEN).ok_or(InvalidProtectedPayloadError)?;
+    if !is_protected(payload) {
+        return Err(Box::new(InvalidProtectedPayloadError));
+    }
+
+    // the fields are stored three times, so each byte is taken by majority
+    let fields: Vec<u8> = (0..FIELDS_LEN)
+        .map(|i| {
+            let (a, b, c) = (header[i], header[FIELDS_LEN + i], header[2 * FIELDS_LEN + i]);
+            if a == b || a == c { a } else { b }
+        })
+        .collect();
+    let nsym = fields[0] as usize;
+    let len = u32::from_be_bytes(fields[1..].try_into()?) as usize;
+    if !(2..MAX_BLOCK_LEN).contains(&nsym) {
+        return Err(Box::new(InvalidProtectedPayloadError));
+    }
+    Ok((nsym, len))
+}
+
+/// Repairs a payload made by `protect`, returning it with the number of
+/// bytes that were corrected
+pub fn recover(payload: &[u8]) -> Result<(Vec<u8>, usize)> {
+    let (nsym, len) = fields(payload)?;
+    let (blocks, data_len) = layout(len, nsym);
+    let body = &payload[MAGIC.len() + 3 * FIELDS_LEN..];
+    if body.len() != blocks * (data_len + nsym) {
+        return Err(Box::new(InvalidProtectedPayloadError));
+    }
+
+    let mut corrected = 0;
+    let mut data = Vec::with_capacity(blocks * data_len);
+    for j in 0..blocks {
+        let mut block: Vec<u8> = body.iter().skip(j).step_by(blocks).copied().collect();
+        corrected += correct_block(&mut block, nsym)?;
+        data.extend(&block[..data_len]);
+    }
+    data.truncate(len);
+    Ok((data, corrected))
+}
+
+/// Error specifying a parity ratio outside of (0, 1]
+#[derive(Debug)]
+pub struct InvalidRatioError(f64);
+
+impl fmt::Display for InvalidRatioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Error Correction Ratio! ({}, expected more than 0 and at most 1)", self.0)
+    }
+}
+
+impl error::Error for InvalidRatioError {}
+
+/// Error specifying a protected payload that could not be parsed
+#[derive(Debug)]
+pub struct InvalidProtectedPayloadError;
+
+impl fmt::Display for InvalidProtectedPayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Error Corrected Payload!")
+    }
+}
+
+impl error::Error for InvalidProtectedPayloadError {}
+
+/// Error specifying a payload too damaged to repair
+#[derive(Debug)]
+pub struct UncorrectableError;
+
+impl fmt::Display for UncorrectableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Too Many Errors to Correct!")
+    }
+}
+
+impl error::Error for UncorrectableError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_payload() -> Vec<u8> {
+        (0..1000u32).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for payload in [Vec::new(), b"x".to_vec(), testing_payload()] {
+            let protected = protect(&payload, 0.25).unwrap();
+            assert!(is_protected(&protected));
+            assert_eq!(protected_len(payload.len(), 0.25).unwrap(), protected.len());
+            assert_eq!(recover(&protected).unwrap(), (payload, 0));
+        }
+
+        let protected = protect(&testing_payload(), 0.5).unwrap();
+        let replaced: Vec<u8> = testing_payload().iter().map(|byte| !byte).collect();
+        let reprotected = reprotect(&protected, &replaced).unwrap();
+        assert_eq!(reprotected.len(), protected.len());
+        assert_eq!(recover(&reprotected).unwrap(), (replaced, 0));
+    }
+
+    #[test]
+    fn test_corrects_burst() {
+        let payload = testing_payload();
+        let mut protected = protect(&payload, 0.25).unwrap();
+        let blocks = layout(payload.len(), parity_len(0.25).unwrap()).0;
+
+        // a run of damaged bytes, spread over the blocks by interleaving
+        for byte in &mut protected[100..100 + 20 * blocks] {
+            *byte ^= 0x5a;
+        }
+        protected[MAGIC.len()] ^= 0xff;
+        assert_eq!(recover(&protected).unwrap(), (payload, 20 * blocks));
+    }
+
+    #[test]
+    fn test_too_damaged() {
+        let mut protected = protect(&testing_payload(), 0.1).unwrap();
+        let end = protected.len();
+        for byte in &mut protected[end / 2..end] {
+            *byte = !*byte;
+        }
+        assert!(recover(&protected).is_err());
+        assert!(protect(b"x", 0.0).is_err());
+        assert!(protect(b"x", 1.5).is_err());
+    }
+}
@@ -0,0 +1,617 @@
+//!
+//! Versioned envelope of password-encrypted payloads, and deniable
+//! envelopes holding two of them.
+//!
+//! An envelope (version 1) is laid out as
+//!
+//! | bytes | field                                             |
+//! |-------|---------------------------------------------------|
+//! | 4     | magic `PMEV`                                      |
+//! | 1     | version                                           |
+//! | 1     | cipher id (see the cipher module)                 |
+//! | 13    | KDF id (1 Argon2id, 2 scrypt) and three u32 costs |
+//! | 16    | salt                                              |
+//! | 12    | nonce                                             |
+//! | n     | ciphertext                                        |
+//! | 16    | tag                                               |
+//!
+//! The tag authenticates the ciphertext together with every header byte
+//! before it, so the header cannot be changed either. Readers refuse
+//! versions they do not know; later versions may change everything after
+//! the version byte.
+//!
+//! A deniable envelope holds two messages under two passwords, each
+//! password opening only its own message. Both slots are padded to the
+//! same length and placed in random order, and an unused slot is random
+//! bytes, so nothing shows how many messages it holds or which one a
+//! password belongs to. Encode writes every password-encrypted payload
+//! as one, with or without a decoy; plain envelopes are still read.
+//!
+//! Every layer pngme wraps payloads in starts with magic bytes of its own,
+//! listed by `Layer`, and most follow them with a version byte. Readers
+//! negotiate that version through `negotiate`, so a payload written by a
+//! later release is refused instead of misread. A payload starting with
+//! no known magic bytes is a plain message, as every message was before
+//! the layers existed, and decodes as it is. A plain message that starts
+//! with the magic bytes of a layer by chance is stored behind `PMPL`
+//! (see `escape`), and one written before that existed is read as it is
+//! when it is text that does not open as the layer (see `is_legacy_plain`).
+//!
+use std::{fmt, error, ops::RangeInclusive, str};
+#[cfg(feature = "fido")]
+use crate::auth;
+use crate::{checksum, cipher::{self, Cipher}, compress, crypto::{self, Kdf, CIPHER_TAG_LEN, KDF_LEN, NONCE_LEN, SALT_LEN}, ecc, fileinfo, 
+    pack, patch, png, provenance, seal, whiten, Result};
+
+/// Magic bytes an envelope starts with
+pub const MAGIC: &[u8; 4] = b"PMEV";
+/// Magic bytes in front of a plain message that starts with the magic
+/// bytes of a layer
+pub const PLAIN_MAGIC: &[u8; 4] = b"PMPL";
+/// Version written by this release
+pub const VERSION: u8 = 1;
+
+/// Layer pngme wraps a payload (or a file it writes) in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    /// Password-encrypted envelope
+    Envelope,
+    /// Deniable envelope holding two messages
+    Deniable,
+    /// Message with an HMAC tag (--hmac-key)
+    Authenticated,
+    /// Message XORed with a keystream (--obfuscate)
+    Whitened,
+    /// Compressed message (--compress)
+    Compressed,
+    /// Message with its SHA-256 (--checksum)
+    Checksum,
+    /// Payload with Reed-Solomon parity (--ecc)
+    ErrorCorrection,
+    /// Piece of a payload split across chunks (--shard-size)
+    Shard,
+    /// File with its name and properties (--file)
+    FileInfo,
+    /// Message with its author, note and encoding time (--author, --note)
+    Provenance,
+    /// Detached seal signature file
+    DetachedSeal,
+    /// Pack of files
+    Pack,
+    /// Binary patch between two PNG files
+    Patch,
+    /// Plain message escaped so it is not taken for another layer
+    Plain,
+    /// Payload whose key is wrapped with a FIDO2 security key
+    #[cfg(feature = "fido")]
+    Fido,
+}
+
+impl Layer {
+    /// Every layer, in no particular order
+    pub const ALL: &[Layer] = &[
+        Layer::Envelope, Layer::Deniable, Layer::Authenticated, Layer::Whitened,
+        Layer::Compressed, Layer::Checksum, Layer::ErrorCorrection, Layer::Shard, Layer::FileInfo, Layer::Provenance,
+        Layer::DetachedSeal, Layer::Pack, Layer::Patch, Layer::Plain,
+        #[cfg(feature = "fido")]
+        Layer::Fido,
+    ];
+
+    /// Magic bytes the layer starts with
+    pub fn magic(&self) -> &'static [u8; 4] {
+        match self {
+            Layer::Envelope => MAGIC,
+            Layer::Deniable => DENIABLE_MAGIC,
+            Layer::Authenticated => crypto::AUTH_MAGIC,
+            Layer::Whitened => whiten::MAGIC,
+            Layer::Compressed => compress::MAGIC,
+            Layer::Checksum => checksum::MAGIC,
+            Layer::ErrorCorrection => ecc::MAGIC,
+            Layer::Shard => png::SHARD_MAGIC,
+            Layer::FileInfo => fileinfo::MAGIC,
+            Layer::Provenance => provenance::MAGIC,
+            Layer::DetachedSeal => seal::DETACHED_MAGIC,
+            Layer::Pack => pack::MAGIC,
+            Layer::Patch => patch::MAGIC,
+            Layer::Plain => PLAIN_MAGIC,
+            #[cfg(feature = "fido")]
+            Layer::Fido => auth::MAGIC,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layer::Envelope => "envelope",
+            Layer::Deniable => "deniable envelope",
+            Layer::Authenticated => "authenticated payload",
+            Layer::Whitened => "obfuscated payload",
+            Layer::Compressed => "compressed payload",
+            Layer::Checksum => "checksummed payload",
+            Layer::ErrorCorrection => "error-corrected payload",
+            Layer::Shard => "shard",
+            Layer::FileInfo => "file header",
+            Layer::Provenance => "provenance record",
+            Layer::DetachedSeal => "detached seal",
+            Layer::Pack => "pack",
+            Layer::Patch => "patch",
+            Layer::Plain => "escaped plain message",
+            #[cfg(feature = "fido")]
+            Layer::Fido => "FIDO-wrapped payload",
+        }
+    }
+
+    /// Versions of the layer this release reads, for the layers whose
+    /// magic bytes are followed by a version byte. The others describe
+    /// their format in fields of their own.
+    pub fn versions(&self) -> Option<RangeInclusive<u8>> {
+        let latest = match self {
+            Layer::Envelope | Layer::Deniable => VERSION,
+            Layer::FileInfo => fileinfo::VERSION,
+            Layer::Provenance => provenance::VERSION,
+            Layer::DetachedSeal => seal::VERSION,
+            Layer::Pack => pack::VERSION,
+            Layer::Patch => patch::VERSION,
+            #[cfg(feature = "fido")]
+            Layer::Fido => auth::VERSION,
+            _ => return None,
+        };
+        Some(1..=latest)
+    }
+
+    /// Returns the layer the payload starts with, or None for a plain
+    /// message
+    pub fn detect(payload: &[u8]) -> Option<Layer> {
+        Layer::ALL.iter().copied().find(|layer| payload.starts_with(layer.magic()))
+    }
+}
+
+/// Finds the layer the payload starts with and checks that this release
+/// reads the version it was written in, returning None for a plain message
+pub fn negotiate(payload: &[u8]) -> Result<Option<Layer>> {
+    let layer = match Layer::detect(payload) {
+        Some(layer) => layer,
+        None => return Ok(None),
+    };
+    let version = payload.get(layer.magic().len());
+    if let Some((versions, &version)) = layer.versions().zip(version) {
+        if !versions.contains(&version) {
+            return Err(Box::new(UnsupportedVersionError(layer, version)));
+        }
+    }
+    Ok(Some(layer))
+}
+
+/// Puts `PLAIN_MAGIC` in front of a plain message that starts with the
+/// magic bytes of a layer, so decode does not take it for that layer
+pub fn escape(message: &[u8]) -> Vec<u8> {
+    match Layer::detect(message) {
+        Some(_) => [PLAIN_MAGIC, message].concat(),
+        None => message.to_vec(),
+    }
+}
+
+/// Returns the plain message of a payload made by `escape`
+pub fn unescape(payload: &[u8]) -> &[u8] {
+    payload.strip_prefix(PLAIN_MAGIC).unwrap_or(payload)
+}
+
+/// Checks if the payload could be a plain message written before `escape`
+/// existed: UTF-8 text that starts with the magic bytes of a layer. Layered
+/// payloads hold binary hashes, lengths or ciphertext, so text that fails
+/// to open as the layer is taken for such a message.
+pub fn is_legacy_plain(payload: &[u8]) -> bool {
+    Layer::detect(payload).is_some_and(|layer| layer != Layer::Plain) && str::from_utf8(payload).is_ok()
+}
+
+/// Length of the header up to the ciphertext
+const HEADER_LEN: usize = MAGIC.len() + 2 + KDF_LEN + SALT_LEN + NONCE_LEN;
+
+/// Password-encrypted payload
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope {
+    pub version: u8,
+    /// Id of the cipher, see `cipher::by_id`
+    pub cipher: u8,
+    pub kdf: Kdf,
+    pub salt: [u8; SALT_LEN],
+    pub nonce: [u8; NONCE_LEN],
+    pub ciphertext: Vec<u8>,
+    pub tag: [u8; CIPHER_TAG_LEN],
+}
+
+impl Envelope {
+    /// Encrypts the plaintext under a key derived from the password by the
+    /// KDF, with a random salt and nonce
+    pub fn seal(cipher: &dyn Cipher, kdf: Kdf, password: &[u8], plaintext: &[u8]) -> Result<Envelope> {
+        kdf.validate()?;
+        let mut envelope = Envelope {
+            version: VERSION,
+            cipher: cipher.id(),
+            kdf,
+            salt: crypto::random_bytes(),
+            nonce: crypto::random_bytes(),
+            ciphertext: Vec::new(),
+            tag: [0; CIPHER_TAG_LEN],
+        };
+
+        let key = kdf.derive_key(password, &envelope.salt)?;
+        let mut sealed = cipher.seal(&key, &envelope.nonce, plaintext, &envelope.header())?;
+        envelope.tag = sealed.split_off(sealed.len() - CIPHER_TAG_LEN).try_into().unwrap();
+        envelope.ciphertext = sealed;
+        Ok(envelope)
+    }
+
+    /// Decrypts the envelope, failing for a wrong password or any
+    /// modification of the envelope
+    pub fn open(&self, password: &[u8]) -> Result<Vec<u8>> {
+        let key = self.kdf.derive_key(password, &self.salt)?;
+        let mut sealed = self.ciphertext.clone();
+        sealed.extend(self.tag);
+        cipher::by_id(self.cipher)?.open(&key, &self.nonce, &sealed, &self.header())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header();
+        bytes.extend(&self.ciphertext);
+        bytes.extend(self.tag);
+        bytes
+    }
+
+    /// Bytes up to the ciphertext, authenticated along with it
+    fn header(&self) -> Vec<u8> {
+        let mut header = MAGIC.to_vec();
+        header.extend([self.version, self.cipher]);
+        header.extend(self.kdf.to_bytes());
+        header.extend(self.salt);
+        header.extend(self.nonce);
+        header
+    }
+}
+
+impl TryFrom<&[u8]> for Envelope {
+    type Error = crate::Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Envelope> {
+        if !is_envelope(bytes) {
+            return Err(Box::new(crypto::NotEncryptedError));
+        }
+        negotiate(bytes)?;
+        let version = bytes[MAGIC.len()];
+        if bytes.len() < HEADER_LEN + CIPHER_TAG_LEN {
+            return Err(Box::new(InvalidEnvelopeError));
+        }
+
+        let (params, rest) = bytes[MAGIC.len() + 1..].split_at(1 + KDF_LEN);
+        let (salt, rest) = rest.split_at(SALT_LEN);
+        let (nonce, rest) = rest.split_at(NONCE_LEN);
+        let (ciphertext, tag) = rest.split_at(rest.len() - CIPHER_TAG_LEN);
+
+        Ok(Envelope {
+            version,
+            cipher: cipher::by_id(params[0])?.id(),
+            kdf: Kdf::from_bytes(&params[1..])?,
+            salt: salt.try_into()?,
+            nonce: nonce.try_into()?,
+            ciphertext: ciphertext.to_vec(),
+            tag: tag.try_into()?,
+        })
+    }
+}
+
+/// Checks if the payload starts with the envelope magic bytes
+pub fn is_envelope(payload: &[u8]) -> bool {
+    payload.len() > MAGIC.len() && payload.starts_with(MAGIC)
+}
+
+/// Magic bytes a deniable envelope starts with
+pub const DENIABLE_MAGIC: &[u8; 4] = b"PMDE";
+
+/// Version, cipher id and KDF shared by both slots
+const DENIABLE_HEADER_LEN: usize = DENIABLE_MAGIC.len() + 2 + KDF_LEN;
+const LENGTH_LEN: usize = 4;
+
+/// Checks if the payload starts with the deniable envelope magic bytes
+pub fn is_deniable(payload: &[u8]) -> bool {
+    payload.starts_with(DENIABLE_MAGIC)
+}
+
+/// Seals the message under the password and the optional second message
+/// under its own password. The payload holds the magic bytes, the version,
+/// cipher id and KDF of the envelope format and the two slots, each an
+/// envelope's salt, nonce, ciphertext and tag.
+pub fn seal_deniable(cipher: &dyn Cipher, kdf: Kdf, password: &[u8], message: &[u8], second: Option<(&[u8], &[u8])>) -> Result<Vec<u8>> {
+    if second.is_some_and(|(password2, _)| password2 == password) {
+        return Err(Box::new(SamePasswordError));
+    }
+
+    let padded_len = message.len().max(second.map_or(0, |(_, message2)| message2.len()));
+    let first = slot_bytes(&seal_slot(cipher, kdf, password, message, padded_len)?);
+
+    let second = match second {
+        Some((password2, message2)) => slot_bytes(&seal_slot(cipher, kdf, password2, message2, padded_len)?),
+        None => random_vec(first.len()),
+    };
+    let mut slots = [first, second];
+    if crypto::random_bytes::<1>()[0] & 1 == 1 {
+        slots.swap(0, 1);
+    }
+
+    let mut payload = DENIABLE_MAGIC.to_vec();
+    payload.extend([VERSION, cipher.id()]);
+    payload.extend(kdf.to_bytes());
+    payload.extend(slots.concat());
+    Ok(payload)
+}
+
+/// Opens whichever slot of the deniable envelope the password belongs to
+pub fn open_deniable(password: &[u8], payload: &[u8]) -> Result<Vec<u8>> {
+    if !is_deniable(payload) || payload.len() < DENIABLE_HEADER_LEN {
+        return Err(Box::new(InvalidEnvelopeError));
+    }
+    negotiate(payload)?;
+
+    for slot in deniable_slots(payload).ok_or(InvalidEnvelopeError)? {
+        if let Ok(plaintext) = slot_envelope(payload, slot)?.open(password) {
+            return unpad(&plaintext);
+        }
+    }
+    Err(Box::new(crypto::DecryptionError))
+}
+
+/// Seals the slot of the deniable envelope the old password opens again
+/// under the new password. The other slot is left as it is, so a decoy
+/// still opens with its own password and random bytes stay random.
+pub fn rekey_deniable(old_password: &[u8], new_password: &[u8], payload: &[u8]) -> Result<Vec<u8>> {
+    if !is_deniable(payload) || payload.len() < DENIABLE_HEADER_LEN {
+        return Err(Box::new(InvalidEnvelopeError));
+    }
+    negotiate(payload)?;
+
+    let slots = deniable_slots(payload).ok_or(InvalidEnvelopeError)?;
+    for (i, slot) in slots.iter().enumerate() {
+        let envelope = slot_envelope(payload, slot)?;
+        let plaintext = match envelope.open(old_password) {
+            Ok(plaintext) => plaintext,
+            Err(_) => continue,
+        };
+        // the other slot's password would then open either message
+        if slot_envelope(payload, slots[1 - i])?.open(new_password).is_ok() {
+            return Err(Box::new(SamePasswordError));
+        }
+
+        let resealed = slot_bytes(&Envelope::seal(cipher::by_id(envelope.cipher)?, envelope.kdf, new_password, &plaintext)?);
+        let mut rekeyed = payload[..DENIABLE_HEADER_LEN].to_vec();
+        for (j, slot) in slots.iter().enumerate() {
+            rekeyed.extend(if i == j { &resealed[..] } else { slot });
+        }
+        return Ok(rekeyed);
+    }
+    Err(Box::new(crypto::DecryptionError))
+}
+
+/// Returns the envelope of the payload, or for a deniable envelope that of
+/// its first slot, which carries the header both slots share
+pub fn outer_envelope(payload: &[u8]) -> Result<Envelope> {
+    match deniable_slots(payload) {
+        Some([slot, _]) => slot_envelope(payload, slot),
+        None => Envelope::try_from(payload),
+    }
+}
+
+/// Returns the envelope made of the header of the deniable envelope and
+/// one of its slots
+fn slot_envelope(payload: &[u8], slot: &[u8]) -> Result<Envelope> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(&payload[DENIABLE_MAGIC.len()..DENIABLE_HEADER_LEN]);
+    bytes.extend(slot);
+    Envelope::try_from(&bytes[..])
+}
+
+/// Returns the two slots of a deniable envelope, each starting with the
+/// salt and nonce of its envelope
+pub(crate) fn deniable_slots(payload: &[u8]) -> Option<[&[u8]; 2]> {
+    let slots = payload.get(DENIABLE_HEADER_LEN..).filter(|_| is_deniable(payload))?;
+    if !slots.len().is_multiple_of(2) || slots.len() / 2 < SALT_LEN + NONCE_LEN + CIPHER_TAG_LEN {
+        return None;
+    }
+    Some([&slots[..slots.len() / 2], &slots[slots.len() / 2..]])
+}
+
+/// Seals the message, prefixed with its length and zero padded to
+/// `padded_len`
+fn seal_slot(cipher: &dyn Cipher, kdf: Kdf, password: &[u8], message: &[u8], padded_len: usize) -> Result<Envelope> {
+    let mut plaintext = (message.len() as u32).to_be_bytes().to_vec();
+    plaintext.extend(message);
+    plaintext.resize(LENGTH_LEN + padded_len, 0);
+
+    Envelope::seal(cipher, kdf, password, &plaintext)
+}
+
+/// Returns the envelope without the header the slots share
+fn slot_bytes(envelope: &Envelope) -> Vec<u8> {
+    envelope.to_bytes().split_off(MAGIC.len() + 2 + KDF_LEN)
+}
+
+fn unpad(plaintext: &[u8]) -> Result<Vec<u8>> {
+    if plaintext.len() < LENGTH_LEN {
+        return Err(Box::new(InvalidEnvelopeError));
+    }
+    let len = u32::from_be_bytes(plaintext[..LENGTH_LEN].try_into()?) as usize;
+    plaintext.get(LENGTH_LEN..LENGTH_LEN + len)
+        .map(|message| message.to_vec())
+        .ok_or_else(|| Box::new(InvalidEnvelopeError).into())
+}
+
+fn random_vec(len: usize) -> Vec<u8> {
+    (0..len.div_ceil(32)).flat_map(|_| crypto::random_bytes::<32>()).take(len).collect()
+}
+
+/// Error specifying a version of a layer this release cannot read
+#[derive(Debug)]
+pub struct UnsupportedVersionError(pub Layer, pub u8);
+
+impl fmt::Display for UnsupportedVersionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let latest = self.0.versions().map_or(0, |versions| *versions.end());
+        write!(f, "Unsupported {} Version {}! (this release reads up to version {})", self.0.name(), self.1, latest)
+    }
+}
+
+impl error::Error for UnsupportedVersionError {}
+
+/// Error specifying both slots of an envelope sealed with one password
+#[derive(Debug)]
+pub struct SamePasswordError;
+
+impl fmt::Display for SamePasswordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Both Messages Use the Same Password!")
+    }
+}
+
+impl error::Error for SamePasswordError {}
+
+/// Error specifying a payload that is not a well-formed envelope
+#[derive(Debug)]
+pub struct InvalidEnvelopeError;
+
+impl fmt::Display for InvalidEnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Envelope!")
+    }
+}
+
+impl error::Error for InvalidEnvelopeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "aes-gcm")]
+    use crate::cipher::Aes256Gcm;
+    #[cfg(feature = "chacha20poly1305")]
+    use crate::cipher::ChaCha20Poly1305;
+
+    #[cfg(any(feature = "aes-gcm", feature = "chacha20poly1305"))]
+    const KDF: Kdf = Kdf::Argon2 { memory_kib: 64, iterations: 1, parallelism: 1 };
+
+    #[cfg(feature = "chacha20poly1305")]
+    #[test]
+    fn test_envelope_round_trip() {
+        let envelope = Envelope::seal(&ChaCha20Poly1305, KDF, b"hunter2", b"message").unwrap();
+        let bytes = envelope.to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN + 7 + CIPHER_TAG_LEN);
+        assert_eq!(&bytes[..5], b"PMEV\x01");
+
+        let parsed = Envelope::try_from(&bytes[..]).unwrap();
+        assert_eq!(parsed, envelope);
+        assert_eq!(parsed.open(b"hunter2").unwrap(), b"message");
+        assert!(parsed.open(b"hunter3").is_err());
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_modified_envelope() {
+        let envelope = Envelope::seal(&Aes256Gcm, KDF, b"hunter2", b"message").unwrap();
+
+        let mut modified = envelope.clone();
+        modified.nonce[0] ^= 1;
+        assert!(modified.open(b"hunter2").is_err());
+
+        let mut modified = envelope.clone();
+        modified.ciphertext[0] ^= 1;
+        assert!(modified.open(b"hunter2").is_err());
+
+        let mut modified = envelope;
+        modified.tag[0] ^= 1;
+        assert!(modified.open(b"hunter2").is_err());
+    }
+
+    #[cfg(feature = "chacha20poly1305")]
+    #[test]
+    fn test_unsupported_version() {
+        let mut bytes = Envelope::seal(&ChaCha20Poly1305, KDF, b"hunter2", b"message").unwrap().to_bytes();
+        bytes[MAGIC.len()] = 2;
+        assert!(Envelope::try_from(&bytes[..]).is_err());
+
+        bytes[MAGIC.len()] = VERSION;
+        assert!(Envelope::try_from(&bytes[..HEADER_LEN]).is_err());
+    }
+
+    #[test]
+    fn test_layers() {
+        for (i, layer) in Layer::ALL.iter().enumerate() {
+            assert!(Layer::ALL[i + 1..].iter().all(|other| other.magic() != layer.magic()), "{} reuses magic bytes", layer.name());
+            assert_eq!(Layer::detect(layer.magic()), Some(*layer));
+        }
+        assert_eq!(negotiate(b"plain text").unwrap(), None);
+        assert_eq!(negotiate(b"PMFI\x01").unwrap(), Some(Layer::FileInfo));
+        assert_eq!(negotiate(b"PMCZ\x09").unwrap(), Some(Layer::Compressed));
+        assert!(negotiate(b"PMFI\x02").unwrap_err().is::<UnsupportedVersionError>());
+        assert!(negotiate(b"PMEV\x00").is_err());
+    }
+
+    #[test]
+    fn test_escape() {
+        for layer in Layer::ALL {
+            let message = [layer.magic().as_slice(), b" is a plain message"].concat();
+            let escaped = escape(&message);
+            assert_eq!(Layer::detect(&escaped), Some(Layer::Plain));
+            assert_eq!(unescape(&escaped), message);
+            assert_eq!(is_legacy_plain(&message), *layer != Layer::Plain);
+        }
+        assert_eq!(escape(b"plain text"), b"plain text");
+        assert_eq!(unescape(b"plain text"), b"plain text");
+        assert!(!is_legacy_plain(b"PMCZ\x01\xff\x00"));
+    }
+
+    #[cfg(feature = "chacha20poly1305")]
+    #[test]
+    fn test_two_messages() {
+        let payload = seal_deniable(&ChaCha20Poly1305, KDF, b"real", b"the real plan", Some((b"decoy", b"groceries"))).unwrap();
+        assert!(is_deniable(&payload));
+        assert_eq!(open_deniable(b"real", &payload).unwrap(), b"the real plan");
+        assert_eq!(open_deniable(b"decoy", &payload).unwrap(), b"groceries");
+        assert!(open_deniable(b"wrong", &payload).is_err());
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn test_one_message_looks_like_two() {
+        let one = seal_deniable(&Aes256Gcm, KDF, b"password", b"message", None).unwrap();
+        let two = seal_deniable(&Aes256Gcm, KDF, b"password", b"message", Some((b"other", b"message"))).unwrap();
+        assert_eq!(one.len(), two.len());
+        assert_eq!(open_deniable(b"password", &one).unwrap(), b"message");
+    }
+
+    #[cfg(feature = "chacha20poly1305")]
+    #[test]
+    fn test_rekey_deniable() {
+        let payload = seal_deniable(&ChaCha20Poly1305, KDF, b"real", b"the real plan", Some((b"decoy", b"groceries"))).unwrap();
+        let rekeyed = rekey_deniable(b"real", b"new", &payload).unwrap();
+        assert_eq!(rekeyed.len(), payload.len());
+        assert_eq!(open_deniable(b"new", &rekeyed).unwrap(), b"the real plan");
+        assert_eq!(open_deniable(b"decoy", &rekeyed).unwrap(), b"groceries");
+        assert!(open_deniable(b"real", &rekeyed).is_err());
+
+        assert!(rekey_deniable(b"wrong", b"new", &payload).is_err());
+        assert!(rekey_deniable(b"real", b"decoy", &payload).unwrap_err().is::<SamePasswordError>());
+        assert_eq!(outer_envelope(&rekeyed).unwrap().kdf, KDF);
+    }
+
+    #[cfg(feature = "chacha20poly1305")]
+    #[test]
+    fn test_same_password() {
+        assert!(seal_deniable(&ChaCha20Poly1305, KDF, b"password", b"a", Some((b"password", b"b"))).is_err());
+    }
+
+    #[cfg(feature = "chacha20poly1305")]
+    #[test]
+    fn test_modified_deniable_envelope() {
+        let mut payload = seal_deniable(&ChaCha20Poly1305, KDF, b"real", b"message", Some((b"decoy", b"other"))).unwrap();
+        let last = payload.len() - 1;
+        payload[last] ^= 1;
+        payload[DENIABLE_HEADER_LEN] ^= 1;
+        assert!(open_deniable(b"real", &payload).is_err());
+        assert!(open_deniable(b"real", &payload[..last]).is_err());
+    }
+}
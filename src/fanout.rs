@@ -0,0 +1,325 @@
+//!
+//! Per-recipient watermarking: one copy of a carrier for each recipient,
+//! tagged with a random value in a private chunk (optionally signed with an
+//! HMAC key), and a ledger mapping the tags back to the recipients so a
+//! leaked copy can be traced
+//!
+use std::{error, fmt, str::FromStr, thread};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::{chunk::Chunk, chunk_type::ChunkType, crypto, pack, png::Png, Result};
+
+/// Chunk type of the watermark (ancillary, private, safe to copy)
+pub const WATERMARK_TYPE: &str = "pnWm";
+
+/// Name of the ledger written next to the copies
+pub const LEDGER_FILE: &str = "fanout.json";
+
+const TAG_LEN: usize = 16;
+
+/// Copy issued to one recipient
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Issued {
+    pub recipient: String,
+    /// Hex of the random tag in the watermark chunk
+    pub tag: String,
+    /// Whether the watermark was signed with an HMAC key
+    pub signed: bool,
+    pub sha256: String,
+}
+
+/// Copies issued from one carrier
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Ledger {
+    /// SHA-256 of the carrier the copies were made from
+    pub carrier: String,
+    pub copies: Vec<Issued>,
+}
+
+/// How a suspect file was matched to an issued copy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Evidence {
+    /// The file is byte for byte the issued copy
+    Identical,
+    /// The file was modified but still carries the copy's watermark
+    Watermark,
+}
+
+/// Reads the recipient ids from CSV text with a header row naming an `id`
+/// column. The ids become file names, so they must be plain and unique.
+pub fn parse_recipients(csv: &str) -> Result<Vec<String>> {
+    let mut rows = csv.lines().filter(|line| !line.trim().is_empty()).map(split_csv_line);
+    let header = rows.next().ok_or_else(|| InvalidRecipientsError(String::from("the CSV is empty")))?;
+    let column = header.iter().position(|name| name.trim().eq_ignore_ascii_case("id"))
+        .ok_or_else(|| InvalidRecipientsError(String::from("no id column in the header row")))?;
+
+    let mut ids: Vec<String> = Vec::new();
+    for row in rows {
+        let id = row.get(column).map(|id| id.trim()).unwrap_or_default();
+        if !pack::is_valid_name(id) {
+            return Err(Box::new(InvalidRecipientsError(format!("{:?} is not a plain file name", id))));
+        }
+        if ids.iter().any(|other| other == id) {
+            return Err(Box::new(InvalidRecipientsError(format!("{} is listed twice", id))));
+        }
+        ids.push(String::from(id));
+    }
+    Ok(ids)
+}
+
+/// Splits a CSV line into fields, unquoting fields in double quotes
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            },
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            _ => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+/// Returns a copy of the PNG with a watermark chunk holding the tag placed
+/// right before the IEND chunk, replacing any earlier watermark
+fn watermark(png: &Png, tag: &[u8], key: Option<&[u8]>) -> Result<Png> {
+    let chunk_type = ChunkType::from_str(WATERMARK_TYPE)?;
+    let mark = match key {
+        Some(key) => Chunk::new_authenticated(chunk_type, tag, key),
+        None => Chunk::new(chunk_type, tag.to_vec()),
+    };
+
+    let mut chunks = png.chunks().iter()
+        .filter(|chunk| chunk.chunk_type().as_str() != WATERMARK_TYPE)
+        .map(|chunk| Chunk::try_from(&chunk.as_bytes()[..]))
+        .collect::<Result<Vec<Chunk>>>()?;
+    let index = chunks.iter().position(|chunk| chunk.chunk_type().as_str() == "IEND").unwrap_or(chunks.len());
+    chunks.insert(index, mark);
+    Ok(Png::from_chunks(chunks))
+}
+
+impl Ledger {
+    /// Makes a watermarked copy of the carrier for each recipient, spread
+    /// over the available cores, and hands each to `write` with the
+    /// recipient's id. The copies are recorded in the ledger, which must be
+    /// empty or come from the same carrier.
+    pub fn fanout<W>(&mut self, carrier: &Png, recipients: &[String], key: Option<&[u8]>, write: W) -> Result<()>
+    where
+        W: Fn(&str, &[u8]) -> Result<()> + Sync,
+    {
+        // the tags are drawn up front in recipient order, so the copies and
+        // the ledger do not depend on how the work is spread over threads
+        let tags: Vec<[u8; TAG_LEN]> = recipients.iter().map(|_| crypto::random_bytes::<TAG_LEN>()).collect();
+        let workers = thread::available_parallelism().map_or(1, |n| n.get());
+        self.fanout_with(carrier, recipients, &tags, key, workers, write)
+    }
+
+    /// Makes the copies with the given tags on at most `workers` threads
+    fn fanout_with<W>(&mut self, carrier: &Png, recipients: &[String], tags: &[[u8; TAG_LEN]], key: Option<&[u8]>, 
+        workers: usize, write: W) -> Result<()>
+    where
+        W: Fn(&str, &[u8]) -> Result<()> + Sync,
+    {
+        let hash = hex::encode(Sha256::digest(carrier.as_bytes()));
+        if !self.carrier.is_empty() && self.carrier != hash {
+            return Err(Box::new(LedgerMismatchError));
+        }
+        if let Some(issued) = self.copies.iter().find(|issued| recipients.contains(&issued.recipient)) {
+            return Err(Box::new(InvalidRecipientsError(format!("{} already has a copy", issued.recipient))));
+        }
+
+        let jobs: Vec<(&String, &[u8; TAG_LEN])> = recipients.iter().zip(tags).collect();
+        let batch = jobs.len().div_ceil(workers.max(1)).max(1);
+        let issued = thread::scope(|scope| {
+            let handles: Vec<_> = jobs.chunks(batch)
+                .map(|batch| scope.spawn(|| {
+                    batch.iter()
+                        .map(|(recipient, tag)| issue(carrier, recipient, tag, key, &write).map_err(|e| e.to_string()))
+                        .collect::<std::result::Result<Vec<Issued>, String>>()
+                }))
+                .collect();
+            handles.into_iter()
+                .map(|handle| handle.join().unwrap_or_else(|_| Err(String::from("watermarking thread panicked"))))
+                .collect::<std::result::Result<Vec<Vec<Issued>>, String>>()
+        })?;
+
+        self.carrier = hash;
+        self.copies.extend(issued.into_iter().flatten());
+        Ok(())
+    }
+
+    /// Finds the issued copy the suspect file came from, first by its hash
+    /// and then by its watermark. Signed watermarks are only accepted once
+    /// verified with the key, so a copy cannot be framed on a recipient
+    /// by forging their tag.
+    pub fn identify(&self, png: &Png, key: Option<&[u8]>) -> Result<Option<(&Issued, Evidence)>> {
+        let hash = hex::encode(Sha256::digest(png.as_bytes()));
+        if let Some(issued) = self.copies.iter().find(|issued| issued.sha256 == hash) {
+            return Ok(Some((issued, Evidence::Identical)));
+        }
+
+        let Some(mark) = png.chunk_by_type(WATERMARK_TYPE) else {
+            return Ok(None);
+        };
+        let (tag, verified) = match key {
+            Some(key) if crypto::is_authenticated(mark.data()) => (mark.authenticated_data(key)?, true),
+            None if crypto::is_authenticated(mark.data()) => return Err(Box::new(crypto::NotAuthenticatedError)),
+            _ => (mark.data(), false),
+        };
+
+        let tag = hex::encode(tag);
+        match self.copies.iter().find(|issued| issued.tag == tag) {
+            Some(issued) if issued.signed && !verified => Err(Box::new(crypto::AuthenticationError)),
+            Some(issued) => Ok(Some((issued, Evidence::Watermark))),
+            None => Ok(None),
+        }
+    }
+}
+
+fn issue<W>(carrier: &Png, recipient: &str, tag: &[u8; TAG_LEN], key: Option<&[u8]>, write: &W) -> Result<Issued>
+where
+    W: Fn(&str, &[u8]) -> Result<()>,
+{
+    let bytes = watermark(carrier, tag, key)?.as_bytes();
+    write(recipient, &bytes)?;
+    Ok(Issued {
+        recipient: String::from(recipient),
+        tag: hex::encode(tag),
+        signed: key.is_some(),
+        sha256: hex::encode(Sha256::digest(&bytes)),
+    })
+}
+
+/// Error specifying a recipient list that cannot be used
+#[derive(Debug)]
+pub struct InvalidRecipientsError(String);
+
+impl fmt::Display for InvalidRecipientsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Recipients! ({})", self.0)
+    }
+}
+
+impl error::Error for InvalidRecipientsError {}
+
+/// Error specifying a ledger kept for a different carrier
+#[derive(Debug)]
+pub struct LedgerMismatchError;
+
+impl fmt::Display for LedgerMismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Ledger Mismatch! (the ledger records copies of a different carrier)")
+    }
+}
+
+impl error::Error for LedgerMismatchError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use crate::png::testing::chunk;
+
+    fn carrier() -> Png {
+        Png::from_chunks(vec![chunk("IHDR", b"header"), chunk("IDAT", &[7; 100]), chunk("IEND", b"")])
+    }
+
+    fn fanout(ids: &[&str], key: Option<&[u8]>) -> (Ledger, Vec<(String, Vec<u8>)>) {
+        let written = Mutex::new(Vec::new());
+        let mut ledger = Ledger::default();
+        let ids: Vec<String> = ids.iter().map(|id| String::from(*id)).collect();
+        ledger.fanout(&carrier(), &ids, key, |id, bytes| {
+            written.lock().unwrap().push((String::from(id), bytes.to_vec()));
+            Ok(())
+        }).unwrap();
+        (ledger, written.into_inner().unwrap())
+    }
+
+    #[test]
+    fn test_parse_recipients() {
+        let csv = "name,id\n\"Doe, Jane\",jane\n\nBob,\"bob\"\n";
+        assert_eq!(parse_recipients(csv).unwrap(), ["jane", "bob"]);
+
+        assert!(parse_recipients("name\nJane\n").is_err());
+        assert!(parse_recipients("id\njane\njane\n").is_err());
+        assert!(parse_recipients("id\n../jane\n").is_err());
+    }
+
+    #[test]
+    fn test_fanout_and_identify() {
+        let (ledger, written) = fanout(&["alice", "bob", "carol"], None);
+        assert_eq!(written.len(), 3);
+        assert_eq!(ledger.copies.iter().map(|c| c.recipient.as_str()).collect::<Vec<_>>(), ["alice", "bob", "carol"]);
+
+        let (id, bytes) = &written[1];
+        let copy = Png::try_from(&bytes[..]).unwrap();
+        assert_eq!(copy.chunks().last().unwrap().chunk_type().as_str(), "IEND");
+        let (issued, evidence) = ledger.identify(&copy, None).unwrap().unwrap();
+        assert_eq!((issued.recipient.as_str(), evidence), (id.as_str(), Evidence::Identical));
+
+        // a leaked copy that was modified is still traced by its watermark
+        let mut modified = Png::try_from(&bytes[..]).unwrap();
+        modified.insert_chunk(1, chunk("tEXt", b"edited"));
+        let (issued, evidence) = ledger.identify(&modified, None).unwrap().unwrap();
+        assert_eq!((issued.recipient.as_str(), evidence), (id.as_str(), Evidence::Watermark));
+
+        assert!(ledger.identify(&carrier(), None).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_signed_watermarks() {
+        let (ledger, written) = fanout(&["alice", "bob"], Some(b"secret"));
+        let copy = Png::try_from(&written[0].1[..]).unwrap();
+        assert!(ledger.identify(&copy, None).unwrap().is_some());
+
+        // an unsigned watermark naming a signed copy's tag is rejected
+        let mut forged = carrier();
+        let tag = hex::decode(&ledger.copies[0].tag).unwrap();
+        forged = watermark(&forged, &tag, None).unwrap();
+        assert!(ledger.identify(&forged, Some(b"secret")).is_err());
+        assert!(ledger.identify(&forged, None).is_err());
+
+        forged = watermark(&carrier(), &tag, Some(b"guess")).unwrap();
+        assert!(ledger.identify(&forged, Some(b"secret")).is_err());
+        forged = watermark(&carrier(), &tag, Some(b"secret")).unwrap();
+        assert_eq!(ledger.identify(&forged, Some(b"secret")).unwrap().unwrap().0.recipient, "alice");
+    }
+
+    #[test]
+    fn test_output_independent_of_threads() {
+        let ids: Vec<String> = (0..7).map(|i| format!("recipient{}", i)).collect();
+        let tags: Vec<[u8; TAG_LEN]> = (0..7).map(|i| [i; TAG_LEN]).collect();
+        let run = |workers: usize| {
+            let written = Mutex::new(Vec::new());
+            let mut ledger = Ledger::default();
+            ledger.fanout_with(&carrier(), &ids, &tags, Some(b"secret"), workers, |id, bytes| {
+                written.lock().unwrap().push((String::from(id), bytes.to_vec()));
+                Ok(())
+            }).unwrap();
+            let mut written = written.into_inner().unwrap();
+            written.sort();
+            (ledger.copies, written)
+        };
+
+        let single = run(1);
+        for workers in [2, 3, 8] {
+            assert_eq!(run(workers), single);
+        }
+    }
+
+    #[test]
+    fn test_ledger_of_other_carrier() {
+        let (mut ledger, _) = fanout(&["alice"], None);
+        let mut other = carrier();
+        other.insert_chunk(1, chunk("tEXt", b"other"));
+        assert!(ledger.fanout(&other, &[String::from("bob")], None, |_, _| Ok(())).is_err());
+        assert!(ledger.fanout(&carrier(), &[String::from("alice")], None, |_, _| Ok(())).is_err());
+        assert!(ledger.fanout(&carrier(), &[String::from("bob")], None, |_, _| Ok(())).is_ok());
+    }
+}
@@ -0,0 +1,215 @@
+//!
+//! Header recording the original name, size, mime type and modification
+//! time of a file embedded with encode --file, so decode can restore the
+//! file as it was or describe it without extracting it.
+//!
+//! | Field                                   | Size       |
+//! |-----------------------------------------|------------|
+//! | magic `PMFI`                            | 4          |
+//! | version                                 | 1          |
+//! | name length                             | 2          |
+//! | name (UTF-8 file name)                  | variable   |
+//! | size                                    | 8          |
+//! | mime type length                        | 1          |
+//! | mime type                               | variable   |
+//! | modification time (Unix seconds, or all | 8          |
+//! | ones when unknown)                      |            |
+//! | file contents                           | size       |
+//!
+use std::{error, fmt};
+use crate::{envelope, pack, Result};
+
+pub const MAGIC: &[u8; 4] = b"PMFI";
+pub const VERSION: u8 = 1;
+
+/// Stands for an unknown modification time
+const UNKNOWN_TIME: u64 = u64::MAX;
+
+/// Mime types of common file extensions
+const MIME_TYPES: [(&str, &str); 16] = [
+    ("txt", "text/plain"),
+    ("csv", "text/csv"),
+    ("html", "text/html"),
+    ("json", "application/json"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("7z", "application/x-7z-compressed"),
+    ("rar", "application/vnd.rar"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+    ("docx", "application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+];
+
+/// Mime type of files nothing more is known about
+const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+/// Original properties of an embedded file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileInfo {
+    pub name: String,
+    pub size: u64,
+    pub mime_type: String,
+    /// Modification time in seconds since the Unix epoch
+    pub modified: Option<u64>,
+}
+
+impl FileInfo {
+    /// Describes a file of the name and contents, guessing its mime type
+    /// from the extension
+    pub fn new(name: &str, contents: &[u8], modified: Option<u64>) -> Result<FileInfo> {
+        if !pack::is_valid_name(name) || name.len() > u16::MAX as usize {
+            return Err(Box::new(InvalidFileNameError(String::from(name))));
+        }
+        Ok(FileInfo {
+            name: String::from(name),
+            size: contents.len() as u64,
+            mime_type: String::from(mime_type(name)),
+            modified,
+        })
+    }
+}
+
+impl fmt::Display for FileInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({}, {} bytes", self.name, self.mime_type, self.size)?;
+        match self.modified {
+            Some(modified) => write!(f, ", modified at {} (Unix time))", modified),
+            None => write!(f, ")"),
+        }
+    }
+}
+
+/// Guesses the mime type of a file from the extension of its name
+pub fn mime_type(name: &str) -> &'static str {
+    let extension = name.rsplit_once('.').map(|(_, extension)| extension.to_ascii_lowercase());
+    MIME_TYPES.iter()
+        .find(|(known, _)| extension.as_deref() == Some(known))
+        .map_or(DEFAULT_MIME_TYPE, |(_, mime_type)| mime_type)
+}
+
+/// Checks if the payload starts with the magic bytes of a file header
+pub fn is_file_info(payload: &[u8]) -> bool {
+    payload.starts_with(MAGIC)
+}
+
+/// Puts the header describing the file in front of its contents
+pub fn wrap(info: &FileInfo, contents: &[u8]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    bytes.extend((info.name.len() as u16).to_be_bytes());
+    bytes.extend(info.name.as_bytes());
+    bytes.extend(info.size.to_be_bytes());
+    let mime_type = &info.mime_type.as_bytes()[..info.mime_type.len().min(u8::MAX as usize)];
+    bytes.push(mime_type.len() as u8);
+    bytes.extend(mime_type);
+    bytes.extend(info.modified.unwrap_or(UNKNOWN_TIME).to_be_bytes());
+    bytes.extend(contents);
+    bytes
+}
+
+/// Splits a payload made by `wrap` into the file's description and its
+/// contents. Names that are not plain file names are refused, so restoring
+/// the file cannot write outside of the current directory.
+pub fn unwrap(payload: &[u8]) -> Result<(FileInfo, &[u8])> {
+    envelope::negotiate(payload)?;
+    let mut rest = payload;
+    let mut take = |len: usize| -> Result<&[u8]> {
+        if rest.len() < len {
+            return Err(Box::new(InvalidFileInfoError));
+        }
+        let (taken, tail) = rest.split_at(len);
+        rest = tail;
+        Ok(taken)
+    };
+
+    if take(MAGIC.len())? != MAGIC {
+        return Err(Box::new(InvalidFileInfoError));
+    }
+    // the version, negotiated above
+    take(1)?;
+    let name_len = u16::from_be_bytes(take(2)?.try_into()?) as usize;
+    let name = String::from_utf8(take(name_len)?.to_vec())?;
+    if !pack::is_valid_name(&name) {
+        return Err(Box::new(InvalidFileNameError(name)));
+    }
+    let size = u64::from_be_bytes(take(8)?.try_into()?);
+    let mime_len = take(1)?[0] as usize;
+    let mime_type = String::from_utf8(take(mime_len)?.to_vec())?;
+    let modified = match u64::from_be_bytes(take(8)?.try_into()?) {
+        UNKNOWN_TIME => None,
+        modified => Some(modified),
+    };
+
+    if rest.len() as u64 != size {
+        return Err(Box::new(InvalidFileInfoError));
+    }
+    Ok((FileInfo { name, size, mime_type, modified }, rest))
+}
+
+/// Error specifying a file name that cannot be restored safely
+#[derive(Debug)]
+pub struct InvalidFileNameError(String);
+
+impl fmt::Display for InvalidFileNameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid File Name! ({:?})", self.0)
+    }
+}
+
+impl error::Error for InvalidFileNameError {}
+
+/// Error specifying a message that holds no file header
+#[derive(Debug)]
+pub struct NoFileInfoError;
+
+impl fmt::Display for NoFileInfoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "No File Header! (the message was not encoded with --file)")
+    }
+}
+
+impl error::Error for NoFileInfoError {}
+
+/// Error specifying a file header that could not be parsed
+#[derive(Debug)]
+pub struct InvalidFileInfoError;
+
+impl fmt::Display for InvalidFileInfoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid File Header!")
+    }
+}
+
+impl error::Error for InvalidFileInfoError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let info = FileInfo::new("Report.PDF", b"%PDF-1.7", Some(1_700_000_000)).unwrap();
+        assert_eq!(info.mime_type, "application/pdf");
+
+        let payload = wrap(&info, b"%PDF-1.7");
+        assert!(is_file_info(&payload));
+        let (unwrapped, contents) = unwrap(&payload).unwrap();
+        assert_eq!(unwrapped, info);
+        assert_eq!(contents, b"%PDF-1.7");
+        assert_eq!(unwrapped.to_string(), "Report.PDF (application/pdf, 8 bytes, modified at 1700000000 (Unix time))");
+
+        assert!(unwrap(&payload[..payload.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(FileInfo::new("notes", b"", None).unwrap().mime_type, DEFAULT_MIME_TYPE);
+        assert!(FileInfo::new("../etc/passwd", b"", None).is_err());
+        assert!(FileInfo::new("", b"", None).is_err());
+    }
+}
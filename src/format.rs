@@ -0,0 +1,153 @@
+//!
+//! Formats decode writes messages in, so binary payloads can be printed
+//! to a terminal or piped into other tools safely, and structured data
+//! types encode and decode check messages against
+//!
+use std::{error, fmt, str};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::ValueEnum;
+use crate::{platform, Result};
+
+/// How decode writes the message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum MessageFormat {
+    /// Text as text and anything else as raw bytes, which are refused on
+    /// a terminal
+    #[default]
+    Auto,
+    /// The bytes as they are
+    Raw,
+    /// UTF-8 text, refusing messages that are not
+    Utf8,
+    /// UTF-8 text, with invalid bytes replaced by U+FFFD
+    Lossy,
+    /// UTF-8 text, with invalid bytes and control characters written as
+    /// \xNN escapes
+    Escaped,
+    /// Lowercase hexadecimal
+    Hex,
+    /// Standard base64 with padding
+    Base64,
+}
+
+impl MessageFormat {
+    /// Formats the message, returning None when `Auto` finds it is not text
+    pub fn format(&self, message: &[u8]) -> Result<Option<Vec<u8>>> {
+        let line = |text: String| Some(format!("{}\n", text).into_bytes());
+        Ok(match self {
+            MessageFormat::Auto => platform::decode_text(message).ok().and_then(line),
+            MessageFormat::Raw => Some(message.to_vec()),
+            MessageFormat::Utf8 => line(String::from(str::from_utf8(message)?)),
+            MessageFormat::Lossy => line(String::from_utf8_lossy(message).into_owned()),
+            MessageFormat::Escaped => line(escape(message)),
+            MessageFormat::Hex => line(hex::encode(message)),
+            MessageFormat::Base64 => line(STANDARD.encode(message)),
+        })
+    }
+}
+
+/// Writes the bytes as UTF-8 text, escaping invalid bytes and control
+/// characters other than newlines and tabs as `\xNN` and backslashes as
+/// `\\`, so the text shows every byte and cannot drive a terminal
+pub fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::new();
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => escaped.push_str("\\\\"),
+                '\n' | '\t' => escaped.push(c),
+                c if c.is_control() && c.is_ascii() => escaped.push_str(&format!("\\x{:02x}", c as u8)),
+                c if c.is_control() => escaped.extend(c.escape_unicode()),
+                c => escaped.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            escaped.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    escaped
+}
+
+/// What kind of data a message holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum DataType {
+    /// Any bytes, stored and printed as they are
+    #[default]
+    Raw,
+    /// A JSON document, minified by encode and pretty-printed by decode
+    Json,
+}
+
+impl DataType {
+    /// Checks the message before it is encoded, returning it in the form
+    /// it is stored in
+    pub fn prepare(&self, message: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            DataType::Raw => Ok(message.to_vec()),
+            DataType::Json => Ok(serde_json::to_vec(&parse_json(message)?)?),
+        }
+    }
+
+    /// Checks a decoded message, returning it in the form it is printed in
+    pub fn present(&self, message: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            DataType::Raw => Ok(message.to_vec()),
+            DataType::Json => {
+                let mut pretty = serde_json::to_vec_pretty(&parse_json(message)?)?;
+                pretty.push(b'\n');
+                Ok(pretty)
+            },
+        }
+    }
+}
+
+fn parse_json(message: &[u8]) -> Result<serde_json::Value> {
+    serde_json::from_slice(message).map_err(|e| InvalidJsonError(e.to_string()).into())
+}
+
+/// Error specifying a message that is not the JSON document it should be
+#[derive(Debug)]
+pub struct InvalidJsonError(String);
+
+impl fmt::Display for InvalidJsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid JSON! ({})", self.0)
+    }
+}
+
+impl error::Error for InvalidJsonError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formats() {
+        let format = |format: MessageFormat, message: &[u8]| format.format(message).unwrap().map(String::from_utf8);
+        assert_eq!(format(MessageFormat::Auto, b"hi"), Some(Ok(String::from("hi\n"))));
+        assert_eq!(format(MessageFormat::Auto, b"\xff"), None);
+        assert_eq!(format(MessageFormat::Hex, b"\x00\xff"), Some(Ok(String::from("00ff\n"))));
+        assert_eq!(format(MessageFormat::Base64, b"\x00\xff"), Some(Ok(String::from("AP8=\n"))));
+        assert_eq!(MessageFormat::Raw.format(b"\xff").unwrap(), Some(b"\xff".to_vec()));
+        assert!(MessageFormat::Utf8.format(b"\xff").is_err());
+        assert_eq!(format(MessageFormat::Lossy, b"a\xffb"), Some(Ok(String::from("a\u{fffd}b\n"))));
+        assert_eq!(format(MessageFormat::Escaped, b"a\xffb"), Some(Ok(String::from("a\\xffb\n"))));
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape(b"line\n\ttab \\ \x1b[31m \xe9\x80"), "line\n\ttab \\\\ \\x1b[31m \\xe9\\x80");
+        assert_eq!(escape("caf\u{e9} \u{85}".as_bytes()), "caf\u{e9} \\u{85}");
+    }
+
+    #[test]
+    fn test_json() {
+        let stored = DataType::Json.prepare(b"{ \"on\": true,\n  \"key\": [1, 2] }").unwrap();
+        assert_eq!(stored, br#"{"on":true,"key":[1,2]}"#);
+        assert_eq!(String::from_utf8(DataType::Json.present(&stored).unwrap()).unwrap(),
+            "{\n  \"on\": true,\n  \"key\": [\n    1,\n    2\n  ]\n}\n");
+        assert!(DataType::Json.prepare(b"{ \"key\": ").unwrap_err().is::<InvalidJsonError>());
+        assert!(DataType::Json.present(b"not json").is_err());
+        assert_eq!(DataType::Raw.prepare(b"\xff").unwrap(), b"\xff");
+    }
+}
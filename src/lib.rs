@@ -1,8 +1,52 @@
 pub mod args;
+pub mod audit;
+#[cfg(feature = "fido")]
+pub mod auth;
+pub mod cancel;
+pub mod capacity;
+pub mod carrier;
+pub mod checksum;
+pub mod check;
+pub mod cipher;
 pub mod chunk;
 pub mod chunk_type;
 pub mod commands;
+pub mod compress;
+pub mod crypto;
+pub mod digest;
+pub mod ecc;
+pub mod envelope;
+pub mod fanout;
+pub mod fileinfo;
+pub mod format;
+pub mod lint;
+pub mod manifest;
+pub mod notes;
+pub mod oplog;
+pub mod pack;
+pub mod patch;
+pub mod peek;
+#[cfg(feature = "pgp")]
+pub mod pgp;
+pub mod pixels;
+pub mod placement;
+pub mod platform;
 pub mod png;
+pub mod pool;
+pub mod provenance;
+pub mod recover;
+pub mod reflink;
+pub mod remote;
+pub mod seal;
+pub mod shares;
+pub mod storage;
+pub mod survive;
+pub mod stream;
+pub mod text;
+pub mod thumbnail;
+pub mod whiten;
+pub mod workspace;
+pub mod xmp;
 
 pub type Error = Box<dyn std::error::Error>;
 pub type Result<T> = std::result::Result<T, Error>;
\ No newline at end of file
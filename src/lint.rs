@@ -0,0 +1,256 @@
+//!
+//! Spec-conformance lint: deviations from the PNG specification that
+//! decoders usually tolerate, each with a rule ID. Findings marked as
+//! fixable can be corrected without touching the image data.
+//!
+use std::fmt;
+use crate::{chunk::Chunk, png::Png, text};
+
+/// Where the specification requires an ancillary chunk to be placed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    /// Before PLTE and IDAT
+    BeforePlte,
+    /// After PLTE and before IDAT
+    AfterPlte,
+    /// Before IDAT
+    BeforeIdat,
+}
+
+impl Placement {
+    fn of(chunk_type: &str) -> Option<Placement> {
+        match chunk_type {
+            "cHRM" | "gAMA" | "iCCP" | "sBIT" | "sRGB" | "cICP" | "mDCV" | "cLLI" => Some(Placement::BeforePlte),
+            "bKGD" | "hIST" | "tRNS" => Some(Placement::AfterPlte),
+            "pHYs" | "sPLT" | "eXIf" | "acTL" | "oFFs" | "pCAL" | "sCAL" | "sTER" => Some(Placement::BeforeIdat),
+            _ => None,
+        }
+    }
+}
+
+/// A lint rule
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    /// Critical chunk of a type the specification does not define
+    UnknownCritical,
+    /// Ancillary chunk outside the position the specification requires
+    MisplacedAncillary(Placement),
+    /// PLTE chunk after the first one
+    MultiplePlte,
+    /// IEND chunk with data
+    NonEmptyIend,
+    /// Text chunk after the image data, where streaming decoders only see
+    /// it once the whole image is read
+    TextAfterIdat,
+    /// Chunk after IEND, which ends the datastream, so most decoders and
+    /// editors never read it
+    AfterIend,
+    /// IHDR chunk that is not the first chunk, or a first chunk that is not
+    /// IHDR
+    IhdrNotFirst,
+}
+
+impl Rule {
+    /// Stable identifier of the rule
+    pub fn id(&self) -> &'static str {
+        match self {
+            Rule::UnknownCritical => "L001",
+            Rule::MisplacedAncillary(_) => "L002",
+            Rule::MultiplePlte => "L003",
+            Rule::NonEmptyIend => "L004",
+            Rule::TextAfterIdat => "L005",
+            Rule::AfterIend => "L006",
+            Rule::IhdrNotFirst => "L007",
+        }
+    }
+
+    /// Checks if `fix` corrects findings of the rule
+    pub fn is_fixable(&self) -> bool {
+        matches!(self, Rule::MisplacedAncillary(_) | Rule::NonEmptyIend | Rule::TextAfterIdat | Rule::AfterIend)
+    }
+}
+
+/// A deviation found in the chunk at `index`
+#[derive(Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub rule: Rule,
+    pub index: usize,
+    pub chunk_type: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: chunk {} ({}) ", self.rule.id(), self.index, self.chunk_type)?;
+        match self.rule {
+            Rule::UnknownCritical => write!(f, "is an unknown critical chunk")?,
+            Rule::MisplacedAncillary(Placement::BeforePlte) => write!(f, "must come before PLTE and IDAT")?,
+            Rule::MisplacedAncillary(Placement::AfterPlte) => write!(f, "must come after PLTE and before IDAT")?,
+            Rule::MisplacedAncillary(Placement::BeforeIdat) => write!(f, "must come before IDAT")?,
+            Rule::MultiplePlte => write!(f, "is a second PLTE chunk")?,
+            Rule::NonEmptyIend => write!(f, "has data, IEND must be empty")?,
+            Rule::TextAfterIdat => write!(f, "is a text chunk after the image data")?,
+            Rule::AfterIend => write!(f, "comes after IEND, which must be last")?,
+            Rule::IhdrNotFirst if self.chunk_type == "IHDR" => write!(f, "is an IHDR chunk that is not first")?,
+            Rule::IhdrNotFirst => write!(f, "is first, where IHDR must be")?,
+        }
+        if self.rule.is_fixable() {
+            write!(f, " [fixable]")?;
+        }
+        Ok(())
+    }
+}
+
+fn position(png: &Png, chunk_type: &str) -> Option<usize> {
+    png.chunks().iter().position(|chunk| chunk.chunk_type().as_str() == chunk_type)
+}
+
+/// Lists the spec deviations of the PNG in chunk order
+pub fn lint(png: &Png) -> Vec<Finding> {
+    let first_plte = position(png, "PLTE");
+    let first_idat = position(png, "IDAT");
+    let first_iend = position(png, "IEND");
+    let after = |first: Option<usize>, index: usize| first.is_some_and(|first| index > first);
+
+    let mut findings = Vec::new();
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        let chunk_type = chunk.chunk_type();
+        let name = chunk_type.as_str();
+        let rule = if (index == 0) != (name == "IHDR") {
+            Some(Rule::IhdrNotFirst)
+        } else if chunk_type.is_critical() && !chunk_type.is_registered() {
+            Some(Rule::UnknownCritical)
+        } else if name == "PLTE" && first_plte != Some(index) {
+            Some(Rule::MultiplePlte)
+        } else if name == "IEND" && chunk.length() > 0 {
+            Some(Rule::NonEmptyIend)
+        } else if after(first_iend, index) {
+            Some(Rule::AfterIend)
+        } else if text::is_text_chunk_type(&name) && after(first_idat, index) {
+            Some(Rule::TextAfterIdat)
+        } else {
+            Placement::of(&name)
+                .filter(|&placement| after(first_idat, index) || match placement {
+                    Placement::BeforePlte => after(first_plte, index),
+                    Placement::AfterPlte => first_plte.is_some_and(|plte| index < plte),
+                    Placement::BeforeIdat => false,
+                })
+                .map(Rule::MisplacedAncillary)
+        };
+
+        if let Some(rule) = rule {
+            findings.push(Finding { rule, index, chunk_type: name });
+        }
+    }
+    findings
+}
+
+/// Corrects the fixable findings: empties IEND and moves misplaced chunks
+/// to the earliest position the specification allows, keeping their order.
+/// Chunks after IEND are moved in front of it. Returns the number of
+/// findings fixed.
+pub fn fix(png: &mut Png) -> usize {
+    let findings: Vec<Finding> = lint(png).into_iter().filter(|finding| finding.rule.is_fixable()).collect();
+
+    let moved: Vec<(Rule, Chunk)> = findings.iter()
+        .filter(|finding| finding.rule != Rule::NonEmptyIend)
+        .map(|finding| (finding.rule, Chunk::try_from(&png.chunks()[finding.index].as_bytes()[..]).unwrap()))
+        .collect();
+    let mut index = 0;
+    png.retain_chunks(|_| {
+        index += 1;
+        !findings.iter().any(|finding| finding.index == index - 1 && finding.rule != Rule::NonEmptyIend)
+    });
+
+    for (rule, chunk) in moved {
+        let target = match rule {
+            Rule::MisplacedAncillary(Placement::BeforePlte) => position(png, "PLTE").or(position(png, "IDAT")),
+            Rule::AfterIend => position(png, "IEND"),
+            _ => position(png, "IDAT"),
+        };
+        let target = target.or(position(png, "IEND")).unwrap_or(png.chunks().len());
+        png.insert_chunk(target, chunk);
+    }
+
+    png.map_chunks(|chunk| if chunk.chunk_type().as_str() == "IEND" && chunk.length() > 0 {
+        Chunk::new(chunk.chunk_type().bytes().try_into().unwrap(), Vec::new())
+    } else {
+        chunk
+    });
+    findings.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::testing::{chunk, testing_png};
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks().iter().map(|chunk| chunk.chunk_type().as_str()).collect()
+    }
+
+    #[test]
+    fn test_clean() {
+        let png = Png::from_chunks(vec![chunk("IHDR", b"header"), chunk("gAMA", b"1"), chunk("PLTE", b"rgb"),
+            chunk("tRNS", b"a"), chunk("tEXt", b"Title\0x"), chunk("IDAT", b"data"), chunk("tIME", b"t"), chunk("IEND", b"")]);
+        assert!(lint(&png).is_empty());
+    }
+
+    #[test]
+    fn test_findings() {
+        let png = Png::from_chunks(vec![chunk("IHDR", b"header"), chunk("tRNS", b"a"), chunk("PLTE", b"rgb"),
+            chunk("gAMA", b"1"), chunk("PLTE", b"rgb"), chunk("IDAT", b"data"), chunk("RUST", b"x"),
+            chunk("pHYs", b"p"), chunk("iTXt", b"Title\0\0\0\0x"), chunk("IEND", b"junk")]);
+        let rules: Vec<(&str, usize)> = lint(&png).iter().map(|finding| (finding.rule.id(), finding.index)).collect();
+        assert_eq!(rules, vec![("L002", 1), ("L002", 3), ("L003", 4), ("L001", 6), ("L002", 7), ("L005", 8), ("L004", 9)]);
+        assert_eq!(lint(&png)[1].to_string(), "L002: chunk 3 (gAMA) must come before PLTE and IDAT [fixable]");
+    }
+
+    #[test]
+    fn test_fix() {
+        let mut png = Png::from_chunks(vec![chunk("IHDR", b"header"), chunk("PLTE", b"rgb"), chunk("IDAT", b"data"),
+            chunk("cHRM", b"c"), chunk("tEXt", b"Title\0x"), chunk("bKGD", b"b"), chunk("pHYs", b"p"),
+            chunk("IDAT", b"more"), chunk("IEND", b"junk")]);
+        assert_eq!(fix(&mut png), 5);
+        assert_eq!(types(&png), vec!["IHDR", "cHRM", "PLTE", "tEXt", "bKGD", "pHYs", "IDAT", "IDAT", "IEND"]);
+        assert_eq!(png.chunks()[8].length(), 0);
+        assert!(lint(&png).is_empty());
+        assert_eq!(fix(&mut png), 0);
+    }
+
+    #[test]
+    fn test_after_iend() {
+        // as encode leaves a message and a text chunk
+        let mut png = testing_png();
+        png.append_chunk(chunk("ruSt", b"message"));
+        png.append_chunk(chunk("tEXt", b"Comment\0message"));
+        let rules: Vec<(&str, usize)> = lint(&png).iter().map(|finding| (finding.rule.id(), finding.index)).collect();
+        assert_eq!(rules, vec![("L006", 3), ("L006", 4)]);
+        assert_eq!(lint(&png)[0].to_string(), "L006: chunk 3 (ruSt) comes after IEND, which must be last [fixable]");
+
+        assert_eq!(fix(&mut png), 2);
+        assert_eq!(types(&png), vec!["IHDR", "IDAT", "ruSt", "tEXt", "IEND"]);
+        // the text chunk is still after the image data
+        assert_eq!(fix(&mut png), 1);
+        assert!(lint(&png).is_empty());
+    }
+
+    #[test]
+    fn test_ihdr_not_first() {
+        assert!(lint(&testing_png()).is_empty());
+
+        let png = Png::from_chunks(vec![chunk("ruSt", b"message"), chunk("IHDR", b"header"), chunk("IDAT", b"data"),
+            chunk("IHDR", b"header"), chunk("IEND", b"")]);
+        let rules: Vec<(&str, usize)> = lint(&png).iter().map(|finding| (finding.rule.id(), finding.index)).collect();
+        assert_eq!(rules, vec![("L007", 0), ("L007", 1), ("L007", 3)]);
+        assert_eq!(lint(&png)[0].to_string(), "L007: chunk 0 (ruSt) is first, where IHDR must be");
+        assert_eq!(lint(&png)[1].to_string(), "L007: chunk 1 (IHDR) is an IHDR chunk that is not first");
+    }
+
+    #[test]
+    fn test_unfixable() {
+        let mut png = Png::from_chunks(vec![chunk("IHDR", b"header"), chunk("PLTE", b"rgb"), chunk("PLTE", b"rgb"),
+            chunk("IDAT", b"data"), chunk("IEND", b"")]);
+        assert_eq!(fix(&mut png), 0);
+        assert_eq!(lint(&png), vec![Finding { rule: Rule::MultiplePlte, index: 2, chunk_type: String::from("PLTE") }]);
+    }
+}
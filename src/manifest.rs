@@ -0,0 +1,205 @@
+//!
+//! Manifest chunk recording a hash of every other chunk in the PNG, so any
+//! later modification of the file can be detected from the file alone
+//!
+use std::{fmt, error, str::{self, FromStr}};
+use sha2::{Digest, Sha256};
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png, Result};
+
+/// Chunk type of the manifest (ancillary, private, safe to copy)
+pub const MANIFEST_TYPE: &str = "pnMf";
+
+/// Chunk type and SHA-256 (over chunk type and data) of each chunk
+#[derive(Debug, PartialEq, Eq)]
+pub struct Manifest {
+    entries: Vec<(String, [u8; 32])>,
+}
+
+/// A difference between a PNG and its manifest
+#[derive(Debug, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The chunk at this position no longer matches its recorded hash
+    Modified { index: usize, chunk_type: String },
+    /// A chunk listed in the manifest is no longer in the PNG
+    Missing { index: usize, chunk_type: String },
+    /// The PNG contains a chunk the manifest does not list
+    Unexpected { index: usize, chunk_type: String },
+}
+
+impl Manifest {
+    fn hash(chunk: &Chunk) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(chunk.chunk_type().bytes());
+        hasher.update(chunk.data());
+        hasher.finalize().into()
+    }
+
+    /// Builds the manifest of every chunk in the PNG except manifests
+    pub fn of(png: &Png) -> Manifest {
+        let entries = png.chunks().iter()
+            .filter(|chunk| chunk.chunk_type().as_str() != MANIFEST_TYPE)
+            .map(|chunk| (chunk.chunk_type().as_str(), Manifest::hash(chunk)))
+            .collect();
+        Manifest { entries }
+    }
+
+    /// Returns the manifest stored in the PNG, if there is one
+    pub fn from_png(png: &Png) -> Result<Option<Manifest>> {
+        match png.chunk_by_type(MANIFEST_TYPE) {
+            Some(chunk) => Ok(Some(Manifest::try_from(chunk)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Lists how the PNG differs from this manifest, in chunk order
+    pub fn verify(&self, png: &Png) -> Vec<Mismatch> {
+        mismatches(&self.entries, &Manifest::of(png).entries)
+    }
+
+    /// Serializes the manifest as one "<chunk type> <sha256 hex>" line per chunk
+    pub fn to_chunk(&self) -> Chunk {
+        let data: String = self.entries.iter()
+            .map(|(chunk_type, hash)| format!("{} {}\n", chunk_type, hex::encode(hash)))
+            .collect();
+        Chunk::new(ChunkType::from_str(MANIFEST_TYPE).unwrap(), data.into_bytes())
+    }
+}
+
+/// Lists how the recorded chunk types and hashes differ from the current
+/// ones, in chunk order
+pub(crate) fn mismatches(recorded: &[(String, [u8; 32])], current: &[(String, [u8; 32])]) -> Vec<Mismatch> {
+    let mut mismatches = Vec::new();
+
+    for index in 0..recorded.len().max(current.len()) {
+        match (recorded.get(index), current.get(index)) {
+            (Some(expected), Some(actual)) if expected != actual => {
+                mismatches.push(Mismatch::Modified { index, chunk_type: actual.0.clone() });
+            },
+            (Some(expected), None) => {
+                mismatches.push(Mismatch::Missing { index, chunk_type: expected.0.clone() });
+            },
+            (None, Some(actual)) => {
+                mismatches.push(Mismatch::Unexpected { index, chunk_type: actual.0.clone() });
+            },
+            _ => {},
+        }
+    }
+    mismatches
+}
+
+/// Replaces any manifest in the PNG with an up to date one, placed right
+/// before the IEND chunk
+pub fn embed(png: &mut Png) {
+    png.retain_chunks(|chunk| chunk.chunk_type().as_str() != MANIFEST_TYPE);
+    let manifest = Manifest::of(png).to_chunk();
+
+    let index = png.chunks().iter()
+        .position(|chunk| chunk.chunk_type().as_str() == "IEND")
+        .unwrap_or(png.chunks().len());
+    png.insert_chunk(index, manifest);
+}
+
+impl TryFrom<&Chunk> for Manifest {
+    type Error = crate::Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        let text = str::from_utf8(chunk.data())?;
+        let mut entries = Vec::new();
+
+        for line in text.lines() {
+            let (chunk_type, hash) = line.split_once(' ').ok_or(InvalidManifestError)?;
+            let hash: [u8; 32] = hex::decode(hash)?.try_into().map_err(|_| InvalidManifestError)?;
+            if ChunkType::try_from(<[u8; 4]>::try_from(chunk_type.as_bytes())?).is_err() {
+                return Err(Box::new(InvalidManifestError));
+            }
+            entries.push((String::from(chunk_type), hash));
+        }
+        Ok(Manifest { entries })
+    }
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::Modified { index, chunk_type } => write!(f, "chunk {} ({}) was modified", index, chunk_type),
+            Mismatch::Missing { index, chunk_type } => write!(f, "chunk {} ({}) is missing", index, chunk_type),
+            Mismatch::Unexpected { index, chunk_type } => write!(f, "chunk {} ({}) was added", index, chunk_type),
+        }
+    }
+}
+
+/// Error specifying that a manifest chunk could not be parsed
+#[derive(Debug)]
+pub struct InvalidManifestError;
+
+impl fmt::Display for InvalidManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Manifest Chunk!")
+    }
+}
+
+impl error::Error for InvalidManifestError {}
+
+/// Error specifying that a PNG no longer matches its manifest
+#[derive(Debug)]
+pub struct ManifestMismatchError(pub usize);
+
+impl fmt::Display for ManifestMismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} chunk(s) do not match the manifest!", self.0)
+    }
+}
+
+impl error::Error for ManifestMismatchError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::testing::{chunk, testing_png};
+
+
+    #[test]
+    fn test_embed_before_iend() {
+        let mut png = testing_png();
+        embed(&mut png);
+        embed(&mut png);
+
+        let types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, vec!["IHDR", "IDAT", MANIFEST_TYPE, "IEND"]);
+    }
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let png = testing_png();
+        let manifest = Manifest::of(&png);
+        assert_eq!(Manifest::try_from(&manifest.to_chunk()).unwrap(), manifest);
+    }
+
+    #[test]
+    fn test_unmodified_png_verifies() {
+        let mut png = testing_png();
+        embed(&mut png);
+        let manifest = Manifest::from_png(&png).unwrap().unwrap();
+        assert!(manifest.verify(&png).is_empty());
+    }
+
+    #[test]
+    fn test_modifications_are_detected() {
+        let mut png = testing_png();
+        embed(&mut png);
+        let manifest = Manifest::from_png(&png).unwrap().unwrap();
+
+        png.map_chunks(|c| if c.chunk_type().as_str() == "IDAT" { chunk("IDAT", b"evil") } else { c });
+        png.append_chunk(chunk("ruSt", b"secret"));
+
+        assert_eq!(manifest.verify(&png), vec![
+            Mismatch::Modified { index: 1, chunk_type: String::from("IDAT") },
+            Mismatch::Unexpected { index: 3, chunk_type: String::from("ruSt") },
+        ]);
+    }
+
+    #[test]
+    fn test_invalid_manifest() {
+        assert!(Manifest::try_from(&chunk(MANIFEST_TYPE, b"IHDR nothex\n")).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+//!
+//! Local notes analysts attach to chunk types, either in one specific file
+//! or in every file
+//!
+use std::{path::Path, time::{SystemTime, UNIX_EPOCH}};
+use serde::{Deserialize, Serialize};
+use crate::{storage, Result};
+
+/// Name of the notes file in the data directory
+pub const NOTES_FILE: &str = "notes.json";
+
+/// A note on a chunk type. Notes without a file apply to every file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Note {
+    pub id: u64,
+    pub file: Option<String>,
+    pub chunk_type: String,
+    pub text: String,
+    /// Seconds since the Unix epoch
+    pub created: u64,
+}
+
+/// On-disk collection of notes
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct NoteStore {
+    next_id: u64,
+    notes: Vec<Note>,
+}
+
+impl NoteStore {
+    /// Loads the notes file, which is empty until the first note is added
+    pub fn load(path: &Path) -> Result<NoteStore> {
+        storage::load_json(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        storage::save_json(path, self)
+    }
+
+    /// Adds a note and returns its id
+    pub fn add(&mut self, file: Option<String>, chunk_type: &str, text: &str) -> u64 {
+        self.next_id += 1;
+        let created = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        self.notes.push(Note {
+            id: self.next_id,
+            file,
+            chunk_type: String::from(chunk_type),
+            text: String::from(text),
+            created,
+        });
+        self.next_id
+    }
+
+    /// Removes the note with the id, returning whether it existed
+    pub fn remove(&mut self, id: u64) -> bool {
+        let count = self.notes.len();
+        self.notes.retain(|note| note.id != id);
+        self.notes.len() != count
+    }
+
+    /// Returns every note
+    pub fn notes(&self) -> &[Note] {
+        &self.notes
+    }
+
+    /// Returns the notes that apply to the file: its own notes and the
+    /// notes on chunk types in every file
+    pub fn notes_for(&self, file: &str) -> Vec<&Note> {
+        self.notes.iter()
+            .filter(|note| note.file.as_deref().is_none_or(|f| f == file))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_and_remove() {
+        let mut store = NoteStore::default();
+        let first = store.add(None, "ruSt", "seen in every sample");
+        let second = store.add(Some(String::from("/a.png")), "prIv", "payload from incident 42");
+        assert_ne!(first, second);
+        assert_eq!(store.notes().len(), 2);
+
+        assert!(store.remove(first));
+        assert!(!store.remove(first));
+        assert_eq!(store.notes()[0].text, "payload from incident 42");
+    }
+
+    #[test]
+    fn test_ids_are_not_reused() {
+        let mut store = NoteStore::default();
+        let first = store.add(None, "ruSt", "note");
+        store.remove(first);
+        assert_ne!(store.add(None, "ruSt", "note"), first);
+    }
+
+    #[test]
+    fn test_notes_for_file() {
+        let mut store = NoteStore::default();
+        store.add(None, "ruSt", "everywhere");
+        store.add(Some(String::from("/a.png")), "prIv", "only a");
+        store.add(Some(String::from("/b.png")), "prIv", "only b");
+
+        let texts: Vec<&str> = store.notes_for("/a.png").iter().map(|n| n.text.as_str()).collect();
+        assert_eq!(texts, vec!["everywhere", "only a"]);
+    }
+}
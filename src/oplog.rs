@@ -0,0 +1,109 @@
+//!
+//! Structured operation log: one line per command run, appended to a log
+//! file as plain text or JSON
+//!
+use std::{fs, io::Write, path::Path, time::{Duration, SystemTime, UNIX_EPOCH}};
+use clap::ValueEnum;
+use schemars::JsonSchema;
+use serde::Serialize;
+use crate::Result;
+
+/// Layout of the lines in the log file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Record of a single operation
+#[derive(Debug, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct LogEntry {
+    /// Seconds since the Unix epoch at which the operation finished
+    pub timestamp: u64,
+    pub operation: String,
+    pub file: Option<String>,
+    /// Chunk types the operation read or wrote
+    pub chunks: Vec<String>,
+    pub duration_ms: u64,
+    /// "ok" or "error"
+    pub outcome: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl LogEntry {
+    pub fn new(operation: &str, file: Option<String>, chunks: Vec<String>, duration: Duration, error: Option<String>) -> LogEntry {
+        LogEntry {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+            operation: String::from(operation),
+            file,
+            chunks,
+            duration_ms: duration.as_millis() as u64,
+            outcome: String::from(if error.is_none() { "ok" } else { "error" }),
+            error,
+        }
+    }
+
+    /// Formats the entry as a single log line without the line break
+    pub fn to_line(&self, format: LogFormat) -> Result<String> {
+        match format {
+            LogFormat::Json => Ok(serde_json::to_string(self)?),
+            LogFormat::Text => {
+                let mut line = format!("{} {} file={} chunks={} duration_ms={} outcome={}",
+                    self.timestamp,
+                    self.operation,
+                    self.file.as_deref().unwrap_or("-"),
+                    if self.chunks.is_empty() { String::from("-") } else { self.chunks.join(",") },
+                    self.duration_ms,
+                    self.outcome);
+                if let Some(error) = &self.error {
+                    line.push_str(&format!(" error={:?}", error));
+                }
+                Ok(line)
+            },
+        }
+    }
+}
+
+/// Appends the entry to the log file, creating the file if needed
+pub fn append(path: &Path, entry: &LogEntry, format: LogFormat) -> Result<()> {
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", entry.to_line(format)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_entry(error: Option<String>) -> LogEntry {
+        let mut entry = LogEntry::new("encode", Some(String::from("dice.png")), vec![String::from("ruSt")],
+            Duration::from_millis(12), error);
+        entry.timestamp = 1700000000;
+        entry
+    }
+
+    #[test]
+    fn test_json_line() {
+        let line = testing_entry(None).to_line(LogFormat::Json).unwrap();
+        assert_eq!(line, "{\"timestamp\":1700000000,\"operation\":\"encode\",\"file\":\"dice.png\",\
+            \"chunks\":[\"ruSt\"],\"duration_ms\":12,\"outcome\":\"ok\"}");
+    }
+
+    #[test]
+    fn test_text_line() {
+        let line = testing_entry(Some(String::from("Chunk not Found!"))).to_line(LogFormat::Text).unwrap();
+        assert_eq!(line, "1700000000 encode file=dice.png chunks=ruSt duration_ms=12 outcome=error error=\"Chunk not Found!\"");
+    }
+
+    #[test]
+    fn test_append() {
+        let path = std::env::temp_dir().join(format!("pngme-oplog-test-{}.log", std::process::id()));
+        append(&path, &testing_entry(None), LogFormat::Json).unwrap();
+        append(&path, &testing_entry(None), LogFormat::Json).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(contents.lines().count(), 2);
+    }
+}
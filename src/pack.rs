@@ -0,0 +1,290 @@
+//!
+//! Deduplicating container for many variants of one carrier, such as
+//! per-recipient watermarked copies. Every distinct chunk is stored once,
+//! and each variant is the list of chunks it is made of.
+//!
+//! | Field                         | Size              |
+//! |-------------------------------|-------------------|
+//! | magic `PNGK`                  | 4                 |
+//! | version                       | 1                 |
+//! | chunk count                   | 4                 |
+//! | chunks, as in a PNG file      | variable          |
+//! | variant count                 | 4                 |
+//! | per variant: name length      | 2                 |
+//! | name (UTF-8 file name)        | variable          |
+//! | chunk index count             | 4                 |
+//! | chunk indices                 | 4 per chunk       |
+//!
+use std::{collections::HashMap, error, fmt};
+use sha2::{Digest, Sha256};
+use crate::{chunk::Chunk, png::Png, Result};
+
+pub const MAGIC: &[u8; 4] = b"PNGK";
+pub const VERSION: u8 = 1;
+
+/// Variants of a carrier sharing their common chunks
+#[derive(Default)]
+pub struct Pack {
+    chunks: Vec<Chunk>,
+    variants: Vec<(String, Vec<u32>)>,
+    /// Position of each stored chunk by the hash of its bytes
+    positions: HashMap<[u8; 32], u32>,
+}
+
+fn key(chunk: &Chunk) -> [u8; 32] {
+    Sha256::digest(chunk.as_bytes()).into()
+}
+
+/// Checks that the variant name is a plain file name, so unpacking cannot
+/// write outside of the output directory
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\', '\0'])
+}
+
+impl Pack {
+    /// Adds the PNG as a variant of the name, storing only the chunks the
+    /// container does not hold yet
+    pub fn add(&mut self, name: &str, png: &Png) -> Result<()> {
+        if !is_valid_name(name) || name.len() > u16::MAX as usize {
+            return Err(Box::new(InvalidVariantNameError(String::from(name))));
+        }
+        if self.variant(name).is_some() {
+            return Err(Box::new(DuplicateVariantError(String::from(name))));
+        }
+
+        let mut indices = Vec::new();
+        for chunk in png.chunks() {
+            let next = self.chunks.len() as u32;
+            let index = *self.positions.entry(key(chunk)).or_insert(next);
+            if index == next {
+                self.chunks.push(Chunk::try_from(&chunk.as_bytes()[..])?);
+            }
+            indices.push(index);
+        }
+        self.variants.push((String::from(name), indices));
+        Ok(())
+    }
+
+    /// Names of the variants, in the order they were added
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.variants.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Number of distinct chunks stored
+    pub fn unique_chunks(&self) -> usize {
+        self.chunks.len()
+    }
+
+    fn variant(&self, name: &str) -> Option<&[u32]> {
+        self.variants.iter().find(|(other, _)| other == name).map(|(_, indices)| &indices[..])
+    }
+
+    /// Rebuilds the variant of the name
+    pub fn unpack(&self, name: &str) -> Result<Png> {
+        let indices = self.variant(name).ok_or_else(|| VariantNotFoundError(String::from(name)))?;
+        let chunks = indices.iter()
+            .map(|&index| Chunk::try_from(&self.chunks[index as usize].as_bytes()[..]))
+            .collect::<Result<Vec<Chunk>>>()?;
+        Ok(Png::from_chunks(chunks))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.extend((self.chunks.len() as u32).to_be_bytes());
+        for chunk in &self.chunks {
+            bytes.extend(chunk.as_bytes());
+        }
+        bytes.extend((self.variants.len() as u32).to_be_bytes());
+        for (name, indices) in &self.variants {
+            bytes.extend((name.len() as u16).to_be_bytes());
+            bytes.extend(name.as_bytes());
+            bytes.extend((indices.len() as u32).to_be_bytes());
+            for index in indices {
+                bytes.extend(index.to_be_bytes());
+            }
+        }
+        bytes
+    }
+}
+
+/// Reads big-endian integers and byte runs off the front of a slice
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(Box::new(InvalidPackError));
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+}
+
+impl TryFrom<&[u8]> for Pack {
+    type Error = crate::Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Pack> {
+        let mut cursor = Cursor(bytes);
+        if cursor.take(MAGIC.len())? != MAGIC {
+            return Err(Box::new(InvalidPackError));
+        }
+        crate::envelope::negotiate(bytes)?;
+        cursor.take(1)?;
+
+        let mut pack = Pack::default();
+        for index in 0..cursor.u32()? {
+            let length = u32::from_be_bytes(cursor.0.get(..4).ok_or(InvalidPackError)?.try_into()?) as usize;
+            let chunk = Chunk::try_from(cursor.take(length.saturating_add(12))?)?;
+            pack.positions.insert(key(&chunk), index);
+            pack.chunks.push(chunk);
+        }
+
+        for _ in 0..cursor.u32()? {
+            let len = cursor.u16()? as usize;
+            let name = String::from(std::str::from_utf8(cursor.take(len)?)?);
+            if !is_valid_name(&name) || pack.variant(&name).is_some() {
+                return Err(Box::new(InvalidVariantNameError(name)));
+            }
+
+            let count = cursor.u32()?;
+            let mut indices = Vec::new();
+            for _ in 0..count {
+                let index = cursor.u32()?;
+                if index as usize >= pack.chunks.len() {
+                    return Err(Box::new(InvalidPackError));
+                }
+                indices.push(index);
+            }
+            pack.variants.push((name, indices));
+        }
+
+        if !cursor.0.is_empty() {
+            return Err(Box::new(InvalidPackError));
+        }
+        Ok(pack)
+    }
+}
+
+/// Error specifying a container that cannot be read
+#[derive(Debug)]
+pub struct InvalidPackError;
+
+impl fmt::Display for InvalidPackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Pack!")
+    }
+}
+
+impl error::Error for InvalidPackError {}
+
+/// Error specifying a variant name that is not a plain file name
+#[derive(Debug)]
+pub struct InvalidVariantNameError(pub String);
+
+impl fmt::Display for InvalidVariantNameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Variant Name! ({:?} is not a plain file name)", self.0)
+    }
+}
+
+impl error::Error for InvalidVariantNameError {}
+
+/// Error specifying a variant name the container already holds
+#[derive(Debug)]
+pub struct DuplicateVariantError(String);
+
+impl fmt::Display for DuplicateVariantError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Duplicate Variant {}!", self.0)
+    }
+}
+
+impl error::Error for DuplicateVariantError {}
+
+/// Error specifying a variant name the container does not hold
+#[derive(Debug)]
+pub struct VariantNotFoundError(String);
+
+impl fmt::Display for VariantNotFoundError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Variant {} Not Found!", self.0)
+    }
+}
+
+impl error::Error for VariantNotFoundError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::testing::chunk;
+
+    fn variant(payload: &[u8]) -> Png {
+        Png::from_chunks(vec![chunk("IHDR", b"header"), chunk("IDAT", &[7; 10_000]), chunk("ruSt", payload), chunk("IEND", b"")])
+    }
+
+    #[test]
+    fn test_pack_and_unpack() {
+        let mut pack = Pack::default();
+        pack.add("alice.png", &variant(b"for alice")).unwrap();
+        pack.add("bob.png", &variant(b"for bob")).unwrap();
+        assert_eq!(pack.unique_chunks(), 5);
+
+        let bytes = pack.to_bytes();
+        assert!(bytes.len() < 10_200);
+
+        let pack = Pack::try_from(&bytes[..]).unwrap();
+        assert_eq!(pack.names().collect::<Vec<_>>(), ["alice.png", "bob.png"]);
+        assert_eq!(pack.unpack("bob.png").unwrap().as_bytes(), variant(b"for bob").as_bytes());
+        assert!(pack.unpack("carol.png").is_err());
+    }
+
+    #[test]
+    fn test_add_after_reading() {
+        let mut pack = Pack::default();
+        pack.add("alice.png", &variant(b"for alice")).unwrap();
+        let mut pack = Pack::try_from(&pack.to_bytes()[..]).unwrap();
+
+        pack.add("bob.png", &variant(b"for bob")).unwrap();
+        assert_eq!(pack.unique_chunks(), 5);
+        assert!(pack.add("bob.png", &variant(b"again")).is_err());
+    }
+
+    #[test]
+    fn test_invalid_names() {
+        let mut pack = Pack::default();
+        for name in ["", "..", "../escape.png", "dir/file.png", r"dir\file.png"] {
+            assert!(pack.add(name, &variant(b"")).is_err());
+        }
+
+        // a crafted container naming a path outside of the output directory
+        pack.add("abc.png", &variant(b"")).unwrap();
+        let bytes = pack.to_bytes();
+        let crafted = [&bytes[..bytes.len() - 4 * 4 - 4 - 7], b"../.png", &bytes[bytes.len() - 4 * 4 - 4..]].concat();
+        assert!(Pack::try_from(&crafted[..]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_pack() {
+        let mut pack = Pack::default();
+        pack.add("alice.png", &variant(b"for alice")).unwrap();
+        let bytes = pack.to_bytes();
+        assert!(Pack::try_from(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Pack::try_from(&bytes[1..]).is_err());
+
+        // an index past the stored chunks
+        let mut bytes = bytes;
+        let last = bytes.len() - 1;
+        bytes[last] = 200;
+        assert!(Pack::try_from(&bytes[..]).is_err());
+    }
+}
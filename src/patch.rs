@@ -0,0 +1,262 @@
+//!
+//! Chunk-level binary patches between two PNG files, so a large carrier can
+//! be shipped once and later versions of its payloads as small patches.
+//!
+//! | Field             | Size     |
+//! |-------------------|----------|
+//! | magic `PNGP`      | 4        |
+//! | version           | 1        |
+//! | SHA-256 of base   | 32       |
+//! | SHA-256 of result | 32       |
+//! | operation count   | 4        |
+//! | operations        | variable |
+//!
+//! An operation either copies a run of chunks from the base (tag 0, then
+//! the index of the first chunk and the number of chunks) or inserts a new
+//! chunk (tag 1, then the chunk as it appears in a PNG file).
+//!
+use std::{collections::HashMap, error, fmt};
+use sha2::{Digest, Sha256};
+use crate::{chunk::Chunk, png::Png, Result};
+
+pub const MAGIC: &[u8; 4] = b"PNGP";
+pub const VERSION: u8 = 1;
+const HEADER_LEN: usize = 4 + 1 + 32 + 32 + 4;
+
+const COPY: u8 = 0;
+const INSERT: u8 = 1;
+
+/// One step in rebuilding the modified file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Operation {
+    /// Copies `count` chunks of the base, starting at `start`
+    Copy { start: u32, count: u32 },
+    /// Inserts a chunk the base does not have, kept as it appears in a PNG
+    /// file
+    Insert(Vec<u8>),
+}
+
+/// Changes turning one PNG into another
+#[derive(Debug, PartialEq, Eq)]
+pub struct Patch {
+    base: [u8; 32],
+    result: [u8; 32],
+    pub operations: Vec<Operation>,
+}
+
+fn hash(png: &Png) -> [u8; 32] {
+    Sha256::digest(png.as_bytes()).into()
+}
+
+fn same(a: &Chunk, b: &Chunk) -> bool {
+    a.chunk_type() == b.chunk_type() && a.data() == b.data()
+}
+
+impl Patch {
+    /// Builds the patch from the base to the modified PNG. Each chunk of
+    /// the modified PNG is taken from the longest run of equal chunks in the
+    /// base that continues from it, and inserted when the base lacks it.
+    pub fn create(base: &Png, modified: &Png) -> Patch {
+        let key = |chunk: &Chunk| -> [u8; 32] { Sha256::digest(chunk.as_bytes()).into() };
+        let mut positions: HashMap<[u8; 32], Vec<usize>> = HashMap::new();
+        for (index, chunk) in base.chunks().iter().enumerate() {
+            positions.entry(key(chunk)).or_default().push(index);
+        }
+
+        let (from, to) = (base.chunks(), modified.chunks());
+        let mut operations = Vec::new();
+        let mut next = 0;
+        while next < to.len() {
+            let run = positions.get(&key(&to[next])).and_then(|starts| starts.iter()
+                .map(|&start| {
+                    let count = from[start..].iter().zip(&to[next..]).take_while(|(a, b)| same(a, b)).count();
+                    (start, count)
+                })
+                .max_by_key(|&(_, count)| count));
+
+            match run {
+                Some((start, count)) => {
+                    operations.push(Operation::Copy { start: start as u32, count: count as u32 });
+                    next += count;
+                },
+                None => {
+                    operations.push(Operation::Insert(to[next].as_bytes()));
+                    next += 1;
+                },
+            }
+        }
+        Patch { base: hash(base), result: hash(modified), operations }
+    }
+
+    /// Rebuilds the modified PNG from the base the patch was created
+    /// against, checking the hashes of both
+    pub fn apply(&self, base: &Png) -> Result<Png> {
+        if hash(base) != self.base {
+            return Err(Box::new(PatchMismatchError("the PNG is not the base the patch was created against")));
+        }
+
+        let mut chunks = Vec::new();
+        for operation in &self.operations {
+            match operation {
+                Operation::Copy { start, count } => {
+                    let run = base.chunks().get(*start as usize..(*start as usize).saturating_add(*count as usize))
+                        .ok_or(InvalidPatchError)?;
+                    for chunk in run {
+                        chunks.push(Chunk::try_from(&chunk.as_bytes()[..])?);
+                    }
+                },
+                Operation::Insert(bytes) => chunks.push(Chunk::try_from(&bytes[..])?),
+            }
+        }
+
+        let png = Png::from_chunks(chunks);
+        if hash(&png) != self.result {
+            return Err(Box::new(PatchMismatchError("the patched PNG does not match the one the patch was created from")));
+        }
+        Ok(png)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.extend(self.base);
+        bytes.extend(self.result);
+        bytes.extend((self.operations.len() as u32).to_be_bytes());
+        for operation in &self.operations {
+            match operation {
+                Operation::Copy { start, count } => {
+                    bytes.push(COPY);
+                    bytes.extend(start.to_be_bytes());
+                    bytes.extend(count.to_be_bytes());
+                },
+                Operation::Insert(chunk) => {
+                    bytes.push(INSERT);
+                    bytes.extend(chunk);
+                },
+            }
+        }
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for Patch {
+    type Error = crate::Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Patch> {
+        if bytes.len() < HEADER_LEN || &bytes[..4] != MAGIC {
+            return Err(Box::new(InvalidPatchError));
+        }
+        crate::envelope::negotiate(bytes)?;
+
+        let count = u32::from_be_bytes(bytes[69..HEADER_LEN].try_into()?);
+        let mut operations = Vec::new();
+        let mut rest = &bytes[HEADER_LEN..];
+        for _ in 0..count {
+            let (&tag, tail) = rest.split_first().ok_or(InvalidPatchError)?;
+            let (operation, len) = match tag {
+                COPY if tail.len() >= 8 => {
+                    let start = u32::from_be_bytes(tail[..4].try_into()?);
+                    let count = u32::from_be_bytes(tail[4..8].try_into()?);
+                    (Operation::Copy { start, count }, 8)
+                },
+                INSERT if tail.len() >= 12 => {
+                    let len = 12 + u32::from_be_bytes(tail[..4].try_into()?) as usize;
+                    let chunk = tail.get(..len).ok_or(InvalidPatchError)?;
+                    Chunk::try_from(chunk)?;
+                    (Operation::Insert(chunk.to_vec()), len)
+                },
+                _ => return Err(Box::new(InvalidPatchError)),
+            };
+            operations.push(operation);
+            rest = &tail[len..];
+        }
+        if !rest.is_empty() {
+            return Err(Box::new(InvalidPatchError));
+        }
+
+        Ok(Patch {
+            base: bytes[5..37].try_into()?,
+            result: bytes[37..69].try_into()?,
+            operations,
+        })
+    }
+}
+
+/// Error specifying a patch file that cannot be read
+#[derive(Debug)]
+pub struct InvalidPatchError;
+
+impl fmt::Display for InvalidPatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Patch!")
+    }
+}
+
+impl error::Error for InvalidPatchError {}
+
+/// Error specifying a patch applied to a PNG it was not created for
+#[derive(Debug)]
+pub struct PatchMismatchError(&'static str);
+
+impl fmt::Display for PatchMismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Patch Does Not Apply! ({})", self.0)
+    }
+}
+
+impl error::Error for PatchMismatchError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::testing::chunk;
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            chunk("IHDR", b"header"),
+            chunk("IDAT", &[1; 5000]),
+            chunk("IDAT", &[2; 5000]),
+            chunk("ruSt", b"old payload"),
+            chunk("IEND", b""),
+        ])
+    }
+
+    #[test]
+    fn test_create_and_apply() {
+        let base = testing_png();
+        let mut modified = testing_png();
+        modified.remove_chunk("ruSt").unwrap();
+        modified.append_chunk(chunk("ruSt", b"new payload"));
+
+        let patch = Patch::create(&base, &modified);
+        assert_eq!(patch.operations, vec![
+            Operation::Copy { start: 0, count: 3 },
+            Operation::Copy { start: 4, count: 1 },
+            Operation::Insert(chunk("ruSt", b"new payload").as_bytes()),
+        ]);
+
+        let bytes = patch.to_bytes();
+        assert!(bytes.len() < 200);
+        let patch = Patch::try_from(&bytes[..]).unwrap();
+        assert_eq!(patch.apply(&base).unwrap().as_bytes(), modified.as_bytes());
+    }
+
+    #[test]
+    fn test_apply_to_other_base() {
+        let mut modified = testing_png();
+        modified.append_chunk(chunk("ruSt", b"another"));
+        let patch = Patch::create(&testing_png(), &modified);
+        assert!(patch.apply(&modified).is_err());
+    }
+
+    #[test]
+    fn test_invalid_patch() {
+        let bytes = Patch::create(&testing_png(), &testing_png()).to_bytes();
+        assert!(Patch::try_from(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Patch::try_from(&bytes[1..]).is_err());
+
+        let mut extended = bytes.clone();
+        extended.push(0);
+        assert!(Patch::try_from(&extended[..]).is_err());
+    }
+}
@@ -0,0 +1,269 @@
+//!
+//! Looks for messages everywhere pngme puts them: chunks of types the PNG
+//! specification does not register, and bytes after the IEND chunk. Each
+//! payload is classified by its header and opened when no key is needed,
+//! or when the password given opens it. Files other tools append to
+//! images, such as zip archives, are recognized by their signatures.
+//!
+use std::fmt;
+use crate::{audit::AUDIT_TYPE, checksum, compress, crypto, ecc, fanout::WATERMARK_TYPE, fileinfo, manifest::MANIFEST_TYPE, placement::REPLICA_TYPE, 
+    png::Png, provenance, seal::SEAL_TYPE, shares::SHARE_TYPE, thumbnail::THUMBNAIL_TYPE, whiten};
+#[cfg(feature = "pgp")]
+use crate::pgp;
+#[cfg(feature = "fido")]
+use crate::auth;
+
+/// Chunk types pngme writes for its own bookkeeping rather than messages.
+/// A single share does not reveal anything, so shares are left to combine.
+pub const METADATA_TYPES: [&str; 6] = [MANIFEST_TYPE, AUDIT_TYPE, SEAL_TYPE, THUMBNAIL_TYPE, WATERMARK_TYPE, SHARE_TYPE];
+
+/// Name, extension and signature of file formats commonly appended to
+/// images by other tools (or hidden with encode --file)
+const FILE_SIGNATURES: [(&str, &str, &[u8]); 7] = [
+    ("zip archive", "zip", b"PK\x03\x04"),
+    ("RAR archive", "rar", b"Rar!\x1a\x07"),
+    ("7-Zip archive", "7z", b"7z\xbc\xaf\x27\x1c"),
+    ("gzip data", "gz", b"\x1f\x8b"),
+    ("PDF document", "pdf", b"%PDF-"),
+    ("PNG image", "png", b"\x89PNG\r\n\x1a\n"),
+    ("JPEG image", "jpg", b"\xff\xd8\xff"),
+];
+
+/// Where a payload was found
+#[derive(Debug, PartialEq, Eq)]
+pub enum Location {
+    /// Index and type of the chunk
+    Chunk(usize, String),
+    /// Bytes after the IEND chunk
+    Trailer,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Location::Chunk(index, chunk_type) => write!(f, "chunk {} ({})", index, chunk_type),
+            Location::Trailer => write!(f, "after IEND"),
+        }
+    }
+}
+
+impl Location {
+    /// Name for a file the payload is extracted to, without extension
+    pub fn file_stem(&self) -> String {
+        match self {
+            Location::Chunk(index, chunk_type) => format!("chunk-{}-{}", index, chunk_type),
+            Location::Trailer => String::from("trailer"),
+        }
+    }
+}
+
+/// How a payload is encoded, as told by its header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Plain,
+    Whitened,
+    Authenticated,
+    Encrypted,
+    Age,
+    #[cfg(feature = "pgp")]
+    Pgp,
+    #[cfg(feature = "fido")]
+    Fido,
+    /// A file in a format other than pngme's, by its name and extension
+    File(&'static str, &'static str),
+}
+
+impl Format {
+    pub fn of(payload: &[u8]) -> Format {
+        match payload {
+            p if crypto::is_encrypted(p) => Format::Encrypted,
+            p if crypto::is_age_encrypted(p) => Format::Age,
+            #[cfg(feature = "pgp")]
+            p if pgp::is_pgp_encrypted(p) => Format::Pgp,
+            #[cfg(feature = "fido")]
+            p if auth::is_fido_wrapped(p) => Format::Fido,
+            p if crypto::is_authenticated(p) => Format::Authenticated,
+            p if whiten::is_whitened(p) => Format::Whitened,
+            p => FILE_SIGNATURES.iter()
+                .find(|(_, _, signature)| p.starts_with(signature))
+                .map_or(Format::Plain, |&(name, extension, _)| Format::File(name, extension)),
+        }
+    }
+
+    /// Extension of a file holding the opened payload
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::File(_, extension) => extension,
+            _ => "bin",
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Format::Plain => "plain",
+            Format::Whitened => "obfuscated (--obfuscate)",
+            Format::Authenticated => "HMAC-tagged (--hmac-key)",
+            Format::Encrypted => "password-encrypted (--encrypt)",
+            Format::Age => "age-encrypted (--recipient)",
+            #[cfg(feature = "pgp")]
+            Format::Pgp => "OpenPGP-encrypted (--pgp-recipient)",
+            #[cfg(feature = "fido")]
+            Format::Fido => "security key encrypted (--fido)",
+            Format::File(name, _) => name,
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A payload and, if it could be opened, its message
+#[derive(Debug)]
+pub struct Finding {
+    pub location: Location,
+    pub format: Format,
+    pub len: usize,
+    /// Cipher and KDF of a password-encrypted payload, or the name and
+    /// properties of a file encoded with --file
+    pub details: Option<String>,
+    pub message: Option<Vec<u8>>,
+}
+
+/// Lists the payloads in the PNG, opening encrypted ones with the password
+/// when it is given. Only a PNG parsed with `preserve` keeps its trailer.
+pub fn peek(png: &Png, password: Option<&[u8]>) -> Vec<Finding> {
+    let chunks = png.chunks().iter().enumerate()
+        .filter(|(_, chunk)| !chunk.chunk_type().is_registered()
+            && !METADATA_TYPES.contains(&chunk.chunk_type().as_str().as_str()))
+        .map(|(index, chunk)| {
+            // copies made by resilient placement start with the chunk type
+            let payload = match chunk.chunk_type().as_str().as_str() {
+                REPLICA_TYPE => chunk.data().get(4..).unwrap_or_default(),
+                _ => chunk.data(),
+            };
+            (Location::Chunk(index, chunk.chunk_type().to_string()), payload)
+        });
+    let trailer = Some((Location::Trailer, png.trailer())).filter(|(_, trailer)| !trailer.is_empty());
+
+    chunks.chain(trailer)
+        .map(|(location, stored)| {
+            // payloads protected with encode --ecc are repaired first
+            let payload = match ecc::is_protected(stored) {
+                true => ecc::recover(stored).map_or_else(|_| stored.to_vec(), |(payload, _)| payload),
+                false => stored.to_vec(),
+            };
+            let payload = &payload[..];
+            let format = Format::of(payload);
+            let details = match format {
+                Format::Encrypted => crypto::payload_cipher(payload).ok()
+                    .zip(crypto::payload_kdf(payload).ok())
+                    .map(|(cipher, kdf)| format!("{}, {}", cipher.name(), kdf)),
+                _ => None,
+            };
+            let message = open(format, payload, password);
+            let join = |details: Option<String>, more: String| Some(match details {
+                Some(details) => format!("{}, {}", details, more),
+                None => more,
+            });
+
+            // messages encoded with --author or --note carry who encoded
+            // them and when
+            let recorded = message.as_deref()
+                .and_then(|message| provenance::unwrap(message).ok())
+                .map(|(provenance, message)| (provenance.to_string(), message.to_vec()));
+            let (details, message) = match recorded {
+                Some((provenance, message)) => (join(details, provenance), Some(message)),
+                None => (details, message),
+            };
+
+            // files encoded with --file carry their name and properties
+            let file = message.as_deref()
+                .and_then(|message| fileinfo::unwrap(message).ok())
+                .map(|(info, contents)| (info.to_string(), contents.to_vec()));
+            match file {
+                Some((info, contents)) => Finding { location, format, len: stored.len(), details: join(details, info), message: Some(contents) },
+                None => Finding { location, format, len: stored.len(), details, message },
+            }
+        })
+        .collect()
+}
+
+/// Returns the message of a payload that needs no key or that the password
+/// opens
+fn open(format: Format, payload: &[u8], password: Option<&[u8]>) -> Option<Vec<u8>> {
+    let message = match (format, password) {
+        (Format::Plain | Format::File(..), _) => Some(payload.to_vec()),
+        (Format::Whitened, _) => whiten::unwhiten(payload).ok(),
+        (Format::Authenticated, _) => crypto::unverified_message(payload).ok().map(<[u8]>::to_vec),
+        (Format::Encrypted, Some(password)) => crypto::decrypt(password, payload).ok(),
+        _ => None,
+    }?;
+    let message = match compress::is_compressed(&message) {
+        true => compress::decompress(&message, compress::DEFAULT_MAX_LEN).ok()?,
+        false => message,
+    };
+    match checksum::is_checksummed(&message) {
+        true => checksum::verify(&message).ok().map(<[u8]>::to_vec),
+        false => Some(message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chunk::Chunk, png::{testing::chunk, ParseOptions}};
+
+    fn testing_png(extra: Vec<Chunk>) -> Png {
+        let mut chunks = vec![chunk("IHDR", b"header"), chunk("tEXt", b"Title\0x"), chunk("IDAT", b"data")];
+        chunks.extend(extra);
+        chunks.push(chunk("IEND", b""));
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_locations() {
+        let png = testing_png(vec![chunk("ruSt", b"plain"), chunk(MANIFEST_TYPE, b"ignored"), chunk("prIv", &whiten::whiten(b"hidden"))]);
+        let mut bytes = png.as_bytes();
+        bytes.extend(b"appended");
+        let png = Png::parse(&bytes, &ParseOptions { preserve: true, ..ParseOptions::default() }).unwrap();
+
+        let findings = peek(&png, None);
+        let found: Vec<(&Location, Format, Option<&[u8]>)> = findings.iter()
+            .map(|f| (&f.location, f.format, f.message.as_deref()))
+            .collect();
+        assert_eq!(found, vec![
+            (&Location::Chunk(3, String::from("ruSt")), Format::Plain, Some(&b"plain"[..])),
+            (&Location::Chunk(5, String::from("prIv")), Format::Whitened, Some(&b"hidden"[..])),
+            (&Location::Trailer, Format::Plain, Some(&b"appended"[..])),
+        ]);
+    }
+
+    #[test]
+    fn test_appended_files() {
+        let mut bytes = testing_png(vec![chunk("ruSt", b"%PDF-1.7")]).as_bytes();
+        bytes.extend(b"PK\x03\x04\x14\x00\x00\x00");
+        let png = Png::parse(&bytes, &ParseOptions { preserve: true, ..ParseOptions::default() }).unwrap();
+
+        let findings = peek(&png, None);
+        assert_eq!(findings[0].format, Format::File("PDF document", "pdf"));
+        assert_eq!(findings[1].location, Location::Trailer);
+        assert_eq!(findings[1].format.extension(), "zip");
+        assert_eq!(findings[1].message.as_ref().unwrap().len(), 8);
+    }
+
+    #[cfg(feature = "chacha20poly1305")]
+    #[test]
+    fn test_password() {
+        let kdf = crypto::Kdf::Argon2 { memory_kib: 64, iterations: 1, parallelism: 1 };
+        let cipher = crypto::CipherKind::default().cipher().unwrap();
+        let png = testing_png(vec![chunk("ruSt", &crypto::encrypt_with(cipher, kdf, b"password", b"secret").unwrap())]);
+
+        let findings = peek(&png, None);
+        assert_eq!(findings[0].format, Format::Encrypted);
+        assert!(findings[0].details.is_some());
+        assert!(findings[0].message.is_none());
+
+        assert!(peek(&png, Some(b"wrong"))[0].message.is_none());
+        assert_eq!(peek(&png, Some(b"password"))[0].message.as_deref(), Some(&b"secret"[..]));
+    }
+}
@@ -0,0 +1,97 @@
+//!
+//! OpenPGP payload encryption by handing the payload to the user's gpg,
+//! so existing keyrings and agents are used for recipients and decryption
+//!
+use std::{env, fmt, error, io::Write, process::{Command, Stdio}, thread};
+use crate::Result;
+
+/// Environment variable naming the gpg executable, `gpg` by default
+pub const GPG_VAR: &str = "PNGME_GPG";
+
+/// Checks if the payload starts with an OpenPGP public-key encrypted
+/// session key packet, which every message encrypted to a key begins with
+pub fn is_pgp_encrypted(payload: &[u8]) -> bool {
+    match payload.first() {
+        // new packet format: tag in the low six bits
+        Some(&b) if b & 0xc0 == 0xc0 => b & 0x3f == 1,
+        // old packet format: tag in bits 2-5
+        Some(&b) if b & 0xc0 == 0x80 => (b >> 2) & 0x0f == 1,
+        _ => false,
+    }
+}
+
+/// Encrypts the plaintext to the public keys (key ids, fingerprints or user
+/// ids) in the user's keyring. gpg's trust model applies, so a key that is
+/// not valid in the keyring (signed or trusted by the user) is refused
+/// rather than encrypted to.
+pub fn encrypt(recipients: &[String], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let mut args = vec![String::from("--encrypt")];
+    for recipient in recipients {
+        args.push(String::from("--recipient"));
+        args.push(recipient.clone());
+    }
+    gpg(&args, plaintext)
+}
+
+/// Decrypts the payload with a secret key from the user's keyring. gpg
+/// asks for the passphrase through its agent when needed.
+pub fn decrypt(payload: &[u8]) -> Result<Vec<u8>> {
+    gpg(&[String::from("--decrypt")], payload)
+}
+
+/// Runs gpg on the input, returning its output
+fn gpg(args: &[String], input: &[u8]) -> Result<Vec<u8>> {
+    let program = env::var(GPG_VAR).unwrap_or_else(|_| String::from("gpg"));
+    let mut child = Command::new(&program)
+        .args(["--batch", "--yes", "--quiet", "--output", "-"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| GpgError(format!("could not run {}: {}", program, e)))?;
+
+    // write from another thread so a full output pipe cannot block gpg
+    let mut stdin = child.stdin.take().unwrap();
+    let input = input.to_vec();
+    let writer = thread::spawn(move || stdin.write_all(&input));
+
+    let output = child.wait_with_output()?;
+    writer.join().unwrap()?;
+
+    if !output.status.success() {
+        return Err(Box::new(GpgError(String::from_utf8_lossy(&output.stderr).trim().to_string())));
+    }
+    Ok(output.stdout)
+}
+
+/// Error specifying that gpg failed
+#[derive(Debug)]
+pub struct GpgError(String);
+
+impl fmt::Display for GpgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "gpg Failed! ({})", self.0)
+    }
+}
+
+impl error::Error for GpgError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_pgp_encrypted() {
+        // old format PKESK packets with one and two byte lengths
+        assert!(is_pgp_encrypted(&[0x84, 0x5e, 0x03]));
+        assert!(is_pgp_encrypted(&[0x85, 0x01, 0x0c]));
+        // new format PKESK packet
+        assert!(is_pgp_encrypted(&[0xc1, 0x5e, 0x03]));
+
+        // literal data packet and plain text
+        assert!(!is_pgp_encrypted(&[0xcb, 0x0d]));
+        assert!(!is_pgp_encrypted(b"plain message"));
+        assert!(!is_pgp_encrypted(b""));
+    }
+}
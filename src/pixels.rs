@@ -0,0 +1,182 @@
+//!
+//! Pixel layer: decoding PNG image data into 8-bit RGBA pixels and back
+//!
+use std::io::{Cursor, Write};
+use ::png::{BitDepth, ColorType, Decoder, Encoder, Transformations};
+use crate::{cancel::CancellationToken, Result};
+
+/// Image as rows of 8-bit RGBA pixels
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    /// Decodes a complete PNG file, converting every color type and bit
+    /// depth to 8-bit RGBA
+    pub fn decode(bytes: &[u8]) -> Result<RgbaImage> {
+        let mut decoder = Decoder::new(Cursor::new(bytes));
+        decoder.set_transformations(Transformations::normalize_to_color8() | Transformations::ALPHA);
+        let mut reader = decoder.read_info()?;
+
+        let mut buf = vec![0; reader.output_buffer_size().unwrap_or(0)];
+        let info = reader.next_frame(&mut buf)?;
+        buf.truncate(info.buffer_size());
+
+        let pixels: Vec<u8> = match info.color_type {
+            ColorType::Rgba => buf,
+            ColorType::Rgb => buf.chunks(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+            ColorType::GrayscaleAlpha => buf.chunks(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+            ColorType::Grayscale => buf.iter().flat_map(|&v| [v, v, v, 255]).collect(),
+            // palettes are always expanded by the transformations above
+            ColorType::Indexed => unreachable!(),
+        };
+
+        Ok(RgbaImage { width: info.width, height: info.height, pixels })
+    }
+
+    /// Encodes the image as a complete 8-bit RGBA PNG file
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        self.encode_with_cancel(&CancellationToken::new())
+    }
+
+    /// Encodes the image like `encode`, checking the token between rows
+    pub fn encode_with_cancel(&self, cancel: &CancellationToken) -> Result<Vec<u8>> {
+        let mut bytes: Vec<u8> = Vec::new();
+        let mut encoder = Encoder::new(&mut bytes, self.width, self.height);
+        encoder.set_color(ColorType::Rgba);
+        encoder.set_depth(BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        let mut stream = writer.stream_writer()?;
+        for row in self.pixels.chunks((self.width as usize * 4).max(1)) {
+            cancel.check()?;
+            stream.write_all(row)?;
+        }
+        stream.finish()?;
+        writer.finish()?;
+
+        // the stream writer splits the image data, so store it in one chunk
+        // like a single write would
+        let mut png = crate::png::Png::try_from(&bytes[..])?;
+        png.merge_idat_with_cancel(None, cancel)?;
+        Ok(png.as_bytes())
+    }
+
+    /// Shrinks the image to fit in a `max_size` square, keeping its aspect
+    /// ratio. Each output pixel averages the block of pixels it covers.
+    pub fn downscale(&self, max_size: u32) -> RgbaImage {
+        self.downscale_with_cancel(max_size, &CancellationToken::new())
+            .expect("a new token is never cancelled")
+    }
+
+    /// Shrinks the image like `downscale`, checking the token between rows
+    pub fn downscale_with_cancel(&self, max_size: u32, cancel: &CancellationToken) -> Result<RgbaImage> {
+        let scale = (self.width.max(self.height) as f64 / max_size.max(1) as f64).max(1.0);
+        let width = ((self.width as f64 / scale).round() as u32).max(1);
+        let height = ((self.height as f64 / scale).round() as u32).max(1);
+
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            cancel.check()?;
+            let (y0, y1) = Self::span(y, height, self.height);
+            for x in 0..width {
+                let (x0, x1) = Self::span(x, width, self.width);
+
+                let mut sums = [0u64; 4];
+                for sy in y0..y1 {
+                    for sx in x0..x1 {
+                        let i = ((sy * self.width + sx) * 4) as usize;
+                        for (sum, &v) in sums.iter_mut().zip(&self.pixels[i..i + 4]) {
+                            *sum += v as u64;
+                        }
+                    }
+                }
+
+                let count = ((y1 - y0) * (x1 - x0)) as u64;
+                pixels.extend(sums.iter().map(|sum| (sum / count) as u8));
+            }
+        }
+
+        Ok(RgbaImage { width, height, pixels })
+    }
+
+    /// Cuts out the rectangle at (x, y), clamped to the image
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> RgbaImage {
+        let x = x.min(self.width);
+        let y = y.min(self.height);
+        let width = width.min(self.width - x);
+        let height = height.min(self.height - y);
+
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for row in y..y + height {
+            let start = ((row * self.width + x) * 4) as usize;
+            pixels.extend(&self.pixels[start..start + (width * 4) as usize]);
+        }
+        RgbaImage { width, height, pixels }
+    }
+
+    /// Range of source positions covered by output position `i` of `n`
+    fn span(i: u32, n: u32, source: u32) -> (u32, u32) {
+        let start = (i as u64 * source as u64 / n as u64) as u32;
+        let end = ((i as u64 + 1) * source as u64 / n as u64) as u32;
+        (start, end.max(start + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkerboard(width: u32, height: u32) -> RgbaImage {
+        let pixels = (0..width * height)
+            .flat_map(|i| if (i % width + i / width).is_multiple_of(2) { [0, 0, 0, 255] } else { [255, 255, 255, 255] })
+            .collect();
+        RgbaImage { width, height, pixels }
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() {
+        let image = checkerboard(5, 3);
+        let decoded = RgbaImage::decode(&image.encode().unwrap()).unwrap();
+        assert_eq!(decoded, image);
+    }
+
+    #[test]
+    fn test_cancel() {
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        assert!(checkerboard(5, 3).encode_with_cancel(&cancel).is_err());
+        assert!(checkerboard(100, 50).downscale_with_cancel(10, &cancel).is_err());
+    }
+
+    #[test]
+    fn test_downscale_keeps_aspect_ratio() {
+        let thumbnail = checkerboard(100, 50).downscale(10);
+        assert_eq!((thumbnail.width, thumbnail.height), (10, 5));
+        assert_eq!(thumbnail.pixels.len(), 10 * 5 * 4);
+    }
+
+    #[test]
+    fn test_downscale_averages() {
+        let thumbnail = checkerboard(2, 2).downscale(1);
+        assert_eq!(thumbnail.pixels, vec![127, 127, 127, 255]);
+    }
+
+    #[test]
+    fn test_crop() {
+        let image = checkerboard(4, 3);
+        let cropped = image.crop(1, 1, 2, 5);
+        assert_eq!((cropped.width, cropped.height), (2, 2));
+        assert_eq!(&cropped.pixels[..8], &image.pixels[20..28]);
+        assert_eq!(image.crop(0, 0, 4, 3), image);
+    }
+
+    #[test]
+    fn test_downscale_never_enlarges() {
+        let image = checkerboard(4, 4);
+        assert_eq!(image.downscale(64), image);
+    }
+}
@@ -0,0 +1,263 @@
+//!
+//! Resilient placement: extra copies of a payload in chunks that image
+//! processors tend to keep, so a message survives pipelines that strip
+//! some kinds of chunks. Besides the chunk of the requested type, one copy
+//! goes into a private safe-to-copy chunk (the chunk type followed by the
+//! payload) and one into an iTXt chunk under the keyword `pngme:<type>`
+//! (the payload in base64). Every copy, the chunk of the requested type
+//! included, goes before IEND, as processors drop whatever follows it.
+//!
+use std::{error, fmt, str::FromStr};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use clap::ValueEnum;
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png, text, Result};
+
+/// Chunk type of the payload copies (ancillary, private, safe to copy)
+pub const REPLICA_TYPE: &str = "pnRp";
+
+/// Prefix of the keyword of the iTXt copies, followed by the chunk type
+pub const KEYWORD_PREFIX: &str = "pngme:";
+
+/// Where encode stores the payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Placement {
+    /// Only in a chunk of the requested type
+    #[default]
+    Standard,
+    /// Also in a private safe-to-copy chunk and an iTXt chunk
+    Resilient,
+}
+
+/// What encode does when the file already holds chunks of the chunk type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Mode {
+    /// Keep the existing chunks and add another message
+    #[default]
+    Append,
+    /// Remove the existing chunks of the type, their shards and copies
+    Replace,
+    /// Refuse to encode
+    Fail,
+}
+
+/// Prepares the PNG for a new payload under the chunk type: keeps the
+/// chunks already using it (`Append`), removes them along with the copies
+/// `replicate` made (`Replace`) or refuses if there are any (`Fail`)
+pub fn make_room(png: &mut Png, chunk_type: &str, mode: Mode) -> Result<()> {
+    let uses_type = |chunk: &Chunk| chunk.chunk_type().as_str() == chunk_type || is_copy(chunk, chunk_type);
+    match mode {
+        Mode::Append => {},
+        // the chunks of registered types are part of the image
+        Mode::Replace if ChunkType::from_str(chunk_type)?.is_registered()
+        => return Err(Box::new(RegisteredChunkTypeError(String::from(chunk_type)))),
+        Mode::Replace => png.retain_chunks(|chunk| !uses_type(chunk)),
+        Mode::Fail if png.chunks().iter().any(uses_type)
+        => return Err(Box::new(ChunkTypeInUseError(String::from(chunk_type)))),
+        Mode::Fail => {},
+    }
+    Ok(())
+}
+
+/// Adds the copies of the payload stored under the chunk type, placed
+/// right before the IEND chunk
+pub fn replicate(png: &mut Png, chunk_type: &str, payload: &[u8]) {
+    let index = png.chunks().iter()
+        .position(|chunk| chunk.chunk_type().as_str() == "IEND")
+        .unwrap_or(png.chunks().len());
+
+    png.insert_chunk(index, replica(chunk_type, payload));
+    png.insert_chunk(index + 1, text_copy(chunk_type, payload));
+}
+
+/// Returns the private chunk copy of a payload stored under the chunk type
+fn replica(chunk_type: &str, payload: &[u8]) -> Chunk {
+    Chunk::new(ChunkType::from_str(REPLICA_TYPE).unwrap(), [chunk_type.as_bytes(), payload].concat())
+}
+
+/// Returns the iTXt copy of a payload stored under the chunk type
+fn text_copy(chunk_type: &str, payload: &[u8]) -> Chunk {
+    text::itxt(&format!("{}{}", KEYWORD_PREFIX, chunk_type), &STANDARD.encode(payload))
+}
+
+/// Moves the chunks from `start` to the end of the PNG, in order, in front
+/// of the IEND chunk before them, where encode's copies are
+pub fn move_before_iend(png: &mut Png, start: usize) {
+    let iend = match png.chunks()[..start].iter().position(|chunk| chunk.chunk_type().as_str() == "IEND") {
+        Some(iend) => iend,
+        None => return,
+    };
+    let moved: Vec<Chunk> = png.chunks()[start..].iter().map(|c| Chunk::try_from(&c.as_bytes()[..]).unwrap()).collect();
+    let mut index = 0;
+    png.retain_chunks(|_| {
+        index += 1;
+        index <= start
+    });
+    for (i, chunk) in moved.into_iter().enumerate() {
+        png.insert_chunk(iend + i, chunk);
+    }
+}
+
+/// Checks if the chunk is a copy made by `replicate` of a payload stored
+/// under the chunk type
+pub fn is_copy(chunk: &Chunk, chunk_type: &str) -> bool {
+    copy_payload(chunk, chunk_type).is_some()
+}
+
+/// Returns the payload of a copy of the chunk type, or None if the chunk
+/// is not one (or is too damaged to read)
+fn copy_payload(chunk: &Chunk, chunk_type: &str) -> Option<Vec<u8>> {
+    match chunk.chunk_type().as_str().as_str() {
+        REPLICA_TYPE => chunk.data().strip_prefix(chunk_type.as_bytes()).map(<[u8]>::to_vec),
+        "iTXt" => match text::parse(chunk) {
+            Ok(Some(entry)) if entry.keyword.strip_prefix(KEYWORD_PREFIX) == Some(chunk_type)
+            => STANDARD.decode(entry.text).ok(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns the payload the chunk stores under the chunk type, as a chunk
+/// of that type or as a copy, if it stores one
+pub fn stored_payload(chunk: &Chunk, chunk_type: &str) -> Option<Vec<u8>> {
+    if chunk.chunk_type().as_str() == chunk_type {
+        return Some(chunk.data().to_vec());
+    }
+    copy_payload(chunk, chunk_type)
+}
+
+/// Returns the chunk with the payload it stores under the chunk type
+/// replaced, keeping it a chunk of the type or the same kind of copy, or
+/// None if it stores no such payload
+pub fn with_payload(chunk: &Chunk, chunk_type: &str, payload: &[u8]) -> Option<Chunk> {
+    stored_payload(chunk, chunk_type)?;
+    Some(match chunk.chunk_type().as_str().as_str() {
+        REPLICA_TYPE if chunk_type != REPLICA_TYPE => replica(chunk_type, payload),
+        "iTXt" if chunk_type != "iTXt" => text_copy(chunk_type, payload),
+        _ => Chunk::new(ChunkType::try_from(chunk.chunk_type().bytes()).unwrap(), payload.to_vec()),
+    })
+}
+
+/// Returns the payloads stored under the chunk type, those of chunks of
+/// the type first and then those of the copies, without duplicates
+pub fn copies(png: &Png, chunk_type: &str) -> Vec<Vec<u8>> {
+    let primary = png.chunks().iter()
+        .filter(|chunk| chunk.chunk_type().as_str() == chunk_type)
+        .map(|chunk| chunk.data().to_vec());
+    let copies = png.chunks().iter().filter_map(|chunk| copy_payload(chunk, chunk_type));
+
+    let mut payloads: Vec<Vec<u8>> = Vec::new();
+    for payload in primary.chain(copies) {
+        if !payloads.contains(&payload) {
+            payloads.push(payload);
+        }
+    }
+    payloads
+}
+
+/// Error specifying a chunk type the file already stores a payload under
+#[derive(Debug)]
+pub struct ChunkTypeInUseError(String);
+
+impl fmt::Display for ChunkTypeInUseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Chunk Type In Use! (the file already has {} chunks, see --mode)", self.0)
+    }
+}
+
+impl error::Error for ChunkTypeInUseError {}
+
+/// Error specifying a chunk type of the PNG specification, whose chunks
+/// encode will not remove
+#[derive(Debug)]
+pub struct RegisteredChunkTypeError(String);
+
+impl fmt::Display for RegisteredChunkTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Registered Chunk Type! ({} chunks belong to the image and are not replaced)", self.0)
+    }
+}
+
+impl error::Error for RegisteredChunkTypeError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::testing::{chunk, testing_png};
+
+    #[test]
+    fn test_replicate() {
+        let mut png = testing_png();
+        png.insert_chunk(2, chunk("ruSt", b"\x00binary\xff"));
+        replicate(&mut png, "ruSt", b"\x00binary\xff");
+        assert_eq!(png.chunks().last().unwrap().chunk_type().as_str(), "IEND");
+
+        let png = Png::try_from(&png.as_bytes()[..]).unwrap();
+        assert_eq!(copies(&png, "ruSt"), vec![b"\x00binary\xff".to_vec()]);
+        assert_eq!(png.chunks().iter().filter(|c| is_copy(c, "ruSt")).count(), 2);
+        assert!(copies(&png, "prIv").is_empty());
+    }
+
+    #[test]
+    fn test_move_before_iend() {
+        let payload: Vec<u8> = (0..50).collect();
+        let mut png = testing_png();
+        replicate(&mut png, "ruSt", &payload);
+        png.append_sharded(chunk("ruSt", &payload), 41);
+        move_before_iend(&mut png, 5);
+        let types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().as_str()).collect();
+        assert_eq!(types, vec!["IHDR", "IDAT", "pnRp", "iTXt", "ruSt", "ruSt", "IEND"]);
+        assert_eq!(png.reassemble("ruSt").unwrap(), Some(payload));
+
+        let mut unterminated = Png::from_chunks(vec![chunk("IHDR", b"header"), chunk("ruSt", b"payload")]);
+        move_before_iend(&mut unterminated, 1);
+        assert_eq!(unterminated.chunks().len(), 2);
+    }
+
+    #[test]
+    fn test_with_payload() {
+        let mut png = testing_png();
+        png.insert_chunk(2, chunk("ruSt", b"old"));
+        replicate(&mut png, "ruSt", b"old");
+        png.map_chunks(|c| with_payload(&c, "ruSt", b"new").unwrap_or(c));
+        assert_eq!(copies(&png, "ruSt"), vec![b"new".to_vec()]);
+        assert_eq!(png.chunks().iter().filter(|c| is_copy(c, "ruSt")).count(), 2);
+        assert!(with_payload(&png.chunks()[0], "ruSt", b"new").is_none());
+    }
+
+    #[test]
+    fn test_make_room() {
+        let mut png = testing_png();
+        png.insert_chunk(2, chunk("ruSt", b"old"));
+        replicate(&mut png, "ruSt", b"old");
+        replicate(&mut png, "prIv", b"other");
+
+        let mut appended = Png::from_chunks(png.chunks().iter().map(|c| Chunk::try_from(&c.as_bytes()[..]).unwrap()).collect());
+        make_room(&mut appended, "ruSt", Mode::Append).unwrap();
+        assert_eq!(appended.chunks().len(), png.chunks().len());
+
+        assert!(make_room(&mut png, "ruSt", Mode::Fail).unwrap_err().is::<ChunkTypeInUseError>());
+        make_room(&mut png, "niEw", Mode::Fail).unwrap();
+        assert!(make_room(&mut png, "IDAT", Mode::Replace).unwrap_err().is::<RegisteredChunkTypeError>());
+
+        make_room(&mut png, "ruSt", Mode::Replace).unwrap();
+        assert!(copies(&png, "ruSt").is_empty());
+        assert_eq!(copies(&png, "prIv"), vec![b"other".to_vec()]);
+        assert_eq!(png.chunks().len(), 5);
+    }
+
+    #[test]
+    fn test_survives_stripping() {
+        let mut png = testing_png();
+        replicate(&mut png, "ruSt", b"payload");
+
+        // processors that drop private chunks keep the iTXt copy
+        let mut stripped = Png::from_chunks(png.chunks().iter().map(|c| Chunk::try_from(&c.as_bytes()[..]).unwrap()).collect());
+        stripped.retain_chunks(|chunk| chunk.chunk_type().as_str() != REPLICA_TYPE);
+        assert_eq!(copies(&stripped, "ruSt"), vec![b"payload".to_vec()]);
+
+        // and those that drop text chunks keep the private copy
+        png.retain_chunks(|chunk| chunk.chunk_type().as_str() != "iTXt");
+        assert_eq!(copies(&png, "ruSt"), vec![b"payload".to_vec()]);
+    }
+}
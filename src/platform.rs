@@ -0,0 +1,172 @@
+//!
+//! Windows path and console edge cases: long and verbatim (`\\?\`) paths,
+//! reserved device names, and text written as UTF-16 by Windows tools.
+//! The functions work on any platform; callers apply them on Windows.
+//!
+use std::{fmt, error, path::Path};
+use crate::Result;
+
+/// Longest path the classic Windows APIs accept without the `\\?\` prefix
+const MAX_PATH: usize = 260;
+
+const VERBATIM_PREFIX: &str = r"\\?\";
+const VERBATIM_UNC_PREFIX: &str = r"\\?\UNC\";
+
+/// Device names Windows reserves in every directory, with any extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Returns the path in the verbatim `\\?\` form when it is an absolute
+/// path too long for the classic APIs, so it can still be opened
+pub fn long_path(path: &str) -> String {
+    let is_absolute = path.as_bytes().get(1..3) == Some(b":\\") || path.as_bytes().get(1..3) == Some(b":/")
+        || path.starts_with(r"\\") || path.starts_with("//");
+    if path.len() < MAX_PATH || !is_absolute || path.starts_with(VERBATIM_PREFIX) {
+        return String::from(path);
+    }
+
+    // verbatim paths are not normalized, so they need backslashes
+    let path = path.replace('/', "\\");
+    match path.strip_prefix(r"\\") {
+        Some(unc) => format!("{}{}", VERBATIM_UNC_PREFIX, unc),
+        None => format!("{}{}", VERBATIM_PREFIX, path),
+    }
+}
+
+/// Returns the path without its verbatim `\\?\` prefix (as added by
+/// `fs::canonicalize` on Windows) when it is short enough not to need it
+pub fn simplify(path: &str) -> String {
+    let simple = if let Some(unc) = path.strip_prefix(VERBATIM_UNC_PREFIX) {
+        format!(r"\\{}", unc)
+    } else if let Some(local) = path.strip_prefix(VERBATIM_PREFIX) {
+        String::from(local)
+    } else {
+        return String::from(path);
+    };
+
+    if simple.len() < MAX_PATH && reserved_name(&simple).is_none() {
+        simple
+    } else {
+        String::from(path)
+    }
+}
+
+/// Returns the reserved device name the file name of the path is, if any.
+/// Windows ignores trailing dots and spaces and the extension, so `nul.png`
+/// and `CON .txt` name devices too.
+pub fn reserved_name(path: &str) -> Option<&'static str> {
+    let name = path.rsplit(['/', '\\']).next()?;
+    let stem = name.split('.').next()?.trim_end_matches([' ', '.']);
+    RESERVED_NAMES.iter().copied().find(|reserved| reserved.eq_ignore_ascii_case(stem))
+}
+
+/// Refuses to write to a file whose name is a reserved device name, which
+/// Windows would silently turn into a write to the device
+pub fn check_writable_name(path: &Path) -> Result<()> {
+    match reserved_name(&path.to_string_lossy()) {
+        Some(name) => Err(Box::new(ReservedNameError(name))),
+        None => Ok(()),
+    }
+}
+
+/// Decodes text that may have been written by Windows tools: UTF-16 with a
+/// byte order mark (as PowerShell redirects write it) or UTF-8 with or
+/// without one
+pub fn decode_text(bytes: &[u8]) -> Result<String> {
+    let utf16 = |bytes: &[u8], from_bytes: fn([u8; 2]) -> u16| {
+        if !bytes.len().is_multiple_of(2) {
+            return Err(Box::new(InvalidUtf16Error).into());
+        }
+        let units: Vec<u16> = bytes.chunks(2).map(|pair| from_bytes([pair[0], pair[1]])).collect();
+        String::from_utf16(&units).map_err(|_| Box::new(InvalidUtf16Error).into())
+    };
+
+    match bytes {
+        [0xff, 0xfe, rest @ ..] => utf16(rest, u16::from_le_bytes),
+        [0xfe, 0xff, rest @ ..] => utf16(rest, u16::from_be_bytes),
+        [0xef, 0xbb, 0xbf, rest @ ..] => Ok(String::from(std::str::from_utf8(rest)?)),
+        _ => Ok(String::from(std::str::from_utf8(bytes)?)),
+    }
+}
+
+/// Error specifying a file name Windows reserves for a device
+#[derive(Debug)]
+pub struct ReservedNameError(&'static str);
+
+impl fmt::Display for ReservedNameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Reserved File Name! ({} is a device on Windows)", self.0)
+    }
+}
+
+impl error::Error for ReservedNameError {}
+
+/// Error specifying text with a UTF-16 byte order mark that is not UTF-16
+#[derive(Debug)]
+pub struct InvalidUtf16Error;
+
+impl fmt::Display for InvalidUtf16Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid UTF-16 Text!")
+    }
+}
+
+impl error::Error for InvalidUtf16Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_long_path() {
+        let long = format!(r"C:\images\{}.png", "a".repeat(300));
+        assert_eq!(long_path(&long), format!(r"\\?\{}", long));
+        assert_eq!(long_path(&long.replace('\\', "/")), format!(r"\\?\{}", long));
+
+        let unc = format!(r"\\server\share\{}.png", "a".repeat(300));
+        assert_eq!(long_path(&unc), format!(r"\\?\UNC\server\share\{}.png", "a".repeat(300)));
+
+        assert_eq!(long_path(r"C:\dice.png"), r"C:\dice.png");
+        assert_eq!(long_path(&"a".repeat(300)), "a".repeat(300));
+    }
+
+    #[test]
+    fn test_simplify() {
+        assert_eq!(simplify(r"\\?\C:\images\dice.png"), r"C:\images\dice.png");
+        assert_eq!(simplify(r"\\?\UNC\server\share\dice.png"), r"\\server\share\dice.png");
+        assert_eq!(simplify("/home/user/dice.png"), "/home/user/dice.png");
+
+        // the prefix is still needed for these
+        let long = format!(r"\\?\C:\{}.png", "a".repeat(300));
+        assert_eq!(simplify(&long), long);
+        assert_eq!(simplify(r"\\?\C:\images\nul.png"), r"\\?\C:\images\nul.png");
+    }
+
+    #[test]
+    fn test_reserved_name() {
+        assert_eq!(reserved_name("nul.png"), Some("NUL"));
+        assert_eq!(reserved_name(r"C:\out\Com3.tar.gz"), Some("COM3"));
+        assert_eq!(reserved_name("out/CON .txt"), Some("CON"));
+        assert_eq!(reserved_name("console.png"), None);
+        assert_eq!(reserved_name("COM10.png"), None);
+        assert!(check_writable_name(Path::new("aux.png")).is_err());
+    }
+
+    #[test]
+    fn test_decode_text() {
+        let mut le = vec![0xff, 0xfe];
+        le.extend("héllo".encode_utf16().flat_map(u16::to_le_bytes));
+        assert_eq!(decode_text(&le).unwrap(), "héllo");
+
+        let mut be = vec![0xfe, 0xff];
+        be.extend("héllo".encode_utf16().flat_map(u16::to_be_bytes));
+        assert_eq!(decode_text(&be).unwrap(), "héllo");
+
+        assert_eq!(decode_text("\u{feff}héllo".as_bytes()).unwrap(), "héllo");
+        assert_eq!(decode_text("héllo".as_bytes()).unwrap(), "héllo");
+        assert!(decode_text(&[0xff, 0xfe, 0x41]).is_err());
+    }
+}
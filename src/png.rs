@@ -1,22 +1,60 @@
-use crate::{chunk::{self, Chunk}, chunk_type, Result, Error};
-use std::{fmt, error, str};
+use crate::{cancel::CancellationToken, chunk, chunk_type::ChunkType, Result, Error};
+use std::{fmt, error, io, str};
+use sha2::{Digest, Sha256};
+
+/// Default limit on the number of chunks in a parsed PNG. Real images stay
+/// far below it even with small IDAT chunks, while crafted files with
+/// millions of empty chunks are refused before they exhaust memory. Chunks
+/// are kept one by one rather than in a compact arena, so the limit is what
+/// bounds the memory such a file can take, at a few megabytes.
+pub const DEFAULT_MAX_CHUNKS: usize = 65_536;
+
+/// Marks a chunk holding one shard of a payload split across several
+/// chunks of the same type. It is followed by the id of the payload, the
+/// index of the shard and the number of shards, each 4 bytes big-endian.
+pub const SHARD_MAGIC: &[u8; 4] = b"PMSQ";
+
+/// Length of the header starting every shard
+pub const SHARD_HEADER_LEN: usize = 16;
+
+/// Limits applied while parsing a PNG
+#[derive(Debug, Clone, Copy)]
+pub struct ParseOptions {
+    /// Largest number of chunks accepted, or None for no limit
+    pub max_chunks: Option<usize>,
+    /// Keep chunks with CRC mismatches and bytes after IEND that are not
+    /// chunks, instead of refusing the file, so they are written back as is
+    pub preserve: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions { max_chunks: Some(DEFAULT_MAX_CHUNKS), preserve: false }
+    }
+}
 
-/// Png object consisting of the png chunks and signature block
+/// Png object consisting of the png chunks and signature block. Chunks
+/// are kept in file order and written back byte for byte, whether or not
+/// pngme knows their type.
 pub struct Png {
     signature: [u8; 8],
-    chunks: Vec<chunk::Chunk>
+    chunks: Vec<chunk::Chunk>,
+    /// Bytes after IEND that are not chunks, kept when parsing with
+    /// `preserve`
+    trailer: Vec<u8>,
 }
 
 impl Png {
 
-    const STANDARD_HEADER: [u8;8] = [137, 80, 78, 71, 13, 10, 26, 10];
+    pub const STANDARD_HEADER: [u8;8] = [137, 80, 78, 71, 13, 10, 26, 10];
 
     /// Turn a set of chunks to a png file
     pub fn from_chunks(chunks: Vec<chunk::Chunk>) -> Png {
         let signature: [u8; 8] = Png::STANDARD_HEADER;
         Png {
-            signature: signature,
-            chunks: chunks
+            signature,
+            chunks,
+            trailer: Vec::new(),
         }
     }
 
@@ -30,6 +68,133 @@ impl Png {
         self.chunks.push(chunk);
     }
 
+    /// Adds the chunk to the end of the PNG, split into numbered shards of
+    /// the same type when its data is longer than `max_len` bytes. Each
+    /// shard holds at most `max_len` bytes, its header included.
+    pub fn append_sharded(&mut self, chunk: chunk::Chunk, max_len: usize) {
+        let max_len = Png::shard_len(max_len);
+        if chunk.data().len() <= max_len {
+            return self.append_chunk(chunk);
+        }
+
+        let id = &Sha256::digest(chunk.data())[..4];
+        let pieces: Vec<&[u8]> = chunk.data().chunks(max_len - SHARD_HEADER_LEN).collect();
+        for (index, piece) in pieces.iter().enumerate() {
+            let data = [SHARD_MAGIC, id, &(index as u32).to_be_bytes(), &(pieces.len() as u32).to_be_bytes(), piece].concat();
+            let chunk_type = ChunkType::try_from(chunk.chunk_type().bytes()).unwrap();
+            self.append_chunk(chunk::Chunk::new(chunk_type, data));
+        }
+    }
+
+    /// Number of chunks `append_sharded` stores data of the length in
+    pub fn shard_count(len: usize, max_len: usize) -> usize {
+        let max_len = Png::shard_len(max_len);
+        match len <= max_len {
+            true => 1,
+            false => len.div_ceil(max_len - SHARD_HEADER_LEN),
+        }
+    }
+
+    /// Largest shard length that leaves room for data after the header
+    /// and fits a chunk
+    fn shard_len(max_len: usize) -> usize {
+        max_len.clamp(SHARD_HEADER_LEN + 1, chunk::Chunk::MAX_LENGTH)
+    }
+
+    /// Returns the positions of the shards of the payload the first chunk
+    /// of the type belongs to, or nothing if that chunk is not a shard
+    pub fn shard_positions(&self, chunk_type: &str) -> Vec<usize> {
+        match self.positions_by_type(chunk_type).first() {
+            Some(&position) => self.shard_positions_at(position),
+            None => Vec::new(),
+        }
+    }
+
+    /// Returns the positions of the shards of the payload the chunk at the
+    /// position belongs to, or nothing if that chunk is not a shard
+    pub fn shard_positions_at(&self, position: usize) -> Vec<usize> {
+        let chunk = match self.chunks.get(position) {
+            Some(chunk) => chunk,
+            None => return Vec::new(),
+        };
+        let id = match Shard::parse(chunk.data()) {
+            Some(shard) => shard.id,
+            None => return Vec::new(),
+        };
+
+        let chunk_type = chunk.chunk_type().as_str();
+        (0..self.chunks.len())
+            .filter(|&i| self.chunks[i].chunk_type().as_str() == chunk_type
+                && Shard::parse(self.chunks[i].data()).is_some_and(|shard| shard.id == id))
+            .collect()
+    }
+
+    /// Joins the shards of the payload the first chunk of the type belongs
+    /// to, or returns None if that chunk is not a shard. Shards may be in
+    /// any order but none may be missing.
+    pub fn reassemble(&self, chunk_type: &str) -> Result<Option<Vec<u8>>> {
+        match self.positions_by_type(chunk_type).first() {
+            Some(&position) => self.reassemble_at(position),
+            None => Ok(None),
+        }
+    }
+
+    /// Joins the shards of the payload the chunk at the position belongs
+    /// to, or returns None if that chunk is not a shard
+    pub fn reassemble_at(&self, position: usize) -> Result<Option<Vec<u8>>> {
+        let positions = self.shard_positions_at(position);
+        if positions.is_empty() {
+            return Ok(None);
+        }
+
+        let mut shards: Vec<Shard> = positions.iter()
+            .filter_map(|&i| Shard::parse(self.chunks[i].data()))
+            .collect();
+        shards.sort_by_key(|shard| shard.index);
+        shards.dedup_by_key(|shard| shard.index);
+
+        let count = shards[0].count;
+        if let Some(index) = (0..count).find(|&i| shards.get(i as usize).is_none_or(|shard| shard.index != i)) {
+            return Err(Box::new(MissingShardError(index, count)));
+        }
+        if shards.len() != count as usize || shards.iter().any(|shard| shard.count != count) {
+            return Err(Box::new(InconsistentShardsError));
+        }
+        Ok(Some(shards.iter().flat_map(|shard| shard.data).copied().collect()))
+    }
+
+    /// Replaces the payload the shards of the chunk at the position join to
+    /// with data of the same length. Each shard keeps its place and holds
+    /// the same range of the data as before.
+    pub fn reshard_at(&mut self, position: usize, data: &[u8]) -> Result<()> {
+        if self.reassemble_at(position)?.map(|payload| payload.len()) != Some(data.len()) {
+            return Err(Box::new(InconsistentShardsError));
+        }
+
+        let positions = self.shard_positions_at(position);
+        let mut pieces: Vec<(u32, usize)> = positions.iter()
+            .filter_map(|&i| Shard::parse(self.chunks[i].data()).map(|shard| (shard.index, shard.data.len())))
+            .collect();
+        pieces.sort();
+        pieces.dedup_by_key(|(index, _)| *index);
+
+        let id = &Sha256::digest(data)[..4];
+        for i in positions {
+            let shard = Shard::parse(self.chunks[i].data()).unwrap();
+            let start: usize = pieces.iter().take_while(|(index, _)| *index < shard.index).map(|(_, len)| len).sum();
+            let piece = &data[start..start + shard.data.len()];
+            let bytes = [SHARD_MAGIC, id, &shard.index.to_be_bytes(), &shard.count.to_be_bytes(), piece].concat();
+            let chunk_type = ChunkType::try_from(self.chunks[i].chunk_type().bytes()).unwrap();
+            self.chunks[i] = chunk::Chunk::new(chunk_type, bytes);
+        }
+        Ok(())
+    }
+
+    /// Inserts a chunk at the given position in the PNG
+    pub fn insert_chunk(&mut self, index: usize, chunk: chunk::Chunk) {
+        self.chunks.insert(index, chunk);
+    }
+
     /// Removes specified chunk from PNG by searching for the specified
     /// chunk by chunk type. 
     pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<chunk::Chunk> {
@@ -41,6 +206,60 @@ impl Png {
         Err(Box::new(ChunkNotFoundError))
     }
 
+    /// Replaces every chunk in the PNG with the chunk returned by the
+    /// function, keeping the chunk order
+    pub fn map_chunks<F>(&mut self, f: F)
+    where
+        F: FnMut(chunk::Chunk) -> chunk::Chunk,
+    {
+        self.chunks = std::mem::take(&mut self.chunks).into_iter().map(f).collect();
+    }
+
+    /// Keeps only the chunks for which the predicate returns true
+    pub fn retain_chunks<F>(&mut self, f: F)
+    where
+        F: FnMut(&chunk::Chunk) -> bool,
+    {
+        self.chunks.retain(f);
+    }
+
+    /// Replaces the IDAT chunks with the concatenated image data stream,
+    /// stored in a single chunk or in pieces of at most `target_size` bytes.
+    /// The merged chunks take the place of the first IDAT chunk and the
+    /// compressed stream is preserved byte for byte. An empty stream is
+    /// kept in one empty IDAT chunk, as a PNG needs at least one.
+    pub fn merge_idat(&mut self, target_size: Option<usize>) {
+        self.merge_idat_with_cancel(target_size, &CancellationToken::new())
+            .expect("a new token is never cancelled")
+    }
+
+    /// Merges the IDAT chunks like `merge_idat`, checking the token between
+    /// chunks. A cancelled merge leaves the chunks as they were.
+    pub fn merge_idat_with_cancel(&mut self, target_size: Option<usize>, cancel: &CancellationToken) -> Result<()> {
+        let position = match self.chunks.iter().position(|c| c.chunk_type().as_str() == "IDAT") {
+            Some(position) => position,
+            None => return Ok(()),
+        };
+
+        let mut stream: Vec<u8> = Vec::new();
+        for chunk in self.chunks.iter().filter(|c| c.chunk_type().as_str() == "IDAT") {
+            cancel.check()?;
+            stream.extend_from_slice(chunk.data());
+        }
+        self.retain_chunks(|c| c.chunk_type().as_str() != "IDAT");
+
+        let piece_size = target_size.unwrap_or(chunk::Chunk::MAX_LENGTH)
+            .clamp(1, chunk::Chunk::MAX_LENGTH);
+        let mut merged: Vec<chunk::Chunk> = stream.chunks(piece_size)
+            .map(|piece| chunk::Chunk::new(ChunkType::try_from(*b"IDAT").unwrap(), piece.to_vec()))
+            .collect();
+        if merged.is_empty() {
+            merged.push(chunk::Chunk::new(ChunkType::try_from(*b"IDAT").unwrap(), Vec::new()));
+        }
+        self.chunks.splice(position..position, merged);
+        Ok(())
+    }
+
     /// Returns the header chunk (signature) of the PNG
     pub fn header(&self) -> &[u8; 8] {
         &self.signature
@@ -61,15 +280,82 @@ impl Png {
        None
     }
 
+    /// Returns every chunk of the type, in file order, so messages encoded
+    /// one after the other under the same type can all be reached
+    pub fn chunks_by_type(&self, chunk_type: &str) -> Vec<&chunk::Chunk> {
+        self.chunks.iter()
+            .filter(|chunk| chunk.chunk_type().as_str() == chunk_type)
+            .collect()
+    }
+
+    /// Returns the positions of the chunks of the type, in file order
+    pub fn positions_by_type(&self, chunk_type: &str) -> Vec<usize> {
+        (0..self.chunks.len())
+            .filter(|&i| self.chunks[i].chunk_type().as_str() == chunk_type)
+            .collect()
+    }
+
+    /// Returns the position of the first chunk of every message stored
+    /// under the type, in file order, a payload split into shards counting
+    /// as one message
+    pub fn message_positions(&self, chunk_type: &str) -> Vec<usize> {
+        self.positions_by_type(chunk_type).into_iter()
+            .filter(|&i| self.shard_positions_at(i).first().is_none_or(|&first| first == i))
+            .collect()
+    }
+
     /// Turns the Png file to a vector of bytes
     pub fn as_bytes(&self) -> Vec<u8> {
         let mut bytes: Vec<u8> = Vec::from(self.signature);
 
         let chunk_bytes: Vec<u8> = self.chunks.iter().flat_map(|chunk| chunk.as_bytes()).collect();
         bytes.extend(&chunk_bytes);
+        bytes.extend(&self.trailer);
         bytes
     }
 
+    /// Returns the bytes after IEND that are not chunks
+    pub fn trailer(&self) -> &[u8] {
+        &self.trailer
+    }
+
+    /// Returns the image properties from the IHDR chunk, if the PNG has a
+    /// well formed one
+    pub fn image_header(&self) -> Option<ImageHeader> {
+        let data = self.chunk_by_type("IHDR")?.data();
+        if data.len() != 13 {
+            return None;
+        }
+
+        Some(ImageHeader {
+            width: u32::from_be_bytes(data[0..4].try_into().ok()?),
+            height: u32::from_be_bytes(data[4..8].try_into().ok()?),
+            bit_depth: data[8],
+            color_type: data[9],
+            compression_method: data[10],
+            filter_method: data[11],
+            interlace_method: data[12],
+        })
+    }
+
+    /// Returns a reader over the image data stream, which is the logical
+    /// concatenation of the data of every IDAT chunk in order. No joined
+    /// copy of the stream is made, however fragmented it is.
+    pub fn idat_reader(&self) -> impl io::Read + '_ {
+        IdatReader {
+            chunks: self.chunks.iter(),
+            current: &[],
+        }
+    }
+
+    /// Returns the ancillary chunks of types the specification does not
+    /// register, with their positions, which is where messages are hidden
+    pub fn hidden_chunks(&self) -> Vec<(usize, &chunk::Chunk)> {
+        self.chunks.iter().enumerate()
+            .filter(|(_, chunk)| !chunk.chunk_type().is_critical() && !chunk.chunk_type().is_registered())
+            .collect()
+    }
+
     pub fn ancillary_chunks(&self) -> Vec<String> {
         let mut ancilliary_chunks: Vec<String> = Vec::new();
 
@@ -82,13 +368,81 @@ impl Png {
     }
 }
 
-///Turns a byte array to a PNG 
-impl TryFrom<&[u8]> for Png {
-    type Error = Error;
+/// One piece of a payload split by `Png::append_sharded`
+struct Shard<'a> {
+    id: &'a [u8],
+    index: u32,
+    count: u32,
+    data: &'a [u8],
+}
 
-    /// Turn an array of bytes to a Png File
-    fn try_from(value: &[u8]) -> Result<Self> {
-        let signature: [u8; 8] = value[..8].try_into()?;
+impl Shard<'_> {
+    fn parse(data: &[u8]) -> Option<Shard<'_>> {
+        if data.len() < SHARD_HEADER_LEN || !data.starts_with(SHARD_MAGIC) {
+            return None;
+        }
+        Some(Shard {
+            id: &data[4..8],
+            index: u32::from_be_bytes(data[8..12].try_into().ok()?),
+            count: u32::from_be_bytes(data[12..16].try_into().ok()?),
+            data: &data[SHARD_HEADER_LEN..],
+        })
+    }
+}
+
+/// Reader over the data of consecutive IDAT chunks
+struct IdatReader<'a> {
+    chunks: std::slice::Iter<'a, chunk::Chunk>,
+    current: &'a [u8],
+}
+
+impl io::Read for IdatReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // move on to the next non-empty IDAT chunk once the current one is used up
+        while self.current.is_empty() {
+            match self.chunks.next() {
+                Some(chunk) if chunk.chunk_type().as_str() == "IDAT" => self.current = chunk.data(),
+                Some(_) => {},
+                None => return Ok(0),
+            }
+        }
+
+        let n = buf.len().min(self.current.len());
+        buf[..n].copy_from_slice(&self.current[..n]);
+        self.current = &self.current[n..];
+        Ok(n)
+    }
+}
+
+/// Image properties stored in the IHDR chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHeader {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: u8,
+    pub compression_method: u8,
+    pub filter_method: u8,
+    pub interlace_method: u8,
+}
+
+impl ImageHeader {
+    /// Color type value of indexed-color (palette) images
+    pub const INDEXED_COLOR: u8 = 3;
+
+    /// Checks if the image is stored with Adam7 interlacing
+    pub fn is_interlaced(&self) -> bool {
+        self.interlace_method == 1
+    }
+}
+
+///Turns a byte array to a PNG 
+impl Png {
+    /// Parses a PNG file, refusing files that exceed the limits of the
+    /// options. Chunks of unknown types, private critical chunks included,
+    /// are kept as they are.
+    pub fn parse(value: &[u8], options: &ParseOptions) -> Result<Png> {
+        let signature: [u8; 8] = value.get(..8).ok_or(TruncatedPngError)?.try_into()?;
 
         //Check for valid signature bit
         if !Png::is_valid_signature(signature) {
@@ -99,18 +453,23 @@ impl TryFrom<&[u8]> for Png {
         
         //Iterator to represent start of a new chunk
         let mut j: usize = 8;
+        let mut after_iend = false;
 
         while j < value.len() {
 
-            //Get the length of the chunk
-            let length_bytes: [u8;4] = value[j..(j+4)].try_into()?;
-            let length: u32 = u32::from_be_bytes(length_bytes);
-            let length: usize = length as usize;
+            if options.max_chunks.is_some_and(|max| chunks.len() >= max) {
+                return Err(Box::new(TooManyChunksError(chunks.len())));
+            }
 
-            //Get the byte corresponding to the chunk 
-            //12 added to account for length, chunk_type, and crc bytes. 
-            let bytes: Vec<u8> = value[j..(j+12+length)].to_vec();
-            let chunk = chunk::Chunk::try_from(bytes.as_slice())?;
+            // when preserving, whatever follows IEND and is not a chunk is kept as is
+            let chunk = match Png::parse_chunk(&value[j..], options.preserve) {
+                Err(_) if options.preserve && after_iend => {
+                    return Ok(Png { signature, chunks, trailer: value[j..].to_vec() });
+                },
+                chunk => chunk?,
+            };
+            let length = chunk.length() as usize;
+            after_iend |= chunk.chunk_type().as_str() == "IEND";
 
             //Add chunk to chunks
             chunks.push(chunk);
@@ -118,7 +477,32 @@ impl TryFrom<&[u8]> for Png {
             //Update iterator
             j += 12 + length;
         }
-        Ok(Png { signature: signature, chunks: chunks })
+        Ok(Png { signature, chunks, trailer: Vec::new() })
+    }
+
+    /// Parses the chunk at the start of the bytes, keeping a CRC mismatch
+    /// when preserving
+    fn parse_chunk(value: &[u8], preserve: bool) -> Result<chunk::Chunk> {
+        //Get the length of the chunk
+        let length_bytes: [u8;4] = value.get(..4).ok_or(TruncatedPngError)?.try_into()?;
+        let length = u32::from_be_bytes(length_bytes) as usize;
+
+        //Parse the chunk straight from the file bytes. 
+        //12 added to account for length, chunk_type, and crc bytes. 
+        let bytes = value.get(..length.checked_add(12).ok_or(TruncatedPngError)?).ok_or(TruncatedPngError)?;
+        match preserve {
+            true => chunk::Chunk::try_from_unchecked(bytes),
+            false => chunk::Chunk::try_from(bytes),
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for Png {
+    type Error = Error;
+
+    /// Turn an array of bytes to a Png File with the default parse options
+    fn try_from(value: &[u8]) -> Result<Self> {
+        Png::parse(value, &ParseOptions::default())
     }
 }
 
@@ -128,7 +512,7 @@ impl fmt::Display for Png{
         let mut png_str = String::new();
         
         for chunk in &self.chunks {
-            png_str += &(chunk.data_as_string()).unwrap();
+            png_str += &chunk.data_as_lossy_string();
 
             //Add Spacing between chunks for easy seperation
             png_str += " ";
@@ -151,9 +535,33 @@ impl fmt::Display for ChunkNotFoundError {
 
 impl error::Error for ChunkNotFoundError {}
 
+/// Error specifying a shard missing from a payload split across chunks
+#[derive(Debug)]
+pub struct MissingShardError(u32, u32);
+
+impl fmt::Display for MissingShardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Missing Shard! (shard {} of {})", self.0 + 1, self.1)
+    }
+}
+
+impl error::Error for MissingShardError {}
+
+/// Error specifying shards that disagree on how many there are
+#[derive(Debug)]
+pub struct InconsistentShardsError;
+
+impl fmt::Display for InconsistentShardsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Inconsistent Shards!")
+    }
+}
+
+impl error::Error for InconsistentShardsError {}
+
 /// Error specifying a signature being invalid in the chunk
 #[derive(Debug)]
-struct InvalidSignatureError;
+pub struct InvalidSignatureError;
 
 impl fmt::Display for InvalidSignatureError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -163,22 +571,60 @@ impl fmt::Display for InvalidSignatureError {
 
 impl error::Error for InvalidSignatureError {}
 
+/// Error specifying a PNG that ends in the middle of a chunk
+#[derive(Debug)]
+pub struct TruncatedPngError;
+
+impl fmt::Display for TruncatedPngError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PNG is Truncated!")
+    }
+}
+
+impl error::Error for TruncatedPngError {}
+
+/// Error specifying a PNG with more chunks than the parse options allow
+#[derive(Debug)]
+pub struct TooManyChunksError(pub usize);
+
+impl fmt::Display for TooManyChunksError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Too Many Chunks! (limit of {} reached)", self.0)
+    }
+}
+
+impl error::Error for TooManyChunksError {}
+
+/// Fixtures shared by the tests of the modules working on PNGs
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::str::FromStr;
+    use crate::{chunk::Chunk, chunk_type::ChunkType};
+    use super::Png;
+
+    pub fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    /// PNG of an IHDR, an IDAT and an IEND chunk holding placeholder data
+    pub fn testing_png() -> Png {
+        Png::from_chunks(vec![chunk("IHDR", b"header"), chunk("IDAT", b"data"), chunk("IEND", b"")])
+    }
+}
+
 #[cfg(test)]
 mod tests { 
     use super::*;
     use crate::chunk_type::ChunkType;
     use crate::chunk::Chunk;
-    use std::str::FromStr;
     use std::convert::TryFrom;
 
     fn testing_chunks() -> Vec<Chunk> {
-        let mut chunks = Vec::new();
-
-        chunks.push(chunk_from_strings("FrSt", "I am the first chunk").unwrap());
-        chunks.push(chunk_from_strings("miDl", "I am another chunk").unwrap());
-        chunks.push(chunk_from_strings("LASt", "I am the last chunk").unwrap());
-
-        chunks
+        vec![
+            chunk_from_strings("FrSt", "I am the first chunk").unwrap(),
+            chunk_from_strings("miDl", "I am another chunk").unwrap(),
+            chunk_from_strings("LASt", "I am the last chunk").unwrap(),
+        ]
     }
 
     fn testing_png() -> Png {
@@ -296,6 +742,165 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_map_chunks() {
+        let mut png = testing_png();
+        png.map_chunks(|chunk| {
+            let data = chunk.data_as_string().unwrap().to_uppercase();
+            Chunk::new(ChunkType::try_from(chunk.chunk_type().bytes()).unwrap(), data.into_bytes())
+        });
+
+        assert_eq!(png.chunks().len(), 3);
+        let chunk = png.chunk_by_type("miDl").unwrap();
+        assert_eq!(&chunk.data_as_string().unwrap(), "I AM ANOTHER CHUNK");
+    }
+
+    #[test]
+    fn test_retain_chunks() {
+        let mut png = testing_png();
+        png.retain_chunks(|chunk| chunk.chunk_type().is_critical());
+
+        let types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, vec!["FrSt", "LASt"]);
+    }
+
+    #[test]
+    fn test_idat_reader() {
+        use std::io::Read;
+
+        let png = Png::from_chunks(vec![
+            chunk_from_strings("IDAT", "fragmented ").unwrap(),
+            chunk_from_strings("teXt", "not image data").unwrap(),
+            chunk_from_strings("IDAT", "").unwrap(),
+            chunk_from_strings("IDAT", "image data").unwrap(),
+        ]);
+
+        let mut stream = String::new();
+        png.idat_reader().read_to_string(&mut stream).unwrap();
+        assert_eq!(stream, "fragmented image data");
+    }
+
+    #[test]
+    fn test_merge_idat() {
+        let mut png = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", "header").unwrap(),
+            chunk_from_strings("IDAT", "abc").unwrap(),
+            chunk_from_strings("IDAT", "defg").unwrap(),
+            chunk_from_strings("IDAT", "h").unwrap(),
+            chunk_from_strings("IEND", "").unwrap(),
+        ]);
+
+        png.merge_idat(None);
+        let types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, vec!["IHDR", "IDAT", "IEND"]);
+        assert_eq!(png.chunks()[1].data(), b"abcdefgh");
+
+        png.merge_idat(Some(3));
+        let pieces: Vec<&[u8]> = png.chunks()[1..4].iter().map(|c| c.data()).collect();
+        assert_eq!(pieces, vec![&b"abc"[..], b"def", b"gh"]);
+        assert_eq!(png.chunks().len(), 5);
+    }
+
+    #[test]
+    fn test_merge_idat_empty() {
+        let mut png = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", "header").unwrap(),
+            chunk_from_strings("IDAT", "").unwrap(),
+            chunk_from_strings("IDAT", "").unwrap(),
+            chunk_from_strings("IEND", "").unwrap(),
+        ]);
+
+        png.merge_idat(Some(3));
+        let types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, vec!["IHDR", "IDAT", "IEND"]);
+        assert_eq!(png.chunks()[1].length(), 0);
+    }
+
+    #[test]
+    fn test_merge_idat_cancelled() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        assert!(png.merge_idat_with_cancel(None, &cancel).is_err());
+        assert_eq!(png.as_bytes(), PNG_FILE.to_vec());
+    }
+
+    #[test]
+    fn test_merge_idat_preserves_image_file() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.merge_idat(None);
+        assert_eq!(png.as_bytes(), PNG_FILE.to_vec());
+    }
+
+    #[test]
+    fn test_sharding() {
+        let mut png = testing_png();
+        let payload: Vec<u8> = (0..100).collect();
+        png.append_sharded(Chunk::new(ChunkType::try_from(*b"ruSt").unwrap(), payload.clone()), 41);
+        png.append_sharded(chunk_from_strings("ruSt", "short").unwrap(), 41);
+
+        let shards = png.shard_positions("ruSt");
+        assert_eq!(shards, vec![3, 4, 5, 6]);
+        assert_eq!((Png::shard_count(100, 41), Png::shard_count(5, 41)), (4, 1));
+        assert!(shards.iter().all(|&i| png.chunks()[i].length() <= 41));
+
+        let mut png = Png::try_from(&png.as_bytes()[..]).unwrap();
+        assert_eq!(png.reassemble("ruSt").unwrap(), Some(payload));
+        assert_eq!(png.reassemble("FrSt").unwrap(), None);
+        assert_eq!(png.reassemble_at(5).unwrap(), Some((0..100).collect()));
+        assert_eq!(png.reassemble_at(7).unwrap(), None);
+        assert_eq!(png.message_positions("ruSt"), vec![3, 7]);
+
+        let replaced: Vec<u8> = (100..200).collect();
+        png.reshard_at(4, &replaced).unwrap();
+        assert_eq!(png.shard_positions("ruSt"), shards);
+        assert_eq!(png.reassemble("ruSt").unwrap(), Some(replaced));
+        assert!(png.reshard_at(4, b"short").unwrap_err().is::<InconsistentShardsError>());
+
+        let mut index = 0;
+        png.retain_chunks(|_| {
+            index += 1;
+            index != 6
+        });
+        assert!(png.reassemble("ruSt").unwrap_err().is::<MissingShardError>());
+    }
+
+    #[test]
+    fn test_hidden_chunks() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert!(png.hidden_chunks().is_empty());
+        png.insert_chunk(1, chunk_from_strings("ruSt", "message").unwrap());
+        png.insert_chunk(1, chunk_from_strings("tEXt", "Comment\0text").unwrap());
+        let hidden: Vec<(usize, String)> = png.hidden_chunks().iter()
+            .map(|(i, chunk)| (*i, chunk.chunk_type().to_string()))
+            .collect();
+        assert_eq!(hidden, vec![(2, String::from("ruSt"))]);
+    }
+
+    #[test]
+    fn test_chunks_by_type() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("FrSt", "I am the second message").unwrap());
+        let chunks = png.chunks_by_type("FrSt");
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(&chunks[0].data_as_string().unwrap(), "I am the first chunk");
+        assert_eq!(&chunks[1].data_as_string().unwrap(), "I am the second message");
+        assert_eq!(png.positions_by_type("FrSt"), vec![0, 3]);
+        assert!(png.chunks_by_type("TeSt").is_empty());
+    }
+
+    #[test]
+    fn test_image_header() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let header = png.image_header().unwrap();
+        assert_eq!((header.width, header.height), (50, 50));
+        assert_eq!(header.bit_depth, 8);
+        assert_eq!(header.color_type, 6);
+        assert!(!header.is_interlaced());
+
+        assert!(testing_png().image_header().is_none());
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
@@ -306,7 +911,7 @@ mod tests {
     fn test_as_bytes() {
         let png = Png::try_from(&PNG_FILE[..]).unwrap(); //goes from [u8] -> PngFile
         let actual = png.as_bytes(); //goes from PngFile -> [u8]
-        let expected: Vec<u8> = PNG_FILE.iter().copied().collect();
+        let expected: Vec<u8> = PNG_FILE.to_vec();
         assert_eq!(actual, expected);
     }
 
@@ -574,4 +1179,83 @@ mod tests {
         202, 28, 31, 66, 176, 235, 16, 0, 0, 0, 3, 82, 117, 83, 116, 104, 101, 121, 158, 176, 245,
         160, 0, 0, 0, 0, 73, 69, 78, 68, 174, 66, 96, 130,
     ];
-}
\ No newline at end of file
+
+    #[test]
+    fn test_max_chunks() {
+        let empty = || chunk_from_strings("emPt", "").unwrap();
+        let bytes = Png::from_chunks((0..10).map(|_| empty()).collect()).as_bytes();
+
+        let options = ParseOptions { max_chunks: Some(10), ..ParseOptions::default() };
+        assert_eq!(Png::parse(&bytes, &options).unwrap().chunks().len(), 10);
+
+        let options = ParseOptions { max_chunks: Some(9), ..ParseOptions::default() };
+        assert!(Png::parse(&bytes, &options).is_err());
+
+        let options = ParseOptions { max_chunks: None, ..ParseOptions::default() };
+        assert!(Png::parse(&bytes, &options).is_ok());
+    }
+
+    #[test]
+    fn test_default_max_chunks() {
+        // the adversarial input of the parse benchmark
+        let empty = chunk_from_strings("emPt", "").unwrap().as_bytes();
+        let mut bytes = Png::from_chunks(Vec::new()).as_bytes();
+        for _ in 0..1_000_000 {
+            bytes.extend(&empty);
+        }
+        let error = Png::try_from(&bytes[..]).err().unwrap();
+        assert_eq!(error.downcast_ref::<TooManyChunksError>().unwrap().0, DEFAULT_MAX_CHUNKS);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let bytes = testing_png().as_bytes();
+        for len in [4, 10, bytes.len() - 1] {
+            assert!(Png::try_from(&bytes[..len]).is_err());
+        }
+    }
+
+    /// Chunks of unknown types (private critical, reserved bit set, empty
+    /// or after IEND) and their order survive parsing and serializing
+    #[test]
+    fn test_round_trip_unknown_chunks() {
+        let chunks = vec![
+            chunk_from_strings("IHDR", "header").unwrap(),
+            chunk_from_strings("PrIv", "private critical").unwrap(),
+            chunk_from_strings("prIv", "private ancillary").unwrap(),
+            chunk_from_strings("abcd", "reserved bit set").unwrap(),
+            chunk_from_strings("emPt", "").unwrap(),
+            chunk_from_strings("IDAT", "data").unwrap(),
+            chunk_from_strings("IEND", "").unwrap(),
+            chunk_from_strings("afTr", "after IEND").unwrap(),
+        ];
+        let bytes = Png::from_chunks(chunks).as_bytes();
+        assert_eq!(Png::try_from(&bytes[..]).unwrap().as_bytes(), bytes);
+
+        // editing one chunk leaves the bytes of every other chunk in place
+        let mut png = Png::try_from(&bytes[..]).unwrap();
+        png.remove_chunk("prIv").unwrap();
+        let removed = 12 + "private ancillary".len();
+        let start = 8 + 12 + 6 + 12 + "private critical".len();
+        assert_eq!(png.as_bytes(), [&bytes[..start], &bytes[start + removed..]].concat());
+    }
+
+    #[test]
+    fn test_preserve() {
+        let mut bytes = testing_png().as_bytes();
+        bytes.extend(chunk_from_strings("IEND", "").unwrap().as_bytes());
+        bytes.extend(b"trailing data");
+        // break the CRC of the first chunk
+        bytes[8 + 8 + 2] ^= 1;
+
+        let preserve = ParseOptions { preserve: true, ..ParseOptions::default() };
+        assert!(Png::parse(&bytes, &ParseOptions::default()).is_err());
+        let png = Png::parse(&bytes, &preserve).unwrap();
+        assert_eq!(png.chunks().len(), 4);
+        assert_eq!(png.trailer(), b"trailing data");
+        assert_eq!(png.as_bytes(), bytes);
+
+        // without IEND, data that is not a chunk is still refused
+        assert!(Png::parse(&[&testing_png().as_bytes()[..], b"trailing data"].concat(), &preserve).is_err());
+    }
+}
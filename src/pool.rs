@@ -0,0 +1,173 @@
+//!
+//! Carrier pool: a directory of vetted carrier PNGs and an index of their
+//! capacities, from which encode can take the smallest carrier that fits
+//! a payload. Each carrier is used once, so messages do not share one.
+//!
+use std::{error, fmt, path::Path};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::{carrier, pack, png::Png, storage, Result};
+
+/// Name of the index kept in the pool directory
+pub const INDEX_FILE: &str = "pool.json";
+
+/// Largest growth of a carrier, in percent of its size, the payload chunk
+/// may cause. A file much larger than its dimensions suggest stands out.
+pub const MAX_GROWTH_PERCENT: u64 = 10;
+
+/// Carrier in the pool
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolCarrier {
+    /// File name in the pool directory
+    pub name: String,
+    pub size: u64,
+    /// Largest payload (chunk data) the carrier takes
+    pub capacity: u64,
+    pub sha256: String,
+    /// Whether encode already took the carrier
+    pub used: bool,
+}
+
+/// Carriers of one pool directory
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Pool {
+    carriers: Vec<PoolCarrier>,
+}
+
+/// Largest payload a carrier of this size takes, leaving room for the
+/// length, type and CRC of its chunk
+pub fn capacity(size: u64) -> u64 {
+    (size * MAX_GROWTH_PERCENT / 100).saturating_sub(12)
+}
+
+impl Pool {
+    /// Loads the index of the pool directory, empty if there is none yet
+    pub fn load(dir: &Path) -> Result<Pool> {
+        storage::load_json(&dir.join(INDEX_FILE))
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        storage::save_json(&dir.join(INDEX_FILE), self)
+    }
+
+    /// Returns every carrier, in the order they were added
+    pub fn carriers(&self) -> &[PoolCarrier] {
+        &self.carriers
+    }
+
+    /// Vets the PNG and records it under the name. Carriers failing a
+    /// health check or already in the pool are refused.
+    pub fn add(&mut self, name: &str, bytes: &[u8]) -> Result<&PoolCarrier> {
+        if !pack::is_valid_name(name) {
+            return Err(Box::new(pack::InvalidVariantNameError(String::from(name))));
+        }
+        let sha256 = hex::encode(Sha256::digest(bytes));
+        if let Some(other) = self.carriers.iter().find(|c| c.name == name || c.sha256 == sha256) {
+            return Err(Box::new(DuplicateCarrierError(other.name.clone())));
+        }
+
+        let warnings = carrier::check(&Png::try_from(bytes)?);
+        if !warnings.is_empty() {
+            return Err(Box::new(carrier::UnhealthyCarrierError(warnings)));
+        }
+
+        let size = bytes.len() as u64;
+        self.carriers.push(PoolCarrier { name: String::from(name), size, capacity: capacity(size), sha256, used: false });
+        Ok(self.carriers.last().unwrap())
+    }
+
+    /// Forgets the carrier with the name, returning it
+    pub fn remove(&mut self, name: &str) -> Result<PoolCarrier> {
+        let index = self.carriers.iter().position(|c| c.name == name)
+            .ok_or_else(|| CarrierNotInPoolError(String::from(name)))?;
+        Ok(self.carriers.remove(index))
+    }
+
+    /// Marks the smallest unused carrier that fits the payload as used and
+    /// returns it
+    pub fn take(&mut self, payload_len: u64) -> Result<&PoolCarrier> {
+        let carrier = self.carriers.iter_mut()
+            .filter(|c| !c.used && c.capacity >= payload_len)
+            .min_by_key(|c| c.size)
+            .ok_or(PoolExhaustedError(payload_len))?;
+        carrier.used = true;
+        Ok(carrier)
+    }
+}
+
+/// Error specifying a carrier whose name or contents are already pooled
+#[derive(Debug)]
+pub struct DuplicateCarrierError(String);
+
+impl fmt::Display for DuplicateCarrierError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Carrier Already In Pool! ({})", self.0)
+    }
+}
+
+impl error::Error for DuplicateCarrierError {}
+
+/// Error specifying a name that is not in the pool
+#[derive(Debug)]
+pub struct CarrierNotInPoolError(String);
+
+impl fmt::Display for CarrierNotInPoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Carrier Not In Pool! ({})", self.0)
+    }
+}
+
+impl error::Error for CarrierNotInPoolError {}
+
+/// Error specifying that no unused carrier fits the payload
+#[derive(Debug)]
+pub struct PoolExhaustedError(u64);
+
+impl fmt::Display for PoolExhaustedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "No Unused Carrier Fits! ({} byte payload)", self.0)
+    }
+}
+
+impl error::Error for PoolExhaustedError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::testing::chunk;
+
+    fn testing_png(image_data: usize) -> Vec<u8> {
+        Png::from_chunks(vec![chunk("IHDR", &[0; 13]), chunk("IDAT", &vec![7; image_data]), chunk("IEND", b"")]).as_bytes()
+    }
+
+    #[test]
+    fn test_take_smallest_fit() {
+        let mut pool = Pool::default();
+        pool.add("large.png", &testing_png(10_000)).unwrap();
+        pool.add("small.png", &testing_png(1_000)).unwrap();
+        pool.add("medium.png", &testing_png(5_000)).unwrap();
+
+        assert_eq!(pool.take(50).unwrap().name, "small.png");
+        assert_eq!(pool.take(50).unwrap().name, "medium.png");
+        assert!(pool.take(capacity(testing_png(10_000).len() as u64) + 1).is_err());
+        assert_eq!(pool.take(800).unwrap().name, "large.png");
+        assert!(pool.take(1).is_err());
+    }
+
+    #[test]
+    fn test_add_and_remove() {
+        let mut pool = Pool::default();
+        pool.add("a.png", &testing_png(100)).unwrap();
+        assert!(pool.add("a.png", &testing_png(200)).is_err());
+        assert!(pool.add("b.png", &testing_png(100)).is_err());
+        assert!(pool.add("../c.png", &testing_png(300)).is_err());
+        assert!(pool.add("d.png", b"not a png").is_err());
+
+        let mut unhealthy = Png::try_from(&testing_png(100)[..]).unwrap();
+        unhealthy.insert_chunk(2, chunk("ruSt", b"payload"));
+        assert!(pool.add("e.png", &unhealthy.as_bytes()).is_err());
+
+        assert_eq!(pool.remove("a.png").unwrap().size, testing_png(100).len() as u64);
+        assert!(pool.remove("a.png").is_err());
+    }
+}
@@ -0,0 +1,162 @@
+//!
+//! Optional record of who encoded a message, when, and why, stored in
+//! front of it by encode --author / --note so decode --info can show it
+//! without printing the message itself.
+//!
+//! | Field                                   | Size       |
+//! |-----------------------------------------|------------|
+//! | magic `PMPV`                            | 4          |
+//! | version                                 | 1          |
+//! | encoding time (Unix seconds)            | 8          |
+//! | author length (0 when not recorded)     | 2          |
+//! | author (UTF-8)                          | variable   |
+//! | note length (0 when not recorded)       | 2          |
+//! | note (UTF-8)                            | variable   |
+//! | message                                 | variable   |
+//!
+use std::{error, fmt, time::{SystemTime, UNIX_EPOCH}};
+use crate::{envelope, Result};
+
+pub const MAGIC: &[u8; 4] = b"PMPV";
+pub const VERSION: u8 = 1;
+
+/// Who encoded a message, when, and with what note
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    pub author: Option<String>,
+    pub note: Option<String>,
+    /// Encoding time in seconds since the Unix epoch
+    pub created: u64,
+}
+
+impl Provenance {
+    /// Records the author and note with the current time
+    pub fn new(author: Option<String>, note: Option<String>) -> Result<Provenance> {
+        let created = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        Provenance::at(author, note, created)
+    }
+
+    /// Records the author and note with the given time
+    pub fn at(author: Option<String>, note: Option<String>, created: u64) -> Result<Provenance> {
+        for (field, value) in [("author", &author), ("note", &note)] {
+            if value.as_ref().is_some_and(|value| value.len() > u16::MAX as usize) {
+                return Err(Box::new(FieldTooLongError(field)));
+            }
+        }
+        // empty fields cannot be told apart from missing ones once stored
+        let author = author.filter(|author| !author.is_empty());
+        let note = note.filter(|note| !note.is_empty());
+        Ok(Provenance { author, note, created })
+    }
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "encoded at {} (Unix time)", self.created)?;
+        if let Some(author) = &self.author {
+            write!(f, " by {}", author)?;
+        }
+        if let Some(note) = &self.note {
+            write!(f, ", note: {}", note)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks if the payload starts with the magic bytes of a provenance record
+pub fn is_recorded(payload: &[u8]) -> bool {
+    payload.starts_with(MAGIC)
+}
+
+/// Puts the provenance record in front of the message
+pub fn wrap(provenance: &Provenance, message: &[u8]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    bytes.extend(provenance.created.to_be_bytes());
+    for field in [&provenance.author, &provenance.note] {
+        let field = field.as_deref().unwrap_or_default();
+        bytes.extend((field.len() as u16).to_be_bytes());
+        bytes.extend(field.as_bytes());
+    }
+    bytes.extend(message);
+    bytes
+}
+
+/// Splits a payload made by `wrap` into the provenance record and the
+/// message
+pub fn unwrap(payload: &[u8]) -> Result<(Provenance, &[u8])> {
+    envelope::negotiate(payload)?;
+    let mut rest = payload;
+    let mut take = |len: usize| -> Result<&[u8]> {
+        if rest.len() < len {
+            return Err(Box::new(InvalidProvenanceError));
+        }
+        let (taken, tail) = rest.split_at(len);
+        rest = tail;
+        Ok(taken)
+    };
+
+    if take(MAGIC.len())? != MAGIC {
+        return Err(Box::new(InvalidProvenanceError));
+    }
+    // the version, negotiated above
+    take(1)?;
+    let created = u64::from_be_bytes(take(8)?.try_into()?);
+    let mut field = || -> Result<Option<String>> {
+        let len = u16::from_be_bytes(take(2)?.try_into()?) as usize;
+        let value = String::from_utf8(take(len)?.to_vec())?;
+        Ok(Some(value).filter(|value| !value.is_empty()))
+    };
+    let author = field()?;
+    let note = field()?;
+
+    Ok((Provenance { author, note, created }, rest))
+}
+
+/// Error specifying an author or note too long to be recorded
+#[derive(Debug)]
+pub struct FieldTooLongError(&'static str);
+
+impl fmt::Display for FieldTooLongError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Field Too Long! (the {} is limited to {} bytes)", self.0, u16::MAX)
+    }
+}
+
+impl error::Error for FieldTooLongError {}
+
+/// Error specifying a provenance record that could not be parsed
+#[derive(Debug)]
+pub struct InvalidProvenanceError;
+
+impl fmt::Display for InvalidProvenanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Provenance Record!")
+    }
+}
+
+impl error::Error for InvalidProvenanceError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let provenance = Provenance::at(Some(String::from("alice")), Some(String::from("for bob")), 1_700_000_000).unwrap();
+        let payload = wrap(&provenance, b"message");
+        assert!(is_recorded(&payload));
+
+        let (unwrapped, message) = unwrap(&payload).unwrap();
+        assert_eq!(unwrapped, provenance);
+        assert_eq!(message, b"message");
+        assert_eq!(unwrapped.to_string(), "encoded at 1700000000 (Unix time) by alice, note: for bob");
+
+        let anonymous = Provenance::at(Some(String::new()), None, 0).unwrap();
+        assert_eq!(unwrap(&wrap(&anonymous, b"")).unwrap().0, anonymous);
+        assert!(anonymous.author.is_none());
+
+        assert!(unwrap(&payload[..10]).is_err());
+        assert!(Provenance::at(None, Some("x".repeat(70_000)), 0).is_err());
+    }
+}
@@ -0,0 +1,347 @@
+//!
+//! Best-effort recovery of PNG files damaged by text-mode transfers (FTP in
+//! ASCII mode and the like). The damage is recognised from the CR LF, EOF
+//! and LF bytes of the signature. Every chunk is then rebuilt by undoing
+//! the damage the most likely way, trying the fewest possible changes to
+//! that guess until the chunk matches its CRC.
+//!
+use std::{error, fmt};
+use crate::{chunk::{Chunk, ISO_3309}, png::Png, Result};
+
+/// DOS end-of-file byte, which some transfers drop or stop at
+const EOF: u8 = 0x1a;
+
+/// Most changes to the likely reversal tried for a single chunk
+const MAX_EDITS: usize = 3;
+
+/// Most bytes rebuilt while searching for a single chunk
+const SEARCH_BUDGET: u64 = 1 << 24;
+
+/// What a text-mode transfer did to line endings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEndings {
+    /// A CR was inserted before LF bytes (Unix to Windows)
+    CrInserted,
+    /// CR LF pairs were turned into LF (Windows to Unix)
+    CrStripped,
+}
+
+/// What a text-mode transfer did with the DOS end-of-file byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EofByte {
+    /// Every 0x1A byte was dropped
+    Stripped,
+    /// The file was cut off at the first 0x1A byte
+    Truncated,
+}
+
+/// Damage a text-mode transfer did to a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextModeDamage {
+    pub line_endings: Option<LineEndings>,
+    pub eof: Option<EofByte>,
+}
+
+impl TextModeDamage {
+    /// Whether `repair` can try to undo the damage
+    pub fn is_repairable(&self) -> bool {
+        self.eof != Some(EofByte::Truncated)
+    }
+}
+
+impl fmt::Display for TextModeDamage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let changes: Vec<&str> = [
+            self.line_endings.map(|line_endings| match line_endings {
+                LineEndings::CrInserted => "inserted a CR before every LF",
+                LineEndings::CrStripped => "turned CR LF into LF",
+            }),
+            self.eof.map(|eof| match eof {
+                EofByte::Stripped => "dropped the 0x1A bytes",
+                EofByte::Truncated => "stopped at the first 0x1A byte",
+            }),
+        ].into_iter().flatten().collect();
+
+        let outcome = if self.is_repairable() { "pngme repair can try to undo it" } else { "the rest of the file is lost" };
+        write!(f, "the file looks damaged by a text-mode transfer that {} ({})", changes.join(" and "), outcome)
+    }
+}
+
+/// Recognises text-mode transfer damage from the start of a file
+pub fn text_mode_damage(bytes: &[u8]) -> Option<TextModeDamage> {
+    parse_signature(bytes)
+        .map(|(damage, _)| damage)
+        .filter(|damage| damage.line_endings.is_some() || damage.eof.is_some())
+}
+
+/// Reads the damage off the signature, returning it with the length of the
+/// damaged signature. An undamaged signature gives no damage.
+fn parse_signature(bytes: &[u8]) -> Option<(TextModeDamage, usize)> {
+    let rest = bytes.strip_prefix(&Png::STANDARD_HEADER[..4])?;
+    let (line_endings, rest) = if let Some(rest) = rest.strip_prefix(b"\r\r\n") {
+        (Some(LineEndings::CrInserted), rest)
+    } else if let Some(rest) = rest.strip_prefix(b"\r\n") {
+        (None, rest)
+    } else {
+        (Some(LineEndings::CrStripped), rest.strip_prefix(b"\n")?)
+    };
+
+    let (eof, rest) = match rest.split_first() {
+        Some((&EOF, rest)) => (None, rest),
+        Some(_) => (Some(EofByte::Stripped), rest),
+        None => (Some(EofByte::Truncated), rest),
+    };
+    let (line_endings, rest) = match (line_endings, eof) {
+        (_, Some(EofByte::Truncated)) => (line_endings, rest),
+        (Some(LineEndings::CrStripped), _) => (line_endings, rest.strip_prefix(b"\n")?),
+        // converters that leave CR LF pairs alone only change the last LF
+        _ if rest.starts_with(b"\r\n") => (Some(LineEndings::CrInserted), &rest[2..]),
+        (None, _) => (None, rest.strip_prefix(b"\n")?),
+        (Some(LineEndings::CrInserted), _) => return None,
+    };
+    Some((TextModeDamage { line_endings, eof }, bytes.len() - rest.len()))
+}
+
+/// A file rebuilt by `repair`
+#[derive(Debug)]
+pub struct Recovery {
+    pub bytes: Vec<u8>,
+    pub chunks: usize,
+    /// Chunks that only matched their CRC after changes to the likely
+    /// reversal of the damage
+    pub searched: usize,
+}
+
+/// Undoes the text-mode transfer damage the signature shows, rebuilding the
+/// file chunk by chunk. Every rebuilt chunk matches its CRC.
+pub fn repair(bytes: &[u8]) -> Result<Recovery> {
+    let (damage, signature_len) = parse_signature(bytes).ok_or(UnrecoverableError(None))?;
+    if !damage.is_repairable() {
+        return Err(Box::new(UnrecoverableError(Some(damage))));
+    }
+
+    let mut recovery = Recovery { bytes: Png::STANDARD_HEADER.to_vec(), chunks: 0, searched: 0 };
+    let mut search = Search { damaged: bytes, damage, rebuilt: Vec::new(), work: 0 };
+    let mut pos = signature_len;
+    while pos < bytes.len() {
+        let (end, edits) = search.chunk_at(pos).ok_or(UnrecoverableChunkError(pos))?;
+        recovery.bytes.extend(&search.rebuilt);
+        recovery.chunks += 1;
+        recovery.searched += usize::from(edits > 0);
+        pos = end;
+    }
+    Ok(recovery)
+}
+
+/// Depth-first search for the original bytes of one chunk
+struct Search<'a> {
+    damaged: &'a [u8],
+    damage: TextModeDamage,
+    rebuilt: Vec<u8>,
+    work: u64,
+}
+
+impl Search<'_> {
+    /// Rebuilds the chunk starting at the position of the damaged file,
+    /// returning the position after it and the number of changes to the
+    /// likely reversal it needed
+    fn chunk_at(&mut self, pos: usize) -> Option<(usize, usize)> {
+        self.work = 0;
+        (0..=MAX_EDITS).find_map(|edits| {
+            self.rebuilt.clear();
+            self.extend(pos, edits).map(|end| (end, edits))
+        })
+    }
+
+    /// Continues rebuilding from the position with up to `edits` changes
+    /// left, backtracking out of dead ends
+    fn extend(&mut self, mut pos: usize, edits: usize) -> Option<usize> {
+        loop {
+            self.work += 1;
+            if self.work > SEARCH_BUDGET {
+                return None;
+            }
+
+            if self.rebuilt.len() >= 8 {
+                let length = u32::from_be_bytes(self.rebuilt[..4].try_into().ok()?) as usize;
+                if length > Chunk::MAX_LENGTH || !self.rebuilt[4..8].iter().all(u8::is_ascii_alphabetic) {
+                    return None;
+                }
+                if self.rebuilt.len() == length + 12 {
+                    let crc = u32::from_be_bytes(self.rebuilt[length + 8..].try_into().ok()?);
+                    return (ISO_3309.checksum(&self.rebuilt[4..length + 8]) == crc).then_some(pos);
+                }
+            }
+
+            let mark = self.rebuilt.len();
+            if edits > 0 && self.damage.eof == Some(EofByte::Stripped) {
+                self.rebuilt.push(EOF);
+                if let Some(end) = self.extend(pos, edits - 1) {
+                    return Some(end);
+                }
+                self.rebuilt.truncate(mark);
+            }
+
+            let byte = *self.damaged.get(pos)?;
+            let before_lf = self.damaged.get(pos + 1) == Some(&b'\n');
+            match self.damage.line_endings {
+                Some(LineEndings::CrInserted) if byte == b'\r' && before_lf => {
+                    // most likely inserted, but it may have been there before
+                    if edits > 0 {
+                        self.rebuilt.push(b'\r');
+                        if let Some(end) = self.extend(pos + 1, edits - 1) {
+                            return Some(end);
+                        }
+                        self.rebuilt.truncate(mark);
+                    }
+                    pos += 1;
+                    continue;
+                },
+                Some(LineEndings::CrStripped) if byte == b'\n' && edits > 0 => {
+                    self.rebuilt.extend(b"\r\n");
+                    if let Some(end) = self.extend(pos + 1, edits - 1) {
+                        return Some(end);
+                    }
+                    self.rebuilt.truncate(mark);
+                },
+                _ => {},
+            }
+            self.rebuilt.push(byte);
+            pos += 1;
+        }
+    }
+}
+
+/// Error specifying a file whose damage cannot be undone
+#[derive(Debug)]
+pub struct UnrecoverableError(Option<TextModeDamage>);
+
+impl fmt::Display for UnrecoverableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(damage) => write!(f, "Cannot Repair! ({})", damage),
+            None => write!(f, "Cannot Repair! (not a PNG signature, damaged or not)"),
+        }
+    }
+}
+
+impl error::Error for UnrecoverableError {}
+
+/// Error specifying the offset of a chunk no fix matching its CRC was
+/// found for
+#[derive(Debug)]
+pub struct UnrecoverableChunkError(usize);
+
+impl fmt::Display for UnrecoverableChunkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Cannot Repair! (no fix matches the CRC of the chunk at offset {})", self.0)
+    }
+}
+
+impl error::Error for UnrecoverableChunkError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::testing::chunk;
+
+    fn testing_bytes() -> Vec<u8> {
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0, 0, 0, 10, 0, 0, 0, 10, 8, 2, 0, 0, 0]),
+            chunk("tEXt", b"Comment\0first line\r\nsecond line\nend of file \x1a here"),
+            // LF bytes without CR or EOF bytes, as in most image data
+            chunk("IDAT", &[0, 10, 200, 10, 42].repeat(400)),
+            chunk("IEND", b""),
+        ]).as_bytes()
+    }
+
+    /// What a text-mode write on Windows does
+    fn insert_cr(bytes: &[u8]) -> Vec<u8> {
+        bytes.iter().flat_map(|&byte| if byte == b'\n' { vec![b'\r', b'\n'] } else { vec![byte] }).collect()
+    }
+
+    /// What converters that leave existing CR LF pairs alone do
+    fn insert_missing_cr(bytes: &[u8]) -> Vec<u8> {
+        let mut damaged = Vec::new();
+        for (i, &byte) in bytes.iter().enumerate() {
+            if byte == b'\n' && (i == 0 || bytes[i - 1] != b'\r') {
+                damaged.push(b'\r');
+            }
+            damaged.push(byte);
+        }
+        damaged
+    }
+
+    /// What a text-mode read on Windows does
+    fn strip_cr(bytes: &[u8]) -> Vec<u8> {
+        let mut damaged = Vec::new();
+        for (i, &byte) in bytes.iter().enumerate() {
+            if !(byte == b'\r' && bytes.get(i + 1) == Some(&b'\n')) {
+                damaged.push(byte);
+            }
+        }
+        damaged
+    }
+
+    #[test]
+    fn test_text_mode_damage() {
+        let bytes = testing_bytes();
+        assert_eq!(text_mode_damage(&bytes), None);
+
+        let damage = |line_endings, eof| Some(TextModeDamage { line_endings, eof });
+        assert_eq!(text_mode_damage(&insert_cr(&bytes)), damage(Some(LineEndings::CrInserted), None));
+        assert_eq!(text_mode_damage(&insert_missing_cr(&bytes)), damage(Some(LineEndings::CrInserted), None));
+        assert_eq!(text_mode_damage(&strip_cr(&bytes)), damage(Some(LineEndings::CrStripped), None));
+
+        let without_eof: Vec<u8> = bytes.iter().copied().filter(|&byte| byte != EOF).collect();
+        assert_eq!(text_mode_damage(&without_eof), damage(None, Some(EofByte::Stripped)));
+        assert_eq!(text_mode_damage(&strip_cr(&without_eof)), damage(Some(LineEndings::CrStripped), Some(EofByte::Stripped)));
+        assert_eq!(text_mode_damage(&bytes[..6]), damage(None, Some(EofByte::Truncated)));
+    }
+
+    #[test]
+    fn test_repair_inserted_cr() {
+        let bytes = testing_bytes();
+        let recovery = repair(&insert_cr(&bytes)).unwrap();
+        assert_eq!(recovery.bytes, bytes);
+        assert_eq!((recovery.chunks, recovery.searched), (4, 0));
+
+        // the CR of the existing CR LF pair has to be kept
+        let recovery = repair(&insert_missing_cr(&bytes)).unwrap();
+        assert_eq!(recovery.bytes, bytes);
+        assert_eq!(recovery.searched, 1);
+    }
+
+    #[test]
+    fn test_repair_stripped_cr() {
+        let bytes = testing_bytes();
+        let recovery = repair(&strip_cr(&bytes)).unwrap();
+        assert_eq!(recovery.bytes, bytes);
+        assert_eq!(recovery.searched, 1);
+    }
+
+    #[test]
+    fn test_repair_stripped_eof() {
+        let bytes = testing_bytes();
+        let damaged: Vec<u8> = strip_cr(&bytes).into_iter().filter(|&byte| byte != EOF).collect();
+        assert_eq!(repair(&damaged).unwrap().bytes, bytes);
+    }
+
+    #[test]
+    fn test_repair_undamaged() {
+        let bytes = testing_bytes();
+        assert_eq!(repair(&bytes).unwrap().bytes, bytes);
+    }
+
+    #[test]
+    fn test_unrecoverable() {
+        let bytes = testing_bytes();
+        assert!(repair(&bytes[..6]).is_err());
+        assert!(repair(b"GIF89a").is_err());
+
+        let mut damaged = insert_cr(&bytes);
+        let last = damaged.len() - 1;
+        damaged[last] ^= 1;
+        assert!(repair(&damaged).is_err());
+    }
+}
@@ -0,0 +1,85 @@
+//!
+//! Copy-on-write file clones (reflinks), which share the data blocks of
+//! the source until either file is modified
+//!
+use std::{fs, io, path::Path};
+
+/// Creates `dst` as a copy-on-write clone of the source file. Fails when
+/// `dst` exists or the filesystem cannot clone, in which case nothing is
+/// left behind and the caller should copy normally.
+pub fn clone_into(src: &fs::File, dst: &Path) -> io::Result<()> {
+    imp::clone_into(src, dst)
+}
+
+#[cfg(target_os = "linux")]
+mod imp {
+    use std::{fs, io, os::fd::AsRawFd, path::Path};
+
+    pub fn clone_into(src: &fs::File, dst: &Path) -> io::Result<()> {
+        let file = fs::OpenOptions::new().write(true).create_new(true).open(dst)?;
+
+        // SAFETY: both descriptors are open for the duration of the call
+        let result = unsafe { libc::ioctl(file.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) };
+        if result == -1 {
+            let e = io::Error::last_os_error();
+            drop(file);
+            fs::remove_file(dst)?;
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_vendor = "apple")]
+mod imp {
+    use std::{ffi::CString, fs, io, os::{fd::AsRawFd, unix::ffi::OsStrExt}, path::Path};
+
+    pub fn clone_into(src: &fs::File, dst: &Path) -> io::Result<()> {
+        let dst = CString::new(dst.as_os_str().as_bytes())?;
+
+        // SAFETY: the descriptor is open and the path is null-terminated
+        let result = unsafe { libc::fclonefileat(src.as_raw_fd(), libc::AT_FDCWD, dst.as_ptr(), 0) };
+        if result == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_vendor = "apple")))]
+mod imp {
+    use std::{fs, io, path::Path};
+
+    pub fn clone_into(_src: &fs::File, _dst: &Path) -> io::Result<()> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_clone_or_clean_up() {
+        let dir = env::temp_dir().join(format!("pngme-reflink-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let src = dir.join("src.png");
+        let dst = dir.join("dst.png");
+        fs::write(&src, b"contents").unwrap();
+
+        // whether the filesystem supports clones or not, a failed clone
+        // leaves no file behind and a successful one copies the data
+        match clone_into(&fs::File::open(&src).unwrap(), &dst) {
+            Ok(()) => assert_eq!(fs::read(&dst).unwrap(), b"contents"),
+            Err(_) => assert!(!dst.exists()),
+        }
+
+        // never overwrites an existing file
+        fs::write(&dst, b"existing").unwrap();
+        assert!(clone_into(&fs::File::open(&src).unwrap(), &dst).is_err());
+        assert_eq!(fs::read(&dst).unwrap(), b"existing");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+//!
+//! Carrier PNGs fetched from http(s) URLs, so images already published on
+//! the web can be inspected or used for encoding without saving them
+//! first. Downloading needs the `net` feature; without it URLs are refused.
+//!
+use std::{error, fmt};
+use crate::Result;
+
+/// Largest PNG that is downloaded
+pub const MAX_DOWNLOAD_LEN: u64 = 256 * 1024 * 1024;
+
+/// Checks if the path of a PNG is an http(s) URL rather than a file
+pub fn is_url(path: &str) -> bool {
+    let scheme = path.split_once("://").map(|(scheme, _)| scheme.to_ascii_lowercase());
+    matches!(scheme.as_deref(), Some("http" | "https"))
+}
+
+/// Downloads the file at the URL into memory, refusing files larger than
+/// `MAX_DOWNLOAD_LEN`
+#[cfg(feature = "net")]
+pub fn fetch(url: &str) -> Result<Vec<u8>> {
+    use std::{io::Read, time::Duration};
+
+    let agent = ureq::AgentBuilder::new().timeout(Duration::from_secs(60)).build();
+    let response = agent.get(url).call()?;
+
+    let mut bytes = Vec::new();
+    response.into_reader().take(MAX_DOWNLOAD_LEN + 1).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > MAX_DOWNLOAD_LEN {
+        return Err(Box::new(DownloadTooLargeError));
+    }
+    Ok(bytes)
+}
+
+/// Refuses the URL, as pngme was built without the `net` feature
+#[cfg(not(feature = "net"))]
+pub fn fetch(_url: &str) -> Result<Vec<u8>> {
+    Err(Box::new(NetworkUnavailableError))
+}
+
+/// Error specifying a download larger than `MAX_DOWNLOAD_LEN`
+#[derive(Debug)]
+pub struct DownloadTooLargeError;
+
+impl fmt::Display for DownloadTooLargeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Download Too Large! (more than {} bytes)", MAX_DOWNLOAD_LEN)
+    }
+}
+
+impl error::Error for DownloadTooLargeError {}
+
+/// Error specifying a URL given to a pngme built without the `net` feature
+#[derive(Debug)]
+pub struct NetworkUnavailableError;
+
+impl fmt::Display for NetworkUnavailableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Network Unavailable! (pngme was built without the net feature, so it cannot fetch URLs)")
+    }
+}
+
+impl error::Error for NetworkUnavailableError {}
+
+/// Error specifying an encode into a downloaded PNG without an output
+/// file to write the result to
+#[derive(Debug)]
+pub struct OutputRequiredError;
+
+impl fmt::Display for OutputRequiredError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Output File Required! (a PNG fetched from a URL cannot be modified in place)")
+    }
+}
+
+impl error::Error for OutputRequiredError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_url() {
+        assert!(is_url("https://example.com/dice.png"));
+        assert!(is_url("HTTP://example.com/dice.png"));
+        assert!(!is_url("dice.png"));
+        assert!(!is_url("ftp://example.com/dice.png"));
+        assert!(!is_url("C:\\images\\dice.png"));
+    }
+
+    #[cfg(feature = "net")]
+    #[test]
+    fn test_fetch() {
+        use std::{io::{Read, Write}, net::TcpListener, thread};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/dice.png", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\n\x89PNG\r").unwrap();
+        });
+        assert_eq!(fetch(&url).unwrap(), b"\x89PNG\r");
+        server.join().unwrap();
+    }
+
+    #[cfg(not(feature = "net"))]
+    #[test]
+    fn test_fetch_unavailable() {
+        assert!(fetch("https://example.com/dice.png").unwrap_err().is::<NetworkUnavailableError>());
+    }
+}
@@ -0,0 +1,350 @@
+//!
+//! Signed seal chunk for tamper evidence. The seal lists the type and a
+//! hash of every other chunk, and an Ed25519 signature over the Merkle root
+//! of that list, so a verifier holding the public key can tell which chunks
+//! changed since sealing.
+//!
+//! | Field                                  | Size     |
+//! |----------------------------------------|----------|
+//! | version                                | 1        |
+//! | Ed25519 public key                     | 32       |
+//! | chunk count                            | 4        |
+//! | per chunk: type and SHA-256 of data    | 4 + 32   |
+//! | Ed25519 signature over the Merkle root | 64       |
+//!
+//! A detached signature file holds the same fields after the magic bytes
+//! `PMDS`, leaving the PNG itself untouched.
+//!
+use std::{error, fmt, str::FromStr};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
+use sha2::{Digest, Sha256};
+use crate::{chunk::Chunk, chunk_type::ChunkType, crypto, manifest::{self, Mismatch}, png::Png, Result};
+
+/// Chunk type of the seal (ancillary, private, safe to copy)
+pub const SEAL_TYPE: &str = "pnSl";
+
+/// Marks the start of a detached signature file
+pub const DETACHED_MAGIC: &[u8; 4] = b"PMDS";
+
+pub const VERSION: u8 = 1;
+const ENTRY_LEN: usize = 4 + 32;
+
+/// Prefix of the signed message, so seal signatures cannot be replayed as
+/// signatures of anything else
+const DOMAIN: &[u8] = b"pngme seal v1";
+
+/// Chunk types and data hashes of a PNG, signed
+pub struct Seal {
+    entries: Vec<(String, [u8; 32])>,
+    public_key: VerifyingKey,
+    signature: Signature,
+}
+
+/// Lists the chunk type and SHA-256 of the data of every chunk but seals
+fn entries(png: &Png) -> Vec<(String, [u8; 32])> {
+    png.chunks().iter()
+        .filter(|chunk| chunk.chunk_type().as_str() != SEAL_TYPE)
+        .map(|chunk| (chunk.chunk_type().as_str(), Sha256::digest(chunk.data()).into()))
+        .collect()
+}
+
+/// Computes the Merkle root of the entries. Leaves and inner nodes hash
+/// with different prefixes, and an odd node is carried up a level as is.
+fn merkle_root(entries: &[(String, [u8; 32])]) -> [u8; 32] {
+    let mut level: Vec<[u8; 32]> = entries.iter()
+        .map(|(chunk_type, hash)| Sha256::new().chain_update([0]).chain_update(chunk_type).chain_update(hash).finalize().into())
+        .collect();
+    if level.is_empty() {
+        return Sha256::digest([]).into();
+    }
+
+    while level.len() > 1 {
+        level = level.chunks(2)
+            .map(|pair| match pair {
+                [left, right] => Sha256::new().chain_update([1]).chain_update(left).chain_update(right).finalize().into(),
+                _ => pair[0],
+            })
+            .collect();
+    }
+    level[0]
+}
+
+fn signed_message(entries: &[(String, [u8; 32])]) -> Vec<u8> {
+    [DOMAIN, &merkle_root(entries)].concat()
+}
+
+impl Seal {
+    /// Signs the chunks of the PNG, other than seals, with the key
+    pub fn sign(png: &Png, key: &SigningKey) -> Seal {
+        let entries = entries(png);
+        let signature = key.sign(&signed_message(&entries));
+        Seal { entries, public_key: key.verifying_key(), signature }
+    }
+
+    /// Returns the seal stored in the PNG, if there is one
+    pub fn from_png(png: &Png) -> Result<Option<Seal>> {
+        match png.chunk_by_type(SEAL_TYPE) {
+            Some(chunk) => Ok(Some(Seal::try_from(chunk)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Key the seal was signed with
+    pub fn public_key(&self) -> &VerifyingKey {
+        &self.public_key
+    }
+
+    /// Checks the signature, and that it was made by the trusted key when
+    /// one is given. Without one, anyone could have made the seal.
+    pub fn verify_signature(&self, trusted: Option<&VerifyingKey>) -> Result<()> {
+        if trusted.is_some_and(|trusted| *trusted != self.public_key) {
+            return Err(Box::new(UntrustedSealKeyError(hex::encode(self.public_key.as_bytes()))));
+        }
+        self.public_key.verify(&signed_message(&self.entries), &self.signature)
+            .map_err(|_| Box::new(SealSignatureError).into())
+    }
+
+    /// Lists how the PNG differs from the sealed chunks, in chunk order.
+    /// Only meaningful once the signature is verified.
+    pub fn verify(&self, png: &Png) -> Vec<Mismatch> {
+        manifest::mismatches(&self.entries, &entries(png))
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk::new(ChunkType::from_str(SEAL_TYPE).unwrap(), self.to_bytes())
+    }
+
+    /// Returns the contents of a detached signature file
+    pub fn to_detached(&self) -> Vec<u8> {
+        [DETACHED_MAGIC.as_slice(), &self.to_bytes()].concat()
+    }
+
+    /// Parses the contents of a detached signature file
+    pub fn from_detached(bytes: &[u8]) -> Result<Seal> {
+        Seal::from_bytes(bytes.strip_prefix(DETACHED_MAGIC).ok_or(InvalidSealError)?)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut data = vec![VERSION];
+        data.extend(self.public_key.as_bytes());
+        data.extend((self.entries.len() as u32).to_be_bytes());
+        for (chunk_type, hash) in &self.entries {
+            data.extend(chunk_type.as_bytes());
+            data.extend(hash);
+        }
+        data.extend(self.signature.to_bytes());
+        data
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Seal> {
+        let header_len = 1 + PUBLIC_KEY_LENGTH + 4;
+        if data.len() < header_len + SIGNATURE_LENGTH {
+            return Err(Box::new(InvalidSealError));
+        }
+        if data[0] != VERSION {
+            return Err(Box::new(crate::envelope::UnsupportedVersionError(crate::envelope::Layer::DetachedSeal, data[0])));
+        }
+
+        let public_key = VerifyingKey::from_bytes(data[1..1 + PUBLIC_KEY_LENGTH].try_into()?)
+            .map_err(|_| InvalidSealError)?;
+        let count = u32::from_be_bytes(data[1 + PUBLIC_KEY_LENGTH..header_len].try_into()?) as usize;
+        let body = &data[header_len..data.len() - SIGNATURE_LENGTH];
+        if count.checked_mul(ENTRY_LEN) != Some(body.len()) {
+            return Err(Box::new(InvalidSealError));
+        }
+
+        let mut entries = Vec::new();
+        for entry in body.chunks(ENTRY_LEN) {
+            let chunk_type = ChunkType::try_from(<[u8; 4]>::try_from(&entry[..4])?)?;
+            entries.push((chunk_type.to_string(), entry[4..].try_into()?));
+        }
+        let signature = Signature::from_bytes(data[data.len() - SIGNATURE_LENGTH..].try_into()?);
+        Ok(Seal { entries, public_key, signature })
+    }
+}
+
+impl TryFrom<&Chunk> for Seal {
+    type Error = crate::Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        Seal::from_bytes(chunk.data())
+    }
+}
+
+/// Replaces any seal in the PNG with one signed by the key, placed right
+/// before the IEND chunk
+pub fn embed(png: &mut Png, key: &SigningKey) {
+    png.retain_chunks(|chunk| chunk.chunk_type().as_str() != SEAL_TYPE);
+    let seal = Seal::sign(png, key).to_chunk();
+
+    let index = png.chunks().iter()
+        .position(|chunk| chunk.chunk_type().as_str() == "IEND")
+        .unwrap_or(png.chunks().len());
+    png.insert_chunk(index, seal);
+}
+
+/// Generates a new signing key from the operating system's random source
+pub fn generate_key() -> SigningKey {
+    SigningKey::from_bytes(&crypto::random_bytes())
+}
+
+/// Reads a signing key from key file contents (32 bytes, raw or hex)
+pub fn signing_key(contents: &[u8]) -> Result<SigningKey> {
+    let bytes = crypto::parse_key_material(contents);
+    Ok(SigningKey::from_bytes(&bytes[..].try_into().map_err(|_| InvalidSealKeyError)?))
+}
+
+/// Reads a public key from key file contents (32 bytes, raw or hex)
+pub fn verifying_key(contents: &[u8]) -> Result<VerifyingKey> {
+    let bytes = crypto::parse_key_material(contents);
+    VerifyingKey::from_bytes(&bytes[..].try_into().map_err(|_| InvalidSealKeyError)?)
+        .map_err(|_| Box::new(InvalidSealKeyError).into())
+}
+
+/// Error specifying that a seal chunk could not be parsed
+#[derive(Debug)]
+pub struct InvalidSealError;
+
+impl fmt::Display for InvalidSealError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Seal Chunk!")
+    }
+}
+
+impl error::Error for InvalidSealError {}
+
+/// Error specifying a seal whose signature does not match its contents
+#[derive(Debug)]
+pub struct SealSignatureError;
+
+impl fmt::Display for SealSignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Seal Signature! (the seal itself was modified)")
+    }
+}
+
+impl error::Error for SealSignatureError {}
+
+/// Error specifying a seal signed by a key other than the trusted one
+#[derive(Debug)]
+pub struct UntrustedSealKeyError(String);
+
+impl fmt::Display for UntrustedSealKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Untrusted Seal Key! (sealed by {})", self.0)
+    }
+}
+
+impl error::Error for UntrustedSealKeyError {}
+
+/// Error specifying that a PNG no longer matches its seal
+#[derive(Debug)]
+pub struct SealMismatchError(pub usize);
+
+impl fmt::Display for SealMismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} chunk(s) do not match the seal!", self.0)
+    }
+}
+
+impl error::Error for SealMismatchError {}
+
+/// Error specifying a key file that does not hold a 32-byte Ed25519 key
+#[derive(Debug)]
+pub struct InvalidSealKeyError;
+
+impl fmt::Display for InvalidSealKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Seal Key! (expected 32 bytes, raw or hex-encoded)")
+    }
+}
+
+impl error::Error for InvalidSealKeyError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::testing::chunk;
+
+    fn testing_png() -> Png {
+        let mut png = crate::png::testing::testing_png();
+        png.insert_chunk(2, chunk("tEXt", b"Title\0x"));
+        png
+    }
+
+    #[test]
+    fn test_seal_and_verify() {
+        let key = generate_key();
+        let mut png = testing_png();
+        embed(&mut png, &key);
+        assert_eq!(png.chunks()[4].chunk_type().as_str(), "IEND");
+
+        let seal = Seal::from_png(&Png::try_from(&png.as_bytes()[..]).unwrap()).unwrap().unwrap();
+        assert!(seal.verify_signature(Some(&key.verifying_key())).is_ok());
+        assert!(seal.verify(&png).is_empty());
+        assert!(seal.verify_signature(Some(&generate_key().verifying_key())).is_err());
+    }
+
+    #[test]
+    fn test_changed_chunks() {
+        let key = generate_key();
+        let mut png = testing_png();
+        embed(&mut png, &key);
+
+        png.map_chunks(|c| if c.chunk_type().as_str() == "IDAT" { chunk("IDAT", b"edited") } else { c });
+        png.insert_chunk(4, chunk("ruSt", b"added"));
+        let seal = Seal::from_png(&png).unwrap().unwrap();
+        assert_eq!(seal.verify(&png), vec![
+            Mismatch::Modified { index: 1, chunk_type: String::from("IDAT") },
+            Mismatch::Modified { index: 3, chunk_type: String::from("ruSt") },
+            Mismatch::Unexpected { index: 4, chunk_type: String::from("IEND") },
+        ]);
+    }
+
+    #[test]
+    fn test_modified_seal() {
+        let key = generate_key();
+        let mut png = testing_png();
+        embed(&mut png, &key);
+
+        // rewriting a recorded hash to hide a change breaks the signature
+        let mut data = png.chunk_by_type(SEAL_TYPE).unwrap().data().to_vec();
+        data[1 + PUBLIC_KEY_LENGTH + 4 + ENTRY_LEN + 4] ^= 1;
+        let seal = Seal::try_from(&chunk(SEAL_TYPE, &data)).unwrap();
+        assert!(seal.verify_signature(None).is_err());
+
+        assert!(Seal::try_from(&chunk(SEAL_TYPE, &data[..data.len() - 1])).is_err());
+    }
+
+    #[test]
+    fn test_detached() {
+        let key = generate_key();
+        let png = testing_png();
+        let detached = Seal::sign(&png, &key).to_detached();
+        assert!(detached.starts_with(DETACHED_MAGIC));
+
+        let seal = Seal::from_detached(&detached).unwrap();
+        assert!(seal.verify_signature(Some(&key.verifying_key())).is_ok());
+        assert!(seal.verify(&png).is_empty());
+        assert!(Seal::from_detached(&detached[DETACHED_MAGIC.len()..]).is_err());
+    }
+
+    #[test]
+    fn test_merkle_root() {
+        let entry = |n: u8| (String::from("IDAT"), [n; 32]);
+        let roots: Vec<[u8; 32]> = (0..5).map(|n| merkle_root(&(0..n).map(entry).collect::<Vec<_>>())).collect();
+        for (i, a) in roots.iter().enumerate() {
+            assert!(roots[i + 1..].iter().all(|b| a != b));
+        }
+        assert_ne!(merkle_root(&[entry(1), entry(2)]), merkle_root(&[entry(2), entry(1)]));
+    }
+
+    #[test]
+    fn test_keys() {
+        let key = generate_key();
+        let hex = hex::encode(key.to_bytes());
+        assert_eq!(signing_key(hex.as_bytes()).unwrap().verifying_key(), key.verifying_key());
+        assert_eq!(verifying_key(hex::encode(key.verifying_key().as_bytes()).as_bytes()).unwrap(), key.verifying_key());
+        assert!(signing_key(b"abcd").is_err());
+    }
+}
@@ -0,0 +1,216 @@
+//!
+//! Shamir secret sharing of a message across several PNGs: any `threshold`
+//! of the shares recover the message, fewer reveal nothing about it
+//!
+use std::{fmt, error, str::FromStr};
+use sha2::{Digest, Sha256};
+use sharks::Sharks;
+use crate::{chunk::Chunk, chunk_type::ChunkType, crypto, png::Png, Result};
+
+/// Chunk type of a share (ancillary, private, safe to copy)
+pub const SHARE_TYPE: &str = "pnSh";
+
+const VERSION: u8 = 1;
+const SET_ID_LEN: usize = 16;
+const HEADER_LEN: usize = 1 + SET_ID_LEN + 2;
+const DIGEST_LEN: usize = 32;
+
+/// One share of a message with the metadata needed to reassemble it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Share {
+    /// Random id shared by all shares of one split
+    pub set_id: [u8; SET_ID_LEN],
+    pub threshold: u8,
+    pub total: u8,
+    /// x coordinate followed by the y values of the share
+    pub data: Vec<u8>,
+}
+
+impl Share {
+    /// Serializes the share as version, set id, threshold, total and data
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = vec![VERSION];
+        data.extend(self.set_id);
+        data.extend([self.threshold, self.total]);
+        data.extend(&self.data);
+        Chunk::new(ChunkType::from_str(SHARE_TYPE).unwrap(), data)
+    }
+}
+
+impl TryFrom<&Chunk> for Share {
+    type Error = crate::Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Share> {
+        let data = chunk.data();
+        if data.len() < HEADER_LEN + 2 || data[0] != VERSION {
+            return Err(Box::new(InvalidShareError));
+        }
+
+        Ok(Share {
+            set_id: data[1..1 + SET_ID_LEN].try_into()?,
+            threshold: data[1 + SET_ID_LEN],
+            total: data[2 + SET_ID_LEN],
+            data: data[HEADER_LEN..].to_vec(),
+        })
+    }
+}
+
+/// Splits the message into `total` shares of which any `threshold`
+/// recover it. A SHA-256 digest is shared along with the message, so that
+/// combining shares of different splits is detected without the shares
+/// revealing anything about the message.
+pub fn split(message: &[u8], threshold: u8, total: u8) -> Result<Vec<Share>> {
+    if threshold == 0 || threshold > total {
+        return Err(Box::new(InvalidThresholdError(threshold, total)));
+    }
+
+    let mut secret = message.to_vec();
+    secret.extend(Sha256::digest(message));
+
+    let set_id = crypto::random_bytes::<SET_ID_LEN>();
+    Ok(Sharks(threshold).dealer(&secret)
+        .take(total as usize)
+        .map(|share| Share { set_id, threshold, total, data: Vec::from(&share) })
+        .collect())
+}
+
+/// Recovers the message from at least `threshold` shares of one split
+pub fn combine(shares: &[Share]) -> Result<Vec<u8>> {
+    let first = shares.first().ok_or(NotEnoughSharesError(0, 1))?;
+    if shares.iter().any(|share| share.set_id != first.set_id) {
+        return Err(Box::new(MixedSharesError));
+    }
+
+    let shares = shares.iter()
+        .map(|share| sharks::Share::try_from(&share.data[..]).map_err(|_| InvalidShareError))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    let count = shares.iter().map(|share| share.x.0).collect::<std::collections::HashSet<_>>().len();
+    if count < first.threshold as usize {
+        return Err(Box::new(NotEnoughSharesError(count, first.threshold)));
+    }
+
+    let secret = Sharks(first.threshold).recover(&shares).map_err(|_| InvalidShareError)?;
+    if secret.len() < DIGEST_LEN {
+        return Err(Box::new(InvalidShareError));
+    }
+    let (message, digest) = secret.split_at(secret.len() - DIGEST_LEN);
+    if Sha256::digest(message).as_slice() != digest {
+        return Err(Box::new(MixedSharesError));
+    }
+    Ok(message.to_vec())
+}
+
+/// Returns the share stored in the PNG, if any
+pub fn get_share(png: &Png) -> Result<Option<Share>> {
+    png.chunk_by_type(SHARE_TYPE).map(Share::try_from).transpose()
+}
+
+/// Stores the share in the PNG before IEND, replacing any existing share
+pub fn set_share(png: &mut Png, share: &Share) {
+    png.retain_chunks(|chunk| chunk.chunk_type().as_str() != SHARE_TYPE);
+    let index = png.chunks().iter()
+        .position(|chunk| chunk.chunk_type().as_str() == "IEND")
+        .unwrap_or(png.chunks().len());
+    png.insert_chunk(index, share.to_chunk());
+}
+
+/// Error specifying a threshold outside of 1 to the number of shares
+#[derive(Debug)]
+pub struct InvalidThresholdError(u8, u8);
+
+impl fmt::Display for InvalidThresholdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Threshold {}! (must be between 1 and {})", self.0, self.1)
+    }
+}
+
+impl error::Error for InvalidThresholdError {}
+
+/// Error specifying fewer shares than the threshold
+#[derive(Debug)]
+pub struct NotEnoughSharesError(usize, u8);
+
+impl fmt::Display for NotEnoughSharesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Not Enough Shares! ({} of {} needed)", self.0, self.1)
+    }
+}
+
+impl error::Error for NotEnoughSharesError {}
+
+/// Error specifying shares that do not belong to the same split
+#[derive(Debug)]
+pub struct MixedSharesError;
+
+impl fmt::Display for MixedSharesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Shares Do Not Belong Together!")
+    }
+}
+
+impl error::Error for MixedSharesError {}
+
+/// Error specifying share data that cannot be read
+#[derive(Debug)]
+pub struct InvalidShareError;
+
+impl fmt::Display for InvalidShareError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Share!")
+    }
+}
+
+impl error::Error for InvalidShareError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::testing::chunk;
+
+    #[test]
+    fn test_split_and_combine() {
+        let shares = split(b"meet at dawn", 2, 3).unwrap();
+        assert_eq!(shares.len(), 3);
+
+        for pair in [[0, 1], [0, 2], [1, 2]] {
+            let subset: Vec<Share> = pair.iter().map(|&i| shares[i].clone()).collect();
+            assert_eq!(combine(&subset).unwrap(), b"meet at dawn");
+        }
+        assert!(combine(&shares[..1]).is_err());
+    }
+
+    #[test]
+    fn test_chunk_round_trip() {
+        let share = split(b"message", 1, 1).unwrap().remove(0);
+        assert_eq!(Share::try_from(&share.to_chunk()).unwrap(), share);
+    }
+
+    #[test]
+    fn test_mixed_shares() {
+        let mut shares = split(b"message", 2, 2).unwrap();
+        let other = split(b"message", 2, 2).unwrap();
+        shares[1] = other[1].clone();
+        assert!(combine(&shares).is_err());
+
+        // shares of another split relabelled with this set id
+        shares[1].set_id = shares[0].set_id;
+        assert!(combine(&shares).is_err());
+    }
+
+    #[test]
+    fn test_invalid_threshold() {
+        assert!(split(b"message", 0, 3).is_err());
+        assert!(split(b"message", 4, 3).is_err());
+    }
+
+    #[test]
+    fn test_set_share_replaces() {
+        let mut png = Png::from_chunks(vec![chunk("IHDR", b""), chunk("IEND", b"")]);
+        let shares = split(b"message", 2, 2).unwrap();
+        set_share(&mut png, &shares[0]);
+        set_share(&mut png, &shares[1]);
+
+        assert_eq!(png.chunks().len(), 3);
+        assert_eq!(get_share(&png).unwrap().unwrap(), shares[1]);
+    }
+}
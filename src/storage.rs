@@ -0,0 +1,75 @@
+//!
+//! Location and persistence of pngme's local data files
+//!
+use std::{env, error, fmt, fs, path::{Path, PathBuf}};
+use serde::{de::DeserializeOwned, Serialize};
+use crate::Result;
+
+/// Environment variable that overrides the data directory
+pub const DATA_DIR_VAR: &str = "PNGME_DATA_DIR";
+
+/// Returns the directory pngme keeps its local data in: $PNGME_DATA_DIR if
+/// set, otherwise a pngme directory in the platform's user data directory
+pub fn data_dir() -> Result<PathBuf> {
+    match env::var_os(DATA_DIR_VAR) {
+        Some(dir) => Ok(PathBuf::from(dir)),
+        None => match dirs::data_dir() {
+            Some(dir) => Ok(dir.join("pngme")),
+            None => Err(Box::new(NoDataDirError)),
+        },
+    }
+}
+
+/// Reads a JSON data file, returning the default value if it does not exist
+pub fn load_json<T: DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(Box::new(e)),
+    }
+}
+
+/// Writes a JSON data file, creating its directory if needed. The file is
+/// replaced atomically so a crash never leaves it half written.
+pub fn save_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(value)?)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+/// Error specifying that there is no directory to keep local data in
+#[derive(Debug)]
+pub struct NoDataDirError;
+
+impl fmt::Display for NoDataDirError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "No data directory found! (set {})", DATA_DIR_VAR)
+    }
+}
+
+impl error::Error for NoDataDirError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_round_trip() {
+        let dir = env::temp_dir().join(format!("pngme-storage-{}", std::process::id()));
+        let path = dir.join("nested").join("data.json");
+
+        let missing: Vec<String> = load_json(&path).unwrap();
+        assert!(missing.is_empty());
+
+        save_json(&path, &vec![String::from("saved")]).unwrap();
+        let loaded: Vec<String> = load_json(&path).unwrap();
+        assert_eq!(loaded, vec!["saved"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,243 @@
+//!
+//! Streaming chunk I/O: writing a chunk whose data arrives as a stream and
+//! reading the data of a chunk without buffering it
+//!
+use std::{error, fmt, io::{self, BufRead, Read, Seek, SeekFrom, Write}};
+use crc::Digest;
+use crate::{chunk::{Chunk, ISO_3309}, chunk_type::ChunkType, png::{InvalidSignatureError, Png}, Result};
+
+/// Writes a single chunk to a seekable output while its data is streamed
+/// in. The length is patched in and the CRC appended by `finish`.
+pub struct ChunkWriter<'a, W: Write + Seek> {
+    output: W,
+    start: u64,
+    length: u64,
+    digest: Digest<'a, u32>,
+}
+
+impl<W: Write + Seek> ChunkWriter<'_, W> {
+    /// Starts a chunk of the type at the current position of the output
+    pub fn new(mut output: W, chunk_type: &ChunkType) -> Result<Self> {
+        let start = output.stream_position()?;
+        output.write_all(&[0; 4])?;
+        output.write_all(&chunk_type.bytes())?;
+
+        let mut digest = ISO_3309.digest();
+        digest.update(&chunk_type.bytes());
+        Ok(ChunkWriter { output, start, length: 0, digest })
+    }
+
+    /// Completes the chunk and returns the output, positioned right after
+    /// the chunk, and the chunk's data length
+    pub fn finish(mut self) -> Result<(W, u32)> {
+        let length = self.length as u32;
+        self.output.write_all(&self.digest.finalize().to_be_bytes())?;
+        let end = self.output.stream_position()?;
+
+        self.output.seek(SeekFrom::Start(self.start))?;
+        self.output.write_all(&length.to_be_bytes())?;
+        self.output.seek(SeekFrom::Start(end))?;
+        Ok((self.output, length))
+    }
+}
+
+impl<W: Write + Seek> Write for ChunkWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.length + buf.len() as u64 > Chunk::MAX_LENGTH as u64 {
+            return Err(io::Error::other(ChunkTooLongError));
+        }
+
+        let written = self.output.write(buf)?;
+        self.digest.update(&buf[..written]);
+        self.length += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Reads a single chunk from an input, handing out its data as a stream.
+/// The CRC is checked by `finish` once all data has been read.
+pub struct ChunkReader<'a, R: Read> {
+    input: R,
+    chunk_type: ChunkType,
+    remaining: u32,
+    digest: Digest<'a, u32>,
+}
+
+impl<R: Read> ChunkReader<'_, R> {
+    /// Reads the length and type of the chunk starting at the current
+    /// position of the input
+    pub fn new(mut input: R) -> Result<Self> {
+        let mut header = [0u8; 8];
+        input.read_exact(&mut header)?;
+
+        let remaining = u32::from_be_bytes(header[..4].try_into()?);
+        let type_bytes: [u8; 4] = header[4..].try_into()?;
+        let chunk_type = ChunkType::try_from(type_bytes)?;
+
+        let mut digest = ISO_3309.digest();
+        digest.update(&type_bytes);
+        Ok(ChunkReader { input, chunk_type, remaining, digest })
+    }
+
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+
+    /// Number of data bytes not read yet
+    pub fn remaining(&self) -> u32 {
+        self.remaining
+    }
+
+    /// Skips any unread data, checks the CRC and returns the input,
+    /// positioned at the next chunk
+    pub fn finish(mut self) -> Result<R> {
+        io::copy(&mut self, &mut io::sink())?;
+
+        let mut crc = [0u8; 4];
+        self.input.read_exact(&mut crc)?;
+        if self.digest.finalize() != u32::from_be_bytes(crc) {
+            return Err(Box::new(StreamCrcError));
+        }
+        Ok(self.input)
+    }
+}
+
+impl<R: Read> Read for ChunkReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.remaining as usize);
+        if len == 0 {
+            return Ok(0);
+        }
+
+        let read = self.input.read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        self.digest.update(&buf[..read]);
+        self.remaining -= read as u32;
+        Ok(read)
+    }
+}
+
+/// Reads a PNG from the input up to the first chunk of the type, returning
+/// a reader for its data or `None` if the file ends without one. Like
+/// `Png::parse`, chunks after IEND are searched too. The chunks skipped on
+/// the way have their CRCs checked.
+pub fn find_chunk<'a, R: BufRead>(mut input: R, chunk_type: &str) -> Result<Option<ChunkReader<'a, R>>> {
+    let mut signature = [0u8; 8];
+    input.read_exact(&mut signature)?;
+    if signature != Png::STANDARD_HEADER {
+        return Err(Box::new(InvalidSignatureError));
+    }
+
+    while !input.fill_buf()?.is_empty() {
+        let reader = ChunkReader::new(input)?;
+        if reader.chunk_type().to_string() == chunk_type {
+            return Ok(Some(reader));
+        }
+        input = reader.finish()?;
+    }
+    Ok(None)
+}
+
+/// Error specifying streamed chunk data longer than a chunk can hold
+#[derive(Debug)]
+pub struct ChunkTooLongError;
+
+impl fmt::Display for ChunkTooLongError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Chunk Too Long! (at most {} bytes of data)", Chunk::MAX_LENGTH)
+    }
+}
+
+impl error::Error for ChunkTooLongError {}
+
+/// Error specifying a streamed chunk whose CRC does not match its data
+#[derive(Debug)]
+pub struct StreamCrcError;
+
+impl fmt::Display for StreamCrcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid CRC in Streamed Chunk!")
+    }
+}
+
+impl error::Error for StreamCrcError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::testing::chunk;
+    use std::{io::Cursor, str::FromStr};
+
+    fn chunk_type() -> ChunkType {
+        ChunkType::from_str("RuSt").unwrap()
+    }
+
+    #[test]
+    fn test_chunk_writer() {
+        let mut writer = ChunkWriter::new(Cursor::new(Vec::new()), &chunk_type()).unwrap();
+        for piece in ["This is where ", "your secret message ", "will be!"] {
+            writer.write_all(piece.as_bytes()).unwrap();
+        }
+        let (output, length) = writer.finish().unwrap();
+
+        let expected = Chunk::new(chunk_type(), b"This is where your secret message will be!".to_vec());
+        assert_eq!(length, 42);
+        assert_eq!(output.into_inner(), expected.as_bytes());
+    }
+
+    #[test]
+    fn test_chunk_writer_after_existing_data() {
+        let mut output = Cursor::new(b"prefix".to_vec());
+        output.seek(SeekFrom::End(0)).unwrap();
+
+        let mut writer = ChunkWriter::new(output, &chunk_type()).unwrap();
+        io::copy(&mut &b"streamed"[..], &mut writer).unwrap();
+        let (mut output, _) = writer.finish().unwrap();
+        output.write_all(b"suffix").unwrap();
+
+        let mut expected = b"prefix".to_vec();
+        expected.extend(Chunk::new(chunk_type(), b"streamed".to_vec()).as_bytes());
+        expected.extend(b"suffix");
+        assert_eq!(output.into_inner(), expected);
+    }
+
+    #[test]
+    fn test_chunk_reader() {
+        let mut bytes = Chunk::new(chunk_type(), b"first".to_vec()).as_bytes();
+        bytes.extend(Chunk::new(chunk_type(), b"second".to_vec()).as_bytes());
+
+        let mut reader = ChunkReader::new(&bytes[..]).unwrap();
+        assert_eq!(reader.chunk_type(), &chunk_type());
+        let mut data = String::new();
+        reader.read_to_string(&mut data).unwrap();
+        assert_eq!(data, "first");
+
+        // finishing without reading skips the data
+        let reader = ChunkReader::new(reader.finish().unwrap()).unwrap();
+        assert!(reader.finish().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_find_chunk() {
+        let bytes = Png::from_chunks(vec![chunk("IHDR", b"header"), chunk("RuSt", b"payload"), chunk("IEND", b"")]).as_bytes();
+
+        let mut data = String::new();
+        find_chunk(&bytes[..], "RuSt").unwrap().unwrap().read_to_string(&mut data).unwrap();
+        assert_eq!(data, "payload");
+        assert!(find_chunk(&bytes[..], "ruSt").unwrap().is_none());
+        assert!(find_chunk(&bytes[1..], "RuSt").is_err());
+    }
+
+    #[test]
+    fn test_chunk_reader_invalid_crc() {
+        let mut bytes = Chunk::new(chunk_type(), b"data".to_vec()).as_bytes();
+        *bytes.last_mut().unwrap() ^= 1;
+        assert!(ChunkReader::new(&bytes[..]).unwrap().finish().is_err());
+    }
+}
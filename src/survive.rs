@@ -0,0 +1,122 @@
+//!
+//! Simulated transforms of common image pipelines, for checking whether a
+//! payload survives metadata stripping, re-encoding and cropping before
+//! trusting a file to them
+//!
+use std::fmt;
+use clap::ValueEnum;
+use crate::{chunk::Chunk, pixels::RgbaImage, placement, png::Png, text, Result};
+
+/// What a pipeline does to the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Transform {
+    /// Drop tEXt, zTXt and iTXt chunks, as privacy scrubbers do
+    StripText,
+    /// Drop ancillary chunks the PNG specification does not register, as
+    /// optimizers do
+    StripPrivate,
+    /// Keep only the chunks needed to display the image
+    StripMetadata,
+    /// Decode the pixels and encode them into a fresh PNG, as image hosts do
+    Reencode,
+    /// Cut a tenth off every edge and re-encode
+    Crop,
+}
+
+/// Chunks a metadata stripper keeps, as they change how the image looks
+const DISPLAY_TYPES: [&str; 5] = ["IHDR", "PLTE", "tRNS", "IDAT", "IEND"];
+
+impl Transform {
+    /// Applies the transform to the PNG. Data after IEND never survives
+    /// a rewrite, so the result has none.
+    pub fn apply(&self, png: &Png) -> Result<Png> {
+        let keep = |keep: &dyn Fn(&Chunk) -> bool| -> Result<Png> {
+            Ok(Png::from_chunks(png.chunks().iter()
+                .filter(|chunk| keep(chunk))
+                .map(|chunk| Chunk::try_from(&chunk.as_bytes()[..]))
+                .collect::<Result<_>>()?))
+        };
+        let reencode = |image: RgbaImage| Png::try_from(&image.encode()?[..]);
+
+        Ok(match self {
+            Transform::StripText => keep(&|chunk| !text::is_text_chunk(chunk))?,
+            Transform::StripPrivate => keep(&|chunk| chunk.chunk_type().is_critical() || chunk.chunk_type().is_registered())?,
+            Transform::StripMetadata => keep(&|chunk| DISPLAY_TYPES.contains(&chunk.chunk_type().as_str().as_str()))?,
+            Transform::Reencode => reencode(RgbaImage::decode(&png.as_bytes())?)?,
+            Transform::Crop => {
+                let image = RgbaImage::decode(&png.as_bytes())?;
+                let (dx, dy) = (image.width / 10, image.height / 10);
+                reencode(image.crop(dx, dy, image.width - 2 * dx, image.height - 2 * dy))?
+            },
+        })
+    }
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_possible_value().unwrap().get_name())
+    }
+}
+
+/// Outcome of one transform
+#[derive(Debug, PartialEq, Eq)]
+pub struct Outcome {
+    pub transform: Transform,
+    /// Chunks still holding an intact copy of the payload, of the number
+    /// there were
+    pub surviving: usize,
+    pub total: usize,
+}
+
+/// Runs each transform on the PNG and counts the chunks holding the
+/// payload stored under the chunk type, or a copy of it (see the placement
+/// module), that come through unchanged
+pub fn survive(png: &Png, chunk_type: &str, transforms: &[Transform]) -> Result<Vec<Outcome>> {
+    let originals = placement::copies(png, chunk_type);
+    let holders = |png: &Png| png.chunks().iter()
+        .filter_map(|chunk| placement::stored_payload(chunk, chunk_type))
+        .filter(|payload| originals.contains(payload))
+        .count();
+
+    let total = holders(png);
+    transforms.iter()
+        .map(|&transform| Ok(Outcome { transform, surviving: holders(&transform.apply(png)?), total }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::testing::chunk;
+
+    fn testing_png() -> Png {
+        let image = RgbaImage { width: 20, height: 20, pixels: vec![128; 20 * 20 * 4] };
+        Png::try_from(&image.encode().unwrap()[..]).unwrap()
+    }
+
+    fn surviving(png: &Png) -> Vec<usize> {
+        survive(png, "ruSt", Transform::value_variants()).unwrap().iter().map(|o| o.surviving).collect()
+    }
+
+    #[test]
+    fn test_standard_placement() {
+        let mut png = testing_png();
+        png.insert_chunk(2, chunk("ruSt", b"payload"));
+        assert_eq!(surviving(&png), vec![1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_resilient_placement() {
+        let mut png = testing_png();
+        png.insert_chunk(2, chunk("ruSt", b"payload"));
+        placement::replicate(&mut png, "ruSt", b"payload");
+        assert_eq!(surviving(&png), vec![2, 1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_crop() {
+        let cropped = Transform::Crop.apply(&testing_png()).unwrap();
+        let image = RgbaImage::decode(&cropped.as_bytes()).unwrap();
+        assert_eq!((image.width, image.height), (16, 16));
+    }
+}
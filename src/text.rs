@@ -0,0 +1,360 @@
+//!
+//! Textual keyword/value metadata stored in tEXt, zTXt and iTXt chunks
+//!
+use std::{fmt, error, io::{Read, Write}, str::{self, FromStr}};
+use clap::ValueEnum;
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png, Result};
+
+/// Keyword/value pair read from a text chunk
+#[derive(Debug, PartialEq, Eq)]
+pub struct TextEntry {
+    pub keyword: String,
+    pub text: String,
+}
+
+/// What setting a keyword does when text chunks already use it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OnDuplicate {
+    /// Remove the existing chunks with the keyword
+    #[default]
+    Replace,
+    /// Keep the existing chunks and add another
+    Append,
+    /// Refuse to set the keyword
+    Error,
+}
+
+/// Keywords registered by the PNG specification. Others are allowed but
+/// other readers may not know what they mean.
+pub const REGISTERED_KEYWORDS: &[&str] = &[
+    "Title", "Author", "Description", "Copyright", "Creation Time", "Software", "Disclaimer", "Warning", "Source",
+    "Comment", "XML:com.adobe.xmp", "Collection",
+];
+
+/// Longest keyword the PNG specification allows, in Latin-1 characters
+pub const MAX_KEYWORD_LEN: usize = 79;
+
+/// Checks if the chunk type is one of the text chunk types
+pub fn is_text_chunk(chunk: &Chunk) -> bool {
+    is_text_chunk_type(&chunk.chunk_type().as_str())
+}
+
+/// Checks if the chunk type is tEXt, zTXt or iTXt
+pub fn is_text_chunk_type(chunk_type: &str) -> bool {
+    matches!(chunk_type, "tEXt" | "zTXt" | "iTXt")
+}
+
+/// Checks the keyword against the rules of the PNG specification: 1 to 79
+/// printable Latin-1 characters, with no leading, trailing or consecutive
+/// spaces
+pub fn validate_keyword(keyword: &str) -> Result<()> {
+    let reason = if keyword.is_empty() {
+        "it is empty"
+    } else if keyword.chars().count() > MAX_KEYWORD_LEN {
+        "it is longer than 79 characters"
+    } else if !keyword.chars().all(|c| matches!(c, ' '..='~' | '\u{a1}'..='\u{ff}')) {
+        "it has characters that are not printable Latin-1"
+    } else if keyword.starts_with(' ') || keyword.ends_with(' ') {
+        "it has leading or trailing spaces"
+    } else if keyword.contains("  ") {
+        "it has consecutive spaces"
+    } else {
+        return Ok(());
+    };
+    Err(Box::new(InvalidKeywordError(String::from(keyword), reason)))
+}
+
+/// Checks if the keyword is one the PNG specification registers
+pub fn is_registered_keyword(keyword: &str) -> bool {
+    REGISTERED_KEYWORDS.contains(&keyword)
+}
+
+/// Returns the keyword at the start of text chunk data
+pub fn keyword(data: &[u8]) -> Result<String> {
+    let keyword_end = data.iter().position(|&b| b == 0).ok_or(InvalidTextChunkError)?;
+    Ok(latin1_string(&data[..keyword_end]))
+}
+
+/// Lays out the text under the keyword as the data of a tEXt, zTXt or
+/// iTXt chunk. tEXt and zTXt hold Latin-1 text only.
+pub fn text_data(chunk_type: &str, keyword: &str, text: &str) -> Result<Vec<u8>> {
+    if !is_text_chunk_type(chunk_type) {
+        return Err(Box::new(NotTextChunkTypeError(String::from(chunk_type))));
+    }
+    if chunk_type == "iTXt" {
+        return Ok(itxt(keyword, text).data().to_vec());
+    }
+    if text.chars().any(|c| u8::try_from(c).is_err()) {
+        return Err(Box::new(NotLatin1Error));
+    }
+
+    let mut data = latin1_bytes(keyword);
+    data.push(0);
+    if chunk_type == "zTXt" {
+        // compression method 0 (zlib)
+        data.push(0);
+        data.extend(deflate(&latin1_bytes(text))?);
+    } else {
+        data.extend(latin1_bytes(text));
+    }
+    Ok(data)
+}
+
+/// Builds an uncompressed iTXt chunk holding UTF-8 text under the keyword
+pub fn itxt(keyword: &str, text: &str) -> Chunk {
+    let mut data: Vec<u8> = latin1_bytes(keyword);
+    // null separator, compression flag and method, empty language tag and
+    // empty translated keyword
+    data.extend([0, 0, 0, 0, 0]);
+    data.extend(text.as_bytes());
+    Chunk::new(ChunkType::from_str("iTXt").unwrap(), data)
+}
+
+/// Parses a tEXt, zTXt or iTXt chunk. Returns None for other chunk types.
+pub fn parse(chunk: &Chunk) -> Result<Option<TextEntry>> {
+    let data = chunk.data();
+    let chunk_type = chunk.chunk_type().as_str();
+    if !is_text_chunk(chunk) {
+        return Ok(None);
+    }
+
+    let keyword_end = data.iter().position(|&b| b == 0).ok_or(InvalidTextChunkError)?;
+    let keyword = latin1_string(&data[..keyword_end]);
+    let rest = &data[keyword_end + 1..];
+
+    let text = match chunk_type.as_str() {
+        "tEXt" => latin1_string(rest),
+        "zTXt" => {
+            // compression method byte followed by the zlib stream
+            let compressed = rest.get(1..).ok_or(InvalidTextChunkError)?;
+            latin1_string(&inflate(compressed)?)
+        },
+        _ => {
+            let (&compressed, rest) = rest.split_first().ok_or(InvalidTextChunkError)?;
+            let rest = rest.get(1..).ok_or(InvalidTextChunkError)?;
+
+            // skip the language tag and translated keyword
+            let text = rest.splitn(3, |&b| b == 0).nth(2).ok_or(InvalidTextChunkError)?;
+
+            let text = if compressed == 1 { inflate(text)? } else { text.to_vec() };
+            String::from_utf8(text)?
+        },
+    };
+
+    Ok(Some(TextEntry { keyword, text }))
+}
+
+/// Returns the text stored under the keyword in the PNG, if any
+pub fn get_text(png: &Png, keyword: &str) -> Result<Option<String>> {
+    for chunk in png.chunks() {
+        if let Some(entry) = parse(chunk)? {
+            if entry.keyword == keyword {
+                return Ok(Some(entry.text));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Stores the text under the keyword as an iTXt chunk, handling text
+/// chunks that already use the keyword as `on_duplicate` says. New chunks
+/// go right before IEND.
+pub fn set_text(png: &mut Png, keyword: &str, text: &str, on_duplicate: OnDuplicate) -> Result<()> {
+    make_room(png, keyword, on_duplicate)?;
+
+    let index = png.chunks().iter()
+        .position(|chunk| chunk.chunk_type().as_str() == "IEND")
+        .unwrap_or(png.chunks().len());
+    png.insert_chunk(index, itxt(keyword, text));
+    Ok(())
+}
+
+/// Prepares the PNG for a new text chunk with the keyword: removes the
+/// text chunks already using it (`Replace`), keeps them (`Append`) or
+/// refuses if there are any (`Error`)
+pub fn make_room(png: &mut Png, keyword: &str, on_duplicate: OnDuplicate) -> Result<()> {
+    let uses_keyword = |chunk: &Chunk| matches!(parse(chunk), Ok(Some(entry)) if entry.keyword == keyword);
+    match on_duplicate {
+        OnDuplicate::Replace => png.retain_chunks(|chunk| !uses_keyword(chunk)),
+        OnDuplicate::Append => {},
+        OnDuplicate::Error if png.chunks().iter().any(uses_keyword) 
+        => return Err(Box::new(DuplicateKeywordError(String::from(keyword)))),
+        OnDuplicate::Error => {},
+    }
+    Ok(())
+}
+
+fn deflate(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+fn inflate(compressed: &[u8]) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    ZlibDecoder::new(compressed).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Keywords are Latin-1, so every byte maps to the char with that code point
+fn latin1_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+/// Encodes the string as Latin-1, replacing chars outside of it with '?'
+fn latin1_bytes(s: &str) -> Vec<u8> {
+    s.chars().map(|c| u8::try_from(c).unwrap_or(b'?')).collect()
+}
+
+/// Error specifying a text chunk that does not follow the PNG layout
+#[derive(Debug)]
+pub struct InvalidTextChunkError;
+
+impl fmt::Display for InvalidTextChunkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Text Chunk!")
+    }
+}
+
+impl error::Error for InvalidTextChunkError {}
+
+/// Error specifying a keyword that breaks the rules of the PNG
+/// specification
+#[derive(Debug)]
+pub struct InvalidKeywordError(String, &'static str);
+
+impl fmt::Display for InvalidKeywordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid Keyword {:?}! ({})", self.0, self.1)
+    }
+}
+
+impl error::Error for InvalidKeywordError {}
+
+/// Error specifying a keyword that text chunks already use
+#[derive(Debug)]
+pub struct DuplicateKeywordError(String);
+
+impl fmt::Display for DuplicateKeywordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Duplicate Keyword {:?}! (see --on-duplicate)", self.0)
+    }
+}
+
+impl error::Error for DuplicateKeywordError {}
+
+/// Error specifying a keyword given for a chunk type that has none
+#[derive(Debug)]
+pub struct NotTextChunkTypeError(String);
+
+impl fmt::Display for NotTextChunkTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Not A Text Chunk Type! ({} has no keyword, use tEXt, zTXt or iTXt)", self.0)
+    }
+}
+
+impl error::Error for NotTextChunkTypeError {}
+
+/// Error specifying text for tEXt or zTXt outside of Latin-1
+#[derive(Debug)]
+pub struct NotLatin1Error;
+
+impl fmt::Display for NotLatin1Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Text Not Latin-1! (use iTXt for other characters)")
+    }
+}
+
+impl error::Error for NotLatin1Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::testing::chunk;
+
+    #[test]
+    fn test_parse_text() {
+        let entry = parse(&chunk("tEXt", b"Author\0Caf\xe9")).unwrap().unwrap();
+        assert_eq!(entry, TextEntry { keyword: String::from("Author"), text: String::from("Café") });
+    }
+
+    #[test]
+    fn test_parse_ztxt() {
+        let mut data = b"Comment\0\0".to_vec();
+        data.extend(deflate(b"compressed comment").unwrap());
+        let entry = parse(&chunk("zTXt", &data)).unwrap().unwrap();
+        assert_eq!(entry.text, "compressed comment");
+    }
+
+    #[test]
+    fn test_parse_itxt() {
+        let entry = parse(&itxt("Title", "héllo wörld")).unwrap().unwrap();
+        assert_eq!(entry, TextEntry { keyword: String::from("Title"), text: String::from("héllo wörld") });
+
+        let mut data = b"Title\0\x01\0en\0Titel\0".to_vec();
+        data.extend(deflate("grüße".as_bytes()).unwrap());
+        let entry = parse(&chunk("iTXt", &data)).unwrap().unwrap();
+        assert_eq!(entry.text, "grüße");
+    }
+
+    #[test]
+    fn test_parse_other_chunk() {
+        assert!(parse(&chunk("ruSt", b"message")).unwrap().is_none());
+        assert!(parse(&chunk("tEXt", b"no separator")).is_err());
+    }
+
+    #[test]
+    fn test_set_text_replaces() {
+        let mut png = Png::from_chunks(vec![chunk("IHDR", b""), chunk("tEXt", b"Copyright\0old"), chunk("IEND", b"")]);
+        set_text(&mut png, "Copyright", "new", OnDuplicate::Replace).unwrap();
+        set_text(&mut png, "License", "MIT", OnDuplicate::Replace).unwrap();
+
+        let types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, vec!["IHDR", "iTXt", "iTXt", "IEND"]);
+        assert_eq!(get_text(&png, "Copyright").unwrap().unwrap(), "new");
+        assert_eq!(get_text(&png, "License").unwrap().unwrap(), "MIT");
+        assert!(get_text(&png, "Author").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_set_text_on_duplicate() {
+        let mut png = Png::from_chunks(vec![chunk("IHDR", b""), chunk("tEXt", b"Comment\0first"), chunk("IEND", b"")]);
+        assert!(set_text(&mut png, "Comment", "second", OnDuplicate::Error).is_err());
+        set_text(&mut png, "Author", "me", OnDuplicate::Error).unwrap();
+        set_text(&mut png, "Comment", "second", OnDuplicate::Append).unwrap();
+
+        let comments: Vec<String> = png.chunks().iter()
+            .filter_map(|c| parse(c).unwrap())
+            .filter(|entry| entry.keyword == "Comment")
+            .map(|entry| entry.text)
+            .collect();
+        assert_eq!(comments, ["first", "second"]);
+        assert_eq!(png.chunks().len(), 5);
+    }
+
+    #[test]
+    fn test_validate_keyword() {
+        for keyword in ["Title", "Creation Time", "Caf\u{e9} Notes", &"k".repeat(79)] {
+            assert!(validate_keyword(keyword).is_ok(), "{}", keyword);
+        }
+        for keyword in ["", " Title", "Title ", "Creation  Time", "Tab\there", "nbsp\u{a0}", "Gr\u{fc}\u{df}e \u{2603}", &"k".repeat(80)] {
+            assert!(validate_keyword(keyword).is_err(), "{}", keyword);
+        }
+        assert!(is_registered_keyword("Comment"));
+        assert!(!is_registered_keyword("comment"));
+    }
+
+    #[test]
+    fn test_text_data() {
+        for chunk_type in ["tEXt", "zTXt", "iTXt"] {
+            let data = text_data(chunk_type, "Comment", "caf\u{e9}").unwrap();
+            assert_eq!(keyword(&data).unwrap(), "Comment");
+            let entry = parse(&chunk(chunk_type, &data)).unwrap().unwrap();
+            assert_eq!(entry.text, "caf\u{e9}");
+        }
+        assert!(text_data("tEXt", "Comment", "\u{2603}").is_err());
+        assert!(text_data("ruSt", "Comment", "text").is_err());
+        assert!(keyword(b"no separator").is_err());
+    }
+}
@@ -0,0 +1,73 @@
+//!
+//! Preview thumbnail embedded as a complete, small PNG file in its own chunk
+//!
+use std::str::FromStr;
+use crate::{chunk::Chunk, chunk_type::ChunkType, pixels::RgbaImage, png::Png, Result};
+
+/// Chunk type of the thumbnail (ancillary, private, safe to copy)
+pub const THUMBNAIL_TYPE: &str = "pnTh";
+
+/// Default width and height limit of generated thumbnails in pixels
+pub const DEFAULT_SIZE: u32 = 128;
+
+/// Renders a thumbnail of the PNG file that fits in a `size` square
+pub fn generate(png_bytes: &[u8], size: u32) -> Result<Vec<u8>> {
+    RgbaImage::decode(png_bytes)?.downscale(size).encode()
+}
+
+/// Returns the thumbnail PNG file embedded in the PNG, if any
+pub fn get_thumbnail(png: &Png) -> Option<&[u8]> {
+    png.chunk_by_type(THUMBNAIL_TYPE).map(|chunk| chunk.data())
+}
+
+/// Embeds the thumbnail PNG file, replacing any existing thumbnail. The
+/// chunk goes before the image data so previews can stop reading early.
+pub fn set_thumbnail(png: &mut Png, thumbnail: Vec<u8>) -> Result<()> {
+    // refuse data that is not a PNG file
+    Png::try_from(&thumbnail[..])?;
+
+    png.retain_chunks(|chunk| chunk.chunk_type().as_str() != THUMBNAIL_TYPE);
+    let index = png.chunks().iter()
+        .position(|chunk| matches!(chunk.chunk_type().as_str().as_str(), "IDAT" | "IEND"))
+        .unwrap_or(png.chunks().len());
+    png.insert_chunk(index, Chunk::new(ChunkType::from_str(THUMBNAIL_TYPE)?, thumbnail));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_png_bytes() -> Vec<u8> {
+        let pixels = (0..64 * 32).flat_map(|i| [(i % 256) as u8, 0, 0, 255]).collect();
+        RgbaImage { width: 64, height: 32, pixels }.encode().unwrap()
+    }
+
+    #[test]
+    fn test_generate() {
+        let thumbnail = generate(&testing_png_bytes(), 16).unwrap();
+        let image = RgbaImage::decode(&thumbnail).unwrap();
+        assert_eq!((image.width, image.height), (16, 8));
+    }
+
+    #[test]
+    fn test_set_and_get_thumbnail() {
+        let bytes = testing_png_bytes();
+        let mut png = Png::try_from(&bytes[..]).unwrap();
+        let thumbnail = generate(&bytes, 8).unwrap();
+
+        set_thumbnail(&mut png, thumbnail.clone()).unwrap();
+        set_thumbnail(&mut png, thumbnail.clone()).unwrap();
+
+        assert_eq!(get_thumbnail(&png).unwrap(), &thumbnail[..]);
+        let types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, vec!["IHDR", THUMBNAIL_TYPE, "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_set_invalid_thumbnail() {
+        let bytes = testing_png_bytes();
+        let mut png = Png::try_from(&bytes[..]).unwrap();
+        assert!(set_thumbnail(&mut png, b"not a png".to_vec()).is_err());
+    }
+}
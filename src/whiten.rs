@@ -0,0 +1,107 @@
+//!
+//! Payload whitening: XOR with a pseudo-random stream so hidden text does
+//! not show up as ASCII in a hex editor. This conceals, it does not
+//! protect: the seed is stored in the payload and anyone who knows the
+//! format can undo it. Use encryption when the message must stay secret.
+//!
+use std::{error, fmt};
+use crate::{crypto, Result};
+
+/// Marks the start of a whitened payload
+pub const MAGIC: &[u8; 4] = b"PMWH";
+
+const SEED_LEN: usize = 8;
+
+/// SplitMix64, a small generator whose output passes for noise
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// XORs the data in place with the stream of the seed
+fn apply(seed: [u8; SEED_LEN], data: &mut [u8]) {
+    let mut rng = SplitMix64(u64::from_be_bytes(seed));
+    for block in data.chunks_mut(8) {
+        for (byte, key) in block.iter_mut().zip(rng.next().to_be_bytes()) {
+            *byte ^= key;
+        }
+    }
+}
+
+/// Checks if the payload starts with the whitening magic bytes
+pub fn is_whitened(payload: &[u8]) -> bool {
+    payload.len() >= MAGIC.len() + SEED_LEN && payload.starts_with(MAGIC)
+}
+
+/// Whitens the data under a fresh random seed, stored after the magic bytes
+pub fn whiten(data: &[u8]) -> Vec<u8> {
+    whiten_with_seed(crypto::random_bytes::<SEED_LEN>(), data)
+}
+
+/// Whitens the data under the seed
+pub(crate) fn whiten_with_seed(seed: [u8; SEED_LEN], data: &[u8]) -> Vec<u8> {
+    let mut payload = MAGIC.to_vec();
+    payload.extend(seed);
+    payload.extend(data);
+    apply(seed, &mut payload[MAGIC.len() + SEED_LEN..]);
+    payload
+}
+
+/// Restores the data of a whitened payload
+pub fn unwhiten(payload: &[u8]) -> Result<Vec<u8>> {
+    if !is_whitened(payload) {
+        return Err(Box::new(NotWhitenedError));
+    }
+    let seed = payload[MAGIC.len()..MAGIC.len() + SEED_LEN].try_into()?;
+    let mut data = payload[MAGIC.len() + SEED_LEN..].to_vec();
+    apply(seed, &mut data);
+    Ok(data)
+}
+
+/// Error specifying a payload that was not whitened
+#[derive(Debug)]
+pub struct NotWhitenedError;
+
+impl fmt::Display for NotWhitenedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Payload Is Not Whitened!")
+    }
+}
+
+impl error::Error for NotWhitenedError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let message = b"This is where your secret message will be!";
+        let payload = whiten(message);
+        assert!(is_whitened(&payload));
+        assert_eq!(unwhiten(&payload).unwrap(), message);
+        assert_ne!(whiten(message), payload);
+    }
+
+    #[test]
+    fn test_hides_text() {
+        let payload = whiten(&b"a".repeat(1000));
+        let printable = payload[MAGIC.len() + SEED_LEN..].iter().filter(|b| b.is_ascii_graphic()).count();
+        assert!(printable < 600, "{} of 1000 bytes printable", printable);
+        assert!(!payload.windows(4).any(|w| w == b"aaaa"));
+    }
+
+    #[test]
+    fn test_not_whitened() {
+        assert!(!is_whitened(b"plain message"));
+        assert!(unwhiten(b"plain message").is_err());
+        assert!(unwhiten(MAGIC).is_err());
+    }
+}
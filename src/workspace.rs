@@ -0,0 +1,173 @@
+//!
+//! Investigation workspace: an on-disk index of the chunk inventories,
+//! hashes and carrier findings of many PNGs that can be queried without
+//! reading the files again
+//!
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::{carrier, png::Png, storage, Result};
+
+/// Name of the directory in the data directory holding the workspaces
+pub const WORKSPACES_DIR: &str = "workspaces";
+
+/// Chunk as recorded in the index
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexedChunk {
+    pub chunk_type: String,
+    pub length: u32,
+    pub crc: u32,
+}
+
+/// PNG file as recorded in the index
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexedFile {
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+    pub chunks: Vec<IndexedChunk>,
+    /// Carrier warnings, or the parse error of files that are not valid PNGs
+    pub findings: Vec<String>,
+}
+
+/// Conditions a chunk must meet to match a query. Unset conditions match
+/// every chunk.
+#[derive(Debug, Default)]
+pub struct Query {
+    pub chunk_type: Option<String>,
+    pub min_size: Option<u32>,
+    pub max_size: Option<u32>,
+    /// Only match chunk types not registered by the PNG specification
+    pub non_standard: bool,
+}
+
+impl Query {
+    fn matches(&self, chunk: &IndexedChunk) -> bool {
+        self.chunk_type.as_ref().is_none_or(|t| *t == chunk.chunk_type)
+            && self.min_size.is_none_or(|min| chunk.length >= min)
+            && self.max_size.is_none_or(|max| chunk.length <= max)
+            && (!self.non_standard || !crate::chunk_type::REGISTERED_TYPES.contains(&chunk.chunk_type.as_str()))
+    }
+}
+
+/// Indexed files of one workspace
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Workspace {
+    files: Vec<IndexedFile>,
+}
+
+impl Workspace {
+    pub fn load(path: &Path) -> Result<Workspace> {
+        storage::load_json(path)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        storage::save_json(path, self)
+    }
+
+    /// Returns every indexed file
+    pub fn files(&self) -> &[IndexedFile] {
+        &self.files
+    }
+
+    /// Records the file's contents under its path, replacing an earlier
+    /// record of the same path
+    pub fn index_file(&mut self, path: &str, bytes: &[u8]) -> &IndexedFile {
+        let (chunks, findings) = match Png::try_from(bytes) {
+            Ok(png) => {
+                let chunks = png.chunks().iter()
+                    .map(|chunk| IndexedChunk {
+                        chunk_type: chunk.chunk_type().as_str(),
+                        length: chunk.length(),
+                        crc: chunk.crc(),
+                    })
+                    .collect();
+                let findings = carrier::check(&png).iter().map(|w| w.to_string()).collect();
+                (chunks, findings)
+            },
+            Err(e) => (Vec::new(), vec![format!("not a valid PNG: {}", e)]),
+        };
+
+        let file = IndexedFile {
+            path: String::from(path),
+            size: bytes.len() as u64,
+            sha256: hex::encode(Sha256::digest(bytes)),
+            chunks,
+            findings,
+        };
+
+        self.files.retain(|f| f.path != path);
+        self.files.push(file);
+        self.files.last().unwrap()
+    }
+
+    /// Returns every indexed chunk matching the query with its file
+    pub fn query(&self, query: &Query) -> Vec<(&IndexedFile, &IndexedChunk)> {
+        self.files.iter()
+            .flat_map(|file| file.chunks.iter().map(move |chunk| (file, chunk)))
+            .filter(|(_, chunk)| query.matches(chunk))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::testing::chunk;
+
+    fn png_bytes(chunks: &[(&str, usize)]) -> Vec<u8> {
+        let chunks = chunks.iter()
+            .map(|(t, len)| chunk(t, &vec![0; *len]))
+            .collect();
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    fn testing_workspace() -> Workspace {
+        let mut workspace = Workspace::default();
+        workspace.index_file("a.png", &png_bytes(&[("IHDR", 13), ("prIv", 2048), ("IEND", 0)]));
+        workspace.index_file("b.png", &png_bytes(&[("IHDR", 13), ("prIv", 10), ("IEND", 0)]));
+        workspace.index_file("c.png", &png_bytes(&[("IHDR", 13), ("tEXt", 4096), ("IEND", 0)]));
+        workspace
+    }
+
+    #[test]
+    fn test_index_file() {
+        let workspace = testing_workspace();
+        let file = &workspace.files()[0];
+        assert_eq!(file.chunks.len(), 3);
+        assert_eq!(file.chunks[1].chunk_type, "prIv");
+        assert_eq!(file.sha256.len(), 64);
+        assert_eq!(file.findings.len(), 1);
+    }
+
+    #[test]
+    fn test_reindex_replaces() {
+        let mut workspace = testing_workspace();
+        workspace.index_file("a.png", &png_bytes(&[("IHDR", 13), ("IEND", 0)]));
+        assert_eq!(workspace.files().len(), 3);
+        assert_eq!(workspace.files()[2].chunks.len(), 2);
+    }
+
+    #[test]
+    fn test_invalid_file() {
+        let mut workspace = Workspace::default();
+        let file = workspace.index_file("bad.png", b"definitely not a png");
+        assert!(file.chunks.is_empty());
+        assert!(file.findings[0].starts_with("not a valid PNG"));
+    }
+
+    #[test]
+    fn test_query() {
+        let workspace = testing_workspace();
+
+        let query = Query { chunk_type: Some(String::from("prIv")), min_size: Some(1024), ..Query::default() };
+        let paths: Vec<&str> = workspace.query(&query).iter().map(|(f, _)| f.path.as_str()).collect();
+        assert_eq!(paths, vec!["a.png"]);
+
+        let query = Query { min_size: Some(1024), non_standard: true, ..Query::default() };
+        assert_eq!(workspace.query(&query).len(), 1);
+
+        let query = Query { max_size: Some(13), ..Query::default() };
+        assert_eq!(workspace.query(&query).len(), 7);
+    }
+}
@@ -0,0 +1,139 @@
+//!
+//! XMP packets stored in the `XML:com.adobe.xmp` iTXt chunk
+//!
+use std::{fmt, error};
+use crate::{png::Png, text, Result};
+
+/// iTXt keyword the XMP specification reserves for PNG files
+pub const XMP_KEYWORD: &str = "XML:com.adobe.xmp";
+
+const PACKET_HEADER: &str = "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n";
+const PACKET_TRAILER: &str = "<?xpacket end=\"w\"?>";
+
+/// Lines of whitespace padding after the XMP data, so other tools can
+/// edit the packet in place (the specification recommends 2-4 KB)
+const PADDING_LINES: usize = 20;
+
+/// Returns the XMP packet stored in the PNG, if any
+pub fn get_packet(png: &Png) -> Result<Option<String>> {
+    text::get_text(png, XMP_KEYWORD)
+}
+
+/// Stores the XMP data as the PNG's packet, replacing any existing one.
+/// The chunk is placed before the image data as the XMP specification asks.
+pub fn set_packet(png: &mut Png, xml: &str) -> Result<()> {
+    let packet = wrap_packet(xml)?;
+
+    png.retain_chunks(|chunk| match text::parse(chunk) {
+        Ok(Some(entry)) => entry.keyword != XMP_KEYWORD,
+        _ => true,
+    });
+
+    let index = png.chunks().iter()
+        .position(|chunk| matches!(chunk.chunk_type().as_str().as_str(), "IDAT" | "IEND"))
+        .unwrap_or(png.chunks().len());
+    png.insert_chunk(index, text::itxt(XMP_KEYWORD, &packet));
+    Ok(())
+}
+
+/// Wraps the `x:xmpmeta` element (or a bare `rdf:RDF` element) of the XMP
+/// data in a packet with header, padding and trailer. An existing wrapper
+/// is replaced.
+pub fn wrap_packet(xml: &str) -> Result<String> {
+    let body = match element(xml, "x:xmpmeta") {
+        Some(xmpmeta) => String::from(xmpmeta),
+        None => {
+            let rdf = element(xml, "rdf:RDF").ok_or(InvalidXmpError)?;
+            format!("<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n{}\n</x:xmpmeta>", rdf)
+        }
+    };
+
+    let padding = format!("{}\n", " ".repeat(99)).repeat(PADDING_LINES);
+    Ok(format!("{}{}\n{}{}", PACKET_HEADER, body, padding, PACKET_TRAILER))
+}
+
+/// Merges two XMP packets by adding the `rdf:Description` elements of the
+/// update to the `rdf:RDF` element of the base. Readers combine multiple
+/// descriptions of the same resource into one set of properties.
+pub fn merge_packets(base: &str, update: &str) -> Result<String> {
+    let rdf = element(base, "rdf:RDF").ok_or(InvalidXmpError)?;
+    let update_rdf = element(update, "rdf:RDF").ok_or(InvalidXmpError)?;
+
+    // everything between the rdf:RDF tags of the update
+    let start = update_rdf.find('>').ok_or(InvalidXmpError)? + 1;
+    let end = update_rdf.rfind("</rdf:RDF>").ok_or(InvalidXmpError)?;
+    let descriptions = update_rdf[start..end].trim();
+
+    let close = rdf.rfind("</rdf:RDF>").ok_or(InvalidXmpError)?;
+    let merged_rdf = format!("{}{}\n{}", &rdf[..close], descriptions, &rdf[close..]);
+
+    wrap_packet(&base.replacen(rdf, &merged_rdf, 1))
+}
+
+/// Returns the first element with the tag name, including its start and
+/// end tags, without parsing the rest of the document
+fn element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}", tag))?;
+    let close = format!("</{}>", tag);
+    let end = xml[start..].find(&close)? + start + close.len();
+    Some(&xml[start..end])
+}
+
+/// Error specifying that data is not an XMP packet
+#[derive(Debug)]
+pub struct InvalidXmpError;
+
+impl fmt::Display for InvalidXmpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid XMP Packet! (expected an x:xmpmeta or rdf:RDF element)")
+    }
+}
+
+impl error::Error for InvalidXmpError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::testing::testing_png;
+
+    const RDF: &str = "<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\
+        <rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\" dc:format=\"image/png\"/>\
+        </rdf:RDF>";
+
+    #[test]
+    fn test_wrap_packet() {
+        let packet = wrap_packet(RDF).unwrap();
+        assert!(packet.starts_with(PACKET_HEADER));
+        assert!(packet.ends_with(PACKET_TRAILER));
+        assert!(packet.contains("<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">"));
+        assert!(packet.len() > 2000);
+
+        // wrapping an existing packet replaces its wrapper
+        assert_eq!(wrap_packet(&packet).unwrap(), packet);
+        assert!(wrap_packet("<not-xmp/>").is_err());
+    }
+
+    #[test]
+    fn test_set_packet_before_idat() {
+        let mut png = testing_png();
+        set_packet(&mut png, RDF).unwrap();
+        set_packet(&mut png, RDF).unwrap();
+
+        let types: Vec<String> = png.chunks().iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, vec!["IHDR", "iTXt", "IDAT", "IEND"]);
+        assert_eq!(get_packet(&png).unwrap().unwrap(), wrap_packet(RDF).unwrap());
+    }
+
+    #[test]
+    fn test_merge_packets() {
+        let update = "<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\
+            <rdf:Description rdf:about=\"\" xmlns:xmpRights=\"http://ns.adobe.com/xap/1.0/rights/\" xmpRights:Marked=\"True\"/>\
+            </rdf:RDF>";
+        let merged = merge_packets(&wrap_packet(RDF).unwrap(), update).unwrap();
+
+        assert_eq!(merged.matches("<rdf:RDF").count(), 1);
+        assert_eq!(merged.matches("<rdf:Description").count(), 2);
+        assert!(merged.contains("dc:format=\"image/png\""));
+        assert!(merged.contains("xmpRights:Marked=\"True\""));
+    }
+}